@@ -0,0 +1,183 @@
+//! Derive macros for [activitypub_federation](https://docs.rs/activitypub_federation).
+//!
+//! Don't depend on this crate directly, instead enable the `derive` feature of
+//! `activitypub_federation` and use the macros which are re-exported there.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{
+    parse_macro_input,
+    parse_quote,
+    spanned::Spanned,
+    Data,
+    DeriveInput,
+    Error,
+    Fields,
+    Variant,
+};
+
+/// Implements `ActivityHandler` for an enum by delegating all methods to the wrapped variant.
+///
+/// The last variant can be marked with `#[activity_handler(other)]` and must then wrap
+/// `activitypub_federation::protocol::unknown_activity::UnknownActivity<Self>`. Activities of
+/// unknown types are parsed into it, and are accepted without any further handling. For this the
+/// macro also implements `KnownActivityTypes`, so that activities whose `type` matches one of the
+/// other variants are never parsed as unknown, but rejected if they are invalid.
+///
+/// See `activitypub_federation::traits::ActivityHandler` for documentation.
+#[proc_macro_derive(ActivityHandler, attributes(activity_handler))]
+pub fn derive_activity_handler(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_activity_handler(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_activity_handler(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "ActivityHandler can only be derived for enums",
+        ));
+    };
+
+    let mut other = None;
+    let mut variants = vec![];
+    for (i, variant) in data.variants.iter().enumerate() {
+        let ty = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => return Err(Error::new_spanned(
+                variant,
+                "ActivityHandler enum variants must wrap exactly one activity, eg `Follow(Follow)`",
+            )),
+        };
+        if is_other(variant)? {
+            if i + 1 != data.variants.len() {
+                return Err(Error::new_spanned(
+                    variant,
+                    "`#[activity_handler(other)]` must be the last variant, as serde tries untagged variants in order",
+                ));
+            }
+            other = Some((&variant.ident, ty));
+        } else {
+            variants.push((&variant.ident, ty));
+        }
+    }
+    let Some((_, first)) = variants.first() else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "ActivityHandler enum needs at least one variant which is not `#[activity_handler(other)]`",
+        ));
+    };
+
+    let krate = quote!(::activitypub_federation);
+    let handler = quote!(#krate::traits::ActivityHandler);
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for (i, (_, ty)) in variants.iter().enumerate() {
+        where_clause.predicates.push(if i == 0 {
+            parse_quote!(#ty: #handler + Send + Sync)
+        } else {
+            // All variants need identical associated types, this gives a readable error otherwise
+            parse_quote!(#ty: #handler<
+                DataType = <#first as #handler>::DataType,
+                Error = <#first as #handler>::Error,
+            > + Send + Sync)
+        });
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let idents: Vec<_> = variants.iter().map(|(ident, _)| ident).collect();
+    let unknown_mod = quote!(#krate::protocol::unknown_activity);
+    let (other_id, other_actor, other_ok, known_types) = match other {
+        Some((other, other_ty)) => {
+            let types: Vec<_> = variants.iter().map(|(_, ty)| ty).collect();
+            let mut generics = input.generics.clone();
+            let known_where = generics.make_where_clause();
+            for ty in &types {
+                known_where
+                    .predicates
+                    .push(parse_quote!(#ty: #krate::__private::DeserializeOwned));
+            }
+            let (impl_generics, ty_generics, known_where) = generics.split_for_impl();
+            // Spanned to the variant, as it fails to compile unless it wraps `UnknownActivity<Self>`
+            let span = other_ty.span();
+            (
+                quote_spanned!(span=> Self::#other(inner) => <#unknown_mod::UnknownActivity<Self>>::id(inner),),
+                quote_spanned!(span=> Self::#other(inner) => <#unknown_mod::UnknownActivity<Self>>::actor(inner),),
+                quote!(Self::#other(_) => ::core::result::Result::Ok(()),),
+                quote! {
+                    impl #impl_generics #unknown_mod::KnownActivityTypes for #name #ty_generics #known_where {
+                        fn is_known_type(kind: &str) -> bool {
+                            false #(|| #unknown_mod::accepts_type::<#types>(kind))*
+                        }
+                    }
+                },
+            )
+        }
+        None => Default::default(),
+    };
+
+    Ok(quote! {
+        #known_types
+
+        #[#krate::__private::async_trait]
+        impl #impl_generics #handler for #name #ty_generics #where_clause {
+            type DataType = <#first as #handler>::DataType;
+            type Error = <#first as #handler>::Error;
+
+            fn id(&self) -> &#krate::__private::Url {
+                match self {
+                    #(Self::#idents(inner) => #handler::id(inner),)*
+                    #other_id
+                }
+            }
+
+            fn actor(&self) -> &#krate::__private::Url {
+                match self {
+                    #(Self::#idents(inner) => #handler::actor(inner),)*
+                    #other_actor
+                }
+            }
+
+            async fn verify(
+                &self,
+                data: &#krate::config::Data<Self::DataType>,
+            ) -> ::core::result::Result<(), Self::Error> {
+                match self {
+                    #(Self::#idents(inner) => #handler::verify(inner, data).await,)*
+                    #other_ok
+                }
+            }
+
+            async fn receive(
+                self,
+                data: &#krate::config::Data<Self::DataType>,
+            ) -> ::core::result::Result<(), Self::Error> {
+                match self {
+                    #(Self::#idents(inner) => #handler::receive(inner, data).await,)*
+                    #other_ok
+                }
+            }
+        }
+    })
+}
+
+/// Returns true if the variant has `#[activity_handler(other)]`
+fn is_other(variant: &Variant) -> syn::Result<bool> {
+    let mut other = false;
+    for attr in &variant.attrs {
+        if attr.path().is_ident("activity_handler") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("other") {
+                    other = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported activity_handler attribute, expected `other`"))
+                }
+            })?;
+        }
+    }
+    Ok(other)
+}
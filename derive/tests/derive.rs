@@ -0,0 +1,318 @@
+use activitypub_federation::{
+    config::{Data, FederationConfig},
+    error::Error,
+    fetch::object_id::ObjectId,
+    protocol::{
+        context::WithContext,
+        unknown_activity::UnknownActivity,
+        verification::verify_urls_match,
+    },
+    test_utils::{DbConnection, DbUser, Follow},
+    traits::ActivityHandler,
+};
+use serde::Deserialize;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+use url::Url;
+
+static LIKE_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Deserialize)]
+struct Like {
+    actor: ObjectId<DbUser>,
+    id: Url,
+    #[serde(rename = "type")]
+    _kind: String,
+    #[serde(rename = "object")]
+    _object: Url,
+}
+
+#[async_trait::async_trait]
+impl ActivityHandler for Like {
+    type DataType = DbConnection;
+    type Error = Error;
+
+    fn id(&self) -> &Url {
+        &self.id
+    }
+
+    fn actor(&self) -> &Url {
+        self.actor.inner()
+    }
+
+    async fn verify(&self, _data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn receive(self, _data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        LIKE_RECEIVED.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+static RECEIVED_FOLLOW: Mutex<Option<(Url, Url)>> = Mutex::new(None);
+
+/// Wraps [Follow] to record which follow was received
+#[derive(Deserialize)]
+struct RecordedFollow {
+    #[serde(flatten)]
+    follow: Follow,
+}
+
+#[async_trait::async_trait]
+impl ActivityHandler for RecordedFollow {
+    type DataType = DbConnection;
+    type Error = Error;
+
+    fn id(&self) -> &Url {
+        self.follow.id()
+    }
+
+    fn actor(&self) -> &Url {
+        self.follow.actor()
+    }
+
+    async fn verify(&self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        self.follow.verify(data).await
+    }
+
+    async fn receive(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        *RECEIVED_FOLLOW.lock().unwrap() =
+            Some((self.follow.id.clone(), self.follow.object.inner().clone()));
+        self.follow.receive(data).await
+    }
+}
+
+#[derive(Deserialize, ActivityHandler)]
+#[serde(untagged)]
+enum InnerActivities {
+    Follow(RecordedFollow),
+}
+
+#[derive(Deserialize)]
+struct Undo {
+    actor: ObjectId<DbUser>,
+    id: Url,
+    #[serde(rename = "type")]
+    _kind: String,
+    object: Box<InnerActivities>,
+}
+
+#[async_trait::async_trait]
+impl ActivityHandler for Undo {
+    type DataType = DbConnection;
+    type Error = Error;
+
+    fn id(&self) -> &Url {
+        &self.id
+    }
+
+    fn actor(&self) -> &Url {
+        self.actor.inner()
+    }
+
+    async fn verify(&self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        // only the actor who sent the inner activity can undo it
+        verify_urls_match(self.actor(), self.object.actor())?;
+        self.object.verify(data).await
+    }
+
+    async fn receive(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        // goes through the ActivityHandler impl for Box
+        self.object.receive(data).await
+    }
+}
+
+#[derive(Deserialize, ActivityHandler)]
+#[serde(untagged)]
+enum OuterActivities {
+    Inner(InnerActivities),
+    Like(Box<Like>),
+    Undo(Undo),
+}
+
+#[derive(Deserialize, ActivityHandler)]
+#[serde(untagged)]
+enum WithOther {
+    Follow(Follow),
+    #[activity_handler(other)]
+    Other(UnknownActivity<Self>),
+}
+
+#[derive(Deserialize, ActivityHandler)]
+#[serde(untagged)]
+enum WithContextAndOther {
+    Follow(WithContext<Follow>),
+    #[activity_handler(other)]
+    Other(UnknownActivity<Self>),
+}
+
+const FOLLOW: &str = r#"{
+    "actor": "https://lemmy.ml/u/alice",
+    "object": "https://example.com/u/bob",
+    "type": "Follow",
+    "id": "https://lemmy.ml/activities/follow/1"
+}"#;
+
+const LIKE: &str = r#"{
+    "actor": "https://lemmy.ml/u/alice",
+    "object": "https://example.com/post/1",
+    "type": "Like",
+    "id": "https://lemmy.ml/activities/like/1"
+}"#;
+
+const UNDO_FOLLOW: &str = r#"{
+    "@context": "https://www.w3.org/ns/activitystreams",
+    "actor": "https://lemmy.ml/u/alice",
+    "object": {
+        "actor": "https://lemmy.ml/u/alice",
+        "object": "https://example.com/u/bob",
+        "type": "Follow",
+        "id": "https://lemmy.ml/activities/follow/1"
+    },
+    "type": "Undo",
+    "id": "https://lemmy.ml/activities/undo/1"
+}"#;
+
+const EMOJI_REACT: &str = r#"{
+    "actor": "https://lemmy.ml/u/alice",
+    "object": "https://example.com/post/1",
+    "type": "EmojiReact",
+    "content": "🐈",
+    "id": "https://lemmy.ml/activities/react/1"
+}"#;
+
+#[test]
+fn test_nested_enum_delegates() {
+    let activity: OuterActivities = serde_json::from_str(FOLLOW).unwrap();
+    assert!(matches!(
+        activity,
+        OuterActivities::Inner(InnerActivities::Follow(_))
+    ));
+    assert_eq!(
+        activity.id().as_str(),
+        "https://lemmy.ml/activities/follow/1"
+    );
+    assert_eq!(activity.actor().as_str(), "https://lemmy.ml/u/alice");
+}
+
+#[actix_rt::test]
+async fn test_receive_dispatches_to_variant() {
+    let config = FederationConfig::builder()
+        .domain("example.com")
+        .app_data(DbConnection::default())
+        .build()
+        .unwrap();
+    let data = config.to_request_data();
+
+    let activity: OuterActivities = serde_json::from_str(LIKE).unwrap();
+    assert!(matches!(activity, OuterActivities::Like(_)));
+    activity.verify(&data).await.unwrap();
+    activity.receive(&data).await.unwrap();
+    assert!(LIKE_RECEIVED.load(Ordering::SeqCst));
+}
+
+#[actix_rt::test]
+async fn test_receive_undo_follow() {
+    let config = FederationConfig::builder()
+        .domain("example.com")
+        .app_data(DbConnection::default())
+        .build()
+        .unwrap();
+    let data = config.to_request_data();
+
+    let activity: WithContext<OuterActivities> = serde_json::from_str(UNDO_FOLLOW).unwrap();
+    assert!(matches!(activity.inner(), OuterActivities::Undo(_)));
+    assert_eq!(activity.id().as_str(), "https://lemmy.ml/activities/undo/1");
+    activity.verify(&data).await.unwrap();
+    activity.receive(&data).await.unwrap();
+
+    // the inner follow was received through the Box delegation
+    let (follow_id, followed) = RECEIVED_FOLLOW.lock().unwrap().take().unwrap();
+    assert_eq!(follow_id.as_str(), "https://lemmy.ml/activities/follow/1");
+    assert_eq!(followed.as_str(), "https://example.com/u/bob");
+}
+
+#[actix_rt::test]
+async fn test_reference_delegates() {
+    async fn verify_and_receive<A: ActivityHandler<DataType = DbConnection>>(
+        activity: A,
+        data: &Data<DbConnection>,
+    ) -> Result<(), A::Error> {
+        activity.verify(data).await?;
+        activity.receive(data).await
+    }
+
+    let config = FederationConfig::builder()
+        .domain("example.com")
+        .app_data(DbConnection::default())
+        .build()
+        .unwrap();
+    let data = config.to_request_data();
+
+    let follow: Follow = serde_json::from_str(FOLLOW).unwrap();
+    verify_and_receive(&follow, &data).await.unwrap();
+    // still usable, as it was only borrowed
+    verify_and_receive(follow, &data).await.unwrap();
+}
+
+#[actix_rt::test]
+async fn test_receive_unknown_activity() {
+    let config = FederationConfig::builder()
+        .domain("example.com")
+        .app_data(DbConnection::default())
+        .build()
+        .unwrap();
+    let data = config.to_request_data();
+
+    let activity: WithOther = serde_json::from_str(FOLLOW).unwrap();
+    assert!(matches!(activity, WithOther::Follow(_)));
+
+    let activity: WithOther = serde_json::from_str(EMOJI_REACT).unwrap();
+    let WithOther::Other(unknown) = &activity else {
+        panic!("EmojiReact must be parsed as unknown activity");
+    };
+    assert_eq!(unknown.kind(), Some("EmojiReact"));
+    assert_eq!(
+        activity.id().as_str(),
+        "https://lemmy.ml/activities/react/1"
+    );
+    assert_eq!(activity.actor().as_str(), "https://lemmy.ml/u/alice");
+    activity.verify(&data).await.unwrap();
+    activity.receive(&data).await.unwrap();
+}
+
+#[test]
+fn test_invalid_known_activity_is_not_unknown() {
+    // a follow without object must not be accepted as unknown activity
+    let invalid_follow = FOLLOW.replace(r#""object": "https://example.com/u/bob","#, "");
+    assert!(serde_json::from_str::<WithOther>(&invalid_follow).is_err());
+}
+
+#[test]
+fn test_unknown_activity_next_to_with_context() {
+    let follow = FOLLOW.replacen(
+        '{',
+        r#"{"@context": "https://www.w3.org/ns/activitystreams","#,
+        1,
+    );
+    let activity: WithContextAndOther = serde_json::from_str(&follow).unwrap();
+    assert!(matches!(activity, WithContextAndOther::Follow(_)));
+
+    let activity: WithContextAndOther = serde_json::from_str(EMOJI_REACT).unwrap();
+    let WithContextAndOther::Other(unknown) = activity else {
+        panic!("EmojiReact must be parsed as unknown activity");
+    };
+    assert_eq!(unknown.kind(), Some("EmojiReact"));
+
+    let invalid_follow = follow.replace(r#""object": "https://example.com/u/bob","#, "");
+    assert!(serde_json::from_str::<WithContextAndOther>(&invalid_follow).is_err());
+}
+
+#[test]
+fn test_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
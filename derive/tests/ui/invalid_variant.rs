@@ -0,0 +1,9 @@
+use activitypub_federation::{test_utils::Follow, traits::ActivityHandler};
+
+#[derive(ActivityHandler)]
+enum Activities {
+    Follow(Follow),
+    Other { follow: Follow },
+}
+
+fn main() {}
@@ -0,0 +1,38 @@
+use activitypub_federation::{
+    config::Data,
+    test_utils::Follow,
+    traits::ActivityHandler,
+};
+use url::Url;
+
+struct Other(Url);
+
+#[async_trait::async_trait]
+impl ActivityHandler for Other {
+    type DataType = ();
+    type Error = activitypub_federation::error::Error;
+
+    fn id(&self) -> &Url {
+        &self.0
+    }
+
+    fn actor(&self) -> &Url {
+        &self.0
+    }
+
+    async fn verify(&self, _data: &Data<()>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn receive(self, _data: &Data<()>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(ActivityHandler)]
+enum Activities {
+    Follow(Follow),
+    Other(Other),
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use activitypub_federation::traits::ActivityHandler;
+
+#[derive(ActivityHandler)]
+struct Activities {
+    id: url::Url,
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use activitypub_federation::{
+    protocol::unknown_activity::UnknownActivity,
+    test_utils::Follow,
+    traits::ActivityHandler,
+};
+
+#[derive(ActivityHandler)]
+enum Activities {
+    #[activity_handler(other)]
+    Other(UnknownActivity<Self>),
+    Follow(Follow),
+}
+
+fn main() {}
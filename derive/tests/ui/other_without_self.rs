@@ -0,0 +1,15 @@
+use activitypub_federation::{
+    protocol::unknown_activity::UnknownActivity,
+    test_utils::Follow,
+    traits::ActivityHandler,
+};
+
+#[derive(serde::Deserialize, ActivityHandler)]
+#[serde(untagged)]
+enum Activities {
+    Follow(Follow),
+    #[activity_handler(other)]
+    Other(UnknownActivity),
+}
+
+fn main() {}
@@ -6,11 +6,10 @@ use crate::{
     DbPost,
 };
 use activitypub_federation::{
-    activity_queue::send_activity,
+    activity_queue::send_activity_to_actors,
     config::Data,
     fetch::object_id::ObjectId,
-    kinds::activity::CreateType,
-    protocol::{context::WithContext, helpers::deserialize_one_or_many},
+    protocol::{context::WithContext, helpers::deserialize_one_or_many, kind::CreateType},
     traits::{ActivityHandler, Object},
 };
 use serde::{Deserialize, Serialize};
@@ -29,17 +28,22 @@ pub struct CreatePost {
 }
 
 impl CreatePost {
-    pub async fn send(note: Note, inbox: Url, data: &Data<DatabaseHandle>) -> Result<(), Error> {
+    pub async fn send(
+        note: Note,
+        recipient: DbUser,
+        data: &Data<DatabaseHandle>,
+    ) -> Result<(), Error> {
         print!("Sending reply to {}", &note.attributed_to);
         let create = CreatePost {
             actor: note.attributed_to.clone(),
             to: note.to.clone(),
             object: note,
-            kind: CreateType::Create,
+            kind: CreateType,
             id: generate_object_id(data.domain())?,
         };
         let create_with_context = WithContext::new_default(create);
-        send_activity(create_with_context, &data.local_user(), vec![inbox], data).await?;
+        send_activity_to_actors(create_with_context, &data.local_user(), &[recipient], data)
+            .await?;
         Ok(())
     }
 }
@@ -3,8 +3,7 @@ use activitypub_federation::{
     config::Data,
     fetch::object_id::ObjectId,
     http_signatures::generate_actor_keypair,
-    kinds::actor::PersonType,
-    protocol::{public_key::PublicKey, verification::verify_domains_match},
+    protocol::{actor::ActorKind, public_key::PublicKey, verification::verify_domains_match},
     traits::{ActivityHandler, Actor, Object},
 };
 use chrono::{Local, NaiveDateTime};
@@ -13,6 +12,7 @@ use std::fmt::Debug;
 use url::Url;
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct DbUser {
     pub name: String,
     pub ap_id: ObjectId<DbUser>,
@@ -27,9 +27,8 @@ pub struct DbUser {
 }
 
 /// List of all activities which this actor can receive.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ActivityHandler)]
 #[serde(untagged)]
-#[enum_delegate::implement(ActivityHandler)]
 pub enum PersonAcceptedActivities {
     CreateNote(CreatePost),
 }
@@ -56,7 +55,7 @@ impl DbUser {
 #[serde(rename_all = "camelCase")]
 pub struct Person {
     #[serde(rename = "type")]
-    kind: PersonType,
+    kind: ActorKind,
     preferred_username: String,
     id: ObjectId<DbUser>,
     inbox: Url,
@@ -85,10 +84,10 @@ impl Object for DbUser {
         Ok(res)
     }
 
-    async fn into_json(self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+    async fn into_json(&self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
         Ok(Person {
             preferred_username: self.name.clone(),
-            kind: Default::default(),
+            kind: ActorKind::Person,
             id: self.ap_id.clone(),
             inbox: self.inbox.clone(),
             public_key: self.public_key(),
@@ -8,15 +8,19 @@ use crate::{
 use activitypub_federation::{
     config::Data,
     fetch::object_id::ObjectId,
-    kinds::{object::NoteType, public},
-    protocol::{helpers::deserialize_one_or_many, verification::verify_domains_match},
-    traits::{Actor, Object},
+    protocol::{
+        audience::public,
+        helpers::deserialize_one_or_many,
+        kind::{MentionType, NoteType},
+        verification::verify_domains_match,
+    },
+    traits::Object,
 };
-use activitystreams_kinds::link::MentionType;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct DbPost {
     pub text: String,
     pub ap_id: ObjectId<DbPost>,
@@ -58,7 +62,7 @@ impl Object for DbPost {
         Ok(None)
     }
 
-    async fn into_json(self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+    async fn into_json(&self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
         unimplemented!()
     }
 
@@ -97,7 +101,7 @@ impl Object for DbPost {
             in_reply_to: Some(json.id.clone()),
             tag: vec![mention],
         };
-        CreatePost::send(note, creator.shared_inbox_or_inbox(), data).await?;
+        CreatePost::send(note, creator, data).await?;
 
         Ok(post)
     }
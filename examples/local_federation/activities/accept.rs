@@ -2,7 +2,7 @@ use crate::{activities::follow::Follow, instance::DatabaseHandle, objects::perso
 use activitypub_federation::{
     config::Data,
     fetch::object_id::ObjectId,
-    kinds::activity::AcceptType,
+    protocol::kind::AcceptType,
     traits::ActivityHandler,
 };
 use serde::{Deserialize, Serialize};
@@ -6,8 +6,7 @@ use crate::{
 use activitypub_federation::{
     config::Data,
     fetch::object_id::ObjectId,
-    kinds::activity::CreateType,
-    protocol::helpers::deserialize_one_or_many,
+    protocol::{helpers::deserialize_one_or_many, kind::CreateType},
     traits::{ActivityHandler, Object},
 };
 use serde::{Deserialize, Serialize};
@@ -31,7 +30,7 @@ impl CreatePost {
             actor: note.attributed_to.clone(),
             to: note.to.clone(),
             object: note,
-            kind: CreateType::Create,
+            kind: CreateType,
             id,
         }
     }
@@ -7,8 +7,8 @@ use crate::{
 use activitypub_federation::{
     config::Data,
     fetch::object_id::ObjectId,
-    kinds::activity::FollowType,
-    traits::{ActivityHandler, Actor},
+    protocol::kind::FollowType,
+    traits::ActivityHandler,
 };
 use serde::{Deserialize, Serialize};
 use url::Url;
@@ -66,9 +66,7 @@ impl ActivityHandler for Follow {
         let follower = self.actor.dereference(data).await?;
         let id = generate_object_id(data.domain())?;
         let accept = Accept::new(local_user.ap_id.clone(), self, id.clone());
-        local_user
-            .send(accept, vec![follower.shared_inbox_or_inbox()], data)
-            .await?;
+        local_user.send(accept, &[follower], data).await?;
         Ok(())
     }
 }
@@ -0,0 +1,43 @@
+use crate::{instance::DatabaseHandle, objects::person::DbUser};
+use activitypub_federation::{config::Data, protocol::migration::Move, traits::ActivityHandler};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Sent by a remote user who moved to another account
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(transparent)]
+pub struct MoveAccount(pub Move<DbUser>);
+
+#[async_trait::async_trait]
+impl ActivityHandler for MoveAccount {
+    type DataType = DatabaseHandle;
+    type Error = crate::error::Error;
+
+    fn id(&self) -> &Url {
+        &self.0.id
+    }
+
+    fn actor(&self) -> &Url {
+        self.0.actor.inner()
+    }
+
+    async fn verify(&self, _data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn receive(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        // only accept the move if both accounts confirm it
+        let (old, new) = self.0.verify(data).await?;
+
+        // replace the old account with the new one in all follower lists
+        let mut users = data.users.lock().unwrap();
+        for user in users.iter_mut().filter(|u| u.local) {
+            for follower in user.followers.iter_mut() {
+                if follower == old.ap_id.inner() {
+                    *follower = new.ap_id.inner().clone();
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,20 @@
 use crate::{
     error::Error,
     instance::DatabaseHandle,
-    objects::person::{DbUser, PersonAcceptedActivities},
+    objects::{
+        followers::DbFollowers,
+        person::{DbUser, PersonAcceptedActivities},
+    },
 };
 use activitypub_federation::{
-    actix_web::inbox::receive_activity,
+    actix_web::{inbox::receive_activity, json::FederationJson},
     config::{Data, FederationConfig, FederationMiddleware},
     fetch::webfinger::{build_webfinger_response, extract_webfinger_name},
     protocol::context::WithContext,
-    traits::Object,
+    traits::{Collection, Object},
     FEDERATION_CONTENT_TYPE,
 };
-use actix_web::{web, web::Bytes, App, HttpRequest, HttpResponse, HttpServer};
+use actix_web::{web, web::Bytes, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use anyhow::anyhow;
 use serde::Deserialize;
 use tracing::info;
@@ -24,6 +27,7 @@ pub fn listen(config: &FederationConfig<DatabaseHandle>) -> Result<(), Error> {
         App::new()
             .wrap(FederationMiddleware::new(config.clone()))
             .route("/{user}", web::get().to(http_get_user))
+            .route("/{user}/followers", web::get().to(http_get_user_followers))
             .route("/{user}/inbox", web::post().to(http_post_user_inbox))
             .route("/.well-known/webfinger", web::get().to(webfinger))
     })
@@ -49,6 +53,31 @@ pub async fn http_get_user(
     }
 }
 
+#[derive(Deserialize)]
+pub struct CollectionQuery {
+    page: Option<usize>,
+}
+
+/// Handles requests to fetch the followers collection of a user, or a single page of it
+pub async fn http_get_user_followers(
+    request: HttpRequest,
+    user_name: web::Path<String>,
+    query: web::Query<CollectionQuery>,
+    data: Data<DatabaseHandle>,
+) -> Result<HttpResponse, Error> {
+    let db_user = data.read_user(&user_name)?;
+    Ok(match query.page {
+        Some(page) => {
+            let page = DbFollowers::read_local_page(&db_user, page, &data).await?;
+            FederationJson(WithContext::new_default(page)).respond_to(&request)
+        }
+        None => {
+            let collection = DbFollowers::read_local(&db_user, &data).await?;
+            FederationJson(WithContext::new_default(collection)).respond_to(&request)
+        }
+    })
+}
+
 /// Handles messages received in user inbox
 pub async fn http_post_user_inbox(
     request: HttpRequest,
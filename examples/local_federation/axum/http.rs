@@ -1,7 +1,10 @@
 use crate::{
     error::Error,
     instance::DatabaseHandle,
-    objects::person::{DbUser, Person, PersonAcceptedActivities},
+    objects::{
+        followers::DbFollowers,
+        person::{DbUser, Person, PersonAcceptedActivities},
+    },
 };
 use activitypub_federation::{
     axum::{
@@ -11,7 +14,7 @@ use activitypub_federation::{
     config::{Data, FederationConfig, FederationMiddleware},
     fetch::webfinger::{build_webfinger_response, extract_webfinger_name, Webfinger},
     protocol::context::WithContext,
-    traits::Object,
+    traits::{Collection, Object},
 };
 use axum::{
     extract::{Path, Query},
@@ -32,6 +35,7 @@ pub fn listen(config: &FederationConfig<DatabaseHandle>) -> Result<(), Error> {
     let app = Router::new()
         .route("/:user/inbox", post(http_post_user_inbox))
         .route("/:user", get(http_get_user))
+        .route("/:user/followers", get(http_get_user_followers))
         .route("/.well-known/webfinger", get(webfinger))
         .layer(FederationMiddleware::new(config));
 
@@ -55,6 +59,30 @@ async fn http_get_user(
     Ok(FederationJson(WithContext::new_default(json_user)))
 }
 
+#[derive(Deserialize)]
+struct CollectionQuery {
+    page: Option<usize>,
+}
+
+#[debug_handler]
+async fn http_get_user_followers(
+    Path(name): Path<String>,
+    Query(query): Query<CollectionQuery>,
+    data: Data<DatabaseHandle>,
+) -> Result<impl IntoResponse, Error> {
+    let db_user = data.read_user(&name)?;
+    Ok(match query.page {
+        Some(page) => {
+            let page = DbFollowers::read_local_page(&db_user, page, &data).await?;
+            FederationJson(WithContext::new_default(page)).into_response()
+        }
+        None => {
+            let collection = DbFollowers::read_local(&db_user, &data).await?;
+            FederationJson(WithContext::new_default(collection)).into_response()
+        }
+    })
+}
+
 #[debug_handler]
 async fn http_post_user_inbox(
     data: Data<DatabaseHandle>,
@@ -23,6 +23,7 @@ pub fn new_instance(
     let config = FederationConfig::builder()
         .domain(hostname)
         .app_data(database)
+        .url_verifier(Box::new(MyUrlVerifier()))
         .debug(true)
         .build()?;
     Ok(config)
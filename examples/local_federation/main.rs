@@ -1,8 +1,9 @@
 use crate::{
     instance::{listen, new_instance, Webserver},
-    objects::post::DbPost,
+    objects::{followers::DbFollowers, post::DbPost},
     utils::generate_object_id,
 };
+use activitypub_federation::fetch::collection_id::CollectionId;
 use error::Error;
 use std::{env::args, str::FromStr};
 use tracing::log::{info, LevelFilter};
@@ -49,6 +50,13 @@ async fn main() -> Result<(), Error> {
     );
     info!("Follow was successful");
 
+    info!("Alpha fetches followers collection of beta");
+    let beta_user = beta.local_user();
+    let followers: DbFollowers = CollectionId::from(beta_user.followers_url()?)
+        .dereference_all_pages(&beta_user, &alpha.to_request_data())
+        .await?;
+    assert_eq!(&followers.0, beta_user.followers());
+
     info!("Beta sends a post to its followers");
     let sent_post = DbPost::new("Hello world!".to_string(), beta.local_user().ap_id)?;
     beta.local_user()
@@ -0,0 +1,74 @@
+use crate::{error::Error, instance::DatabaseHandle, objects::person::DbUser};
+use activitypub_federation::{
+    config::Data,
+    protocol::{
+        collection::{
+            followers_collection,
+            FollowersMode,
+            OrderedCollection,
+            OrderedCollectionPage,
+        },
+        verification::verify_domains_match,
+    },
+    traits::Collection,
+};
+use url::Url;
+
+/// Number of followers which are included in a single collection page
+const PAGE_SIZE: usize = 20;
+
+/// List of users who follow a given user
+#[derive(Debug)]
+pub struct DbFollowers(pub Vec<Url>);
+
+#[async_trait::async_trait]
+impl Collection for DbFollowers {
+    type Owner = DbUser;
+    type DataType = DatabaseHandle;
+    type Kind = OrderedCollection<Url>;
+    type Item = Url;
+    type Error = Error;
+
+    async fn read_local(
+        owner: &Self::Owner,
+        _data: &Data<Self::DataType>,
+    ) -> Result<Self::Kind, Self::Error> {
+        Ok(followers_collection(
+            owner.followers_url()?,
+            owner.followers().len(),
+            FollowersMode::Full {
+                page_size: PAGE_SIZE,
+            },
+        ))
+    }
+
+    async fn read_local_page(
+        owner: &Self::Owner,
+        page: usize,
+        _data: &Data<Self::DataType>,
+    ) -> Result<OrderedCollectionPage<Self::Item>, Self::Error> {
+        Ok(OrderedCollectionPage::new(
+            owner.followers_url()?,
+            owner.followers().clone(),
+            page,
+            PAGE_SIZE,
+        ))
+    }
+
+    async fn verify(
+        json: &Self::Kind,
+        expected_domain: &Url,
+        _data: &Data<Self::DataType>,
+    ) -> Result<(), Self::Error> {
+        verify_domains_match(&json.id, expected_domain)?;
+        Ok(())
+    }
+
+    async fn from_json(
+        json: Self::Kind,
+        _owner: &Self::Owner,
+        _data: &Data<Self::DataType>,
+    ) -> Result<Self, Self::Error> {
+        Ok(DbFollowers(json.ordered_items))
+    }
+}
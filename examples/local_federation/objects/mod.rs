@@ -1,2 +1,3 @@
+pub mod followers;
 pub mod person;
 pub mod post;
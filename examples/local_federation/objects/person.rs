@@ -1,17 +1,27 @@
 use crate::{
-    activities::{accept::Accept, create_post::CreatePost, follow::Follow},
+    activities::{
+        accept::Accept,
+        create_post::CreatePost,
+        follow::Follow,
+        move_account::MoveAccount,
+    },
     error::Error,
     instance::DatabaseHandle,
     objects::post::DbPost,
     utils::generate_object_id,
 };
 use activitypub_federation::{
-    activity_queue::send_activity,
+    activity_queue::send_activity_to_actors,
     config::Data,
     fetch::{object_id::ObjectId, webfinger::webfinger_resolve_actor},
     http_signatures::generate_actor_keypair,
-    kinds::actor::PersonType,
-    protocol::{context::WithContext, public_key::PublicKey, verification::verify_domains_match},
+    protocol::{
+        actor::ActorKind,
+        context::WithContext,
+        migration::ActorMigration,
+        public_key::PublicKey,
+        verification::verify_domains_match,
+    },
     traits::{ActivityHandler, Actor, Object},
 };
 use chrono::{Local, NaiveDateTime};
@@ -20,6 +30,7 @@ use std::fmt::Debug;
 use url::Url;
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct DbUser {
     pub name: String,
     pub ap_id: ObjectId<DbUser>,
@@ -31,16 +42,18 @@ pub struct DbUser {
     last_refreshed_at: NaiveDateTime,
     pub followers: Vec<Url>,
     pub local: bool,
+    pub moved_to: Option<Url>,
+    pub also_known_as: Vec<Url>,
 }
 
 /// List of all activities which this actor can receive.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ActivityHandler)]
 #[serde(untagged)]
-#[enum_delegate::implement(ActivityHandler)]
 pub enum PersonAcceptedActivities {
     Follow(Follow),
     Accept(Accept),
     CreateNote(CreatePost),
+    Move(MoveAccount),
 }
 
 impl DbUser {
@@ -57,6 +70,8 @@ impl DbUser {
             last_refreshed_at: Local::now().naive_local(),
             followers: vec![],
             local: true,
+            moved_to: None,
+            also_known_as: vec![],
         })
     }
 }
@@ -65,11 +80,14 @@ impl DbUser {
 #[serde(rename_all = "camelCase")]
 pub struct Person {
     #[serde(rename = "type")]
-    kind: PersonType,
+    kind: ActorKind,
     preferred_username: String,
     id: ObjectId<DbUser>,
     inbox: Url,
+    followers: Url,
     public_key: PublicKey,
+    #[serde(flatten)]
+    migration: ActorMigration,
 }
 
 impl DbUser {
@@ -85,35 +103,34 @@ impl DbUser {
         let other: DbUser = webfinger_resolve_actor(other, data).await?;
         let id = generate_object_id(data.domain())?;
         let follow = Follow::new(self.ap_id.clone(), other.ap_id.clone(), id.clone());
-        self.send(follow, vec![other.shared_inbox_or_inbox()], data)
-            .await?;
+        self.send(follow, &[other], data).await?;
         Ok(())
     }
 
     pub async fn post(&self, post: DbPost, data: &Data<DatabaseHandle>) -> Result<(), Error> {
         let id = generate_object_id(data.domain())?;
         let create = CreatePost::new(post.into_json(data).await?, id.clone());
-        let mut inboxes = vec![];
+        let mut recipients = vec![];
         for f in self.followers.clone() {
             let user: DbUser = ObjectId::from(f).dereference(data).await?;
-            inboxes.push(user.shared_inbox_or_inbox());
+            recipients.push(user);
         }
-        self.send(create, inboxes, data).await?;
+        self.send(create, &recipients, data).await?;
         Ok(())
     }
 
     pub(crate) async fn send<Activity>(
         &self,
         activity: Activity,
-        recipients: Vec<Url>,
+        recipients: &[DbUser],
         data: &Data<DatabaseHandle>,
     ) -> Result<(), <Activity as ActivityHandler>::Error>
     where
         Activity: ActivityHandler + Serialize + Debug + Send + Sync,
-        <Activity as ActivityHandler>::Error: From<anyhow::Error> + From<serde_json::Error>,
+        <Activity as ActivityHandler>::Error: From<activitypub_federation::error::Error>,
     {
         let activity = WithContext::new_default(activity);
-        send_activity(activity, self, recipients, data).await?;
+        send_activity_to_actors(activity, self, recipients, data).await?;
         Ok(())
     }
 }
@@ -140,13 +157,15 @@ impl Object for DbUser {
         Ok(res)
     }
 
-    async fn into_json(self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+    async fn into_json(&self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
         Ok(Person {
             preferred_username: self.name.clone(),
-            kind: Default::default(),
+            kind: ActorKind::Person,
             id: self.ap_id.clone(),
             inbox: self.inbox.clone(),
+            followers: self.followers_url()?,
             public_key: self.public_key(),
+            migration: ActorMigration::from_actor(self),
         })
     }
 
@@ -169,6 +188,8 @@ impl Object for DbUser {
             last_refreshed_at: Local::now().naive_local(),
             followers: vec![],
             local: false,
+            moved_to: json.migration.moved_to,
+            also_known_as: json.migration.also_known_as,
         };
         let mut mutex = data.users.lock().unwrap();
         mutex.push(user.clone());
@@ -192,4 +213,12 @@ impl Actor for DbUser {
     fn inbox(&self) -> Url {
         self.inbox.clone()
     }
+
+    fn moved_to(&self) -> Option<Url> {
+        self.moved_to.clone()
+    }
+
+    fn also_known_as(&self) -> Vec<Url> {
+        self.also_known_as.clone()
+    }
 }
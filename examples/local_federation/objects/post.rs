@@ -2,14 +2,19 @@ use crate::{error::Error, generate_object_id, instance::DatabaseHandle, objects:
 use activitypub_federation::{
     config::Data,
     fetch::object_id::ObjectId,
-    kinds::{object::NoteType, public},
-    protocol::{helpers::deserialize_one_or_many, verification::verify_domains_match},
+    protocol::{
+        audience::public,
+        helpers::deserialize_one_or_many,
+        kind::NoteType,
+        verification::verify_domains_match,
+    },
     traits::Object,
 };
 use serde::{Deserialize, Serialize};
-use url::Url;
+use url::{Position, Url};
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct DbPost {
     pub text: String,
     pub ap_id: ObjectId<DbPost>,
@@ -19,7 +24,9 @@ pub struct DbPost {
 
 impl DbPost {
     pub fn new(text: String, creator: ObjectId<DbUser>) -> Result<DbPost, Error> {
-        let ap_id = generate_object_id(creator.inner().domain().unwrap())?.into();
+        // includes the port, eg `localhost:8001`
+        let domain = &creator.inner()[Position::BeforeHost..Position::AfterPort];
+        let ap_id = generate_object_id(domain)?.into();
         Ok(DbPost {
             text,
             ap_id,
@@ -59,14 +66,14 @@ impl Object for DbPost {
         Ok(res)
     }
 
-    async fn into_json(self, data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+    async fn into_json(&self, data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
         let creator = self.creator.dereference_local(data).await?;
         Ok(Note {
             kind: Default::default(),
-            id: self.ap_id,
-            attributed_to: self.creator,
+            id: self.ap_id.clone(),
+            attributed_to: self.creator.clone(),
             to: vec![public(), creator.followers_url()?],
-            content: self.text,
+            content: self.text.clone(),
         })
     }
 
@@ -3,43 +3,58 @@
 #![doc = include_str!("../docs/09_sending_activities.md")]
 
 use crate::{
+    clock::Clock,
     config::Data,
-    error::Error,
+    error::{Error, ErrorClass},
+    events::{notify, DeliveryMeta, DeliveryOutcome, FederationEvents},
+    fetch::{body_snippet, retry_after},
     http_signatures::sign_request,
+    media_type::content_type_header,
+    metrics::{self, Timer},
     reqwest_shim::ResponseExt,
     traits::{ActivityHandler, Actor},
-    FEDERATION_CONTENT_TYPE,
 };
-use anyhow::anyhow;
 use background_jobs::{
     memory_storage::{ActixTimer, Storage},
     ActixJob,
     Backoff,
     Manager,
     MaxRetries,
+    QueueHandle,
     WorkerConfig,
 };
-use http::{header::HeaderName, HeaderMap, HeaderValue};
+use futures_util::FutureExt;
+use http::{
+    header::{HeaderName, CONTENT_TYPE},
+    HeaderMap,
+    HeaderValue,
+};
 use httpdate::fmt_http_date;
 use itertools::Itertools;
 use reqwest_middleware::ClientWithMiddleware;
 use serde::{Deserialize, Serialize};
 use std::{
+    any::Any,
+    collections::HashMap,
     fmt::Debug,
     future::Future,
+    panic::AssertUnwindSafe,
     pin::Pin,
+    sync::{Arc, Mutex},
     time::{Duration, SystemTime},
 };
-use tracing::{debug, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, field::Empty, info, info_span, warn, Instrument, Span};
 use url::Url;
 
 /// Send a new activity to the given inboxes
 ///
 /// - `activity`: The activity to be sent, gets converted to json
-/// - `private_key`: Private key belonging to the actor who sends the activity, for signing HTTP
-///                  signature. Generated with [crate::http_signatures::generate_actor_keypair].
+/// - `actor`: The actor who sends the activity. Its [Actor::private_key_pem] is used for signing
+///   the HTTP signature, with a key id derived from [Actor::id]. Returns
+///   [Error::PrivateKeyMissing] if the actor has no private key, eg because it is a remote actor.
 /// - `inboxes`: List of actor inboxes that should receive the activity. Should be built by calling
-///              [crate::traits::Actor::shared_inbox_or_inbox] for each target actor.
+///   [crate::traits::Actor::shared_inbox_or_inbox] for each target actor.
 pub async fn send_activity<Activity, Datatype, ActorType>(
     activity: Activity,
     actor: &ActorType,
@@ -48,17 +63,38 @@ pub async fn send_activity<Activity, Datatype, ActorType>(
 ) -> Result<(), <Activity as ActivityHandler>::Error>
 where
     Activity: ActivityHandler + Serialize,
-    <Activity as ActivityHandler>::Error: From<anyhow::Error> + From<serde_json::Error>,
+    <Activity as ActivityHandler>::Error: From<Error>,
+    Datatype: Clone,
+    ActorType: Actor,
+{
+    let activity_serialized = serde_json::to_string_pretty(&activity).map_err(Error::from)?;
+    queue_activity(
+        activity.actor(),
+        activity.id(),
+        activity_serialized,
+        actor,
+        inboxes,
+        data,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Signs the serialized activity and delivers it to the inboxes, see [send_activity]
+pub(crate) async fn queue_activity<Datatype, ActorType>(
+    actor_id: &Url,
+    activity_id: &Url,
+    activity_serialized: String,
+    actor: &ActorType,
+    inboxes: Vec<Url>,
+    data: &Data<Datatype>,
+) -> Result<(), Error>
+where
     Datatype: Clone,
     ActorType: Actor,
 {
     let config = &data.config;
-    let actor_id = activity.actor();
-    let activity_id = activity.id();
-    let activity_serialized = serde_json::to_string_pretty(&activity)?;
-    let private_key = actor
-        .private_key_pem()
-        .expect("Actor for sending activity has private key");
+    let private_key = actor.private_key_pem().ok_or(Error::PrivateKeyMissing)?;
     let inboxes: Vec<Url> = inboxes
         .into_iter()
         .unique()
@@ -71,6 +107,7 @@ where
         .as_ref()
         .expect("Config has activity queue");
     for inbox in inboxes {
+        config.verify_not_cancelled()?;
         if config.verify_url_valid(&inbox).await.is_err() {
             continue;
         }
@@ -84,7 +121,15 @@ where
             http_signature_compat: config.http_signature_compat,
         };
         if config.debug {
-            let res = do_send(message, &config.client, config.request_timeout).await;
+            let res = deliver(
+                message,
+                &config.client,
+                config.request_timeout,
+                &*config.events,
+                &*config.clock,
+                1,
+            )
+            .await;
             // Don't fail on error, as we intentionally do some invalid actions in tests, to verify that
             // they are rejected on the receiving side. These errors shouldn't bubble up to make the API
             // call fail. This matches the behaviour in production.
@@ -92,8 +137,9 @@ where
                 warn!("{}", e);
             }
         } else {
-            activity_queue.queue(message).await?;
-            let stats = activity_queue.get_stats().await?;
+            activity_queue.queue(message).await.map_err(Error::other)?;
+            let stats = activity_queue.get_stats().await.map_err(Error::other)?;
+            metrics::queue_stats(stats.pending, stats.running);
             let stats_fmt = format!(
                 "Activity queue stats: pending: {}, running: {}, dead (this hour): {}, complete (this hour): {}",
                 stats.pending,
@@ -113,6 +159,59 @@ where
     Ok(())
 }
 
+/// Send a new public activity to the given inboxes and to all relays
+///
+/// Works like [send_activity], but the activity is also delivered to the relay inboxes which
+/// are set with [FederationConfigBuilder::relays](crate::config::FederationConfigBuilder::relays).
+/// Each inbox only receives the activity once, also if a relay inbox is listed in `inboxes`, eg
+/// as shared inbox of a follower. See [crate::protocol::relay] for subscribing to relays.
+pub async fn send_public_activity<Activity, Datatype, ActorType>(
+    activity: Activity,
+    actor: &ActorType,
+    mut inboxes: Vec<Url>,
+    data: &Data<Datatype>,
+) -> Result<(), <Activity as ActivityHandler>::Error>
+where
+    Activity: ActivityHandler + Serialize,
+    <Activity as ActivityHandler>::Error: From<Error>,
+    Datatype: Clone,
+    ActorType: Actor,
+{
+    inboxes.extend(data.config.relays.iter().cloned());
+    send_activity(activity, actor, inboxes, data).await
+}
+
+/// Send a new activity to the given recipient actors
+///
+/// Works like [send_activity], but takes the target actors directly instead of inbox urls. For
+/// each recipient the activity is delivered to [Actor::shared_inbox_or_inbox], so that actors
+/// which share an inbox only receive the activity once.
+pub async fn send_activity_to_actors<Activity, Datatype, ActorType, RecipientType>(
+    activity: Activity,
+    actor: &ActorType,
+    recipients: &[RecipientType],
+    data: &Data<Datatype>,
+) -> Result<(), <Activity as ActivityHandler>::Error>
+where
+    Activity: ActivityHandler + Serialize,
+    <Activity as ActivityHandler>::Error: From<Error>,
+    Datatype: Clone,
+    ActorType: Actor,
+    RecipientType: Actor,
+{
+    let inboxes = recipient_inboxes(recipients);
+    send_activity(activity, actor, inboxes, data).await
+}
+
+/// Returns the deduplicated list of inboxes for the given actors, preferring shared inboxes.
+pub(crate) fn recipient_inboxes<ActorType: Actor>(recipients: &[ActorType]) -> Vec<Url> {
+    recipients
+        .iter()
+        .map(Actor::shared_inbox_or_inbox)
+        .unique()
+        .collect()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct SendActivityTask {
     actor_id: Url,
@@ -128,7 +227,7 @@ impl ActixJob for SendActivityTask {
     type Future = Pin<Box<dyn Future<Output = Result<(), anyhow::Error>>>>;
     const NAME: &'static str = "SendActivityTask";
 
-    const MAX_RETRIES: MaxRetries = MaxRetries::Count(3);
+    const MAX_RETRIES: MaxRetries = MaxRetries::Count(RETRY_COUNT as usize);
     /// This gives the following retry intervals:
     /// - 60s (one minute, for service restart)
     /// - 60min (one hour, for instance maintenance)
@@ -136,20 +235,154 @@ impl ActixJob for SendActivityTask {
     const BACKOFF: Backoff = Backoff::Exponential(60);
 
     fn run(self, state: Self::State) -> Self::Future {
-        Box::pin(async move { do_send(self, &state.client, state.timeout).await })
+        Box::pin(async move {
+            let activity_id = self.activity_id.clone();
+            let key = (self.activity_id.clone(), self.inbox.clone());
+            // also checked before each retry, so that deliveries stop during shutdown
+            if state.cancellation_token.is_cancelled() {
+                info!(
+                    "Dropping delivery of activity {} during shutdown",
+                    activity_id
+                );
+                state.attempts.finish(&key);
+                return Ok(());
+            }
+            let attempt = state.attempts.start(&key);
+            let retry = self.clone();
+            let res = deliver(
+                self,
+                &state.client,
+                state.timeout,
+                &*state.events,
+                &*state.clock,
+                attempt,
+            )
+            .await;
+            // this is the last attempt if it is aborted, succeeds or no retries are left
+            if !matches!(&res, Err(e) if e.is_retryable()) || attempt > RETRY_COUNT {
+                state.attempts.finish(&key);
+            }
+            match res {
+                Err(e) if e.is_retryable() && attempt <= RETRY_COUNT => {
+                    // a new job is scheduled for the requested time, which doesn't reset the
+                    // number of attempts as they are counted separately
+                    if let ErrorClass::RateLimited {
+                        retry_after: Some(retry_after),
+                    } = e.class()
+                    {
+                        let retry_after = retry_after.min(MAX_RETRY_AFTER);
+                        info!(
+                            "Scheduling activity {} for retry in {}s: {}",
+                            activity_id,
+                            retry_after.as_secs(),
+                            e
+                        );
+                        state
+                            .queue_handle
+                            .schedule(retry, SystemTime::now() + retry_after)
+                            .await?;
+                        return Ok(());
+                    }
+                    info!("Queueing activity {} for retry: {}", activity_id, e);
+                    Err(e.into())
+                }
+                Err(e) => {
+                    info!("Aborting delivery of activity {}: {}", activity_id, e);
+                    Ok(())
+                }
+                Ok(()) => Ok(()),
+            }
+        })
+    }
+}
+
+/// Maximum number of retries for a failed delivery
+const RETRY_COUNT: u32 = 3;
+
+/// Upper limit for the `Retry-After` of rate limited deliveries, same as the longest backoff
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60 * 60);
+
+/// Sends the activity within a `deliver_activity` span, see the crate docs about tracing.
+///
+/// Panics while sending, eg in a client middleware, are returned as [Error::DeliveryPanicked],
+/// so that the delivery is retried and the queue worker stays alive.
+async fn deliver(
+    task: SendActivityTask,
+    client: &ClientWithMiddleware,
+    timeout: Duration,
+    events: &dyn FederationEvents,
+    clock: &dyn Clock,
+    attempt: u32,
+) -> Result<(), Error> {
+    let span = info_span!(
+        "deliver_activity",
+        activity.id = %task.activity_id,
+        inbox.host = task.inbox.host_str(),
+        attempt,
+        result = Empty,
+    );
+    let meta = DeliveryMeta {
+        activity_id: task.activity_id.clone(),
+        inbox: task.inbox.clone(),
+        attempt,
+    };
+    async move {
+        let timer = Timer::start(&task.inbox);
+        let res = AssertUnwindSafe(do_send(task, client, timeout, clock))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|payload| {
+                let message = panic_message(&*payload);
+                warn!(
+                    "Delivery of activity {} to {} panicked: {}",
+                    meta.activity_id, meta.inbox, message
+                );
+                metrics::delivery_panicked(&meta.inbox);
+                Err(Error::DeliveryPanicked {
+                    inbox: meta.inbox.clone(),
+                    message,
+                })
+            });
+        let result = match res.as_ref().map_err(Error::class) {
+            Ok(()) => "success",
+            Err(ErrorClass::Permanent) => "permanent",
+            Err(ErrorClass::Transient) => "transient",
+            Err(ErrorClass::RateLimited { .. }) => "rate_limited",
+        };
+        Span::current().record("result", result);
+        metrics::delivery_finished(timer, result);
+        let outcome = DeliveryOutcome::from_result(&res);
+        notify(
+            "delivery_attempted",
+            events.delivery_attempted(&meta, &outcome),
+        )
+        .await;
+        res
     }
+    .instrument(span)
+    .await
+}
+
+/// Returns the message of a panic, which is usually a string
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic payload".to_string())
 }
 
 async fn do_send(
     task: SendActivityTask,
     client: &ClientWithMiddleware,
     timeout: Duration,
-) -> Result<(), anyhow::Error> {
+    clock: &dyn Clock,
+) -> Result<(), Error> {
     debug!("Sending {} to {}", task.activity_id, task.inbox);
     let request_builder = client
         .post(task.inbox.to_string())
         .timeout(timeout)
-        .headers(generate_request_headers(&task.inbox));
+        .headers(generate_request_headers(&task.inbox, clock.now()));
     let request = sign_request(
         request_builder,
         task.actor_id,
@@ -158,63 +391,56 @@ async fn do_send(
         task.http_signature_compat,
     )
     .await?;
-    let response = client.execute(request).await;
+    let response = client
+        .execute(request)
+        .await
+        .map_err(|e| Error::DeliveryError {
+            inbox: task.inbox.clone(),
+            status: None,
+            retry_after: None,
+            source: Some(Box::new(e.into())),
+        })?;
 
-    match response {
-        Ok(o) if o.status().is_success() => {
-            info!(
-                "Activity {} delivered successfully to {}",
-                task.activity_id, task.inbox
-            );
-            Ok(())
-        }
-        Ok(o) if o.status().is_client_error() => {
-            let text = o.text_limited().await.map_err(Error::other)?;
-            info!(
-                "Activity {} was rejected by {}, aborting: {}",
-                task.activity_id, task.inbox, text,
-            );
-            Ok(())
-        }
-        Ok(o) => {
-            let status = o.status();
-            let text = o.text_limited().await.map_err(Error::other)?;
-            Err(anyhow!(
-                "Queueing activity {} to {} for retry after failure with status {}: {}",
-                task.activity_id,
-                task.inbox,
-                status,
-                text,
-            ))
-        }
-        Err(e) => {
-            info!(
-                "Unable to connect to {}, aborting task {}: {}",
-                task.inbox, task.activity_id, e
-            );
-            Ok(())
-        }
+    let status = response.status();
+    if status.is_success() {
+        info!(
+            "Activity {} delivered successfully to {}",
+            task.activity_id, task.inbox
+        );
+        return Ok(());
     }
+    let retry_after = retry_after(response.headers(), clock.now());
+    let body = response.bytes_limited().await.unwrap_or_default();
+    info!(
+        "Activity {} was rejected by {} with status {}: {}",
+        task.activity_id,
+        task.inbox,
+        status,
+        body_snippet(&body)
+    );
+    Err(Error::DeliveryError {
+        inbox: task.inbox,
+        status: Some(status),
+        retry_after,
+        source: None,
+    })
 }
 
-pub(crate) fn generate_request_headers(inbox_url: &Url) -> HeaderMap {
+pub(crate) fn generate_request_headers(inbox_url: &Url, now: SystemTime) -> HeaderMap {
     let mut host = inbox_url.domain().expect("read inbox domain").to_string();
     if let Some(port) = inbox_url.port() {
         host = format!("{}:{}", host, port);
     }
 
     let mut headers = HeaderMap::new();
-    headers.insert(
-        HeaderName::from_static("content-type"),
-        HeaderValue::from_static(FEDERATION_CONTENT_TYPE),
-    );
+    headers.insert(CONTENT_TYPE, content_type_header());
     headers.insert(
         HeaderName::from_static("host"),
         HeaderValue::from_str(&host).expect("Hostname is valid"),
     );
     headers.insert(
         "date",
-        HeaderValue::from_str(&fmt_http_date(SystemTime::now())).expect("Date is valid"),
+        HeaderValue::from_str(&fmt_http_date(now)).expect("Date is valid"),
     );
     headers
 }
@@ -224,14 +450,23 @@ pub(crate) fn create_activity_queue(
     worker_count: u64,
     request_timeout: Duration,
     debug: bool,
+    events: Box<dyn FederationEvents>,
+    clock: Box<dyn Clock>,
+    cancellation_token: CancellationToken,
 ) -> Manager {
     // queue is not used in debug mod, so dont create any workers to avoid log spam
     let worker_count = if debug { 0 } else { worker_count };
 
     // Configure and start our workers
-    WorkerConfig::new_managed(Storage::new(ActixTimer), move |_| QueueState {
+    let attempts = Attempts::default();
+    WorkerConfig::new_managed(Storage::new(ActixTimer), move |queue_handle| QueueState {
+        queue_handle,
         client: client.clone(),
         timeout: request_timeout,
+        attempts: attempts.clone(),
+        events: events.clone(),
+        clock: clock.clone(),
+        cancellation_token: cancellation_token.clone(),
     })
     .register::<SendActivityTask>()
     .set_worker_count("default", worker_count)
@@ -240,6 +475,438 @@ pub(crate) fn create_activity_queue(
 
 #[derive(Clone)]
 struct QueueState {
+    queue_handle: QueueHandle,
     client: ClientWithMiddleware,
     timeout: Duration,
+    attempts: Attempts,
+    events: Box<dyn FederationEvents>,
+    clock: Box<dyn Clock>,
+    cancellation_token: CancellationToken,
+}
+
+/// Counts delivery attempts per activity and inbox, as the job queue doesn't expose them
+#[derive(Clone, Default)]
+struct Attempts(Arc<Mutex<HashMap<(Url, Url), u32>>>);
+
+impl Attempts {
+    /// Returns the number of the attempt which is starting, beginning with 1
+    fn start(&self, key: &(Url, Url)) -> u32 {
+        let mut attempts = self.0.lock().expect("lock attempts");
+        let attempt = attempts.entry(key.clone()).or_default();
+        *attempt += 1;
+        *attempt
+    }
+
+    fn finish(&self, key: &(Url, Url)) {
+        self.0.lock().expect("lock attempts").remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SystemClock,
+        config::FederationConfig,
+        error::ErrorClass,
+        events::NoFederationEvents,
+        fetch::test_server::serve,
+        instrument::TraceCapture,
+        test_utils::{fixtures, DbConnection, DbUser, DB_USER, DB_USER_KEYPAIR},
+    };
+    use reqwest_middleware::{ClientBuilder, Middleware, Next};
+    use std::collections::HashMap;
+    use task_local_extensions::Extensions;
+
+    fn user(id: &str, shared_inbox: Option<&str>) -> DbUser {
+        let mut user = DB_USER.clone();
+        user.federation_id = Url::parse(id).unwrap();
+        user.inbox = Url::parse(&format!("{id}/inbox")).unwrap();
+        user.shared_inbox = shared_inbox.map(|s| Url::parse(s).unwrap());
+        user
+    }
+
+    #[test]
+    fn test_recipient_inboxes_without_shared_inbox() {
+        let alice = user("https://example.com/u/alice", None);
+        let inbox = alice.inbox.clone();
+        assert_eq!(alice.shared_inbox_or_inbox(), inbox);
+        assert_eq!(recipient_inboxes(&[alice]), vec![inbox]);
+    }
+
+    #[test]
+    fn test_recipient_inboxes_deduplicate_shared_inbox() {
+        let shared = "https://example.com/inbox";
+        let alice = user("https://example.com/u/alice", Some(shared));
+        let bob = user("https://example.com/u/bob", Some(shared));
+        let carol = user("https://lemmy.ml/u/carol", None);
+        let inboxes = recipient_inboxes(&[alice, bob, carol.clone()]);
+        assert_eq!(inboxes, vec![Url::parse(shared).unwrap(), carol.inbox]);
+    }
+
+    #[actix_rt::test]
+    async fn test_send_activity_without_private_key() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let mut sender = user("https://lemmy.ml/u/alice", None);
+        sender.private_key = None;
+        let recipient = user("https://lemmy.ml/u/bob", None);
+        let follow = fixtures::follow(&sender.federation_id, &recipient.federation_id);
+        let err = send_activity_to_actors(follow, &sender, &[recipient], &data)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err, Error::PrivateKeyMissing);
+    }
+
+    fn task(path: &str, port: u16) -> SendActivityTask {
+        SendActivityTask {
+            actor_id: DB_USER.federation_id.clone(),
+            activity_id: Url::parse("https://example.com/activities/1").unwrap(),
+            activity: "{}".to_string(),
+            inbox: Url::parse(&format!("http://localhost:{port}{path}")).unwrap(),
+            private_key: DB_USER_KEYPAIR.private_key.clone(),
+            http_signature_compat: false,
+        }
+    }
+
+    async fn send_to(path: &str, port: u16) -> Result<(), Error> {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        deliver(
+            task(path, port),
+            &config.client,
+            config.request_timeout,
+            &*config.events,
+            &*config.clock,
+            1,
+        )
+        .await
+    }
+
+    #[actix_rt::test]
+    async fn test_do_send_error_class() {
+        let port = serve(HashMap::from([
+            ("/ok".to_string(), ("202 Accepted", String::new())),
+            // the test server doesn't support headers, so add it to the status line
+            (
+                "/limited".to_string(),
+                ("429 Too Many Requests\r\nRetry-After: 30", String::new()),
+            ),
+            ("/rejected".to_string(), ("400 Bad Request", String::new())),
+            ("/broken".to_string(), ("502 Bad Gateway", String::new())),
+        ]));
+        assert!(send_to("/ok", port).await.is_ok());
+
+        let err = send_to("/limited", port).await.unwrap_err();
+        assert_eq!(
+            err.class(),
+            ErrorClass::RateLimited {
+                retry_after: Some(Duration::from_secs(30))
+            }
+        );
+        assert!(err.is_retryable());
+
+        let err = send_to("/rejected", port).await.unwrap_err();
+        assert_eq!(err.class(), ErrorClass::Permanent);
+        assert!(!err.is_retryable());
+
+        let err = send_to("/broken", port).await.unwrap_err();
+        assert_eq!(err.class(), ErrorClass::Transient);
+
+        // nothing is listening on this port anymore
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = send_to("/inbox", closed).await.unwrap_err();
+        assert!(matches!(err, Error::DeliveryError { status: None, .. }));
+        assert_eq!(err.class(), ErrorClass::Transient);
+    }
+
+    #[actix_rt::test]
+    async fn test_deliver_span() {
+        let port = serve(HashMap::from([
+            ("/ok".to_string(), ("202 Accepted", String::new())),
+            ("/rejected".to_string(), ("400 Bad Request", String::new())),
+        ]));
+        let capture = TraceCapture::start();
+        assert!(send_to("/ok", port).await.is_ok());
+        assert!(send_to("/rejected", port).await.is_err());
+
+        let spans = capture.spans("deliver_activity");
+        assert_eq!(spans.len(), 2);
+        for span in &spans {
+            assert_eq!(
+                span.fields["activity.id"],
+                "https://example.com/activities/1"
+            );
+            assert_eq!(span.fields["inbox.host"], "localhost");
+            assert_eq!(span.fields["attempt"], "1");
+        }
+        assert_eq!(spans[0].fields["result"], "success");
+        assert_eq!(spans[1].fields["result"], "permanent");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_deliver_metrics() {
+        use crate::metrics::{
+            tests::{counter, record_metrics},
+            DELIVERIES,
+        };
+        let port = serve(HashMap::from([
+            ("/ok".to_string(), ("202 Accepted", String::new())),
+            ("/broken".to_string(), ("502 Bad Gateway", String::new())),
+        ]));
+        let metrics = record_metrics(async {
+            send_to("/ok", port).await.unwrap();
+            send_to("/ok", port).await.unwrap();
+            send_to("/broken", port).await.unwrap_err();
+        });
+        let success = [("domain", "localhost"), ("result", "success")];
+        assert_eq!(counter(&metrics, DELIVERIES, &success), 2);
+        let transient = [("domain", "localhost"), ("result", "transient")];
+        assert_eq!(counter(&metrics, DELIVERIES, &transient), 1);
+    }
+
+    /// Panics for requests to `/panic`, and records the paths of all other delivered requests
+    struct PanicMiddleware(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl Middleware for PanicMiddleware {
+        async fn handle(
+            &self,
+            req: reqwest::Request,
+            extensions: &mut Extensions,
+            next: Next<'_>,
+        ) -> reqwest_middleware::Result<reqwest::Response> {
+            let path = req.url().path().to_string();
+            assert_ne!(path, "/panic", "broken middleware");
+            let res = next.run(req, extensions).await?;
+            self.0.lock().unwrap().push(path);
+            Ok(res)
+        }
+    }
+
+    /// Records the headers of requests, and responds with `202 Accepted` without sending them
+    #[derive(Clone, Default)]
+    struct RecordingMiddleware(Arc<Mutex<Vec<HeaderMap>>>);
+
+    #[async_trait::async_trait]
+    impl Middleware for RecordingMiddleware {
+        async fn handle(
+            &self,
+            req: reqwest::Request,
+            _: &mut Extensions,
+            _: Next<'_>,
+        ) -> reqwest_middleware::Result<reqwest::Response> {
+            self.0.lock().unwrap().push(req.headers().clone());
+            let mut response = http::Response::new(vec![]);
+            *response.status_mut() = http::StatusCode::ACCEPTED;
+            Ok(response.into())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_delivery_headers() {
+        let recorder = RecordingMiddleware::default();
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(recorder.clone())
+            .build();
+        deliver(
+            task("/inbox", 8000),
+            &client,
+            Duration::from_secs(10),
+            &NoFederationEvents,
+            &SystemClock,
+            1,
+        )
+        .await
+        .unwrap();
+
+        let requests = recorder.0.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let headers = &requests[0];
+        assert_eq!(headers.get(CONTENT_TYPE), Some(&content_type_header()));
+        assert_eq!(headers.get("host").unwrap(), "localhost:8000");
+        for name in ["date", "digest", "signature"] {
+            assert!(headers.contains_key(name), "missing {name}");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_queue_worker_survives_panic() {
+        let port = serve(HashMap::from([(
+            "/ok".to_string(),
+            ("202 Accepted", String::new()),
+        )]));
+        let delivered = Arc::new(Mutex::new(vec![]));
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(PanicMiddleware(delivered.clone()))
+            .build();
+        let timeout = Duration::from_secs(10);
+
+        let err = deliver(
+            task("/panic", port),
+            &client,
+            timeout,
+            &NoFederationEvents,
+            &SystemClock,
+            1,
+        )
+        .await
+        .unwrap_err();
+        let Error::DeliveryPanicked { message, .. } = &err else {
+            panic!("expected panic error, got {:?}", err);
+        };
+        assert!(message.contains("broken middleware"), "{message}");
+        assert!(err.is_retryable());
+
+        // a single worker has to deliver the second activity after the first one panicked
+        let events = Box::new(NoFederationEvents);
+        let token = CancellationToken::new();
+        let queue = create_activity_queue(
+            client,
+            1,
+            timeout,
+            false,
+            events,
+            Box::new(SystemClock),
+            token,
+        );
+        queue.queue(task("/panic", port)).await.unwrap();
+        queue.queue(task("/ok", port)).await.unwrap();
+        let start = std::time::Instant::now();
+        while delivered.lock().unwrap().is_empty() && start.elapsed() < timeout {
+            actix_rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(*delivered.lock().unwrap(), vec!["/ok".to_string()]);
+    }
+
+    /// Rejects all requests with `429 Too Many Requests` and `Retry-After: 1`
+    #[derive(Clone, Default)]
+    struct RateLimitMiddleware(Arc<Mutex<Vec<std::time::Instant>>>);
+
+    #[async_trait::async_trait]
+    impl Middleware for RateLimitMiddleware {
+        async fn handle(
+            &self,
+            _: reqwest::Request,
+            _: &mut Extensions,
+            _: Next<'_>,
+        ) -> reqwest_middleware::Result<reqwest::Response> {
+            self.0.lock().unwrap().push(std::time::Instant::now());
+            let response = http::Response::builder()
+                .status(http::StatusCode::TOO_MANY_REQUESTS)
+                .header("retry-after", "1")
+                .body(vec![])
+                .unwrap();
+            Ok(response.into())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_queue_respects_retry_after() {
+        let limiter = RateLimitMiddleware::default();
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(limiter.clone())
+            .build();
+        let timeout = Duration::from_secs(20);
+        let queue = create_activity_queue(
+            client,
+            1,
+            timeout,
+            false,
+            Box::new(NoFederationEvents),
+            Box::new(SystemClock),
+            CancellationToken::new(),
+        );
+        queue.queue(task("/inbox", 8000)).await.unwrap();
+
+        // without Retry-After, the first retry would only happen after a minute
+        let start = std::time::Instant::now();
+        while queue.get_stats().await.unwrap().complete.this_hour() < RETRY_COUNT as usize + 1
+            && start.elapsed() < timeout
+        {
+            actix_rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        let attempts = limiter.0.lock().unwrap().clone();
+        assert_eq!(attempts.len(), RETRY_COUNT as usize + 1);
+        for pair in attempts.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_secs(1));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_queue_drops_deliveries_after_shutdown() {
+        let recorder = RecordingMiddleware::default();
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(recorder.clone())
+            .build();
+        let timeout = Duration::from_secs(10);
+        let token = CancellationToken::new();
+        let events = Box::new(NoFederationEvents);
+        let queue = create_activity_queue(
+            client,
+            1,
+            timeout,
+            false,
+            events,
+            Box::new(SystemClock),
+            token.clone(),
+        );
+
+        token.cancel();
+        queue.queue(task("/inbox", 8000)).await.unwrap();
+        let start = std::time::Instant::now();
+        while queue.get_stats().await.unwrap().complete.this_hour() == 0
+            && start.elapsed() < timeout
+        {
+            actix_rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(queue.get_stats().await.unwrap().complete.this_hour(), 1);
+        assert!(recorder.0.lock().unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_send_activity_after_shutdown() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        config.shutdown();
+        let data = config.to_request_data();
+        let sender = user("https://lemmy.ml/u/alice", None);
+        let recipient = user("https://lemmy.ml/u/bob", None);
+        let follow = fixtures::follow(&sender.federation_id, &recipient.federation_id);
+        let err = send_activity_to_actors(follow, &sender, &[recipient], &data)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err, Error::Cancelled);
+    }
+
+    #[test]
+    fn test_attempts() {
+        let attempts = Attempts::default();
+        let key = (
+            Url::parse("https://example.com/activities/1").unwrap(),
+            Url::parse("https://example.net/inbox").unwrap(),
+        );
+        assert_eq!(attempts.start(&key), 1);
+        assert_eq!(attempts.start(&key), 2);
+        attempts.finish(&key);
+        assert_eq!(attempts.start(&key), 1);
+    }
 }
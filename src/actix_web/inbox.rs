@@ -3,13 +3,11 @@
 use crate::{
     config::Data,
     error::Error,
-    fetch::object_id::ObjectId,
-    http_signatures::{verify_inbox_hash, verify_signature},
+    inbox::remote_addr,
     traits::{ActivityHandler, Actor, Object},
 };
 use actix_web::{web::Bytes, HttpRequest, HttpResponse};
 use serde::de::DeserializeOwned;
-use tracing::debug;
 
 /// Handles incoming activities, verifying HTTP signatures and other checks
 ///
@@ -23,31 +21,19 @@ where
     Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
     ActorT: Object<DataType = Datatype> + Actor + Send + 'static,
     for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
-    <Activity as ActivityHandler>::Error: From<anyhow::Error>
-        + From<Error>
-        + From<<ActorT as Object>::Error>
-        + From<serde_json::Error>,
-    <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
+    <Activity as ActivityHandler>::Error: From<Error> + From<<ActorT as Object>::Error>,
+    <ActorT as Object>::Error: From<Error>,
     Datatype: Clone,
 {
-    verify_inbox_hash(request.headers().get("Digest"), &body)?;
-
-    let activity: Activity = serde_json::from_slice(&body)?;
-    data.config.verify_url_and_domain(&activity).await?;
-    let actor = ObjectId::<ActorT>::from(activity.actor().clone())
-        .dereference(data)
-        .await?;
-
-    verify_signature(
+    crate::inbox::receive_activity::<Activity, ActorT, Datatype, _>(
         request.headers(),
         request.method(),
         request.uri(),
-        actor.public_key_pem(),
-    )?;
-
-    debug!("Receiving activity {}", activity.id().to_string());
-    activity.verify(data).await?;
-    activity.receive(data).await?;
+        &body,
+        remote_addr(request.headers(), request.peer_addr()),
+        data,
+    )
+    .await?;
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -55,14 +41,35 @@ where
 mod test {
     use super::*;
     use crate::{
-        activity_queue::generate_request_headers,
+        activity_queue::send_activity,
         config::FederationConfig,
-        http_signatures::sign_request,
-        traits::tests::{DbConnection, DbUser, Follow, DB_USER_KEYPAIR},
+        events::{
+            tests::{Event, RecordingEvents},
+            ActivityMeta,
+            DeliveryMeta,
+            DeliveryOutcome,
+            RejectionReason,
+        },
+        fetch::test_server::serve,
+        inbox::REJECTED_TARGET,
+        instrument::TraceCapture,
+        protocol::unknown_activity::UnknownActivity,
+        test_utils::{
+            clock::ManualClock,
+            fixtures,
+            request::{SignedRequest, SignedRequestBuilder},
+            DbConnection,
+            DbUser,
+            Follow,
+            DB_USER,
+            DB_USER_KEYPAIR,
+        },
     };
-    use actix_web::test::TestRequest;
-    use reqwest::Client;
-    use reqwest_middleware::ClientWithMiddleware;
+    use actix_web::{http::StatusCode, test::TestRequest};
+    use http::{header::CONTENT_TYPE, HeaderValue};
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::{collections::HashMap, time::Duration};
     use url::Url;
 
     #[actix_rt::test]
@@ -77,6 +84,124 @@ mod test {
         .unwrap();
     }
 
+    #[actix_rt::test]
+    async fn test_receive_activity_span() {
+        let (body, incoming_request, config) = setup_receive_test().await;
+        let activity: Follow = serde_json::from_str(&body).unwrap();
+        let capture = TraceCapture::start();
+        receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request
+                .insert_header(("X-Forwarded-For", "192.0.2.1"))
+                .to_http_request(),
+            body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+
+        let spans = capture.spans("receive_activity");
+        assert_eq!(spans.len(), 1);
+        let fields = &spans[0].fields;
+        assert_eq!(fields["activity.id"], activity.id.as_str());
+        assert_eq!(fields["activity.type"], "Follow");
+        assert_eq!(fields["actor.domain"], "localhost");
+        assert_eq!(fields["remote_addr"], "192.0.2.1");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_receive_activity_metrics() {
+        use crate::metrics::{
+            tests::{counter, record_metrics},
+            ACTIVITIES_RECEIVED,
+        };
+        let metrics = record_metrics(async {
+            let (body, incoming_request, config) = setup_receive_test().await;
+            let data = config.to_request_data();
+            let request = incoming_request.to_http_request();
+            receive_activity::<Follow, DbUser, DbConnection>(request.clone(), body.into(), &data)
+                .await
+                .unwrap();
+            receive_activity::<Follow, DbUser, DbConnection>(request, "invalid".into(), &data)
+                .await
+                .unwrap_err();
+        });
+        let accepted = [("domain", "localhost"), ("result", "accepted")];
+        assert_eq!(counter(&metrics, ACTIVITIES_RECEIVED, &accepted), 1);
+        let rejected = [("domain", "unknown"), ("result", "digest")];
+        assert_eq!(counter(&metrics, ACTIVITIES_RECEIVED, &rejected), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_events() {
+        let (body, incoming_request, mut config) = setup_receive_test().await;
+        let recorder = RecordingEvents::default();
+        config.events = Box::new(recorder.clone());
+        let data = config.to_request_data();
+        let request = incoming_request.to_http_request();
+        receive_activity::<Follow, DbUser, DbConnection>(request.clone(), "invalid".into(), &data)
+            .await
+            .unwrap_err();
+        receive_activity::<Follow, DbUser, DbConnection>(request, body.clone().into(), &data)
+            .await
+            .unwrap();
+
+        let port = serve(HashMap::from([(
+            "/inbox".to_string(),
+            ("202 Accepted", String::new()),
+        )]));
+        let inbox = Url::parse(&format!("http://localhost:{port}/inbox")).unwrap();
+        let activity: Follow = serde_json::from_str(&body).unwrap();
+        let activity_id = activity.id.clone();
+        send_activity(activity, &*DB_USER, vec![inbox.clone()], &data)
+            .await
+            .unwrap();
+
+        let activity = ActivityMeta {
+            id: Some(activity_id),
+            kind: Some("Follow".to_string()),
+            actor: Some(Url::parse("https://localhost/123").unwrap()),
+        };
+        let delivery = DeliveryMeta {
+            activity_id: activity.id.clone().unwrap(),
+            inbox,
+            attempt: 1,
+        };
+        assert_eq!(
+            recorder.events(),
+            vec![
+                Event::Rejected(ActivityMeta::default(), RejectionReason::Digest),
+                Event::Received(activity),
+                Event::Delivered(delivery, DeliveryOutcome::Success),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_rejected_event() {
+        let (body, incoming_request, config) = setup_receive_test().await;
+        let tampered = body.replace("/activities/follow/", "/activities/follow/0");
+        let tampered_activity: Follow = serde_json::from_str(&tampered).unwrap();
+        let capture = TraceCapture::start();
+        receive_activity::<Follow, DbUser, DbConnection>(
+            incoming_request.to_http_request(),
+            tampered.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap_err();
+
+        let events = capture.events(REJECTED_TARGET);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].level, tracing::Level::WARN);
+        let fields = &events[0].fields;
+        assert_eq!(fields["message"], "Rejected incoming activity");
+        assert_eq!(fields["stage"], "digest");
+        assert_eq!(fields["source_domain"], "localhost");
+        assert_eq!(fields["activity_id"], tampered_activity.id.as_str());
+        assert_eq!(fields["status"], "400");
+    }
+
     #[actix_rt::test]
     async fn test_receive_activity_invalid_body_signature() {
         let (_, incoming_request, config) = setup_receive_test().await;
@@ -89,8 +214,7 @@ mod test {
         .err()
         .unwrap();
 
-        let e = err.root_cause().downcast_ref::<Error>().unwrap();
-        assert_eq!(e, &Error::ActivityBodyDigestInvalid)
+        assert_eq!(err, Error::ActivityBodyDigestInvalid)
     }
 
     #[actix_rt::test]
@@ -106,43 +230,171 @@ mod test {
         .err()
         .unwrap();
 
-        let e = err.root_cause().downcast_ref::<Error>().unwrap();
-        assert_eq!(e, &Error::ActivitySignatureInvalid)
+        assert_eq!(err, Error::ActivitySignatureInvalid)
     }
 
-    async fn setup_receive_test() -> (String, TestRequest, FederationConfig<DbConnection>) {
-        let inbox = "https://example.com/inbox";
-        let headers = generate_request_headers(&Url::parse(inbox).unwrap());
-        let request_builder = ClientWithMiddleware::from(Client::default())
-            .post(inbox)
-            .headers(headers);
-        let activity = Follow {
-            actor: ObjectId::parse("http://localhost:123").unwrap(),
-            object: ObjectId::parse("http://localhost:124").unwrap(),
-            kind: Default::default(),
-            id: "http://localhost:123/1".try_into().unwrap(),
-        };
-        let body = serde_json::to_string(&activity).unwrap();
-        let outgoing_request = sign_request(
-            request_builder,
-            activity.actor.into_inner(),
-            body.to_string(),
-            DB_USER_KEYPAIR.private_key.clone(),
-            false,
+    #[actix_rt::test]
+    async fn test_receive_activity_corrupted_request() {
+        async fn receive_corrupted(
+            options: impl FnOnce(SignedRequestBuilder) -> SignedRequestBuilder,
+        ) -> Error {
+            let (_, request, config) = setup_receive_test_with(options).await;
+            receive_activity::<Follow, DbUser, DbConnection>(
+                request.to_test_request().to_http_request(),
+                request.body.into(),
+                &config.to_request_data(),
+            )
+            .await
+            .unwrap_err()
+        }
+
+        let err = receive_corrupted(SignedRequestBuilder::wrong_digest).await;
+        assert_eq!(err, Error::ActivityBodyDigestInvalid);
+        let err = receive_corrupted(|r| r.without_header("signature")).await;
+        assert_eq!(err, Error::ActivitySignatureInvalid);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_json_content_type() {
+        let (_, request, config) =
+            setup_receive_test_with(|r| r.content_type("application/json")).await;
+        receive_activity::<Follow, DbUser, DbConnection>(
+            request.to_test_request().to_http_request(),
+            request.body.into(),
+            &config.to_request_data(),
         )
         .await
         .unwrap();
-        let mut incoming_request = TestRequest::post().uri(outgoing_request.url().path());
-        for h in outgoing_request.headers() {
-            incoming_request = incoming_request.append_header(h);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_content_type() {
+        let (_, mut request, mut config) = setup_receive_test_with(|r| r).await;
+        let recorder = RecordingEvents::default();
+        config.events = Box::new(recorder.clone());
+        let html = HeaderValue::from_static("text/html");
+        request.headers.insert(CONTENT_TYPE, html);
+        let err = receive_activity::<Follow, DbUser, DbConnection>(
+            request.to_test_request().to_http_request(),
+            request.body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, Error::ActivityContentTypeInvalid(String::new()));
+        let Event::Rejected(_, reason) = &recorder.events()[0] else {
+            panic!("expected rejection event");
+        };
+        assert_eq!(*reason, RejectionReason::ContentType);
+        assert_eq!(reason.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_expired_signature() {
+        let (_, request, mut config) = setup_receive_test_with(|r| r).await;
+        let clock = ManualClock::default();
+        config.clock = Box::new(clock.clone());
+        let data = config.to_request_data();
+        let receive = || {
+            receive_activity::<Follow, DbUser, DbConnection>(
+                request.to_test_request().to_http_request(),
+                request.body.clone().into(),
+                &data,
+            )
+        };
+        receive().await.unwrap();
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(
+            receive().await.unwrap_err(),
+            Error::ActivitySignatureInvalid
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_unknown_activity() {
+        #[derive(Deserialize, ActivityHandler)]
+        #[serde(untagged)]
+        enum Activities {
+            Follow(Follow),
+            #[activity_handler(other)]
+            Other(UnknownActivity<Self>),
         }
 
+        let body = json!({
+            "id": "https://localhost/123/react/1",
+            "type": "EmojiReact",
+            "actor": "https://localhost/123",
+            "object": "http://localhost:124/post/1",
+            "content": "🐈"
+        });
+        let parsed: Activities = serde_json::from_value(body.clone()).unwrap();
+        assert!(matches!(parsed, Activities::Other(_)));
+
+        let (request, config) = sign_test_request(&body, |r| r).await;
+        let response = receive_activity::<Activities, DbUser, DbConnection>(
+            request.to_test_request().to_http_request(),
+            request.body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // a follow without object is invalid, and must not be handled as unknown activity
+        let mut invalid_follow = serde_json::to_value(fixtures::follow(
+            &DB_USER.id(),
+            &Url::parse("http://localhost:124").unwrap(),
+        ))
+        .unwrap();
+        invalid_follow.as_object_mut().unwrap().remove("object");
+        let (request, mut config) = sign_test_request(&invalid_follow, |r| r).await;
+        let recorder = RecordingEvents::default();
+        config.events = Box::new(recorder.clone());
+        let err = receive_activity::<Activities, DbUser, DbConnection>(
+            request.to_test_request().to_http_request(),
+            request.body.into(),
+            &config.to_request_data(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Json(_)), "{err:?}");
+        let Event::Rejected(_, reason) = &recorder.events()[0] else {
+            panic!("expected rejection event");
+        };
+        assert_eq!(*reason, RejectionReason::Parse);
+    }
+
+    async fn setup_receive_test() -> (String, TestRequest, FederationConfig<DbConnection>) {
+        let (body, request, config) = setup_receive_test_with(|r| r).await;
+        (body, request.to_test_request(), config)
+    }
+
+    /// Signs a follow activity with the key of [DbUser], after applying `options` to the
+    /// request builder
+    async fn setup_receive_test_with(
+        options: impl FnOnce(SignedRequestBuilder) -> SignedRequestBuilder,
+    ) -> (String, SignedRequest, FederationConfig<DbConnection>) {
+        let bob = Url::parse("http://localhost:124").unwrap();
+        let activity = fixtures::follow(&DB_USER.id(), &bob);
+        let body = serde_json::to_string(&activity).unwrap();
+        let (request, config) = sign_test_request(&activity, options).await;
+        (body, request, config)
+    }
+
+    /// Signs `activity` with the key of [DbUser], and returns it as incoming inbox request
+    async fn sign_test_request(
+        activity: &impl Serialize,
+        options: impl FnOnce(SignedRequestBuilder) -> SignedRequestBuilder,
+    ) -> (SignedRequest, FederationConfig<DbConnection>) {
+        let builder = SignedRequestBuilder::new(activity, &DB_USER_KEYPAIR, &DB_USER.id());
+        let request = options(builder).build().await.unwrap();
+
         let config = FederationConfig::builder()
             .domain("localhost:8002")
-            .app_data(DbConnection)
+            .app_data(DbConnection::default())
             .debug(true)
             .build()
             .unwrap();
-        (body, incoming_request, config)
+        (request, config)
     }
 }
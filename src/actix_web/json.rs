@@ -0,0 +1,101 @@
+//! Wrapper struct to respond with `application/activity+json` in actix-web handlers
+//!
+//! ```
+//! # use anyhow::Error;
+//! # use actix_web::web;
+//! # use activitypub_federation::actix_web::json::FederationJson;
+//! # use activitypub_federation::protocol::context::WithContext;
+//! # use activitypub_federation::config::Data;
+//! # use activitypub_federation::traits::Object;
+//! # use activitypub_federation::test_utils::{DbConnection, DbUser, Person};
+//! async fn http_get_user(name: web::Path<String>, data: Data<DbConnection>) -> Result<FederationJson<WithContext<Person>>, Error> {
+//!     let user: DbUser = data.read_local_user(name.into_inner()).await?;
+//!     let person = user.into_json(&data).await?;
+//!
+//!     Ok(FederationJson(WithContext::new_default(person)))
+//! }
+//! ```
+
+use crate::{
+    media_type::ACTIVITY_JSON,
+    protocol::{context::WithContext, tombstone::Tombstone},
+};
+use actix_web::{body::BoxBody, http::StatusCode, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+
+/// Wrapper struct to respond with `application/activity+json` in actix-web handlers
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FederationJson<Json: Serialize>(pub Json);
+
+impl<Json: Serialize> Responder for FederationJson<Json> {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        HttpResponse::Ok().content_type(ACTIVITY_JSON).json(self.0)
+    }
+}
+
+/// Responds with `410 Gone` and the tombstone, for fetches of local objects which were deleted.
+///
+/// This tells remote servers to remove their cached copy of the object.
+///
+/// ```
+/// # use activitypub_federation::actix_web::json::tombstone_response;
+/// # use activitypub_federation::protocol::tombstone::Tombstone;
+/// # use url::Url;
+/// let id = Url::parse("https://example.com/post/1")?;
+/// let response = tombstone_response(Tombstone::new(id).former_type("Note"));
+/// assert_eq!(response.status(), 410);
+/// # Ok::<(), url::ParseError>(())
+/// ```
+pub fn tombstone_response(tombstone: Tombstone) -> HttpResponse {
+    HttpResponse::build(StatusCode::GONE)
+        .content_type(ACTIVITY_JSON)
+        .json(WithContext::new_default(tombstone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::collection::CollectionPageBuilder;
+    use actix_web::{body::to_bytes, http::header, test::TestRequest};
+    use url::Url;
+
+    #[actix_rt::test]
+    async fn test_federation_json_collection_page() {
+        let builder =
+            CollectionPageBuilder::new(Url::parse("https://example.com/outbox").unwrap(), 3, 2);
+        let page = builder.page(1, vec![1, 2]);
+
+        let response = FederationJson(page).respond_to(&TestRequest::default().to_http_request());
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            ACTIVITY_JSON
+        );
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["orderedItems"], serde_json::json!([1, 2]));
+        assert_eq!(json["next"], "https://example.com/outbox?page=2");
+    }
+
+    #[actix_rt::test]
+    async fn test_tombstone_response() {
+        let tombstone = Tombstone::new(Url::parse("https://example.com/post/1").unwrap());
+        let response = tombstone_response(tombstone);
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            ACTIVITY_JSON
+        );
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "@context": crate::protocol::context::context(),
+                "type": "Tombstone",
+                "id": "https://example.com/post/1"
+            })
+        );
+    }
+}
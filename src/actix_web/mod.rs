@@ -1,5 +1,6 @@
 //! Utilities for using this library with actix-web framework
 
 pub mod inbox;
+pub mod json;
 #[doc(hidden)]
 pub mod middleware;
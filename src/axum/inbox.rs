@@ -5,22 +5,25 @@
 use crate::{
     config::Data,
     error::Error,
-    fetch::object_id::ObjectId,
-    http_signatures::{verify_inbox_hash, verify_signature},
+    inbox::remote_addr,
     traits::{ActivityHandler, Actor, Object},
 };
 use axum::{
     async_trait,
     body::{Bytes, HttpBody},
-    extract::FromRequest,
+    extract::{FromRequest, OriginalUri},
     http::{Request, StatusCode},
     response::{IntoResponse, Response},
 };
 use http::{HeaderMap, Method, Uri};
 use serde::de::DeserializeOwned;
-use tracing::debug;
 
 /// Handles incoming activities, verifying HTTP signatures and other checks
+///
+/// Verification is the same as with
+/// [actix-web](crate::actix_web::inbox::receive_activity), also for routers which are nested
+/// under a path prefix. The only difference is that the address of the TCP peer is unknown, so the
+/// remote address in traces is only read from the `X-Forwarded-For` and `X-Real-IP` headers.
 pub async fn receive_activity<Activity, ActorT, Datatype>(
     activity_data: ActivityData,
     data: &Data<Datatype>,
@@ -29,32 +32,19 @@ where
     Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
     ActorT: Object<DataType = Datatype> + Actor + Send + 'static,
     for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
-    <Activity as ActivityHandler>::Error: From<anyhow::Error>
-        + From<Error>
-        + From<<ActorT as Object>::Error>
-        + From<serde_json::Error>,
-    <ActorT as Object>::Error: From<Error> + From<anyhow::Error>,
+    <Activity as ActivityHandler>::Error: From<Error> + From<<ActorT as Object>::Error>,
+    <ActorT as Object>::Error: From<Error>,
     Datatype: Clone,
 {
-    verify_inbox_hash(activity_data.headers.get("Digest"), &activity_data.body)?;
-
-    let activity: Activity = serde_json::from_slice(&activity_data.body)?;
-    data.config.verify_url_and_domain(&activity).await?;
-    let actor = ObjectId::<ActorT>::from(activity.actor().clone())
-        .dereference(data)
-        .await?;
-
-    verify_signature(
+    crate::inbox::receive_activity::<Activity, ActorT, Datatype, _>(
         &activity_data.headers,
         &activity_data.method,
         &activity_data.uri,
-        actor.public_key_pem(),
-    )?;
-
-    debug!("Receiving activity {}", activity.id().to_string());
-    activity.verify(data).await?;
-    activity.receive(data).await?;
-    Ok(())
+        &activity_data.body,
+        remote_addr(&activity_data.headers, None),
+        data,
+    )
+    .await
 }
 
 /// Contains all data that is necessary to receive an activity from an HTTP request
@@ -79,6 +69,12 @@ where
 
     async fn from_request(req: Request<B>, _state: &S) -> Result<Self, Self::Rejection> {
         let (parts, body) = req.into_parts();
+        // nested routers remove their prefix from the uri, but the signature is over the full
+        // path which was requested
+        let uri = match parts.extensions.get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.clone(),
+            None => parts.uri,
+        };
 
         // this wont work if the body is an long running stream
         let bytes = hyper::body::to_bytes(body)
@@ -88,10 +84,161 @@ where
         Ok(Self {
             headers: parts.headers,
             method: parts.method,
-            uri: parts.uri,
+            uri,
             body: bytes.to_vec(),
         })
     }
 }
 
-// TODO: copy tests from actix-web inbox and implement for axum as well
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        test_utils::{
+            axum::{inbox_handler, inbox_router},
+            clock::ManualClock,
+            fixtures,
+            request::{SignedRequest, SignedRequestBuilder},
+            DbConnection,
+            DbUser,
+            Follow,
+            DB_USER,
+            DB_USER_KEYPAIR,
+        },
+    };
+    use axum::{body::Body, routing::post, Router};
+    use http::{header::CONTENT_TYPE, HeaderValue};
+    use std::time::Duration;
+    use tower::ServiceExt;
+    use url::Url;
+
+    fn config(clock: ManualClock) -> FederationConfig<DbConnection> {
+        FederationConfig::builder()
+            .domain("localhost:8002")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .clock(Box::new(clock))
+            .build()
+            .unwrap()
+    }
+
+    /// Sends the request to the router, and returns status and body of the response
+    async fn send(router: Router, request: SignedRequest) -> (StatusCode, String) {
+        let response = router
+            .oneshot(request.into_http().map(Body::from))
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn receive(request: SignedRequest) -> (StatusCode, String) {
+        let router = inbox_router::<Follow, DbUser, _>("/inbox", config(Default::default()));
+        send(router, request).await
+    }
+
+    fn follow_request() -> SignedRequestBuilder {
+        let bob = Url::parse("http://localhost:124").unwrap();
+        let activity = fixtures::follow(&DB_USER.id(), &bob);
+        SignedRequestBuilder::new(&activity, &DB_USER_KEYPAIR, &DB_USER.id())
+    }
+
+    fn rejected(error: Error) -> (StatusCode, String) {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity() {
+        let request = follow_request().build().await.unwrap();
+        assert_eq!(receive(request).await, (StatusCode::OK, String::new()));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_body_signature() {
+        let mut request = follow_request().build().await.unwrap();
+        request.body = b"invalid".to_vec();
+        let res = receive(request).await;
+        assert_eq!(res, rejected(Error::ActivityBodyDigestInvalid));
+
+        let request = follow_request().wrong_digest().build().await.unwrap();
+        let res = receive(request).await;
+        assert_eq!(res, rejected(Error::ActivityBodyDigestInvalid));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_content_type() {
+        let mut request = follow_request().build().await.unwrap();
+        let html = HeaderValue::from_static("text/html");
+        request.headers.insert(CONTENT_TYPE, html);
+        let res = receive(request).await;
+        let error = Error::ActivityContentTypeInvalid("text/html".to_string());
+        assert_eq!(res, rejected(error));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_invalid_path() {
+        // signed for a different path than the one it is sent to
+        let mut request = follow_request().uri("/wrong").build().await.unwrap();
+        request.uri = Uri::from_static("/inbox");
+        let res = receive(request).await;
+        assert_eq!(res, rejected(Error::ActivitySignatureInvalid));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_missing_signature() {
+        let request = follow_request()
+            .without_header("signature")
+            .build()
+            .await
+            .unwrap();
+        let res = receive(request).await;
+        assert_eq!(res, rejected(Error::ActivitySignatureInvalid));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_expired_signature() {
+        let clock = ManualClock::default();
+        let router = inbox_router::<Follow, DbUser, _>("/inbox", config(clock.clone()));
+        let request = follow_request().build().await.unwrap();
+        clock.advance(Duration::from_secs(60));
+        let res = send(router, request).await;
+        assert_eq!(res, rejected(Error::ActivitySignatureInvalid));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_after_shutdown() {
+        let config = config(Default::default());
+        config.shutdown();
+        let router = inbox_router::<Follow, DbUser, _>("/inbox", config);
+        let request = follow_request().build().await.unwrap();
+        let res = send(router, request).await;
+        assert_eq!(res, rejected(Error::Cancelled));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_nested_router() {
+        // the signature covers the full path, like with actix-web scopes
+        let inbox = inbox_router::<Follow, DbUser, _>("/inbox", config(Default::default()));
+        let router = Router::new().nest("/u/alice", inbox);
+        let request = follow_request()
+            .uri("/u/alice/inbox")
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(send(router, request).await, (StatusCode::OK, String::new()));
+    }
+
+    #[actix_rt::test]
+    async fn test_receive_activity_without_middleware() {
+        let router = Router::new().route(
+            "/inbox",
+            post(inbox_handler::<Follow, DbUser, DbConnection>),
+        );
+        let request = follow_request().build().await.unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("FederationMiddleware"));
+    }
+}
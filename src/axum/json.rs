@@ -7,7 +7,7 @@
 //! # use activitypub_federation::protocol::context::WithContext;
 //! # use activitypub_federation::config::Data;
 //! # use activitypub_federation::traits::Object;
-//! # use activitypub_federation::traits::tests::{DbConnection, DbUser, Person};
+//! # use activitypub_federation::test_utils::{DbConnection, DbUser, Person};
 //! async fn http_get_user(Path(name): Path<String>, data: Data<DbConnection>) -> Result<FederationJson<WithContext<Person>>, Error> {
 //!     let user: DbUser = data.read_local_user(name).await?;
 //!     let person = user.into_json(&data).await?;
@@ -16,9 +16,12 @@
 //! }
 //! ```
 
-use crate::FEDERATION_CONTENT_TYPE;
+use crate::{
+    media_type::content_type_header,
+    protocol::{context::WithContext, tombstone::Tombstone},
+};
 use axum::response::IntoResponse;
-use http::header;
+use http::{header, StatusCode};
 use serde::Serialize;
 
 /// Wrapper struct to respond with `application/activity+json` in axum handlers
@@ -28,12 +31,74 @@ pub struct FederationJson<Json: Serialize>(pub Json);
 impl<Json: Serialize> IntoResponse for FederationJson<Json> {
     fn into_response(self) -> axum::response::Response {
         let mut response = axum::response::Json(self.0).into_response();
-        response.headers_mut().insert(
-            header::CONTENT_TYPE,
-            FEDERATION_CONTENT_TYPE
-                .parse()
-                .expect("Parsing 'application/activity+json' should never fail"),
-        );
         response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type_header());
+        response
+    }
+}
+
+/// Responds with `410 Gone` and the tombstone, for fetches of local objects which were deleted.
+///
+/// This tells remote servers to remove their cached copy of the object.
+///
+/// ```
+/// # use activitypub_federation::axum::json::tombstone_response;
+/// # use activitypub_federation::protocol::tombstone::Tombstone;
+/// # use url::Url;
+/// let id = Url::parse("https://example.com/post/1")?;
+/// let response = tombstone_response(Tombstone::new(id).former_type("Note"));
+/// assert_eq!(response.status(), 410);
+/// # Ok::<(), url::ParseError>(())
+/// ```
+pub fn tombstone_response(tombstone: Tombstone) -> axum::response::Response {
+    let mut response = FederationJson(WithContext::new_default(tombstone)).into_response();
+    *response.status_mut() = StatusCode::GONE;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{media_type::ACTIVITY_JSON, protocol::collection::CollectionPageBuilder};
+    use url::Url;
+
+    #[actix_rt::test]
+    async fn test_federation_json_collection_page() {
+        let builder =
+            CollectionPageBuilder::new(Url::parse("https://example.com/outbox").unwrap(), 3, 2);
+        let page = builder.page(1, vec![1, 2]);
+
+        let response = FederationJson(page).into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            ACTIVITY_JSON
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["orderedItems"], serde_json::json!([1, 2]));
+        assert_eq!(json["next"], "https://example.com/outbox?page=2");
+    }
+
+    #[actix_rt::test]
+    async fn test_tombstone_response() {
+        let tombstone = Tombstone::new(Url::parse("https://example.com/post/1").unwrap());
+        let response = tombstone_response(tombstone);
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            ACTIVITY_JSON
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "@context": crate::protocol::context::context(),
+                "type": "Tombstone",
+                "id": "https://example.com/post/1"
+            })
+        );
     }
 }
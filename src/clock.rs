@@ -0,0 +1,38 @@
+//! Source of the current time
+//!
+//! The library reads the time from the [Clock] which is set with
+//! [FederationConfigBuilder::clock](crate::config::FederationConfigBuilder::clock), when it
+//! decides if an object needs to be refetched, validates the expiration of incoming signatures,
+//! sets the `Date` header of outgoing requests and parses `Retry-After` dates. Only the `(created)`
+//! field of outgoing signatures is always generated from the system time by
+//! `http-signature-normalization`. The schedule of retries in the activity queue is also managed
+//! by `background-jobs` with the system time.
+//!
+//! Tests can use `test_utils::clock::ManualClock` with the `test-utils` feature, which only
+//! moves forward when it is advanced.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use dyn_clone::{clone_trait_object, DynClone};
+use std::time::SystemTime;
+
+/// Returns the current time
+pub trait Clock: DynClone + Send + Sync {
+    /// The current time
+    fn now(&self) -> SystemTime;
+}
+clone_trait_object!(Clock);
+
+/// Default [Clock] which returns the system time
+#[derive(Clone, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Converts a time to the format which is used by [Object](crate::traits::Object)
+pub(crate) fn naive_utc(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Utc>::from(time).naive_utc()
+}
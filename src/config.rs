@@ -16,9 +16,12 @@
 
 use crate::{
     activity_queue::create_activity_queue,
+    clock::{Clock, SystemClock},
     error::Error,
-    protocol::verification::verify_domains_match,
-    traits::ActivityHandler,
+    events::{FederationEvents, NoFederationEvents},
+    fetch::{transport::HttpTransport, FetchChain, SignedFetchDomains},
+    protocol::verification::{host_with_port, normalize_domain, verify_domains_match},
+    traits::{ActivityHandler, Actor},
 };
 use async_trait::async_trait;
 use background_jobs::Manager;
@@ -31,16 +34,19 @@ use std::{
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc,
+        Mutex,
     },
     time::Duration,
 };
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
 /// Configuration for this library, with various federation related settings
 #[derive(Builder, Clone)]
 #[builder(build_fn(private, name = "partial_build"))]
 pub struct FederationConfig<T: Clone> {
-    /// The domain where this federated instance is running
+    /// The domain where this federated instance is running, optionally with port. Internationalized
+    /// domain names may be given in unicode or punycode form.
     #[builder(setter(into))]
     pub(crate) domain: String,
     /// Data which the application requires in handlers, such as database connection
@@ -50,10 +56,28 @@ pub struct FederationConfig<T: Clone> {
     /// [crate::fetch::object_id::ObjectId] for more details.
     #[builder(default = "20")]
     pub(crate) http_fetch_limit: u32,
-    #[builder(default = "reqwest::Client::default().into()")]
-    /// HTTP client used for all outgoing requests. Middleware can be used to add functionality
-    /// like log tracing or retry of failed requests.
+    /// Maximum number of pages which are fetched when dereferencing a paged collection. See
+    /// [crate::fetch::collection_id::CollectionId] for more details.
+    #[builder(default = "10")]
+    pub(crate) collection_page_limit: usize,
+    /// Maximum number of items which are read when dereferencing a paged collection, further
+    /// items are ignored.
+    #[builder(default = "1000")]
+    pub(crate) collection_item_limit: usize,
+    /// Maximum number of collection items which may fail to parse or fetch, before fetching the
+    /// collection is aborted.
+    #[builder(default = "10")]
+    pub(crate) collection_item_failure_limit: usize,
+    /// HTTP client used for all outgoing requests, see [FederationConfigBuilder::client]
+    #[builder(
+        setter(custom),
+        field(type = "ClientOptions", build = "self.client.build()?")
+    )]
     pub(crate) client: ClientWithMiddleware,
+    /// Transport which sends the requests for fetching remote objects. Uses
+    /// [Self::client] if it is not set. See [HttpTransport] for details.
+    #[builder(default, setter(strip_option))]
+    pub(crate) transport: Option<Box<dyn HttpTransport>>,
     /// Number of worker threads for sending outgoing activities
     #[builder(default = "64")]
     pub(crate) worker_count: u64,
@@ -75,6 +99,32 @@ pub struct FederationConfig<T: Clone> {
     /// <https://git.pleroma.social/pleroma/pleroma/-/issues/2939>
     #[builder(default = "false")]
     pub(crate) http_signature_compat: bool,
+    /// Id and private key of the actor which signs fetches, see
+    /// [FederationConfigBuilder::signed_fetch_actor]
+    #[builder(
+        setter(custom),
+        field(type = "Option<(Url, Option<String>)>", build = "None")
+    )]
+    pub(crate) signed_fetch_actor: Option<Arc<(Url, String)>>,
+    /// Domains which require signed fetches
+    #[builder(setter(skip))]
+    pub(crate) signed_fetch_domains: SignedFetchDomains,
+    /// Inboxes of relays which receive all activities that are sent with
+    /// [send_public_activity](crate::activity_queue::send_public_activity). See
+    /// [crate::protocol::relay] for subscribing to a relay.
+    #[builder(default)]
+    pub(crate) relays: Vec<Url>,
+    /// Callbacks which are notified about federation events, see [FederationEvents]
+    #[builder(default = "Box::new(NoFederationEvents)")]
+    pub(crate) events: Box<dyn FederationEvents>,
+    /// Source of the current time, see [Clock]. Defaults to the system time.
+    #[builder(default = "Box::new(SystemClock)")]
+    pub(crate) clock: Box<dyn Clock>,
+    /// Token for graceful shutdown of the application. Once it is cancelled, fetches, deliveries
+    /// of queued activities and inbox handlers return [Error::Cancelled] instead of continuing
+    /// their work. See also [FederationConfig::shutdown].
+    #[builder(default)]
+    pub(crate) cancellation_token: CancellationToken,
     /// Queue for sending outgoing activities. Only optional to make builder work, its always
     /// present once constructed.
     #[builder(setter(skip))]
@@ -98,18 +148,27 @@ impl<T: Clone> FederationConfig<T> {
         self.verify_url_valid(activity.id()).await?;
         if self.is_local_url(activity.id()) {
             return Err(Error::UrlVerificationError(
-                "Activity was sent from local instance",
+                "Activity was sent from local instance".to_string(),
             ));
         }
 
         Ok(())
     }
 
+    /// Transport for fetches, which defaults to the HTTP client
+    pub(crate) fn transport(&self) -> &dyn HttpTransport {
+        match &self.transport {
+            Some(transport) => transport.as_ref(),
+            None => &self.client,
+        }
+    }
+
     /// Create new [Data] from this. You should prefer to use a middleware if possible.
     pub fn to_request_data(&self) -> Data<T> {
         Data {
             config: self.clone(),
             request_counter: Default::default(),
+            fetch_chain: Default::default(),
         }
     }
 
@@ -123,11 +182,15 @@ impl<T: Clone> FederationConfig<T> {
             "http" => {
                 if !self.debug {
                     return Err(Error::UrlVerificationError(
-                        "Http urls are only allowed in debug mode",
+                        "Http urls are only allowed in debug mode".to_string(),
                     ));
                 }
             }
-            _ => return Err(Error::UrlVerificationError("Invalid url scheme")),
+            _ => {
+                return Err(Error::UrlVerificationError(
+                    "Invalid url scheme".to_string(),
+                ))
+            }
         };
 
         // Urls which use our local domain are not a security risk, no further verification needed
@@ -136,19 +199,21 @@ impl<T: Clone> FederationConfig<T> {
         }
 
         if url.domain().is_none() {
-            return Err(Error::UrlVerificationError("Url must have a domain"));
+            return Err(Error::UrlVerificationError(
+                "Url must have a domain".to_string(),
+            ));
         }
 
         if url.domain() == Some("localhost") && !self.debug {
             return Err(Error::UrlVerificationError(
-                "Localhost is only allowed in debug mode",
+                "Localhost is only allowed in debug mode".to_string(),
             ));
         }
 
         self.url_verifier
             .verify(url)
             .await
-            .map_err(Error::UrlVerificationError)?;
+            .map_err(|e| Error::UrlVerificationError(e.to_string()))?;
 
         Ok(())
     }
@@ -156,37 +221,161 @@ impl<T: Clone> FederationConfig<T> {
     /// Returns true if the url refers to this instance. Handles hostnames like `localhost:8540` for
     /// local debugging.
     pub(crate) fn is_local_url(&self, url: &Url) -> bool {
-        let mut domain = url.host_str().expect("id has domain").to_string();
-        if let Some(port) = url.port() {
-            domain = format!("{}:{}", domain, port);
+        host_with_port(url).as_ref() == Some(&self.domain)
+    }
+
+    /// Stops running federation operations by cancelling the
+    /// [cancellation_token](FederationConfigBuilder::cancellation_token). Activities which are
+    /// still in the queue are dropped instead of being delivered.
+    pub fn shutdown(&self) {
+        self.cancellation_token.cancel();
+    }
+
+    /// Returns [Error::Cancelled] if the application is shutting down, see
+    /// [FederationConfig::shutdown].
+    pub(crate) fn verify_not_cancelled(&self) -> Result<(), Error> {
+        if self.cancellation_token.is_cancelled() {
+            return Err(Error::Cancelled);
         }
-        domain == self.domain
+        Ok(())
     }
 
-    /// Returns the local domain
+    /// Returns the local domain. Internationalized domain names are converted to lowercase
+    /// punycode, eg `xn--kbenhavn-54a.example` for `københavn.example`.
     pub fn domain(&self) -> &str {
         &self.domain
     }
 }
 
 impl<T: Clone> FederationConfigBuilder<T> {
+    /// HTTP client used for all outgoing requests. Middleware can be used to add functionality
+    /// like log tracing or retry of failed requests.
+    ///
+    /// If it is not set, a client is built with the settings [Self::pool_max_idle_per_host],
+    /// [Self::pool_idle_timeout] and [Self::tcp_keepalive], and without cookie store. In both cases
+    /// the same client instance is used for fetches and by all workers of the activity queue, so
+    /// that connections and TLS sessions are reused.
+    pub fn client(&mut self, client: ClientWithMiddleware) -> &mut Self {
+        self.client.client = Some(client);
+        self
+    }
+
+    /// Maximum number of idle connections per host which the default client keeps open. Defaults
+    /// to 16.
+    pub fn pool_max_idle_per_host(&mut self, max: usize) -> &mut Self {
+        self.client.settings.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// Time after which idle connections of the default client are closed. Defaults to 60
+    /// seconds.
+    pub fn pool_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.client.settings.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Interval of TCP keepalive probes on connections of the default client. Defaults to 60
+    /// seconds.
+    pub fn tcp_keepalive(&mut self, interval: Duration) -> &mut Self {
+        self.client.settings.tcp_keepalive = interval;
+        self
+    }
+
+    /// Sign fetches of remote objects with the key of `actor`, usually an instance actor.
+    ///
+    /// Fetches are sent without signature first. If the remote server rejects one with `401
+    /// Unauthorized` or `403 Forbidden`, as servers which require "authorized fetch" do, it is
+    /// retried once with a signature. The domain is then remembered for a day, so that further
+    /// fetches from it are signed right away.
+    ///
+    /// [FederationConfigBuilder::build] fails if the actor has no private key.
+    pub fn signed_fetch_actor<A: Actor>(&mut self, actor: &A) -> &mut Self {
+        self.signed_fetch_actor = Some((actor.id(), actor.private_key_pem()));
+        self
+    }
+
     /// Constructs a new config instance with the values supplied to builder.
     ///
     /// Values which are not explicitly specified use the defaults. Also initializes the
     /// queue for outgoing activities, which is stored internally in the config struct.
     pub fn build(&mut self) -> Result<FederationConfig<T>, FederationConfigBuilderError> {
         let mut config = self.partial_build()?;
+        config.domain = normalize_domain(&config.domain).ok_or_else(|| {
+            FederationConfigBuilderError::ValidationError(format!(
+                "Invalid domain {}",
+                config.domain
+            ))
+        })?;
+        if let Some((actor_id, private_key)) = &self.signed_fetch_actor {
+            let private_key = private_key.clone().ok_or_else(|| {
+                FederationConfigBuilderError::ValidationError(format!(
+                    "Signed fetch actor {actor_id} has no private key"
+                ))
+            })?;
+            config.signed_fetch_actor = Some(Arc::new((actor_id.clone(), private_key)));
+        }
         let queue = create_activity_queue(
             config.client.clone(),
             config.worker_count,
             config.request_timeout,
             config.debug,
+            config.events.clone(),
+            config.clock.clone(),
+            config.cancellation_token.clone(),
         );
         config.activity_queue = Some(Arc::new(queue));
         Ok(config)
     }
 }
 
+/// Value of [FederationConfigBuilder::client], which is either set explicitly or built with
+/// [build_federation_client]
+#[derive(Clone, Default)]
+pub(crate) struct ClientOptions {
+    client: Option<ClientWithMiddleware>,
+    settings: ClientSettings,
+}
+
+impl ClientOptions {
+    fn build(&self) -> Result<ClientWithMiddleware, FederationConfigBuilderError> {
+        match &self.client {
+            Some(client) => Ok(client.clone()),
+            None => build_federation_client(&self.settings)
+                .map_err(|e| FederationConfigBuilderError::ValidationError(e.to_string())),
+        }
+    }
+}
+
+/// Settings of the default HTTP client
+#[derive(Clone, Debug)]
+pub(crate) struct ClientSettings {
+    pub(crate) pool_max_idle_per_host: usize,
+    pub(crate) pool_idle_timeout: Duration,
+    pub(crate) tcp_keepalive: Duration,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            pool_max_idle_per_host: 16,
+            pool_idle_timeout: Duration::from_secs(60),
+            tcp_keepalive: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Builds the default HTTP client for fetches and activity delivery
+pub(crate) fn build_federation_client(
+    settings: &ClientSettings,
+) -> Result<ClientWithMiddleware, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+        .pool_idle_timeout(settings.pool_idle_timeout)
+        .tcp_keepalive(settings.tcp_keepalive)
+        .build()?;
+    Ok(client.into())
+}
+
 impl<T: Clone> Deref for FederationConfig<T> {
     type Target = T;
 
@@ -254,11 +443,14 @@ clone_trait_object!(UrlVerifier);
 ///
 /// Additionally it contains a counter for outgoing HTTP requests. This is necessary to
 /// prevent denial of service attacks, where an attacker triggers fetching of recursive objects.
+/// The fetched urls are also stored, so that [Error::RequestLimit] can show which chain of
+/// objects reached the limit.
 ///
 /// <https://www.w3.org/TR/activitypub/#security-recursive-objects>
 pub struct Data<T: Clone> {
     pub(crate) config: FederationConfig<T>,
     pub(crate) request_counter: AtomicU32,
+    pub(crate) fetch_chain: Mutex<FetchChain>,
 }
 
 impl<T: Clone> Data<T> {
@@ -277,6 +469,7 @@ impl<T: Clone> Data<T> {
         Data {
             config: self.config.clone(),
             request_counter: Default::default(),
+            fetch_chain: Default::default(),
         }
     }
     /// Total number of outgoing HTTP requests made with this data.
@@ -303,3 +496,83 @@ impl<T: Clone> FederationMiddleware<T> {
         FederationMiddleware(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{DbUser, DB_USER};
+
+    fn config(domain: &str) -> FederationConfig<()> {
+        FederationConfig::builder()
+            .domain(domain)
+            .app_data(())
+            .build()
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_is_local_url_idn() {
+        let punycode = Url::parse("https://xn--kbenhavn-54a.example/u/soren").unwrap();
+        let unicode = Url::parse("https://københavn.example/u/soren").unwrap();
+        for domain in [
+            "københavn.example",
+            "KØBENHAVN.example",
+            "xn--kbenhavn-54a.example",
+        ] {
+            let config = config(domain);
+            assert_eq!(config.domain(), "xn--kbenhavn-54a.example");
+            assert!(config.is_local_url(&punycode), "{domain}");
+            assert!(config.is_local_url(&unicode), "{domain}");
+            assert_eq!(config.verify_url_valid(&unicode).await, Ok(()));
+        }
+        let config = config("kobenhavn.example");
+        assert!(!config.is_local_url(&punycode));
+        assert!(!config.is_local_url(&unicode));
+    }
+
+    #[actix_rt::test]
+    async fn test_is_local_url_port() {
+        let config = config("Localhost:8001");
+        assert_eq!(config.domain(), "localhost:8001");
+        assert!(config.is_local_url(&Url::parse("http://localhost:8001/u/alice").unwrap()));
+        assert!(!config.is_local_url(&Url::parse("http://localhost:8002/u/alice").unwrap()));
+        assert!(!config.is_local_url(&Url::parse("http://localhost/u/alice").unwrap()));
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_domain() {
+        let result = FederationConfig::builder()
+            .domain("exa mple.com")
+            .app_data(())
+            .build();
+        assert!(matches!(
+            result,
+            Err(FederationConfigBuilderError::ValidationError(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_signed_fetch_actor_without_private_key() {
+        let actor = DbUser {
+            private_key: None,
+            ..DB_USER.clone()
+        };
+        let result = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .signed_fetch_actor(&actor)
+            .build();
+        assert!(matches!(
+            result,
+            Err(FederationConfigBuilderError::ValidationError(_))
+        ));
+
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(())
+            .signed_fetch_actor(&*DB_USER)
+            .build()
+            .unwrap();
+        assert_eq!(config.signed_fetch_actor.unwrap().0, DB_USER.federation_id);
+    }
+}
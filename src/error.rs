@@ -1,37 +1,233 @@
 //! Error messages returned by this library
 
+use crate::protocol::migration::AccountMoveError;
 use displaydoc::Display;
+use http::StatusCode;
+use http_signature_normalization_reqwest::prelude::SignError;
+use openssl::error::ErrorStack;
+use std::{fmt::Display, time::Duration};
+use url::Url;
+
+/// How an error should be handled by retry logic, see [Error::class]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Retrying won't help, eg because data is invalid or the request was rejected
+    Permanent,
+    /// Temporary failure such as a network error or server error, which can be retried later
+    Transient,
+    /// The remote server limits the request rate. Retry after the given duration if it is known,
+    /// or after a backoff otherwise. The activity queue schedules retries of such deliveries
+    /// accordingly.
+    RateLimited {
+        /// Value of the `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+}
+
+/// Details of [Error::RequestLimit], including the urls which were fetched.
+///
+/// To keep the error small for long chains, only the first and last urls are included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestLimitExceeded {
+    /// Configured maximum number of requests, see
+    /// [FederationConfigBuilder::http_fetch_limit](crate::config::FederationConfigBuilder::http_fetch_limit)
+    pub limit: u32,
+    /// First urls which were fetched, in order
+    pub first: Vec<Url>,
+    /// Number of urls which were fetched between `first` and `last`, which are not included
+    pub omitted: usize,
+    /// Last urls which were fetched, ending with the url which exceeded the limit
+    pub last: Vec<Url>,
+}
+
+impl Display for RequestLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Request limit of {} was reached during fetch of ",
+            self.limit
+        )?;
+        let first = self.first.iter().map(Url::as_str);
+        let last = self.last.iter().map(Url::as_str);
+        let omitted = format!("({} more)", self.omitted);
+        let chain: Vec<&str> = if self.omitted > 0 {
+            first.chain([omitted.as_str()]).chain(last).collect()
+        } else {
+            first.chain(last).collect()
+        };
+        write!(f, "{}", chain.join(" -> "))
+    }
+}
 
 /// Error messages returned by this library
 #[derive(thiserror::Error, Debug, Display)]
 pub enum Error {
     /// Object was not found in local database
     NotFound,
-    /// Request limit was reached during fetch
-    RequestLimit,
+    /// {0}
+    RequestLimit(Box<RequestLimitExceeded>),
     /// Response body limit was reached during fetch
     ResponseBodyLimit,
-    /// Object to be fetched was deleted
-    ObjectDeleted,
+    /// Object {0} was deleted
+    ObjectDeleted(Url),
+    /// Pages of fetched collection link to each other in a cycle
+    CollectionCycle,
     /// {0}
-    UrlVerificationError(&'static str),
+    UrlVerificationError(String),
     /// Incoming activity has invalid digest for body
     ActivityBodyDigestInvalid,
     /// Incoming activity has invalid signature
     ActivitySignatureInvalid,
+    /// Incoming activity has unsupported content type {0}
+    ActivityContentTypeInvalid(String),
+    /// Account move is invalid: {0}
+    AccountMoveInvalid(Box<AccountMoveError>),
     /// Failed to resolve actor via webfinger
     WebfingerResolveFailed,
-    /// Other errors which are not explicitly handled
-    #[error(transparent)]
-    Other(#[from] anyhow::Error),
+    /// Actor which sends the activity has no private key
+    PrivateKeyMissing,
+    /// Operation was cancelled because the application is shutting down
+    Cancelled,
+    /// Failed to fetch {url}
+    FetchError {
+        /// Url which was fetched
+        url: Url,
+        /// HTTP status of the response, or `None` if no response was received
+        status: Option<StatusCode>,
+        /// Beginning of the response body for debugging, decoded as lossy UTF-8
+        body_snippet: Option<String>,
+        /// Underlying error, eg a connection failure or invalid json
+        #[source]
+        source: Option<Box<Error>>,
+    },
+    /// Failed to deliver activity to {inbox}
+    DeliveryError {
+        /// Inbox to which the activity was sent
+        inbox: Url,
+        /// HTTP status of the response, or `None` if no response was received
+        status: Option<StatusCode>,
+        /// Value of the `Retry-After` header, if any
+        retry_after: Option<Duration>,
+        /// Underlying error, eg a connection failure
+        #[source]
+        source: Option<Box<Error>>,
+    },
+    /// Delivery of activity to {inbox} panicked: {message}
+    DeliveryPanicked {
+        /// Inbox to which the activity was sent
+        inbox: Url,
+        /// Panic message, if it is a string
+        message: String,
+    },
+    /// Failed to send HTTP request: {0}
+    ReqwestMiddleware(#[from] reqwest_middleware::Error),
+    /// Failed to read HTTP response: {0}
+    Reqwest(#[from] reqwest::Error),
+    /// Failed to serialize or deserialize json: {0}
+    Json(#[from] serde_json::Error),
+    /// Failed to parse url: {0}
+    UrlParse(#[from] url::ParseError),
+    /// Failed to sign HTTP request: {0}
+    SignRequest(#[from] SignError),
+    /// Failed to use signing key: {0}
+    Key(#[from] ErrorStack),
+    /// {0}
+    Other(String),
 }
 
 impl Error {
-    pub(crate) fn other<T>(error: T) -> Self
-    where
-        T: Into<anyhow::Error>,
-    {
-        Error::Other(error.into())
+    pub(crate) fn other<T: Display>(error: T) -> Self {
+        Error::Other(error.to_string())
+    }
+
+    /// Classifies the error for retry logic. This is used by the activity queue to decide if a
+    /// delivery is retried, and can also be used by applications for their own retries.
+    ///
+    /// - [Error::FetchError] and [Error::DeliveryError] are classified by HTTP status:
+    ///   `429 Too Many Requests` is [ErrorClass::RateLimited], `408 Request Timeout` and `5xx`
+    ///   are [ErrorClass::Transient], and other statuses are [ErrorClass::Permanent]. If no
+    ///   response was received, the underlying error is classified instead. `retry_after` is
+    ///   only known for deliveries.
+    /// - Network errors ([Error::ReqwestMiddleware] and [Error::Reqwest]) and
+    ///   [Error::DeliveryPanicked] are [ErrorClass::Transient].
+    /// - All other errors are [ErrorClass::Permanent]. They are caused by invalid or deleted
+    ///   data, by limits, or by the local configuration, so retrying gives the same result.
+    ///   [Error::Cancelled] is also permanent, as nothing should be retried during shutdown.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Error::FetchError { status, source, .. } => http_class(*status, None, source),
+            Error::DeliveryError {
+                status,
+                retry_after,
+                source,
+                ..
+            } => http_class(*status, *retry_after, source),
+            Error::DeliveryPanicked { .. } | Error::ReqwestMiddleware(_) | Error::Reqwest(_) => {
+                ErrorClass::Transient
+            }
+            Error::NotFound
+            | Error::RequestLimit(_)
+            | Error::ResponseBodyLimit
+            | Error::ObjectDeleted(_)
+            | Error::CollectionCycle
+            | Error::UrlVerificationError(_)
+            | Error::ActivityBodyDigestInvalid
+            | Error::ActivitySignatureInvalid
+            | Error::ActivityContentTypeInvalid(_)
+            | Error::AccountMoveInvalid(_)
+            | Error::WebfingerResolveFailed
+            | Error::PrivateKeyMissing
+            | Error::Cancelled
+            | Error::Json(_)
+            | Error::UrlParse(_)
+            | Error::SignRequest(_)
+            | Error::Key(_)
+            | Error::Other(_) => ErrorClass::Permanent,
+        }
+    }
+
+    /// Returns true if the operation which caused the error may succeed when it is retried, ie
+    /// if [Error::class] is not [ErrorClass::Permanent].
+    pub fn is_retryable(&self) -> bool {
+        self.class() != ErrorClass::Permanent
+    }
+
+    /// HTTP status of a failed fetch, see [Error::FetchError]
+    pub fn fetch_status(&self) -> Option<StatusCode> {
+        match self {
+            Error::FetchError { status, .. } => *status,
+            _ => None,
+        }
+    }
+
+    /// Returns true if a fetch was rejected with `401 Unauthorized` or `403 Forbidden`.
+    ///
+    /// This usually means that the remote server only allows signed fetch requests, also known
+    /// as "authorized fetch" or "secure mode".
+    pub fn is_fetch_unauthorized(&self) -> bool {
+        matches!(
+            self.fetch_status(),
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        )
+    }
+}
+
+fn http_class(
+    status: Option<StatusCode>,
+    retry_after: Option<Duration>,
+    source: &Option<Box<Error>>,
+) -> ErrorClass {
+    let Some(status) = status else {
+        return source
+            .as_ref()
+            .map_or(ErrorClass::Transient, |source| source.class());
+    };
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        ErrorClass::RateLimited { retry_after }
+    } else if status == StatusCode::REQUEST_TIMEOUT || status.is_server_error() {
+        ErrorClass::Transient
+    } else {
+        ErrorClass::Permanent
     }
 }
 
@@ -40,3 +236,146 @@ impl PartialEq for Error {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url() -> Url {
+        Url::parse("https://example.com/inbox").unwrap()
+    }
+
+    /// Fetch and delivery errors with the given status and underlying error
+    fn http(status: Option<u16>, source: impl Fn() -> Option<Error>) -> [Error; 2] {
+        let status = status.map(|s| StatusCode::from_u16(s).unwrap());
+        [
+            Error::FetchError {
+                url: url(),
+                status,
+                body_snippet: None,
+                source: source().map(Box::new),
+            },
+            Error::DeliveryError {
+                inbox: url(),
+                status,
+                retry_after: None,
+                source: source().map(Box::new),
+            },
+        ]
+    }
+
+    /// Expected class of each variant. There is no wildcard arm, so that adding a new variant
+    /// requires a decision about its classification here.
+    fn expected_class(error: &Error) -> ErrorClass {
+        match error {
+            Error::FetchError { status, .. } | Error::DeliveryError { status, .. } => {
+                match status.map(|s| s.as_u16()) {
+                    Some(429) => ErrorClass::RateLimited { retry_after: None },
+                    Some(408 | 500..=599) | None => ErrorClass::Transient,
+                    Some(_) => ErrorClass::Permanent,
+                }
+            }
+            Error::DeliveryPanicked { .. } | Error::ReqwestMiddleware(_) | Error::Reqwest(_) => {
+                ErrorClass::Transient
+            }
+            Error::NotFound
+            | Error::RequestLimit(_)
+            | Error::ResponseBodyLimit
+            | Error::ObjectDeleted(_)
+            | Error::CollectionCycle
+            | Error::UrlVerificationError(_)
+            | Error::ActivityBodyDigestInvalid
+            | Error::ActivitySignatureInvalid
+            | Error::ActivityContentTypeInvalid(_)
+            | Error::AccountMoveInvalid(_)
+            | Error::WebfingerResolveFailed
+            | Error::PrivateKeyMissing
+            | Error::Cancelled
+            | Error::Json(_)
+            | Error::UrlParse(_)
+            | Error::SignRequest(_)
+            | Error::Key(_)
+            | Error::Other(_) => ErrorClass::Permanent,
+        }
+    }
+
+    #[test]
+    fn test_error_class() {
+        let reqwest = || reqwest::Client::new().get("invalid").build().unwrap_err();
+        let mut errors = vec![
+            Error::NotFound,
+            Error::RequestLimit(Box::new(RequestLimitExceeded {
+                limit: 20,
+                first: vec![url()],
+                omitted: 0,
+                last: vec![],
+            })),
+            Error::ResponseBodyLimit,
+            Error::ObjectDeleted(url()),
+            Error::CollectionCycle,
+            Error::UrlVerificationError("invalid".to_string()),
+            Error::ActivityBodyDigestInvalid,
+            Error::ActivitySignatureInvalid,
+            Error::ActivityContentTypeInvalid("text/html".to_string()),
+            Error::AccountMoveInvalid(Box::new(AccountMoveError::SameAccount(url()))),
+            Error::WebfingerResolveFailed,
+            Error::PrivateKeyMissing,
+            Error::Cancelled,
+            Error::DeliveryPanicked {
+                inbox: url(),
+                message: "panic".to_string(),
+            },
+            Error::ReqwestMiddleware(reqwest().into()),
+            Error::Reqwest(reqwest()),
+            Error::Json(serde_json::from_str::<u8>("invalid").unwrap_err()),
+            Error::UrlParse(Url::parse("invalid").unwrap_err()),
+            Error::SignRequest(SignError::Host(String::new())),
+            Error::Key(ErrorStack::get()),
+            Error::Other("other".to_string()),
+        ];
+        for status in [Some(429), Some(408), Some(500), Some(503), Some(404), None] {
+            errors.extend(http(status, || None));
+        }
+        for error in &errors {
+            assert_eq!(error.class(), expected_class(error), "{error:?}");
+            assert_eq!(
+                error.is_retryable(),
+                expected_class(error) != ErrorClass::Permanent
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_class_source() {
+        let reqwest = || reqwest::Client::new().get("invalid").build().unwrap_err();
+        let json = || serde_json::from_str::<u8>("invalid").unwrap_err();
+        // without a response, the underlying error decides
+        for error in http(None, || Some(Error::Reqwest(reqwest()))) {
+            assert_eq!(error.class(), ErrorClass::Transient);
+        }
+        for error in http(None, || Some(Error::Json(json()))) {
+            assert_eq!(error.class(), ErrorClass::Permanent);
+        }
+        // with a response, the status decides
+        for error in http(Some(502), || Some(Error::Json(json()))) {
+            assert_eq!(error.class(), ErrorClass::Transient);
+        }
+    }
+
+    #[test]
+    fn test_error_class_retry_after() {
+        let error = Error::DeliveryError {
+            inbox: url(),
+            status: Some(StatusCode::TOO_MANY_REQUESTS),
+            retry_after: Some(Duration::from_secs(30)),
+            source: None,
+        };
+        assert_eq!(
+            error.class(),
+            ErrorClass::RateLimited {
+                retry_after: Some(Duration::from_secs(30))
+            }
+        );
+        assert!(error.is_retryable());
+    }
+}
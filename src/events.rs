@@ -0,0 +1,299 @@
+//! Notifications about federation events, eg to show the health of remote instances
+//!
+//! Implement [FederationEvents] and pass it to
+//! [FederationConfigBuilder::events](crate::config::FederationConfigBuilder::events). The
+//! methods are called with lightweight metadata, and never with the full request or response
+//! body.
+//!
+//! ```
+//! # use activitypub_federation::config::FederationConfig;
+//! # use activitypub_federation::events::{DeliveryOutcome, DeliveryMeta, FederationEvents};
+//! # use async_trait::async_trait;
+//! # let _ = actix_rt::System::new();
+//! #[derive(Clone)]
+//! struct FailedDeliveryLogger;
+//!
+//! #[async_trait]
+//! impl FederationEvents for FailedDeliveryLogger {
+//!     async fn delivery_attempted(&self, delivery: &DeliveryMeta, outcome: &DeliveryOutcome) {
+//!         if let DeliveryOutcome::Failed { status, .. } = outcome {
+//!             println!("Failed to deliver to {}: {:?}", delivery.inbox, status);
+//!         }
+//!     }
+//! }
+//!
+//! let config = FederationConfig::builder()
+//!     .domain("example.com")
+//!     .app_data(())
+//!     .events(Box::new(FailedDeliveryLogger))
+//!     .build()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{
+    error::{Error, ErrorClass},
+    protocol::relay::RelayState,
+};
+use async_trait::async_trait;
+use dyn_clone::{clone_trait_object, DynClone};
+use futures_util::FutureExt;
+use http::StatusCode;
+use std::{future::Future, panic::AssertUnwindSafe, time::Duration};
+use tracing::warn;
+use url::Url;
+
+/// Maximum time which a single [FederationEvents] method may take. If it takes longer, it is
+/// cancelled.
+pub const EVENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Callbacks for federation events. All methods do nothing by default.
+///
+/// The methods are awaited before processing continues, so they should return quickly, eg by
+/// sending the event to a channel. Each call is cancelled after [EVENT_TIMEOUT], and panics are
+/// caught and logged, so that they don't affect federation.
+#[async_trait]
+pub trait FederationEvents: DynClone + Send + Sync {
+    /// An incoming activity was verified and handled successfully
+    async fn activity_received(&self, _activity: &ActivityMeta) {}
+
+    /// An incoming activity was rejected
+    async fn activity_rejected(&self, _activity: &ActivityMeta, _reason: RejectionReason) {}
+
+    /// A remote object was fetched over http
+    async fn object_fetched(&self, _url: &Url, _outcome: &FetchOutcome) {}
+
+    /// An attempt was made to deliver an activity to a remote inbox
+    async fn delivery_attempted(&self, _delivery: &DeliveryMeta, _outcome: &DeliveryOutcome) {}
+
+    /// The subscription to a relay changed, see [crate::protocol::relay]. `relay` is the id of
+    /// the relay actor.
+    async fn relay_state_changed(&self, _relay: &Url, _state: RelayState) {}
+}
+
+clone_trait_object!(FederationEvents);
+
+/// Default implementation which ignores all events
+#[derive(Clone)]
+pub(crate) struct NoFederationEvents;
+
+impl FederationEvents for NoFederationEvents {}
+
+/// Metadata of an incoming activity. Fields are `None` if they are missing or invalid.
+///
+/// The values are read from the request body before it is verified, so for activities which
+/// are rejected they may be forged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActivityMeta {
+    /// Id of the activity
+    pub id: Option<Url>,
+    /// Value of the `type` field, eg `Follow`
+    pub kind: Option<String>,
+    /// Actor who sent the activity
+    pub actor: Option<Url>,
+}
+
+/// Step of receiving an activity which failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// The `Content-Type` header isn't an Activitypub or JSON media type, see
+    /// [media_type](crate::media_type)
+    ContentType,
+    /// The body doesn't match the `Digest` header
+    Digest,
+    /// The body couldn't be parsed as activity
+    Parse,
+    /// Activity id or actor are invalid, eg because the domain is blocked
+    UrlVerification,
+    /// The actor couldn't be fetched
+    ActorFetch,
+    /// The HTTP signature is missing or invalid
+    Signature,
+    /// [ActivityHandler::verify](crate::traits::ActivityHandler::verify) returned an error
+    Verify,
+    /// [ActivityHandler::receive](crate::traits::ActivityHandler::receive) returned an error
+    Receive,
+}
+
+impl RejectionReason {
+    /// Short name in snake case, which is also used as metrics label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::ContentType => "content_type",
+            RejectionReason::Digest => "digest",
+            RejectionReason::Parse => "parse",
+            RejectionReason::UrlVerification => "url_verification",
+            RejectionReason::ActorFetch => "actor_fetch",
+            RejectionReason::Signature => "signature",
+            RejectionReason::Verify => "verify",
+            RejectionReason::Receive => "receive",
+        }
+    }
+
+    /// HTTP status which corresponds to the rejection. Note that the actual response depends on
+    /// how the application converts the error returned by `receive_activity`.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RejectionReason::Digest | RejectionReason::Parse | RejectionReason::Verify => {
+                StatusCode::BAD_REQUEST
+            }
+            RejectionReason::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RejectionReason::UrlVerification => StatusCode::FORBIDDEN,
+            RejectionReason::ActorFetch | RejectionReason::Signature => StatusCode::UNAUTHORIZED,
+            RejectionReason::Receive => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Result of fetching a remote object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchOutcome {
+    /// The object was fetched
+    Success,
+    /// The object was deleted, the server responded with `410 Gone`. When
+    /// [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) refetches an object,
+    /// `404 Not Found` is also reported as deletion, otherwise it is [FetchOutcome::Failed].
+    Deleted,
+    /// The fetch failed
+    Failed {
+        /// HTTP status, or `None` if no response was received
+        status: Option<StatusCode>,
+        /// Classification of the error
+        class: ErrorClass,
+    },
+}
+
+/// Metadata of an outgoing activity
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeliveryMeta {
+    /// Id of the activity
+    pub activity_id: Url,
+    /// Inbox to which the activity was sent
+    pub inbox: Url,
+    /// Number of the delivery attempt, starting at 1
+    pub attempt: u32,
+}
+
+/// Result of an attempt to deliver an activity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    /// The activity was accepted by the inbox
+    Success,
+    /// The delivery failed
+    Failed {
+        /// HTTP status, or `None` if no response was received
+        status: Option<StatusCode>,
+        /// Classification of the error, which decides if the delivery is retried
+        class: ErrorClass,
+    },
+}
+
+impl DeliveryOutcome {
+    pub(crate) fn from_result(res: &Result<(), Error>) -> Self {
+        match res {
+            Ok(()) => DeliveryOutcome::Success,
+            Err(e @ Error::DeliveryError { status, .. }) => DeliveryOutcome::Failed {
+                status: *status,
+                class: e.class(),
+            },
+            Err(e) => DeliveryOutcome::Failed {
+                status: None,
+                class: e.class(),
+            },
+        }
+    }
+}
+
+/// Awaits a [FederationEvents] method, with timeout and catching panics
+pub(crate) async fn notify<F: Future<Output = ()>>(event: &'static str, future: F) {
+    let future = AssertUnwindSafe(future).catch_unwind();
+    match tokio::time::timeout(EVENT_TIMEOUT, future).await {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => warn!("Federation event handler {} panicked", event),
+        Err(_) => warn!(
+            "Federation event handler {} timed out after {:?}",
+            event, EVENT_TIMEOUT
+        ),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Event which was passed to [RecordingEvents]
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub(crate) enum Event {
+        Received(ActivityMeta),
+        Rejected(ActivityMeta, RejectionReason),
+        Fetched(Url, FetchOutcome),
+        Delivered(DeliveryMeta, DeliveryOutcome),
+        Relay(Url, RelayState),
+    }
+
+    /// Stores all events in order
+    #[derive(Clone, Default)]
+    pub(crate) struct RecordingEvents(pub(crate) Arc<Mutex<Vec<Event>>>);
+
+    impl RecordingEvents {
+        pub(crate) fn events(&self) -> Vec<Event> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl FederationEvents for RecordingEvents {
+        async fn activity_received(&self, activity: &ActivityMeta) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(Event::Received(activity.clone()));
+        }
+
+        async fn activity_rejected(&self, activity: &ActivityMeta, reason: RejectionReason) {
+            let event = Event::Rejected(activity.clone(), reason);
+            self.0.lock().unwrap().push(event);
+        }
+
+        async fn object_fetched(&self, url: &Url, outcome: &FetchOutcome) {
+            let event = Event::Fetched(url.clone(), *outcome);
+            self.0.lock().unwrap().push(event);
+        }
+
+        async fn delivery_attempted(&self, delivery: &DeliveryMeta, outcome: &DeliveryOutcome) {
+            let event = Event::Delivered(delivery.clone(), *outcome);
+            self.0.lock().unwrap().push(event);
+        }
+
+        async fn relay_state_changed(&self, relay: &Url, state: RelayState) {
+            let event = Event::Relay(relay.clone(), state);
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[derive(Clone)]
+    struct BrokenEvents;
+
+    #[async_trait]
+    impl FederationEvents for BrokenEvents {
+        async fn activity_received(&self, _: &ActivityMeta) {
+            panic!("broken event handler");
+        }
+
+        async fn activity_rejected(&self, _: &ActivityMeta, _: RejectionReason) {
+            tokio::time::sleep(EVENT_TIMEOUT * 10).await;
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_notify_isolates_handler() {
+        let events = BrokenEvents;
+        let meta = ActivityMeta::default();
+        notify("activity_received", events.activity_received(&meta)).await;
+
+        let start = std::time::Instant::now();
+        let reason = RejectionReason::Digest;
+        notify("activity_rejected", events.activity_rejected(&meta, reason)).await;
+        assert!(start.elapsed() < EVENT_TIMEOUT * 2);
+    }
+}
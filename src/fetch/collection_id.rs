@@ -1,9 +1,21 @@
-use crate::{config::Data, error::Error, fetch::fetch_object_http, traits::Collection};
-use serde::{Deserialize, Serialize};
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::{fetch_object_http, object_id::ObjectId},
+    protocol::{
+        collection::{CollectionItem, OrderedCollection, OrderedCollectionPage, PageRef},
+        verification::verify_domains_match,
+    },
+    traits::{Collection, Object},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use std::{
+    collections::HashSet,
     fmt::{Debug, Display, Formatter},
     marker::PhantomData,
 };
+use tracing::warn;
 use url::Url;
 
 /// Typed wrapper for Activitypub Collection ID which helps with dereferencing.
@@ -28,10 +40,18 @@ where
         Ok(Self(Box::new(url.try_into()?), PhantomData::<Kind>))
     }
 
+    /// Returns a reference to the wrapped URL value
+    pub fn inner(&self) -> &Url {
+        &self.0
+    }
+
     /// Fetches collection over HTTP
     ///
-    /// Unlike [ObjectId::fetch](crate::fetch::object_id::ObjectId::fetch) this method doesn't do
-    /// any caching.
+    /// Only the collection document itself is fetched, pages that it links to are ignored. Use
+    /// [CollectionId::dereference_all_pages] to read the items of a paged [OrderedCollection].
+    ///
+    /// Unlike [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) this method
+    /// doesn't do any caching.
     pub async fn dereference(
         &self,
         owner: &<Kind as Collection>::Owner,
@@ -46,6 +66,175 @@ where
     }
 }
 
+impl<Kind, Item> CollectionId<Kind>
+where
+    Kind: Collection<Kind = OrderedCollection<Item>, Item = Item>,
+    Item: DeserializeOwned,
+{
+    /// Fetches collection over HTTP, including the items on all of its pages.
+    ///
+    /// After fetching the collection document, pages are fetched starting from `first` and
+    /// following the `next` links. This stops when there is no further page, or when
+    /// [collection_page_limit](crate::config::FederationConfigBuilder::collection_page_limit)
+    /// pages or [collection_item_limit](crate::config::FederationConfigBuilder::collection_item_limit)
+    /// items were read. Every page counts against the
+    /// [http_fetch_limit](crate::config::FederationConfigBuilder::http_fetch_limit). Pages need
+    /// to be on the same domain as the collection, and if a page links back to a previous one
+    /// [Error::CollectionCycle] is returned.
+    ///
+    /// Items of all pages are added to `ordered_items` of the collection which is then passed to
+    /// [Collection::verify] and [Collection::from_json]. Items which can't be parsed are skipped,
+    /// unless there are more than
+    /// [collection_item_failure_limit](crate::config::FederationConfigBuilder::collection_item_failure_limit)
+    /// of them. Collections without `first` link, such
+    /// as followers collections which only publish `totalItems`, are passed on without items.
+    ///
+    /// Unlike [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) this method
+    /// doesn't do any caching.
+    pub async fn dereference_all_pages(
+        &self,
+        owner: &<Kind as Collection>::Owner,
+        data: &Data<<Kind as Collection>::DataType>,
+    ) -> Result<Kind, <Kind as Collection>::Error>
+    where
+        <Kind as Collection>::Error: From<Error>,
+    {
+        let raw = fetch_collection_pages(&self.0, data).await?;
+
+        let mut ordered_items = Vec::with_capacity(raw.ordered_items.len());
+        let mut failures = 0;
+        for item in raw.ordered_items {
+            match serde_json::from_value(item) {
+                Ok(item) => ordered_items.push(item),
+                Err(e) => {
+                    failures += 1;
+                    if failures > data.config.collection_item_failure_limit {
+                        return Err(Error::from(e).into());
+                    }
+                    warn!("Skipping invalid item in collection {}: {e}", self.0);
+                }
+            }
+        }
+        let json = OrderedCollection {
+            kind: raw.kind,
+            id: raw.id,
+            total_items: raw.total_items,
+            first: raw.first.map(|first| PageRef::Url(first.id().clone())),
+            last: raw.last,
+            ordered_items,
+        };
+
+        Kind::verify(&json, &self.0, data).await?;
+        Kind::from_json(json, owner, data).await
+    }
+}
+
+/// Items of a remote collection which were fetched with [fetch_collection_items].
+#[derive(Debug)]
+pub struct CollectionItems<Kind: Object> {
+    /// Items which were converted successfully
+    pub items: Vec<Kind>,
+    /// Errors for items which failed to fetch or convert
+    pub errors: Vec<Kind::Error>,
+}
+
+/// Fetches a remote collection and converts its items to the database type `Kind`.
+///
+/// The collection pages are fetched in the same way and with the same limits as in
+/// [CollectionId::dereference_all_pages]. Items may be either urls which are then dereferenced with
+/// [ObjectId::dereference], or embedded objects which are checked with [Object::verify]
+/// against the collection domain and passed to [Object::from_json]. Every fetched page and item
+/// counts against the [http_fetch_limit](crate::config::FederationConfigBuilder::http_fetch_limit).
+///
+/// Items which fail to convert are skipped and their errors returned in
+/// [CollectionItems::errors]. If more than
+/// [collection_item_failure_limit](crate::config::FederationConfigBuilder::collection_item_failure_limit)
+/// items fail, the whole operation is aborted with the last error.
+pub async fn fetch_collection_items<Kind>(
+    collection_id: &Url,
+    data: &Data<<Kind as Object>::DataType>,
+) -> Result<CollectionItems<Kind>, <Kind as Object>::Error>
+where
+    Kind: Object + Send + 'static,
+    for<'de2> <Kind as Object>::Kind: Deserialize<'de2>,
+    <Kind as Object>::Error: From<Error>,
+{
+    let raw = fetch_collection_pages(collection_id, data).await?;
+
+    let mut items = CollectionItems {
+        items: Vec::with_capacity(raw.ordered_items.len()),
+        errors: vec![],
+    };
+    for item in raw.ordered_items {
+        match collection_item::<Kind>(item.into(), collection_id, data).await {
+            Ok(item) => items.items.push(item),
+            Err(e) => {
+                if items.errors.len() >= data.config.collection_item_failure_limit {
+                    return Err(e);
+                }
+                items.errors.push(e);
+            }
+        }
+    }
+    Ok(items)
+}
+
+async fn collection_item<Kind>(
+    item: CollectionItem,
+    collection_id: &Url,
+    data: &Data<<Kind as Object>::DataType>,
+) -> Result<Kind, <Kind as Object>::Error>
+where
+    Kind: Object + Send + 'static,
+    for<'de2> <Kind as Object>::Kind: Deserialize<'de2>,
+    <Kind as Object>::Error: From<Error>,
+{
+    match item {
+        CollectionItem::Url(id) => ObjectId::<Kind>::from(id).dereference(data).await,
+        CollectionItem::Object(item) => {
+            let json = serde_json::from_value(item).map_err(Error::from)?;
+            Kind::verify(&json, collection_id, data).await?;
+            Kind::from_json(json, data).await
+        }
+    }
+}
+
+/// Fetches the collection document and all of its pages, with items of all pages added to
+/// `ordered_items` as raw json. If the first page is embedded in the collection, it is used
+/// directly without fetching.
+async fn fetch_collection_pages<T: Clone>(
+    collection_id: &Url,
+    data: &Data<T>,
+) -> Result<OrderedCollection<Value>, Error> {
+    let config = &data.config;
+    let mut json: OrderedCollection<Value> = fetch_object_http(collection_id, data).await?;
+
+    let mut visited = HashSet::from([collection_id.clone()]);
+    let mut next = json.first.clone();
+    let mut page_count = 0;
+    while let Some(page_ref) = next {
+        if page_count >= config.collection_page_limit
+            || json.ordered_items.len() >= config.collection_item_limit
+        {
+            break;
+        }
+        let page_id = page_ref.id();
+        if !visited.insert(page_id.clone()) {
+            return Err(Error::CollectionCycle);
+        }
+        verify_domains_match(page_id, collection_id)?;
+        let page: OrderedCollectionPage<Value> = match page_ref {
+            PageRef::Url(page_id) => fetch_object_http(&page_id, data).await?,
+            PageRef::Page(page) => *page,
+        };
+        json.ordered_items.extend(page.ordered_items);
+        next = page.next.map(PageRef::Url);
+        page_count += 1;
+    }
+    json.ordered_items.truncate(config.collection_item_limit);
+    Ok(json)
+}
+
 /// Need to implement clone manually, to avoid requiring Kind to be Clone
 impl<Kind> Clone for CollectionId<Kind>
 where
@@ -95,3 +284,387 @@ where
         CollectionId(Box::new(url), PhantomData::<Kind>)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        fetch::{test_server::serve, transport::HttpTransport},
+        test_utils::DbConnection,
+    };
+    use reqwest_middleware::ClientWithMiddleware;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tokio_util::sync::CancellationToken;
+    use url::Position;
+
+    struct DbUrls {
+        items: Vec<Url>,
+        total_items: Option<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Collection for DbUrls {
+        type Owner = ();
+        type DataType = DbConnection;
+        type Kind = OrderedCollection<Url>;
+        type Item = Url;
+        type Error = Error;
+
+        async fn read_local(_: &(), _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn verify(
+            json: &Self::Kind,
+            expected_domain: &Url,
+            _: &Data<Self::DataType>,
+        ) -> Result<(), Error> {
+            verify_domains_match(&json.id, expected_domain)
+        }
+
+        async fn from_json(
+            json: Self::Kind,
+            _: &(),
+            _: &Data<Self::DataType>,
+        ) -> Result<Self, Error> {
+            Ok(DbUrls {
+                items: json.ordered_items,
+                total_items: json.total_items,
+            })
+        }
+    }
+
+    fn collection(first: &str) -> (String, (&'static str, String)) {
+        let json = format!(
+            r#"{{"type":"OrderedCollection","id":"{{base}}/outbox","totalItems":5,
+            "first":"{{base}}/outbox?page={first}"}}"#
+        );
+        ("/outbox".to_string(), ("200 OK", json))
+    }
+
+    fn page(page: u32, items: &[u32], next: Option<u32>) -> (String, (&'static str, String)) {
+        let items: Vec<_> = items
+            .iter()
+            .map(|i| format!(r#""{{base}}/item/{i}""#))
+            .collect();
+        raw_page(page, &items.join(","), next)
+    }
+
+    /// Page with the given json for `orderedItems`
+    fn raw_page(page: u32, items: &str, next: Option<u32>) -> (String, (&'static str, String)) {
+        let next = next
+            .map(|n| format!(r#","next":"{{base}}/outbox?page={n}""#))
+            .unwrap_or_default();
+        let json = format!(
+            r#"{{"type":"OrderedCollectionPage","id":"{{base}}/outbox?page={page}",
+            "partOf":"{{base}}/outbox","orderedItems":[{items}]{next}}}"#
+        );
+        (format!("/outbox?page={page}"), ("200 OK", json))
+    }
+
+    fn data(port: u16, page_limit: usize) -> (CollectionId<DbUrls>, Data<DbConnection>) {
+        data_with_failure_limit(port, page_limit, 10)
+    }
+
+    fn data_with_failure_limit(
+        port: u16,
+        page_limit: usize,
+        failure_limit: usize,
+    ) -> (CollectionId<DbUrls>, Data<DbConnection>) {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .collection_page_limit(page_limit)
+            .collection_item_failure_limit(failure_limit)
+            .build()
+            .unwrap();
+        let id = CollectionId::parse(format!("http://localhost:{port}/outbox").as_str()).unwrap();
+        (id, config.to_request_data())
+    }
+
+    #[actix_rt::test]
+    async fn test_read_local_page_default() {
+        let (_, data) = data(0, 10);
+        let res = DbUrls::read_local_page(&(), 1, &data).await;
+        assert_eq!(res.err(), Some(Error::NotFound));
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_multiple_pages() {
+        let port = serve(HashMap::from([
+            collection("1"),
+            page(1, &[1, 2], Some(2)),
+            page(2, &[3, 4], Some(3)),
+            page(3, &[5], None),
+        ]));
+        let (id, data) = data(port, 10);
+
+        let items = id.dereference_all_pages(&(), &data).await.unwrap().items;
+        let paths: Vec<_> = items.iter().map(Url::path).collect();
+        assert_eq!(
+            paths,
+            vec!["/item/1", "/item/2", "/item/3", "/item/4", "/item/5"]
+        );
+        // collection and three pages
+        assert_eq!(data.request_count(), 4);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_single_document() {
+        let port = serve(HashMap::from([collection("1"), page(1, &[1, 2], None)]));
+        let (id, data) = data(port, 10);
+
+        let urls = id.dereference(&(), &data).await.unwrap();
+        assert!(urls.items.is_empty());
+        assert_eq!(urls.total_items, Some(5));
+        assert_eq!(data.request_count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_page_limit() {
+        let port = serve(HashMap::from([
+            collection("1"),
+            page(1, &[1, 2], Some(2)),
+            page(2, &[3, 4], Some(3)),
+            page(3, &[5], None),
+        ]));
+        let (id, data) = data(port, 2);
+
+        let items = id.dereference_all_pages(&(), &data).await.unwrap().items;
+        assert_eq!(items.len(), 4);
+        assert_eq!(data.request_count(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_cycle() {
+        let port = serve(HashMap::from([collection("1"), page(1, &[1, 2], Some(1))]));
+        let (id, data) = data(port, 10);
+
+        let res = id.dereference_all_pages(&(), &data).await;
+        assert_eq!(res.err(), Some(Error::CollectionCycle));
+        assert_eq!(data.request_count(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_count_only() {
+        let json = r#"{"type":"OrderedCollection","id":"{base}/outbox","totalItems":7}"#;
+        let port = serve(HashMap::from([(
+            "/outbox".to_string(),
+            ("200 OK", json.to_string()),
+        )]));
+        let (id, data) = data(port, 10);
+
+        let urls = id.dereference_all_pages(&(), &data).await.unwrap();
+        assert!(urls.items.is_empty());
+        assert_eq!(urls.total_items, Some(7));
+        assert_eq!(data.request_count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_unordered_collection() {
+        // Lemmy serves followers as unordered collection with `items`
+        let json = r#"{"type":"Collection","id":"{base}/outbox","totalItems":2,
+            "items":["{base}/item/1","{base}/item/2"]}"#;
+        let port = serve(HashMap::from([(
+            "/outbox".to_string(),
+            ("200 OK", json.to_string()),
+        )]));
+        let (id, data) = data(port, 10);
+
+        let urls = id.dereference(&(), &data).await.unwrap();
+        assert_eq!(urls.items.len(), 2);
+        assert_eq!(urls.total_items, Some(2));
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_embedded_first_page() {
+        let json = r#"{"type":"OrderedCollection","id":"{base}/outbox","totalItems":3,
+            "first":{"type":"OrderedCollectionPage","id":"{base}/outbox?page=1",
+                "partOf":"{base}/outbox","orderedItems":["{base}/item/1","{base}/item/2"],
+                "next":"{base}/outbox?page=2"}}"#;
+        let port = serve(HashMap::from([
+            ("/outbox".to_string(), ("200 OK", json.to_string())),
+            page(2, &[3], None),
+        ]));
+        let (id, data) = data(port, 10);
+
+        let items = id.dereference_all_pages(&(), &data).await.unwrap().items;
+        let paths: Vec<_> = items.iter().map(Url::path).collect();
+        assert_eq!(paths, vec!["/item/1", "/item/2", "/item/3"]);
+        // embedded page is not fetched
+        assert_eq!(data.request_count(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_page_fetch_error() {
+        let port = serve(HashMap::from([
+            collection("1"),
+            (
+                "/outbox?page=1".to_string(),
+                ("403 Forbidden", "Authorized fetch required".to_string()),
+            ),
+        ]));
+        let (id, data) = data(port, 10);
+
+        let err = id.dereference_all_pages(&(), &data).await.err().unwrap();
+        let Error::FetchError {
+            url, body_snippet, ..
+        } = &err
+        else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        assert_eq!(url.query(), Some("page=1"));
+        assert_eq!(body_snippet.as_deref(), Some("Authorized fetch required"));
+        assert!(err.is_fetch_unauthorized());
+    }
+
+    /// Sends requests with the client, and cancels the token after the response for `cancel_at`
+    #[derive(Clone)]
+    struct CancellingTransport {
+        client: ClientWithMiddleware,
+        token: CancellationToken,
+        cancel_at: &'static str,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpTransport for CancellingTransport {
+        async fn execute(
+            &self,
+            request: reqwest::Request,
+        ) -> Result<reqwest::Response, reqwest_middleware::Error> {
+            let url = request.url().clone();
+            self.requests
+                .lock()
+                .unwrap()
+                .push(url[Position::BeforePath..].to_string());
+            let response = self.client.execute(request).await;
+            if url[Position::BeforePath..] == *self.cancel_at {
+                self.token.cancel();
+            }
+            response
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_cancelled() {
+        let port = serve(HashMap::from([
+            collection("1"),
+            page(1, &[1, 2], Some(2)),
+            page(2, &[3, 4], Some(3)),
+            page(3, &[5], None),
+        ]));
+        let token = CancellationToken::new();
+        let transport = CancellingTransport {
+            client: reqwest::Client::new().into(),
+            token: token.clone(),
+            cancel_at: "/outbox?page=1",
+            requests: Default::default(),
+        };
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .transport(Box::new(transport.clone()))
+            .cancellation_token(token)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id: CollectionId<DbUrls> =
+            CollectionId::parse(format!("http://localhost:{port}/outbox").as_str()).unwrap();
+
+        let res = id.dereference_all_pages(&(), &data).await;
+        assert_eq!(res.err(), Some(Error::Cancelled));
+        // no further pages are fetched after cancellation
+        assert_eq!(
+            *transport.requests.lock().unwrap(),
+            vec!["/outbox", "/outbox?page=1"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_skips_malformed_item() {
+        let port = serve(HashMap::from([
+            collection("1"),
+            page(1, &[1, 2], Some(2)),
+            raw_page(2, r#""{base}/item/3", 5"#, None),
+        ]));
+        let (id, data) = data(port, 10);
+
+        let items = id.dereference_all_pages(&(), &data).await.unwrap().items;
+        assert_eq!(items.len(), 3);
+
+        let (id, data) = data_with_failure_limit(port, 10, 0);
+        assert!(id.dereference_all_pages(&(), &data).await.is_err());
+    }
+
+    #[derive(Deserialize)]
+    struct Note {
+        id: Url,
+        content: String,
+    }
+
+    #[derive(Debug)]
+    struct DbNote(String);
+
+    #[async_trait::async_trait]
+    impl Object for DbNote {
+        type DataType = DbConnection;
+        type Kind = Note;
+        type Error = Error;
+
+        async fn read_from_id(_: Url, _: &Data<Self::DataType>) -> Result<Option<Self>, Error> {
+            Ok(None)
+        }
+
+        async fn into_json(&self, _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn verify(
+            json: &Self::Kind,
+            expected_domain: &Url,
+            _: &Data<Self::DataType>,
+        ) -> Result<(), Error> {
+            verify_domains_match(&json.id, expected_domain)
+        }
+
+        async fn from_json(json: Self::Kind, _: &Data<Self::DataType>) -> Result<Self, Error> {
+            Ok(DbNote(json.content))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_collection_items_partial_success() {
+        let items = r#""{base}/note/1",
+            {"id":"{base}/note/2","content":"embedded"},
+            {"id":"{base}/note/3"}"#;
+        let port = serve(HashMap::from([
+            collection("1"),
+            raw_page(1, items, None),
+            (
+                "/note/1".to_string(),
+                (
+                    "200 OK",
+                    r#"{"id":"{base}/note/1","content":"fetched"}"#.to_string(),
+                ),
+            ),
+        ]));
+        let (id, data) = data(port, 10);
+
+        let res = fetch_collection_items::<DbNote>(id.inner(), &data)
+            .await
+            .unwrap();
+        let contents: Vec<_> = res.items.iter().map(|n| n.0.as_str()).collect();
+        assert_eq!(contents, vec!["fetched", "embedded"]);
+        // the note without content
+        assert_eq!(res.errors.len(), 1);
+        // collection, page and the note which is referenced by url
+        assert_eq!(data.request_count(), 3);
+    }
+}
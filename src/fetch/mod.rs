@@ -2,10 +2,30 @@
 //!
 #![doc = include_str!("../../docs/07_fetching_data.md")]
 
-use crate::{config::Data, error::Error, reqwest_shim::ResponseExt, FEDERATION_CONTENT_TYPE};
-use http::StatusCode;
+use crate::{
+    clock::naive_utc,
+    config::Data,
+    error::{Error, RequestLimitExceeded},
+    events::{notify, FetchOutcome},
+    http_signatures::sign_fetch_request,
+    media_type::accept_header,
+    metrics::{self, Timer},
+    protocol::{tombstone::Tombstone, verification::host_with_port},
+    reqwest_shim::ResponseExt,
+};
+use chrono::NaiveDateTime;
+use http::{
+    header::{ACCEPT, DATE, RETRY_AFTER},
+    HeaderMap,
+    StatusCode,
+};
+use httpdate::{fmt_http_date, parse_http_date};
 use serde::de::DeserializeOwned;
-use std::sync::atomic::Ordering;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 use tracing::info;
 use url::Url;
 
@@ -13,9 +33,26 @@ use url::Url;
 pub mod collection_id;
 /// Typed wrapper for Activitypub Object ID which helps with dereferencing and caching
 pub mod object_id;
+#[cfg(test)]
+pub(crate) mod test_server;
+/// Pluggable transport for HTTP requests of fetches
+pub mod transport;
 /// Resolves identifiers of the form `name@example.com`
 pub mod webfinger;
 
+/// Information about a remote object which was found to be deleted while fetching it.
+///
+/// Passed to [Object::delete_with_info](crate::traits::Object::delete_with_info).
+#[derive(Clone, Debug)]
+pub struct DeletionInfo {
+    /// HTTP status returned by the remote server, either `410 Gone` or `404 Not Found`
+    pub status: StatusCode,
+    /// Tombstone which was returned in the response body, if any
+    pub tombstone: Option<Tombstone>,
+    /// Time when the deletion was discovered
+    pub fetched_at: NaiveDateTime,
+}
+
 /// Fetch a remote object over HTTP and convert to `Kind`.
 ///
 /// [crate::fetch::object_id::ObjectId::dereference] wraps this function to add caching and
@@ -25,34 +62,651 @@ pub mod webfinger;
 /// Every time an object is fetched via HTTP, [RequestData.request_counter] is incremented by one.
 /// If the value exceeds [FederationSettings.http_fetch_limit], the request is aborted with
 /// [Error::RequestLimit]. This prevents denial of service attacks where an attack triggers
-/// infinite, recursive fetching of data.
+/// infinite, recursive fetching of data. The error contains the urls which were fetched, to
+/// distinguish attacks from legitimately deep chains like long comment threads.
+///
+/// If [FederationConfigBuilder::signed_fetch_actor](crate::config::FederationConfigBuilder::signed_fetch_actor)
+/// is set and the remote server rejects the fetch with `401 Unauthorized` or `403 Forbidden`, the
+/// fetch is retried once with an HTTP signature. The retry doesn't increment the request counter,
+/// so both requests together count as a single fetch.
+///
+/// If [FederationConfig::shutdown](crate::config::FederationConfig::shutdown) was called or the
+/// [cancellation_token](crate::config::FederationConfigBuilder::cancellation_token) is cancelled,
+/// no request is sent and [Error::Cancelled] is returned.
+///
+/// If the remote server responds with `410 Gone`, [Error::ObjectDeleted] is returned. Other
+/// failures, including `404 Not Found`, result in [Error::FetchError], which contains the HTTP
+/// status and the beginning of the response body if a response was received. Only
+/// [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) also treats a `404` as
+/// deletion of a previously fetched object.
 pub async fn fetch_object_http<T: Clone, Kind: DeserializeOwned>(
     url: &Url,
     data: &Data<T>,
 ) -> Result<Kind, Error> {
+    fetch_object_or_deletion_http(url, false, data)
+        .await?
+        .map_err(|_| Error::ObjectDeleted(url.clone()))
+}
+
+/// Same as [fetch_object_http], but returns details about the deletion for `410 Gone` instead of
+/// an error. If `not_found_deleted` is true, `404 Not Found` is also handled as deletion.
+pub(crate) async fn fetch_object_or_deletion_http<T: Clone, Kind: DeserializeOwned>(
+    url: &Url,
+    not_found_deleted: bool,
+    data: &Data<T>,
+) -> Result<Result<Kind, DeletionInfo>, Error> {
+    let timer = Timer::start(url);
+    let res = fetch_object_or_deletion_http_inner(url, not_found_deleted, data).await;
+    let (result, outcome) = match &res {
+        Ok(Ok(_)) => ("success", FetchOutcome::Success),
+        Ok(Err(_)) => ("deleted", FetchOutcome::Deleted),
+        Err(e) => (
+            "error",
+            FetchOutcome::Failed {
+                status: e.fetch_status(),
+                class: e.class(),
+            },
+        ),
+    };
+    metrics::fetch_finished(timer, result);
+    let events = &data.config.events;
+    notify("object_fetched", events.object_fetched(url, &outcome)).await;
+    res
+}
+
+async fn fetch_object_or_deletion_http_inner<T: Clone, Kind: DeserializeOwned>(
+    url: &Url,
+    not_found_deleted: bool,
+    data: &Data<T>,
+) -> Result<Result<Kind, DeletionInfo>, Error> {
     let config = &data.config;
     // dont fetch local objects this way
-    debug_assert!(url.domain() != Some(&config.domain));
+    debug_assert!(!config.is_local_url(url));
     config.verify_url_valid(url).await?;
     info!("Fetching remote object {}", url.to_string());
 
     let counter = data.request_counter.fetch_add(1, Ordering::SeqCst);
-    if counter > config.http_fetch_limit {
-        return Err(Error::RequestLimit);
+    {
+        let mut fetch_chain = data.fetch_chain.lock().expect("lock fetch chain");
+        fetch_chain.push(url);
+        if counter > config.http_fetch_limit {
+            let limit = fetch_chain.to_error(config.http_fetch_limit);
+            return Err(Error::RequestLimit(Box::new(limit)));
+        }
     }
 
-    let res = config
+    let Some(signed_fetch_actor) = config.signed_fetch_actor.as_deref() else {
+        return fetch_once(url, None, not_found_deleted, data).await;
+    };
+    let now = config.clock.now();
+    if config.signed_fetch_domains.requires_signature(url, now) {
+        return fetch_once(url, Some(signed_fetch_actor), not_found_deleted, data).await;
+    }
+    // the retry is part of the same logical fetch, so it doesn't count against the request limit
+    match fetch_once(url, None, not_found_deleted, data).await {
+        Err(e) if e.is_fetch_unauthorized() => {
+            info!("Fetch of {} was rejected, retrying with signature", url);
+            let res = fetch_once(url, Some(signed_fetch_actor), not_found_deleted, data).await;
+            if res.is_ok() {
+                config.signed_fetch_domains.insert(url, now);
+            }
+            res
+        }
+        res => res,
+    }
+}
+
+/// Sends a single fetch request, which is signed if `signed_fetch_actor` is given. Returns
+/// [Error::Cancelled] without sending the request during shutdown.
+async fn fetch_once<T: Clone, Kind: DeserializeOwned>(
+    url: &Url,
+    signed_fetch_actor: Option<&(Url, String)>,
+    not_found_deleted: bool,
+    data: &Data<T>,
+) -> Result<Result<Kind, DeletionInfo>, Error> {
+    let config = &data.config;
+    config.verify_not_cancelled()?;
+    let request_builder = config
         .client
         .get(url.as_str())
-        .header("Accept", FEDERATION_CONTENT_TYPE)
-        .timeout(config.request_timeout)
-        .send()
+        .header(ACCEPT, accept_header())
+        .timeout(config.request_timeout);
+    let request = match signed_fetch_actor {
+        Some((actor_id, private_key)) => sign_fetch_request(
+            request_builder.header(DATE, fmt_http_date(config.clock.now())),
+            actor_id,
+            private_key,
+            config.http_signature_compat,
+        )?,
+        None => request_builder.build()?,
+    };
+    let res = config
+        .transport()
+        .execute(request)
         .await
-        .map_err(Error::other)?;
+        .map_err(|e| Error::FetchError {
+            url: url.clone(),
+            status: None,
+            body_snippet: None,
+            source: Some(Box::new(e.into())),
+        })?;
+
+    let status = res.status();
+    if status == StatusCode::GONE || (not_found_deleted && status == StatusCode::NOT_FOUND) {
+        // the body is only informational, so ignore it if it is missing or invalid
+        let tombstone = res.json_limited().await.ok();
+        return Ok(Err(DeletionInfo {
+            status,
+            tombstone,
+            fetched_at: naive_utc(config.clock.now()),
+        }));
+    }
 
-    if res.status() == StatusCode::GONE {
-        return Err(Error::ObjectDeleted);
+    let body = res.bytes_limited().await;
+    let fetch_error = |body_snippet, source| Error::FetchError {
+        url: url.clone(),
+        status: Some(status),
+        body_snippet,
+        source,
+    };
+    if !status.is_success() {
+        return Err(fetch_error(body.ok().map(|b| body_snippet(&b)), None));
     }
+    let body = body?;
+    serde_json::from_slice(&body)
+        .map(Ok)
+        .map_err(|e| fetch_error(Some(body_snippet(&body)), Some(Box::new(e.into()))))
+}
+
+/// Maximum number of domains in [SignedFetchDomains]
+const SIGNED_FETCH_DOMAINS_LIMIT: usize = 1000;
+
+/// Duration for which a domain is remembered in [SignedFetchDomains]
+const SIGNED_FETCH_DOMAINS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Domains which only answered a fetch once it was signed, so that later fetches from them are
+/// signed right away. Domains are forgotten after [SIGNED_FETCH_DOMAINS_TTL], and the oldest
+/// domain is removed if there are more than [SIGNED_FETCH_DOMAINS_LIMIT].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SignedFetchDomains(Arc<Mutex<HashMap<String, SystemTime>>>);
 
-    res.json_limited().await
+impl SignedFetchDomains {
+    fn requires_signature(&self, url: &Url, now: SystemTime) -> bool {
+        let Some(domain) = host_with_port(url) else {
+            return false;
+        };
+        let domains = self.0.lock().expect("lock signed fetch domains");
+        matches!(domains.get(&domain), Some(added) if !is_expired(*added, now))
+    }
+
+    fn insert(&self, url: &Url, now: SystemTime) {
+        let Some(domain) = host_with_port(url) else {
+            return;
+        };
+        let mut domains = self.0.lock().expect("lock signed fetch domains");
+        if domains.len() >= SIGNED_FETCH_DOMAINS_LIMIT && !domains.contains_key(&domain) {
+            domains.retain(|_, added| !is_expired(*added, now));
+            if domains.len() >= SIGNED_FETCH_DOMAINS_LIMIT {
+                let oldest = domains
+                    .iter()
+                    .min_by_key(|(_, added)| **added)
+                    .map(|(domain, _)| domain.clone());
+                if let Some(oldest) = oldest {
+                    domains.remove(&oldest);
+                }
+            }
+        }
+        domains.insert(domain, now);
+    }
+}
+
+fn is_expired(added: SystemTime, now: SystemTime) -> bool {
+    matches!(now.duration_since(added), Ok(age) if age >= SIGNED_FETCH_DOMAINS_TTL)
+}
+
+/// Number of urls at the start and end of [FetchChain] which are stored
+const FETCH_CHAIN_LENGTH: usize = 5;
+
+/// Urls which were fetched with a [Data] instance, for [Error::RequestLimit].
+///
+/// Only the first and last [FETCH_CHAIN_LENGTH] urls are stored.
+#[derive(Debug, Default)]
+pub(crate) struct FetchChain {
+    first: Vec<Url>,
+    omitted: usize,
+    last: VecDeque<Url>,
+}
+
+impl FetchChain {
+    fn push(&mut self, url: &Url) {
+        if self.first.len() < FETCH_CHAIN_LENGTH {
+            self.first.push(url.clone());
+            return;
+        }
+        if self.last.len() == FETCH_CHAIN_LENGTH {
+            self.last.pop_front();
+            self.omitted += 1;
+        }
+        self.last.push_back(url.clone());
+    }
+
+    fn to_error(&self, limit: u32) -> RequestLimitExceeded {
+        RequestLimitExceeded {
+            limit,
+            first: self.first.clone(),
+            omitted: self.omitted,
+            last: self.last.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Maximum length of [Error::FetchError] body snippets in bytes
+const BODY_SNIPPET_LENGTH: usize = 300;
+
+pub(crate) fn body_snippet(body: &[u8]) -> String {
+    let end = body.len().min(BODY_SNIPPET_LENGTH);
+    String::from_utf8_lossy(&body[..end]).into_owned()
+}
+
+/// Parses the `Retry-After` header, which contains either a number of seconds or a date
+pub(crate) fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => parse_http_date(value).ok()?.duration_since(now).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::Clock,
+        config::FederationConfig,
+        error::ErrorClass,
+        events::{
+            tests::{Event, RecordingEvents},
+            FederationEvents,
+            NoFederationEvents,
+        },
+        fetch::{
+            object_id::ObjectId,
+            test_server::{serve, serve_keep_alive},
+            transport::HttpTransport,
+        },
+        http_signatures::verify_signature,
+        test_utils::{
+            clock::ManualClock,
+            transport::{MockResponse, MockTransport},
+            DbConnection,
+            DB_USER,
+        },
+        traits::{Actor, Object},
+    };
+    use http::{HeaderValue, Method, Uri};
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    async fn fetch(status: StatusCode, body: &str) -> Error {
+        fetch_with_events(status, body, Box::new(NoFederationEvents)).await
+    }
+
+    async fn fetch_with_events(
+        status: StatusCode,
+        body: &str,
+        events: Box<dyn FederationEvents>,
+    ) -> Error {
+        let transport = MockTransport::default();
+        let url = Url::parse("https://example.net/object").unwrap();
+        transport.respond(&url, MockResponse::status(status).body(body));
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .events(events)
+            .transport(Box::new(transport))
+            .build()
+            .unwrap();
+        fetch_object_http::<_, Value>(&url, &config.to_request_data())
+            .await
+            .unwrap_err()
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_accept_header() {
+        let transport = MockTransport::default();
+        let url = Url::parse("https://example.net/object").unwrap();
+        transport.respond(&url, MockResponse::json(&json!({})).unwrap());
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .transport(Box::new(transport.clone()))
+            .build()
+            .unwrap();
+        fetch_object_http::<_, Value>(&url, &config.to_request_data())
+            .await
+            .unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].headers.get(ACCEPT), Some(&accept_header()));
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_unauthorized() {
+        let err = fetch(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":"Request not signed"}"#,
+        )
+        .await;
+        let Error::FetchError {
+            url,
+            status,
+            body_snippet,
+            source,
+            ..
+        } = &err
+        else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        assert_eq!(url.path(), "/object");
+        assert_eq!(*status, Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(
+            body_snippet.as_deref(),
+            Some(r#"{"error":"Request not signed"}"#)
+        );
+        assert!(source.is_none());
+        assert!(err.is_fetch_unauthorized());
+
+        let err = fetch(StatusCode::FORBIDDEN, "").await;
+        assert_eq!(err.fetch_status(), Some(StatusCode::FORBIDDEN));
+        assert!(err.is_fetch_unauthorized());
+    }
+
+    /// Server in authorized fetch mode, which rejects unsigned requests with `401 Unauthorized`
+    #[derive(Clone)]
+    struct AuthorizedFetch(MockTransport);
+
+    #[async_trait::async_trait]
+    impl HttpTransport for AuthorizedFetch {
+        async fn execute(
+            &self,
+            request: reqwest::Request,
+        ) -> Result<reqwest::Response, reqwest_middleware::Error> {
+            let signed = request.headers().contains_key("signature");
+            let response = self.0.execute(request).await?;
+            if signed {
+                return Ok(response);
+            }
+            let mut unauthorized = http::Response::new(vec![]);
+            *unauthorized.status_mut() = StatusCode::UNAUTHORIZED;
+            Ok(unauthorized.into())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_signed_fetch_retry() {
+        let transport = MockTransport::default();
+        let first = Url::parse("https://example.net/first").unwrap();
+        let second = Url::parse("https://example.net/second").unwrap();
+        transport.respond(&first, MockResponse::json(&json!({})).unwrap());
+        transport.respond(&second, MockResponse::json(&json!({})).unwrap());
+        let clock = ManualClock::new(SystemTime::now());
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .transport(Box::new(AuthorizedFetch(transport.clone())))
+            .clock(Box::new(clock.clone()))
+            .signed_fetch_actor(&*DB_USER)
+            .build()
+            .unwrap();
+
+        let data = config.to_request_data();
+        fetch_object_http::<_, Value>(&first, &data).await.unwrap();
+        assert_eq!(data.request_count(), 1);
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].headers.contains_key("signature"));
+        let mut headers = requests[1].headers.clone();
+        headers.insert("host", HeaderValue::from_static("example.net"));
+        let uri = Uri::from_static("/first");
+        verify_signature(
+            &headers,
+            &Method::GET,
+            &uri,
+            &DB_USER.id(),
+            &DB_USER.public_key,
+            clock.now(),
+        )
+        .unwrap();
+
+        // the domain is remembered, so the next fetch is signed right away
+        fetch_object_http::<_, Value>(&second, &data).await.unwrap();
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[2].headers.contains_key("signature"));
+
+        clock.advance(SIGNED_FETCH_DOMAINS_TTL);
+        fetch_object_http::<_, Value>(&second, &data).await.unwrap();
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(!requests[3].headers.contains_key("signature"));
+    }
+
+    #[test]
+    fn test_signed_fetch_domains_limit() {
+        let domains = SignedFetchDomains::default();
+        let now = SystemTime::now();
+        let url = |i| Url::parse(&format!("https://{i}.example")).unwrap();
+        for i in 0..=SIGNED_FETCH_DOMAINS_LIMIT {
+            domains.insert(&url(i), now + Duration::from_secs(i as u64));
+        }
+        let later = now + Duration::from_secs(SIGNED_FETCH_DOMAINS_LIMIT as u64);
+        assert!(!domains.requires_signature(&url(0), later));
+        assert!(domains.requires_signature(&url(1), later));
+        assert!(domains.requires_signature(&url(SIGNED_FETCH_DOMAINS_LIMIT), later));
+
+        // refreshing a known domain doesn't evict another one
+        domains.insert(&url(SIGNED_FETCH_DOMAINS_LIMIT), later);
+        assert!(domains.requires_signature(&url(1), later));
+        assert_eq!(domains.0.lock().unwrap().len(), SIGNED_FETCH_DOMAINS_LIMIT);
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_reuses_connection() {
+        let (port, connections) = serve_keep_alive("{}");
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        for path in ["first", "second"] {
+            let url = Url::parse(&format!("http://localhost:{port}/{path}")).unwrap();
+            fetch_object_http::<_, Value>(&url, &data).await.unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_server_error_snippet() {
+        let body = format!("<html>{}</html>", "Bad Gateway ".repeat(100));
+        let err = fetch(StatusCode::BAD_GATEWAY, &body).await;
+        let Error::FetchError {
+            status,
+            body_snippet,
+            ..
+        } = &err
+        else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        assert_eq!(*status, Some(StatusCode::BAD_GATEWAY));
+        let snippet = body_snippet.as_ref().unwrap();
+        assert_eq!(snippet.len(), BODY_SNIPPET_LENGTH);
+        assert!(body.starts_with(snippet.as_str()));
+        assert!(!err.is_fetch_unauthorized());
+
+        // the body of a 404 is not consumed as tombstone outside of ObjectId::dereference
+        let err = fetch(StatusCode::NOT_FOUND, "No such object").await;
+        let Error::FetchError {
+            status,
+            body_snippet,
+            ..
+        } = &err
+        else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        assert_eq!(*status, Some(StatusCode::NOT_FOUND));
+        assert_eq!(body_snippet.as_deref(), Some("No such object"));
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_not_found_or_gone() {
+        let err = fetch(StatusCode::NOT_FOUND, "").await;
+        assert_eq!(err.fetch_status(), Some(StatusCode::NOT_FOUND));
+        let err = fetch(StatusCode::GONE, "").await;
+        assert!(matches!(err, Error::ObjectDeleted(_)), "{err:?}");
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_invalid_json() {
+        let err = fetch(StatusCode::OK, "<html>Not activitypub</html>").await;
+        let Error::FetchError {
+            status,
+            body_snippet,
+            source,
+            ..
+        } = &err
+        else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        assert_eq!(*status, Some(StatusCode::OK));
+        assert_eq!(
+            body_snippet.as_deref(),
+            Some("<html>Not activitypub</html>")
+        );
+        assert!(matches!(source.as_deref(), Some(Error::Json(_))));
+    }
+
+    /// Object which links to another object, which is fetched as well
+    #[derive(Debug)]
+    struct DbChainLink;
+
+    #[derive(Deserialize)]
+    struct ChainLink {
+        next: ObjectId<DbChainLink>,
+    }
+
+    #[async_trait::async_trait]
+    impl Object for DbChainLink {
+        type DataType = DbConnection;
+        type Kind = ChainLink;
+        type Error = Error;
+
+        async fn read_from_id(_: Url, _: &Data<Self::DataType>) -> Result<Option<Self>, Error> {
+            Ok(None)
+        }
+
+        async fn into_json(&self, _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn from_json(json: Self::Kind, data: &Data<Self::DataType>) -> Result<Self, Error> {
+            json.next.dereference(data).await
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_request_limit_chain() {
+        let objects = (0..20)
+            .map(|i| {
+                let body = json!({ "next": format!("{{base}}/link/{}", i + 1) }).to_string();
+                (format!("/link/{i}"), ("200 OK", body))
+            })
+            .collect();
+        let port = serve(objects);
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .http_fetch_limit(12)
+            .build()
+            .unwrap();
+        let base = format!("http://localhost:{port}");
+        let link = |i| Url::parse(&format!("{base}/link/{i}")).unwrap();
+        let res = ObjectId::<DbChainLink>::from(link(0))
+            .dereference(&config.to_request_data())
+            .await;
+
+        let Err(Error::RequestLimit(limit)) = res else {
+            panic!("expected request limit, got {:?}", res);
+        };
+        assert_eq!(limit.limit, 12);
+        assert_eq!(limit.first, (0..5).map(link).collect::<Vec<_>>());
+        assert_eq!(limit.omitted, 4);
+        assert_eq!(limit.last, (9..14).map(link).collect::<Vec<_>>());
+        let message = Error::RequestLimit(limit).to_string();
+        assert!(message.starts_with("Request limit of 12 was reached during fetch of "));
+        assert!(message.contains("/link/4 -> (4 more) -> "));
+        assert!(message.ends_with("/link/13"));
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_events() {
+        let recorder = RecordingEvents::default();
+        let err = fetch_with_events(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "",
+            Box::new(recorder.clone()),
+        )
+        .await;
+        let Error::FetchError { url, .. } = err else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        let outcome = FetchOutcome::Failed {
+            status: Some(StatusCode::SERVICE_UNAVAILABLE),
+            class: ErrorClass::Transient,
+        };
+        assert_eq!(recorder.events(), vec![Event::Fetched(url, outcome)]);
+
+        // only ObjectId::dereference reports a 404 as deletion
+        let recorder = RecordingEvents::default();
+        let err = fetch_with_events(StatusCode::NOT_FOUND, "", Box::new(recorder.clone())).await;
+        let Error::FetchError { url, .. } = err else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        let outcome = FetchOutcome::Failed {
+            status: Some(StatusCode::NOT_FOUND),
+            class: ErrorClass::Permanent,
+        };
+        assert_eq!(recorder.events(), vec![Event::Fetched(url, outcome)]);
+    }
+
+    #[test]
+    fn test_body_snippet_lossy() {
+        let mut body = "ä".repeat(BODY_SNIPPET_LENGTH).into_bytes();
+        body.push(0xff);
+        let snippet = body_snippet(&body);
+        assert!(snippet.starts_with("ää"));
+        assert!(snippet.len() <= BODY_SNIPPET_LENGTH + 3);
+        assert_eq!(body_snippet(&[0x61, 0xff]), "a\u{fffd}");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_fetch_metrics() {
+        use crate::metrics::{
+            tests::{counter, record_metrics},
+            FETCHES,
+        };
+        let metrics = record_metrics(async {
+            fetch(StatusCode::INTERNAL_SERVER_ERROR, "").await;
+            fetch(StatusCode::NOT_FOUND, "").await;
+            fetch(StatusCode::GONE, "").await;
+        });
+        let error = [("domain", "example.net"), ("result", "error")];
+        assert_eq!(counter(&metrics, FETCHES, &error), 2);
+        let deleted = [("domain", "example.net"), ("result", "deleted")];
+        assert_eq!(counter(&metrics, FETCHES, &deleted), 1);
+    }
 }
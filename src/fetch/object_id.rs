@@ -1,12 +1,18 @@
-use crate::{config::Data, error::Error, fetch::fetch_object_http, traits::Object};
-use anyhow::anyhow;
-use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use crate::{
+    clock::{naive_utc, Clock},
+    config::Data,
+    error::Error,
+    fetch::fetch_object_or_deletion_http,
+    traits::Object,
+};
+use chrono::{Duration as ChronoDuration, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use std::{
     fmt::{Debug, Display, Formatter},
     marker::PhantomData,
     str::FromStr,
 };
+use tracing::{field::Empty, Span};
 use url::Url;
 
 impl<T> FromStr for ObjectId<T>
@@ -35,10 +41,10 @@ where
 /// # use activitypub_federation::fetch::object_id::ObjectId;
 /// # use activitypub_federation::config::FederationConfig;
 /// # use activitypub_federation::error::Error::NotFound;
-/// # use activitypub_federation::traits::tests::{DbConnection, DbUser};
+/// # use activitypub_federation::test_utils::{DbConnection, DbUser};
 /// # let _ = actix_rt::System::new();
 /// # actix_rt::Runtime::new().unwrap().block_on(async {
-/// # let db_connection = DbConnection;
+/// # let db_connection = DbConnection::default();
 /// let config = FederationConfig::builder()
 ///     .domain("example.com")
 ///     .app_data(db_connection)
@@ -86,17 +92,24 @@ where
     }
 
     /// Fetches an activitypub object, either from local database (if possible), or over http.
+    #[tracing::instrument(
+        name = "dereference",
+        level = "debug",
+        skip_all,
+        fields(url = %self.0, cache = Empty)
+    )]
     pub async fn dereference(
         &self,
         data: &Data<<Kind as Object>::DataType>,
     ) -> Result<Kind, <Kind as Object>::Error>
     where
-        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+        <Kind as Object>::Error: From<Error>,
     {
         let db_object = self.dereference_from_db(data).await?;
 
         // if its a local object, only fetch it from the database and not over http
         if data.config.is_local_url(&self.0) {
+            Span::current().record("cache", "local");
             return match db_object {
                 None => Err(Error::NotFound.into()),
                 Some(o) => Ok(o),
@@ -105,20 +118,51 @@ where
 
         // object found in database
         if let Some(object) = db_object {
-            // object is old and should be refetched
+            // object is old and should be refetched. if there is no timestamp, the object is never
+            // refetched.
             if let Some(last_refreshed_at) = object.last_refreshed_at() {
-                if should_refetch_object(last_refreshed_at) {
+                if should_refetch_object(last_refreshed_at, &*data.config.clock) {
+                    Span::current().record("cache", "stale");
                     return self.dereference_from_http(data, Some(object)).await;
                 }
             }
+            Span::current().record("cache", "hit");
             Ok(object)
         }
         // object not found, need to fetch over http
         else {
+            Span::current().record("cache", "miss");
             self.dereference_from_http(data, None).await
         }
     }
 
+    /// Like [ObjectId::dereference], but remote objects are always fetched over http, even if
+    /// they are stored in the database and not outdated yet.
+    ///
+    /// Use this if decisions depend on the current state of the object, eg when verifying an
+    /// account move.
+    #[tracing::instrument(
+        name = "dereference",
+        level = "debug",
+        skip_all,
+        fields(url = %self.0, cache = Empty)
+    )]
+    pub async fn dereference_forced(
+        &self,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Kind, <Kind as Object>::Error>
+    where
+        <Kind as Object>::Error: From<Error>,
+    {
+        let db_object = self.dereference_from_db(data).await?;
+        if data.config.is_local_url(&self.0) {
+            Span::current().record("cache", "local");
+            return db_object.ok_or_else(|| Error::NotFound.into());
+        }
+        Span::current().record("cache", "forced");
+        self.dereference_from_http(data, db_object).await
+    }
+
     /// Fetch an object from the local db. Instead of falling back to http, this throws an error if
     /// the object is not found in the database.
     pub async fn dereference_local(
@@ -132,6 +176,20 @@ where
         object.ok_or_else(|| Error::NotFound.into())
     }
 
+    /// Converts `json` which was received as part of an activity (eg the `object` of `Create/Note`)
+    /// to the database type, using this id as expected id.
+    ///
+    /// [Object::verify] is called before [Object::from_json], so that invalid data such as an
+    /// object with id on a different domain is rejected before it is written to the database.
+    pub async fn dereference_from_json(
+        &self,
+        json: <Kind as Object>::Kind,
+        data: &Data<<Kind as Object>::DataType>,
+    ) -> Result<Kind, <Kind as Object>::Error> {
+        Kind::verify(&json, self.inner(), data).await?;
+        Kind::from_json(json, data).await
+    }
+
     /// returning none means the object was not found in local db
     async fn dereference_from_db(
         &self,
@@ -147,21 +205,20 @@ where
         db_object: Option<Kind>,
     ) -> Result<Kind, <Kind as Object>::Error>
     where
-        <Kind as Object>::Error: From<Error> + From<anyhow::Error>,
+        <Kind as Object>::Error: From<Error>,
     {
-        let res = fetch_object_http(&self.0, data).await;
-
-        if let Err(Error::ObjectDeleted) = &res {
-            if let Some(db_object) = db_object {
-                db_object.delete(data).await?;
+        let res = match fetch_object_or_deletion_http(&self.0, true, data).await? {
+            Ok(res) => res,
+            Err(info) => {
+                if let Some(db_object) = db_object {
+                    db_object.delete_with_info(info, data).await?;
+                }
+                return Err(Error::ObjectDeleted(self.inner().clone()).into());
             }
-            return Err(anyhow!("Fetched remote object {} which was deleted", self).into());
-        }
+        };
 
-        let res2 = res?;
-
-        Kind::verify(&res2, self.inner(), data).await?;
-        Kind::from_json(res2, data).await
+        Kind::verify(&res, self.inner(), data).await?;
+        Kind::from_json(res, data).await
     }
 }
 
@@ -182,14 +239,14 @@ static ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG: i64 = 20;
 /// Determines when a remote actor should be refetched from its instance. In release builds, this is
 /// `ACTOR_REFETCH_INTERVAL_SECONDS` after the last refetch, in debug builds
 /// `ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG`.
-fn should_refetch_object(last_refreshed: NaiveDateTime) -> bool {
+fn should_refetch_object(last_refreshed: NaiveDateTime, clock: &dyn Clock) -> bool {
     let update_interval = if cfg!(debug_assertions) {
         // avoid infinite loop when fetching community outbox
         ChronoDuration::seconds(ACTOR_REFETCH_INTERVAL_SECONDS_DEBUG)
     } else {
         ChronoDuration::seconds(ACTOR_REFETCH_INTERVAL_SECONDS)
     };
-    let refresh_limit = Utc::now().naive_utc() - update_interval;
+    let refresh_limit = naive_utc(clock.now()) - update_interval;
     last_refreshed.lt(&refresh_limit)
 }
 
@@ -244,9 +301,24 @@ where
 }
 
 #[cfg(test)]
-pub mod tests {
+mod tests {
     use super::*;
-    use crate::{fetch::object_id::should_refetch_object, traits::tests::DbUser};
+    use crate::{
+        config::FederationConfig,
+        fetch::{object_id::should_refetch_object, test_server::serve, DeletionInfo},
+        instrument::TraceCapture,
+        protocol::verification::verify_domains_match,
+        test_utils::{clock::ManualClock, DbConnection, DbUser},
+    };
+    use http::StatusCode;
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
 
     #[test]
     fn test_deserialize() {
@@ -261,10 +333,252 @@ pub mod tests {
 
     #[test]
     fn test_should_refetch_object() {
-        let one_second_ago = Utc::now().naive_utc() - ChronoDuration::seconds(1);
-        assert!(!should_refetch_object(one_second_ago));
+        let clock = ManualClock::default();
+        let last_refreshed = naive_utc(clock.now());
+        clock.advance(Duration::from_secs(1));
+        assert!(!should_refetch_object(last_refreshed, &clock));
+
+        clock.advance(Duration::from_secs(2 * 24 * 60 * 60));
+        assert!(should_refetch_object(last_refreshed, &clock));
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_without_last_refreshed_at() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id = ObjectId::<DbUser>::parse("https://lemmy.ml/u/nutomic").unwrap();
+        // `DbUser` is always found in database and doesn't have a refresh timestamp, so it must
+        // never be fetched over http
+        let user = id.dereference(&data).await.unwrap();
+        assert_eq!(user.last_refreshed_at(), None);
+        assert_eq!(data.request_count(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_span() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let capture = TraceCapture::start();
+        let remote = ObjectId::<DbUser>::parse("https://lemmy.ml/u/nutomic").unwrap();
+        remote.dereference(&data).await.unwrap();
+        let local = ObjectId::<DbUser>::parse("https://example.com/u/alice").unwrap();
+        local.dereference(&data).await.unwrap();
+
+        let spans = capture.spans("dereference");
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].fields["url"], "https://lemmy.ml/u/nutomic");
+        assert_eq!(spans[0].fields["cache"], "hit");
+        assert_eq!(spans[1].fields["url"], "https://example.com/u/alice");
+        assert_eq!(spans[1].fields["cache"], "local");
+    }
+
+    static FROM_JSON_CALLED: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Deserialize)]
+    struct Page {
+        id: Url,
+    }
+
+    struct DbPage;
+
+    #[async_trait::async_trait]
+    impl Object for DbPage {
+        type DataType = DbConnection;
+        type Kind = Page;
+        type Error = Error;
+
+        async fn read_from_id(_: Url, _: &Data<Self::DataType>) -> Result<Option<Self>, Error> {
+            Ok(None)
+        }
+
+        async fn into_json(&self, _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn verify(
+            json: &Self::Kind,
+            expected_domain: &Url,
+            _: &Data<Self::DataType>,
+        ) -> Result<(), Error> {
+            verify_domains_match(&json.id, expected_domain)
+        }
 
-        let two_days_ago = Utc::now().naive_utc() - ChronoDuration::days(2);
-        assert!(should_refetch_object(two_days_ago));
+        async fn from_json(_: Self::Kind, _: &Data<Self::DataType>) -> Result<Self, Error> {
+            FROM_JSON_CALLED.store(true, Ordering::SeqCst);
+            Ok(DbPage)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_from_json_verifies() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id = ObjectId::<DbPage>::parse("https://lemmy.ml/page/1").unwrap();
+
+        let json = Page {
+            id: Url::parse("https://malicious.com/page/1").unwrap(),
+        };
+        let res = id.dereference_from_json(json, &data).await;
+        assert_eq!(res.err(), Some(Error::UrlVerificationError(String::new())));
+        assert!(!FROM_JSON_CALLED.load(Ordering::SeqCst));
+
+        let json = Page {
+            id: id.inner().clone(),
+        };
+        assert!(id.dereference_from_json(json, &data).await.is_ok());
+        assert!(FROM_JSON_CALLED.load(Ordering::SeqCst));
+    }
+
+    static DELETION_INFO: Mutex<Option<DeletionInfo>> = Mutex::new(None);
+    static ARTICLE_REFRESHED_AT: Mutex<Option<NaiveDateTime>> = Mutex::new(None);
+
+    /// Stored in database, but outdated so that it is refetched
+    struct DbArticle;
+
+    #[async_trait::async_trait]
+    impl Object for DbArticle {
+        type DataType = DbConnection;
+        type Kind = Page;
+        type Error = Error;
+
+        fn last_refreshed_at(&self) -> Option<NaiveDateTime> {
+            *ARTICLE_REFRESHED_AT.lock().unwrap()
+        }
+
+        async fn read_from_id(_: Url, _: &Data<Self::DataType>) -> Result<Option<Self>, Error> {
+            Ok(Some(DbArticle))
+        }
+
+        async fn delete_with_info(
+            self,
+            info: DeletionInfo,
+            _: &Data<Self::DataType>,
+        ) -> Result<(), Error> {
+            *DELETION_INFO.lock().unwrap() = Some(info);
+            Ok(())
+        }
+
+        async fn into_json(&self, _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn from_json(_: Self::Kind, _: &Data<Self::DataType>) -> Result<Self, Error> {
+            Ok(DbArticle)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_deleted_passes_info() {
+        let tombstone =
+            r#"{"type":"Tombstone","id":"http://localhost/article/1","formerType":"Article"}"#;
+        let port = serve(HashMap::from([(
+            "/article/1".to_string(),
+            ("410 Gone", tombstone.to_string()),
+        )]));
+        let clock = ManualClock::default();
+        *ARTICLE_REFRESHED_AT.lock().unwrap() = Some(naive_utc(clock.now()));
+        clock.advance(Duration::from_secs(2 * 24 * 60 * 60));
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .clock(Box::new(clock.clone()))
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let id =
+            ObjectId::<DbArticle>::parse(format!("http://localhost:{port}/article/1").as_str())
+                .unwrap();
+
+        let Err(Error::ObjectDeleted(deleted)) = id.dereference(&data).await else {
+            panic!("expected deleted object");
+        };
+        assert_eq!(&deleted, id.inner());
+
+        let info = DELETION_INFO.lock().unwrap().take().unwrap();
+        assert_eq!(info.status, StatusCode::GONE);
+        let tombstone = info.tombstone.unwrap();
+        assert_eq!(tombstone.former_type.as_deref(), Some("Article"));
+        assert_eq!(info.fetched_at, naive_utc(clock.now()));
+    }
+
+    static DELETED_COMMENTS: Mutex<Vec<Url>> = Mutex::new(vec![]);
+    static COMMENT_REFRESHED_AT: Mutex<Option<NaiveDateTime>> = Mutex::new(None);
+
+    /// Like [DbArticle], but uses the default [Object::delete_with_info]
+    struct DbComment(Url);
+
+    #[async_trait::async_trait]
+    impl Object for DbComment {
+        type DataType = DbConnection;
+        type Kind = Page;
+        type Error = Error;
+
+        fn last_refreshed_at(&self) -> Option<NaiveDateTime> {
+            *COMMENT_REFRESHED_AT.lock().unwrap()
+        }
+
+        async fn read_from_id(id: Url, _: &Data<Self::DataType>) -> Result<Option<Self>, Error> {
+            Ok(Some(DbComment(id)))
+        }
+
+        async fn delete(self, _: &Data<Self::DataType>) -> Result<(), Error> {
+            DELETED_COMMENTS.lock().unwrap().push(self.0);
+            Ok(())
+        }
+
+        async fn into_json(&self, _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn from_json(json: Self::Kind, _: &Data<Self::DataType>) -> Result<Self, Error> {
+            Ok(DbComment(json.id))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_dereference_default_delete_with_info() {
+        let tombstone = r#"{"type":"Tombstone","id":"http://localhost/comment/3"}"#;
+        let port = serve(HashMap::from([
+            ("/comment/1".to_string(), ("404 Not Found", String::new())),
+            ("/comment/2".to_string(), ("410 Gone", String::new())),
+            (
+                "/comment/3".to_string(),
+                ("404 Not Found", tombstone.to_string()),
+            ),
+        ]));
+        let clock = ManualClock::default();
+        *COMMENT_REFRESHED_AT.lock().unwrap() = Some(naive_utc(clock.now()));
+        clock.advance(Duration::from_secs(2 * 24 * 60 * 60));
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .clock(Box::new(clock))
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let url = |i| Url::parse(&format!("http://localhost:{port}/comment/{i}")).unwrap();
+
+        for i in 1..=3 {
+            let res = ObjectId::<DbComment>::from(url(i)).dereference(&data).await;
+            assert!(matches!(res, Err(Error::ObjectDeleted(_))), "{i}");
+        }
+        assert_eq!(
+            *DELETED_COMMENTS.lock().unwrap(),
+            vec![url(1), url(2), url(3)]
+        );
     }
 }
@@ -0,0 +1,80 @@
+//! Minimal HTTP server for testing fetch functionality without network access
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// Serves the given responses, keyed by path and query such as `/outbox?page=1`, on a random
+/// localhost port which is returned. If there is no response for the query, the response for the
+/// path alone is used. Unknown paths result in `404 Not Found`. The placeholder
+/// `{base}` in response bodies is replaced with the server url, eg `http://localhost:1234`.
+///
+/// The server runs in a background thread until the test process exits.
+pub(crate) fn serve(responses: HashMap<String, (&'static str, String)>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0; 4096];
+            let len = stream.read(&mut buf).unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]);
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let (status, body) = responses
+                .get(path)
+                .or_else(|| responses.get(path.split('?').next().unwrap_or_default()))
+                .cloned()
+                .unwrap_or(("404 Not Found", String::new()));
+            let body = body.replace("{base}", &format!("http://localhost:{port}"));
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: application/activity+json\r\n\
+                Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    port
+}
+
+/// Serves `body` for all requests on a random localhost port, and keeps connections open for
+/// further requests. Returns the port and the number of accepted connections.
+pub(crate) fn serve_keep_alive(body: &'static str) -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while let Ok(len @ 1..) = stream.read(&mut buf) {
+                    request.extend_from_slice(&buf[..len]);
+                    // requests have no body, so each one ends with an empty line
+                    while let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        request.drain(..end + 4);
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/activity+json\r\n\
+                            Content-Length: {}\r\n\r\n{body}",
+                            body.len()
+                        );
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (port, connections)
+}
@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+use dyn_clone::{clone_trait_object, DynClone};
+use reqwest::{Request, Response};
+use reqwest_middleware::ClientWithMiddleware;
+
+/// Sends the HTTP requests for fetching remote objects, including webfinger and collections.
+///
+/// Requests are fully built when they are passed to the transport, with all headers and the
+/// timeout. By default they are sent with
+/// [FederationConfigBuilder::client](crate::config::FederationConfigBuilder::client). A custom
+/// transport can be set with
+/// [FederationConfigBuilder::transport](crate::config::FederationConfigBuilder::transport), eg
+/// `test_utils::transport::MockTransport` from the `test-utils` feature, which answers fetches
+/// in tests without a HTTP server. Outgoing activities are always sent with the client.
+#[async_trait]
+pub trait HttpTransport: DynClone + Send + Sync {
+    /// Sends the request and returns the response, or an error if no response was received
+    async fn execute(&self, request: Request) -> Result<Response, reqwest_middleware::Error>;
+}
+
+clone_trait_object!(HttpTransport);
+
+#[async_trait]
+impl HttpTransport for ClientWithMiddleware {
+    async fn execute(&self, request: Request) -> Result<Response, reqwest_middleware::Error> {
+        ClientWithMiddleware::execute(self, request).await
+    }
+}
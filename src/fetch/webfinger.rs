@@ -2,10 +2,10 @@ use crate::{
     config::Data,
     error::{Error, Error::WebfingerResolveFailed},
     fetch::{fetch_object_http, object_id::ObjectId},
+    media_type::ACTIVITY_JSON,
+    protocol::verification::normalize_domain,
     traits::{Actor, Object},
-    FEDERATION_CONTENT_TYPE,
 };
-use anyhow::anyhow;
 use itertools::Itertools;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -24,8 +24,7 @@ pub async fn webfinger_resolve_actor<T: Clone, Kind>(
 where
     Kind: Object + Actor + Send + 'static + Object<DataType = T>,
     for<'de2> <Kind as Object>::Kind: serde::Deserialize<'de2>,
-    <Kind as Object>::Error:
-        From<crate::error::Error> + From<anyhow::Error> + From<url::ParseError> + Send + Sync,
+    <Kind as Object>::Error: From<crate::error::Error> + Send + Sync,
 {
     let (_, domain) = identifier
         .splitn(2, '@')
@@ -36,9 +35,15 @@ where
         format!("{protocol}://{domain}/.well-known/webfinger?resource=acct:{identifier}");
     debug!("Fetching webfinger url: {}", &fetch_url);
 
-    let res: Webfinger = fetch_object_http(&Url::parse(&fetch_url)?, data).await?;
+    let res: Webfinger =
+        fetch_object_http(&Url::parse(&fetch_url).map_err(Error::from)?, data).await?;
 
-    debug_assert_eq!(res.subject, format!("acct:{identifier}"));
+    debug_assert_eq!(
+        res.subject
+            .strip_prefix("acct:")
+            .and_then(split_webfinger_name),
+        split_webfinger_name(identifier)
+    );
     let links: Vec<Url> = res
         .links
         .iter()
@@ -65,7 +70,9 @@ where
 /// Use this method for your HTTP handler at `.well-known/webfinger` to handle incoming webfinger
 /// request. For a parameter of the form `acct:gargron@mastodon.social` it returns `gargron`.
 ///
-/// Returns an error if query doesn't match local domain.
+/// Returns an error if query doesn't match local domain. Internationalized domain names are
+/// accepted in unicode and in punycode form, so `acct:soren@københavn.example` and
+/// `acct:soren@xn--kbenhavn-54a.example` both return `soren`.
 pub fn extract_webfinger_name<T>(query: &str, data: &Data<T>) -> Result<String, Error>
 where
     T: Clone,
@@ -73,17 +80,21 @@ where
     // TODO: would be nice if we could implement this without regex and remove the dependency
     // Regex taken from Mastodon -
     // https://github.com/mastodon/mastodon/blob/2b113764117c9ab98875141bcf1758ba8be58173/app/models/account.rb#L65
-    let regex = Regex::new(&format!(
-        "^acct:((?i)[a-z0-9_]+([a-z0-9_\\.-]+[a-z0-9_]+)?)@{}$",
-        data.domain()
-    ))
-    .map_err(Error::other)?;
-    Ok(regex
-        .captures(query)
-        .and_then(|c| c.get(1))
-        .ok_or_else(|| Error::other(anyhow!("Webfinger regex failed to match")))?
-        .as_str()
-        .to_string())
+    let regex = Regex::new("^(?i)[a-z0-9_]+([a-z0-9_\\.-]+[a-z0-9_]+)?$").map_err(Error::other)?;
+    let (name, domain) = query
+        .strip_prefix("acct:")
+        .and_then(split_webfinger_name)
+        .ok_or(WebfingerResolveFailed)?;
+    if domain != data.domain() || !regex.is_match(name) {
+        return Err(WebfingerResolveFailed);
+    }
+    Ok(name.to_string())
+}
+
+/// Splits an identifier of the form `name@example.com` into name and normalized domain.
+fn split_webfinger_name(identifier: &str) -> Option<(&str, String)> {
+    let (name, domain) = identifier.rsplit_once('@')?;
+    Some((name, normalize_domain(domain)?))
 }
 
 /// Builds a basic webfinger response for the actor.
@@ -148,7 +159,7 @@ pub fn build_webfinger_response_with_type(
                 },
                 WebfingerLink {
                     rel: Some("self".to_string()),
-                    kind: Some(FEDERATION_CONTENT_TYPE.to_string()),
+                    kind: Some(ACTIVITY_JSON.to_string()),
                     href: Some(url.clone()),
                     properties,
                 },
@@ -196,14 +207,31 @@ mod tests {
     use super::*;
     use crate::{
         config::FederationConfig,
-        traits::tests::{DbConnection, DbUser},
+        fetch::test_server::serve,
+        media_type::accept_header,
+        test_utils::{
+            transport::{MockResponse, MockTransport},
+            DbConnection,
+            DbUser,
+        },
     };
+    use http::{header::ACCEPT, StatusCode};
+    use std::collections::HashMap;
 
     #[actix_rt::test]
     async fn test_webfinger() {
+        let transport = MockTransport::default();
+        let url =
+            "https://mastodon.social/.well-known/webfinger?resource=acct:LemmyDev@mastodon.social";
+        let actor = Url::parse("https://mastodon.social/users/LemmyDev").unwrap();
+        let webfinger =
+            build_webfinger_response("acct:LemmyDev@mastodon.social".to_string(), actor);
+        let response = MockResponse::json(&webfinger).unwrap();
+        transport.respond(&Url::parse(url).unwrap(), response);
         let config = FederationConfig::builder()
             .domain("example.com")
-            .app_data(DbConnection)
+            .app_data(DbConnection::default())
+            .transport(Box::new(transport.clone()))
             .build()
             .unwrap();
         let data = config.to_request_data();
@@ -211,5 +239,147 @@ mod tests {
             webfinger_resolve_actor::<DbConnection, DbUser>("LemmyDev@mastodon.social", &data)
                 .await;
         assert!(res.is_ok());
+        let requests = transport.requests();
+        assert_eq!(requests[0].url.as_str(), url);
+        assert_eq!(requests[0].headers.get(ACCEPT), Some(&accept_header()));
+    }
+
+    #[actix_rt::test]
+    async fn test_webfinger_idn() {
+        let transport = MockTransport::default();
+        let url = Url::parse(
+            "https://københavn.example/.well-known/webfinger?resource=acct:soren@københavn.example",
+        )
+        .unwrap();
+        assert_eq!(url.host_str(), Some("xn--kbenhavn-54a.example"));
+        let actor = Url::parse("https://xn--kbenhavn-54a.example/u/soren").unwrap();
+        // the subject is returned in punycode form
+        let webfinger =
+            build_webfinger_response("acct:soren@xn--kbenhavn-54a.example".to_string(), actor);
+        transport.respond(&url, MockResponse::json(&webfinger).unwrap());
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .transport(Box::new(transport.clone()))
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let res =
+            webfinger_resolve_actor::<DbConnection, DbUser>("soren@københavn.example", &data).await;
+        assert!(res.is_ok());
+        assert_eq!(transport.requests()[0].url, url);
+    }
+
+    #[actix_rt::test]
+    async fn test_extract_webfinger_name() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        for query in ["acct:alice@example.com", "acct:alice@EXAMPLE.com"] {
+            assert_eq!(
+                extract_webfinger_name(query, &data),
+                Ok("alice".to_string())
+            );
+        }
+        for query in [
+            "alice@example.com",
+            "acct:alice@example.net",
+            "acct:alice@exampleXcom",
+            "acct:alice@example.com.evil.net",
+            "acct:al ice@example.com",
+            "acct:@example.com",
+        ] {
+            assert_eq!(
+                extract_webfinger_name(query, &data),
+                Err(WebfingerResolveFailed),
+                "{query}"
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_extract_webfinger_name_idn() {
+        for domain in ["københavn.example", "xn--kbenhavn-54a.example"] {
+            let config = FederationConfig::builder()
+                .domain(domain)
+                .app_data(DbConnection::default())
+                .build()
+                .unwrap();
+            let data = config.to_request_data();
+            for query in [
+                "acct:soren@københavn.example",
+                "acct:soren@KØBENHAVN.example",
+                "acct:soren@xn--kbenhavn-54a.example",
+            ] {
+                assert_eq!(
+                    extract_webfinger_name(query, &data),
+                    Ok("soren".to_string()),
+                    "{domain} {query}"
+                );
+            }
+            assert_eq!(
+                extract_webfinger_name("acct:soren@kobenhavn.example", &data),
+                Err(WebfingerResolveFailed)
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_webfinger_fetch_error() {
+        let port = serve(HashMap::from([(
+            "/.well-known/webfinger".to_string(),
+            (
+                "500 Internal Server Error",
+                "Something went wrong".to_string(),
+            ),
+        )]));
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let identifier = format!("alice@localhost:{port}");
+        let err =
+            webfinger_resolve_actor::<DbConnection, DbUser>(&identifier, &config.to_request_data())
+                .await
+                .err()
+                .unwrap();
+        let Error::FetchError {
+            url,
+            status,
+            body_snippet,
+            ..
+        } = &err
+        else {
+            panic!("expected fetch error, got {:?}", err);
+        };
+        assert_eq!(url.path(), "/.well-known/webfinger");
+        assert_eq!(*status, Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(body_snippet.as_deref(), Some("Something went wrong"));
+    }
+
+    #[actix_rt::test]
+    async fn test_webfinger_not_found() {
+        let port = serve(HashMap::from([(
+            "/.well-known/webfinger".to_string(),
+            ("404 Not Found", String::new()),
+        )]));
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let identifier = format!("alice@localhost:{port}");
+        let err =
+            webfinger_resolve_actor::<DbConnection, DbUser>(&identifier, &config.to_request_data())
+                .await
+                .err()
+                .unwrap();
+        assert_eq!(err.fetch_status(), Some(StatusCode::NOT_FOUND), "{err:?}");
     }
 }
@@ -5,27 +5,23 @@
 //! [receive_activity (actix-web)](crate::actix_web::inbox::receive_activity) /
 //! [receive_activity (axum)](crate::axum::inbox::receive_activity).
 
-use crate::{
-    error::{Error, Error::ActivitySignatureInvalid},
-    protocol::public_key::main_key_id,
-};
+use crate::{error::Error, protocol::public_key::main_key_id};
 use base64::{engine::general_purpose::STANDARD as Base64, Engine};
-use http::{header::HeaderName, uri::PathAndQuery, HeaderValue, Method, Uri};
-use http_signature_normalization_reqwest::prelude::{Config, SignExt};
+use http_signature_normalization_reqwest::prelude::{Config, Sign, SignExt};
 use once_cell::sync::Lazy;
-use openssl::{
-    hash::MessageDigest,
-    pkey::PKey,
-    rsa::Rsa,
-    sign::{Signer, Verifier},
-};
+use openssl::{hash::MessageDigest, pkey::PKey, rsa::Rsa, sign::Signer};
 use reqwest::Request;
 use reqwest_middleware::RequestBuilder;
 use sha2::{Digest, Sha256};
-use std::{collections::BTreeMap, fmt::Debug, io::ErrorKind};
-use tracing::debug;
+use std::fmt::Debug;
 use url::Url;
 
+/// Verification of signatures and body digests of incoming inbox requests
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+mod verify;
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+pub(crate) use verify::{verify_inbox_hash, verify_signature};
+
 /// A private/public key pair used for HTTP signatures
 #[derive(Debug, Clone)]
 pub struct Keypair {
@@ -43,10 +39,10 @@ pub fn generate_actor_keypair() -> Result<Keypair, std::io::Error> {
     let private_key = pkey.private_key_to_pem_pkcs8()?;
     let key_to_string = |key| match String::from_utf8(key) {
         Ok(s) => Ok(s),
-        Err(e) => Err(std::io::Error::new(
-            ErrorKind::Other,
-            format!("Failed converting key to string: {}", e),
-        )),
+        Err(e) => Err(std::io::Error::other(format!(
+            "Failed converting key to string: {}",
+            e
+        ))),
     };
     Ok(Keypair {
         private_key: key_to_string(private_key)?,
@@ -62,7 +58,7 @@ pub(crate) async fn sign_request(
     activity: String,
     private_key: String,
     http_signature_compat: bool,
-) -> Result<Request, anyhow::Error> {
+) -> Result<Request, Error> {
     static CONFIG: Lazy<Config> = Lazy::new(Config::new);
     static CONFIG_COMPAT: Lazy<Config> = Lazy::new(|| Config::new().mastodon_compat());
 
@@ -82,116 +78,54 @@ pub(crate) async fn sign_request(
                 let mut signer = Signer::new(MessageDigest::sha256(), &private_key)?;
                 signer.update(signing_string.as_bytes())?;
 
-                Ok(Base64.encode(signer.sign_to_vec()?)) as Result<_, anyhow::Error>
+                Ok(Base64.encode(signer.sign_to_vec()?)) as Result<_, Error>
             },
         )
         .await
 }
 
-static CONFIG2: Lazy<http_signature_normalization::Config> =
-    Lazy::new(http_signature_normalization::Config::new);
-
-/// Verifies the HTTP signature on an incoming inbox request.
-pub(crate) fn verify_signature<'a, H>(
-    headers: H,
-    method: &Method,
-    uri: &Uri,
-    public_key: &str,
-) -> Result<(), Error>
-where
-    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
-{
-    let mut header_map = BTreeMap::<String, String>::new();
-    for (name, value) in headers {
-        if let Ok(value) = value.to_str() {
-            header_map.insert(name.to_string(), value.to_string());
-        }
-    }
-    let path_and_query = uri.path_and_query().map(PathAndQuery::as_str).unwrap_or("");
-
-    let verified = CONFIG2
-        .begin_verify(method.as_str(), path_and_query, header_map)
-        .map_err(Error::other)?
-        .verify(|signature, signing_string| -> anyhow::Result<bool> {
-            debug!(
-                "Verifying with key {}, message {}",
-                &public_key, &signing_string
-            );
-            let public_key = PKey::public_key_from_pem(public_key.as_bytes())?;
-            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
-            verifier.update(signing_string.as_bytes())?;
-            Ok(verifier.verify(&Base64.decode(signature)?)?)
-        })
-        .map_err(Error::other)?;
-
-    if verified {
-        debug!("verified signature for {}", uri);
-        Ok(())
-    } else {
-        Err(ActivitySignatureInvalid)
-    }
-}
-
-#[derive(Clone, Debug)]
-struct DigestPart {
-    /// We assume that SHA256 is used which is the case with all major fediverse platforms
-    #[allow(dead_code)]
-    pub algorithm: String,
-    /// The hashsum
-    pub digest: String,
-}
-
-impl DigestPart {
-    fn try_from_header(h: &HeaderValue) -> Option<Vec<DigestPart>> {
-        let h = h.to_str().ok()?.split(';').next()?;
-        let v: Vec<_> = h
-            .split(',')
-            .filter_map(|p| {
-                let mut iter = p.splitn(2, '=');
-                iter.next()
-                    .and_then(|alg| iter.next().map(|value| (alg, value)))
-            })
-            .map(|(alg, value)| DigestPart {
-                algorithm: alg.to_owned(),
-                digest: value.to_owned(),
-            })
-            .collect();
-
-        if v.is_empty() {
-            None
-        } else {
-            Some(v)
-        }
-    }
-}
-
-/// Verify body of an inbox request against the hash provided in `Digest` header.
-pub(crate) fn verify_inbox_hash(
-    digest_header: Option<&HeaderValue>,
-    body: &[u8],
-) -> Result<(), Error> {
-    let digest = digest_header
-        .and_then(DigestPart::try_from_header)
-        .ok_or(Error::ActivityBodyDigestInvalid)?;
-    let mut hasher = Sha256::new();
-
-    for part in digest {
-        hasher.update(body);
-        if Base64.encode(hasher.finalize_reset()) != part.digest {
-            return Err(Error::ActivityBodyDigestInvalid);
-        }
-    }
+/// Signs a GET request for fetching a remote object with the key of `actor_id`. Unlike
+/// [sign_request] there is no body, so no `Digest` header is added.
+pub(crate) fn sign_fetch_request(
+    request_builder: RequestBuilder,
+    actor_id: &Url,
+    private_key: &str,
+    http_signature_compat: bool,
+) -> Result<Request, Error> {
+    let sig_conf = match http_signature_compat {
+        false => Config::new(),
+        true => Config::new().mastodon_compat(),
+    };
+    request_builder.signature(&sig_conf, main_key_id(actor_id), |signing_string| {
+        let private_key = PKey::private_key_from_pem(private_key.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &private_key)?;
+        signer.update(signing_string.as_bytes())?;
 
-    Ok(())
+        Ok(Base64.encode(signer.sign_to_vec()?)) as Result<_, Error>
+    })
 }
 
 #[cfg(test)]
-pub mod test {
+mod test {
     use super::*;
-    use crate::activity_queue::generate_request_headers;
+    use crate::{
+        activity_queue::generate_request_headers,
+        clock::Clock,
+        error::Error::ActivitySignatureInvalid,
+        protocol::public_key::PublicKey,
+        test_utils::{clock::ManualClock, DB_USER},
+        traits::Actor,
+    };
+    use http::{HeaderMap, HeaderName, HeaderValue, Method, Uri};
+    use httpdate::parse_http_date;
     use reqwest::Client;
     use reqwest_middleware::ClientWithMiddleware;
-    use std::str::FromStr;
+    use serde::Deserialize;
+    use std::{
+        collections::BTreeMap,
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
 
     static ACTOR_ID: Lazy<Url> = Lazy::new(|| Url::parse("https://example.com/u/alice").unwrap());
     static INBOX_URL: Lazy<Url> =
@@ -199,12 +133,9 @@ pub mod test {
 
     #[actix_rt::test]
     async fn test_sign() {
-        let mut headers = generate_request_headers(&INBOX_URL);
         // use hardcoded date in order to test against hardcoded signature
-        headers.insert(
-            "date",
-            HeaderValue::from_str("Tue, 28 Mar 2023 21:03:44 GMT").unwrap(),
-        );
+        let date = parse_http_date("Tue, 28 Mar 2023 21:03:44 GMT").unwrap();
+        let headers = generate_request_headers(&INBOX_URL, date);
 
         let request_builder = ClientWithMiddleware::from(Client::new())
             .post(INBOX_URL.to_string())
@@ -241,28 +172,94 @@ pub mod test {
 
     #[actix_rt::test]
     async fn test_verify() {
-        let headers = generate_request_headers(&INBOX_URL);
+        // with compat, expiration only depends on the date header
+        for http_signature_compat in [false, true] {
+            let clock = ManualClock::default();
+            let headers = generate_request_headers(&INBOX_URL, clock.now());
+            let request_builder = ClientWithMiddleware::from(Client::new())
+                .post(INBOX_URL.to_string())
+                .headers(headers);
+            let request = sign_request(
+                request_builder,
+                ACTOR_ID.clone(),
+                "my activity".to_string(),
+                test_keypair().private_key,
+                http_signature_compat,
+            )
+            .await
+            .unwrap();
+
+            let verify = || {
+                verify_signature(
+                    request.headers(),
+                    request.method(),
+                    &Uri::from_str(request.url().as_str()).unwrap(),
+                    &ACTOR_ID,
+                    &test_keypair().public_key,
+                    clock.now(),
+                )
+            };
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(verify(), Ok(()));
+            clock.advance(Duration::from_secs(2));
+            assert_eq!(verify(), Err(ActivitySignatureInvalid));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_sign_key_id_matches_actor() {
+        let actor = DB_USER.clone();
+        let public_key = serde_json::to_value(PublicKey::from_actor(&actor)).unwrap();
+        assert_eq!(public_key["owner"], actor.id().as_str());
+
         let request_builder = ClientWithMiddleware::from(Client::new())
             .post(INBOX_URL.to_string())
-            .headers(headers);
+            .headers(generate_request_headers(&INBOX_URL, SystemTime::now()));
         let request = sign_request(
             request_builder,
-            ACTOR_ID.clone(),
+            actor.id(),
             "my activity".to_string(),
-            test_keypair().private_key,
+            actor.private_key_pem().unwrap(),
             false,
         )
         .await
         .unwrap();
 
+        let signature = request
+            .headers()
+            .get("signature")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let key_id = signature
+            .split(',')
+            .find_map(|p| p.strip_prefix("keyId=\""))
+            .and_then(|p| p.strip_suffix('"'))
+            .unwrap();
+        assert_eq!(key_id, public_key["id"]);
+        assert_eq!(key_id, "https://localhost/123#main-key");
+
+        let uri = Uri::from_str(request.url().as_str()).unwrap();
         let valid = verify_signature(
             request.headers(),
             request.method(),
-            &Uri::from_str(request.url().as_str()).unwrap(),
-            &test_keypair().public_key,
+            &uri,
+            &actor.id(),
+            public_key["publicKeyPem"].as_str().unwrap(),
+            SystemTime::now(),
         );
-        println!("{:?}", &valid);
         assert!(valid.is_ok());
+
+        // signature must be rejected if key belongs to an actor on a different domain
+        let valid = verify_signature(
+            request.headers(),
+            request.method(),
+            &uri,
+            &ACTOR_ID,
+            actor.public_key_pem(),
+            SystemTime::now(),
+        );
+        assert_eq!(valid, Err(ActivitySignatureInvalid));
     }
 
     #[test]
@@ -284,6 +281,108 @@ pub mod test {
         assert_eq!(invalid, Err(Error::ActivityBodyDigestInvalid));
     }
 
+    /// Synthetic inbox requests which imitate the headers sent by another implementation, stored
+    /// as json in `assets/signatures/synthetic`. They are signed with test keys by test tooling,
+    /// using `*.example` domains and invented bodies, so they only check that the verifier
+    /// accepts the header formats we expect. They are no replacement for requests captured from
+    /// real servers, which can't catch interop regressions otherwise.
+    #[derive(Deserialize)]
+    struct SignatureVectors {
+        /// Name and version of the implementation whose request format is imitated
+        format: String,
+        scheme: SignatureScheme,
+        /// Id of the actor who signs the requests
+        key_owner: Url,
+        public_key_pem: String,
+        vectors: Vec<SignatureVector>,
+    }
+
+    /// Specification of the signature. An RFC 9421 variant gets added together with its
+    /// verification.
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum SignatureScheme {
+        /// draft-cavage-http-signatures, which is used by all major implementations
+        Cavage,
+    }
+
+    #[derive(Deserialize)]
+    struct SignatureVector {
+        description: String,
+        method: String,
+        /// Path and query of the inbox
+        uri: String,
+        headers: BTreeMap<String, String>,
+        body: String,
+        expected: Expected,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Expected {
+        Valid,
+        DigestInvalid,
+        SignatureInvalid,
+    }
+
+    fn signature_vectors() -> Vec<SignatureVectors> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/signatures/synthetic");
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+            .into_iter()
+            .map(|path| serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap())
+            .collect()
+    }
+
+    /// Verifies the vector at the time of its `Date` header, in the same order as an inbox
+    fn verify_vector(vectors: &SignatureVectors, vector: &SignatureVector) -> Result<(), Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &vector.headers {
+            headers.insert(
+                HeaderName::from_str(name).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        let now = parse_http_date(&vector.headers["date"]).unwrap() + Duration::from_secs(1);
+        verify_inbox_hash(headers.get("digest"), vector.body.as_bytes())?;
+        match vectors.scheme {
+            SignatureScheme::Cavage => verify_signature(
+                &headers,
+                &Method::from_str(&vector.method).unwrap(),
+                &Uri::from_str(&vector.uri).unwrap(),
+                &vectors.key_owner,
+                &vectors.public_key_pem,
+                now,
+            ),
+        }
+    }
+
+    #[test]
+    fn test_synthetic_signature_vectors() {
+        let all_vectors = signature_vectors();
+        assert!(all_vectors.len() >= 4);
+        for vectors in &all_vectors {
+            for vector in &vectors.vectors {
+                let expected = match vector.expected {
+                    Expected::Valid => Ok(()),
+                    Expected::DigestInvalid => Err(Error::ActivityBodyDigestInvalid),
+                    Expected::SignatureInvalid => Err(ActivitySignatureInvalid),
+                };
+                assert_eq!(
+                    verify_vector(vectors, vector),
+                    expected,
+                    "{}: {}",
+                    vectors.format,
+                    vector.description
+                );
+            }
+        }
+    }
+
     pub fn test_keypair() -> Keypair {
         let rsa = Rsa::private_key_from_pem(PRIVATE_KEY.as_bytes()).unwrap();
         let pkey = PKey::from_rsa(rsa).unwrap();
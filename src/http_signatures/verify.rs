@@ -0,0 +1,169 @@
+use crate::{
+    error::{Error, Error::ActivitySignatureInvalid},
+    protocol::public_key::key_id_matches_owner,
+};
+use base64::{engine::general_purpose::STANDARD as Base64, Engine};
+use http::{header::HeaderName, uri::PathAndQuery, HeaderValue, Method, Uri};
+use httpdate::parse_http_date;
+use once_cell::sync::Lazy;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Verifier};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+use url::Url;
+
+/// Incoming signatures expire this long after they were created, which is the default of
+/// `http_signature_normalization`
+const SIGNATURE_EXPIRATION: Duration = Duration::from_secs(10);
+
+/// Expiration is checked with the clock of the config in [verify_not_expired], so the check of
+/// `http_signature_normalization` which uses the system time is effectively disabled
+static CONFIG2: Lazy<http_signature_normalization::Config> = Lazy::new(|| {
+    http_signature_normalization::Config::new()
+        .set_expiration(Duration::from_secs(100 * 365 * 24 * 60 * 60))
+});
+
+/// Verifies the HTTP signature on an incoming inbox request, which needs to be signed with a key
+/// belonging to the actor `key_owner`. The signature must not be expired at `now`.
+pub(crate) fn verify_signature<'a, H>(
+    headers: H,
+    method: &Method,
+    uri: &Uri,
+    key_owner: &Url,
+    public_key: &str,
+    now: SystemTime,
+) -> Result<(), Error>
+where
+    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+{
+    let mut header_map = BTreeMap::<String, String>::new();
+    for (name, value) in headers {
+        if let Ok(value) = value.to_str() {
+            header_map.insert(name.to_string(), value.to_string());
+        }
+    }
+    let path_and_query = uri.path_and_query().map(PathAndQuery::as_str).unwrap_or("");
+    verify_not_expired(&header_map, now)?;
+
+    let unverified = CONFIG2
+        .begin_verify(method.as_str(), path_and_query, header_map)
+        .map_err(|e| {
+            debug!("Failed to parse signature header: {}", e);
+            ActivitySignatureInvalid
+        })?;
+    if !key_id_matches_owner(unverified.key_id(), key_owner) {
+        debug!(
+            "Signature key id {} doesn't belong to {}",
+            unverified.key_id(),
+            key_owner
+        );
+        return Err(ActivitySignatureInvalid);
+    }
+    let verified = unverified.verify(|signature, signing_string| -> Result<bool, Error> {
+        debug!(
+            "Verifying with key {}, message {}",
+            &public_key, &signing_string
+        );
+        let public_key = PKey::public_key_from_pem(public_key.as_bytes())?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+        verifier.update(signing_string.as_bytes())?;
+        let signature = Base64
+            .decode(signature)
+            .map_err(|_| ActivitySignatureInvalid)?;
+        Ok(verifier.verify(&signature)?)
+    })?;
+
+    if verified {
+        debug!("verified signature for {}", uri);
+        Ok(())
+    } else {
+        Err(ActivitySignatureInvalid)
+    }
+}
+
+/// Rejects the request if the `(expires)` field of the signature is before `now`, or if the
+/// `(created)` field or the `Date` header are older than [SIGNATURE_EXPIRATION]
+fn verify_not_expired(headers: &BTreeMap<String, String>, now: SystemTime) -> Result<(), Error> {
+    let signature = headers
+        .get("signature")
+        .or_else(|| headers.get("authorization"))
+        .map(String::as_str)
+        .unwrap_or_default();
+    let timestamp = |name: &str| {
+        signature.split(',').find_map(|param| {
+            let (key, value) = param.trim().split_once('=')?;
+            if key != name {
+                return None;
+            }
+            let seconds = value.trim_matches('"').parse().ok()?;
+            UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+        })
+    };
+    let date = headers.get("date").and_then(|d| parse_http_date(d).ok());
+    let limits = [
+        timestamp("expires"),
+        timestamp("created").and_then(|c| c.checked_add(SIGNATURE_EXPIRATION)),
+        date.and_then(|d| d.checked_add(SIGNATURE_EXPIRATION)),
+    ];
+    if limits.into_iter().flatten().any(|limit| limit < now) {
+        debug!("Signature expired, checked at {:?}", now);
+        return Err(ActivitySignatureInvalid);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct DigestPart {
+    /// We assume that SHA256 is used which is the case with all major fediverse platforms
+    #[allow(dead_code)]
+    pub algorithm: String,
+    /// The hashsum
+    pub digest: String,
+}
+
+impl DigestPart {
+    fn try_from_header(h: &HeaderValue) -> Option<Vec<DigestPart>> {
+        let h = h.to_str().ok()?.split(';').next()?;
+        let v: Vec<_> = h
+            .split(',')
+            .filter_map(|p| {
+                let mut iter = p.splitn(2, '=');
+                iter.next()
+                    .and_then(|alg| iter.next().map(|value| (alg, value)))
+            })
+            .map(|(alg, value)| DigestPart {
+                algorithm: alg.to_owned(),
+                digest: value.to_owned(),
+            })
+            .collect();
+
+        if v.is_empty() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+}
+
+/// Verify body of an inbox request against the hash provided in `Digest` header.
+pub(crate) fn verify_inbox_hash(
+    digest_header: Option<&HeaderValue>,
+    body: &[u8],
+) -> Result<(), Error> {
+    let digest = digest_header
+        .and_then(DigestPart::try_from_header)
+        .ok_or(Error::ActivityBodyDigestInvalid)?;
+    let mut hasher = Sha256::new();
+
+    for part in digest {
+        hasher.update(body);
+        if Base64.encode(hasher.finalize_reset()) != part.digest {
+            return Err(Error::ActivityBodyDigestInvalid);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,246 @@
+//! Verification and reporting of incoming activities, shared by the actix-web and axum inbox
+//! handlers
+
+use crate::{
+    config::Data,
+    error::Error,
+    events::{notify, ActivityMeta, RejectionReason},
+    fetch::object_id::ObjectId,
+    http_signatures::{verify_inbox_hash, verify_signature},
+    media_type::verify_content_type,
+    metrics,
+    traits::{ActivityHandler, Actor, Object},
+};
+use http::{HeaderName, HeaderValue, Method, Uri};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+#[cfg(any(feature = "actix-web", feature = "axum"))]
+use std::net::SocketAddr;
+use tracing::{debug, field::Empty, warn, Span};
+use url::Url;
+
+/// Verifies content type, digest and signature of an incoming activity, and passes it to the
+/// [trait@ActivityHandler]. `remote_addr` is only used for tracing.
+#[tracing::instrument(
+    name = "receive_activity",
+    skip_all,
+    fields(
+        activity.id = Empty,
+        activity.type = Empty,
+        actor.domain = Empty,
+        remote_addr = remote_addr.as_deref(),
+    )
+)]
+pub(crate) async fn receive_activity<'a, Activity, ActorT, Datatype, H>(
+    headers: H,
+    method: &Method,
+    uri: &Uri,
+    body: &[u8],
+    remote_addr: Option<String>,
+    data: &Data<Datatype>,
+) -> Result<(), <Activity as ActivityHandler>::Error>
+where
+    Activity: ActivityHandler<DataType = Datatype> + DeserializeOwned + Send + 'static,
+    ActorT: Object<DataType = Datatype> + Actor + Send + 'static,
+    for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
+    <Activity as ActivityHandler>::Error: From<Error> + From<<ActorT as Object>::Error>,
+    <ActorT as Object>::Error: From<Error>,
+    Datatype: Clone,
+    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)> + Copy,
+{
+    let mut meta = activity_meta(body);
+    let span = Span::current();
+    span.record("activity.id", meta.id.as_ref().map(Url::as_str));
+    span.record("activity.type", meta.kind.as_deref());
+    span.record("actor.domain", meta.actor.as_ref().and_then(Url::host_str));
+
+    // step which is currently running, to report why the activity was rejected
+    let mut step = RejectionReason::ContentType;
+    let res = async {
+        verify_content_type(headers)?;
+
+        step = RejectionReason::Digest;
+        let digest = headers
+            .into_iter()
+            .find_map(|(name, value)| (name == "digest").then_some(value));
+        verify_inbox_hash(digest, body)?;
+
+        step = RejectionReason::Parse;
+        let activity: Activity = serde_json::from_slice(body).map_err(Error::from)?;
+        meta.id = Some(activity.id().clone());
+        meta.actor = Some(activity.actor().clone());
+
+        step = RejectionReason::UrlVerification;
+        data.config.verify_url_and_domain(&activity).await?;
+        step = RejectionReason::ActorFetch;
+        let actor = ObjectId::<ActorT>::from(activity.actor().clone())
+            .dereference(data)
+            .await?;
+
+        step = RejectionReason::Signature;
+        let now = data.config.clock.now();
+        verify_signature(
+            headers,
+            method,
+            uri,
+            &actor.id(),
+            actor.public_key_pem(),
+            now,
+        )?;
+
+        debug!("Receiving activity {}", activity.id().to_string());
+        step = RejectionReason::Verify;
+        activity.verify(data).await?;
+        step = RejectionReason::Receive;
+        data.config.verify_not_cancelled()?;
+        activity.receive(data).await?;
+        Ok::<_, <Activity as ActivityHandler>::Error>(())
+    }
+    .await;
+    report_received(data, &meta, res.as_ref().copied().map_err(|_| step)).await;
+    res
+}
+
+/// Address of the server which sent an incoming request.
+///
+/// This is the first address in `X-Forwarded-For`, then `X-Real-IP`, and finally the address of
+/// the TCP peer if it is known. Headers are not verified, so it is only meant for debugging.
+#[cfg(any(feature = "actix-web", feature = "axum"))]
+pub(crate) fn remote_addr<'a, H>(headers: H, peer: Option<SocketAddr>) -> Option<String>
+where
+    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+{
+    let (mut forwarded_for, mut real_ip) = (None, None);
+    for (name, value) in headers {
+        if name == "x-forwarded-for" {
+            forwarded_for = value.to_str().ok();
+        } else if name == "x-real-ip" {
+            real_ip = value.to_str().ok();
+        }
+    }
+    forwarded_for
+        .and_then(|h| h.split(',').next())
+        .or(real_ip)
+        .map(|addr| addr.trim().to_string())
+        .filter(|addr| !addr.is_empty())
+        .or_else(|| peer.map(|peer| peer.ip().to_string()))
+}
+
+/// Target of the event which is logged for each rejected activity
+pub(crate) const REJECTED_TARGET: &str = "activitypub_federation::inbox::rejected";
+
+/// Reads id, type and actor of an activity from its json, without deserializing anything else.
+///
+/// These values are not verified yet, and fields which are missing or invalid are skipped.
+fn activity_meta(body: &[u8]) -> ActivityMeta {
+    #[derive(Deserialize)]
+    struct RawActivity {
+        id: Option<Value>,
+        #[serde(rename = "type")]
+        kind: Option<Value>,
+        actor: Option<Value>,
+    }
+    let Ok(raw) = serde_json::from_slice::<RawActivity>(body) else {
+        return ActivityMeta::default();
+    };
+    let url = |value: Option<Value>| value?.as_str().and_then(|s| Url::parse(s).ok());
+    ActivityMeta {
+        id: url(raw.id),
+        kind: raw.kind.and_then(|k| k.as_str().map(str::to_string)),
+        actor: url(raw.actor),
+    }
+}
+
+/// Records metrics, events and logs for an incoming activity which was accepted, or rejected
+/// in the given step
+async fn report_received<T: Clone>(
+    data: &Data<T>,
+    meta: &ActivityMeta,
+    result: Result<(), RejectionReason>,
+) {
+    let events = &data.config.events;
+    let reason = match result {
+        Ok(()) => {
+            metrics::activity_received(meta.actor.as_ref(), "accepted");
+            notify("activity_received", events.activity_received(meta)).await;
+            return;
+        }
+        Err(reason) => reason,
+    };
+    metrics::activity_received(meta.actor.as_ref(), reason.as_str());
+    log_rejected(meta, reason);
+    let event = events.activity_rejected(meta, reason);
+    notify("activity_rejected", event).await;
+}
+
+fn log_rejected(meta: &ActivityMeta, reason: RejectionReason) {
+    let source_domain = meta.actor.as_ref().and_then(Url::host_str);
+    let activity_id = meta.id.as_ref().map(Url::as_str);
+    let status = reason.status().as_u16();
+    // possibly forged requests and failures of the local handler are more important than
+    // activities which are invalid or unsupported
+    if matches!(
+        reason,
+        RejectionReason::Digest | RejectionReason::Signature | RejectionReason::Receive
+    ) {
+        warn!(
+            target: REJECTED_TARGET,
+            source_domain,
+            stage = reason.as_str(),
+            activity_id,
+            status,
+            "Rejected incoming activity"
+        );
+    } else {
+        debug!(
+            target: REJECTED_TARGET,
+            source_domain,
+            stage = reason.as_str(),
+            activity_id,
+            status,
+            "Rejected incoming activity"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "actix-web", feature = "axum"))]
+    use http::HeaderMap;
+
+    #[cfg(any(feature = "actix-web", feature = "axum"))]
+    #[test]
+    fn test_remote_addr() {
+        let mut headers = HeaderMap::new();
+        let peer = Some("10.0.0.1:1234".parse().unwrap());
+        assert_eq!(remote_addr(&headers, None), None);
+        assert_eq!(remote_addr(&headers, peer).as_deref(), Some("10.0.0.1"));
+        headers.insert("x-real-ip", "192.0.2.2".parse().unwrap());
+        assert_eq!(remote_addr(&headers, peer).as_deref(), Some("192.0.2.2"));
+        headers.insert("x-forwarded-for", "198.51.100.7, 10.0.0.2".parse().unwrap());
+        assert_eq!(remote_addr(&headers, peer).as_deref(), Some("198.51.100.7"));
+    }
+
+    #[test]
+    fn test_activity_meta() {
+        let meta = activity_meta(
+            br#"{
+                "id": "https://example.com/activities/1",
+                "type": "Follow",
+                "actor": "https://example.com/u/alice",
+                "object": "https://example.net/u/bob"
+            }"#,
+        );
+        assert_eq!(
+            meta.id.unwrap().as_str(),
+            "https://example.com/activities/1"
+        );
+        assert_eq!(meta.kind.as_deref(), Some("Follow"));
+        assert_eq!(meta.actor.unwrap().as_str(), "https://example.com/u/alice");
+
+        let meta = activity_meta(br#"{"type": ["Follow"], "actor": {"id": "invalid"}}"#);
+        assert_eq!(meta, ActivityMeta::default());
+        assert_eq!(activity_meta(b"invalid"), ActivityMeta::default());
+    }
+}
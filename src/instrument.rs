@@ -0,0 +1,135 @@
+//! Helpers for tests which check the tracing spans and events of this library
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    subscriber::DefaultGuard,
+    Event,
+    Level,
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+/// Name and recorded fields of a closed span
+#[derive(Clone, Debug)]
+pub(crate) struct CapturedSpan {
+    pub(crate) name: &'static str,
+    pub(crate) fields: HashMap<&'static str, String>,
+}
+
+/// Target, level and fields of an event, including the message
+#[derive(Clone, Debug)]
+pub(crate) struct CapturedEvent {
+    pub(crate) target: &'static str,
+    pub(crate) level: Level,
+    pub(crate) fields: HashMap<&'static str, String>,
+}
+
+#[derive(Default)]
+struct Fields(HashMap<&'static str, String>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+#[derive(Clone, Default)]
+struct CaptureLayer {
+    spans: Arc<Mutex<Vec<CapturedSpan>>>,
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+}
+
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.events.lock().unwrap().push(CapturedEvent {
+            target: event.metadata().target(),
+            level: *event.metadata().level(),
+            fields: fields.0,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            let fields = span.extensions_mut().remove::<Fields>().unwrap_or_default();
+            self.spans.lock().unwrap().push(CapturedSpan {
+                name: span.name(),
+                fields: fields.0,
+            });
+        }
+    }
+}
+
+/// Records all spans which are closed and events which are emitted on the current thread
+/// while it is alive
+pub(crate) struct TraceCapture {
+    layer: CaptureLayer,
+    _guard: DefaultGuard,
+}
+
+impl TraceCapture {
+    pub(crate) fn start() -> Self {
+        let layer = CaptureLayer::default();
+        let guard = tracing_subscriber::registry()
+            .with(layer.clone())
+            .set_default();
+        TraceCapture {
+            layer,
+            _guard: guard,
+        }
+    }
+
+    /// Returns all closed spans with the given name
+    pub(crate) fn spans(&self, name: &str) -> Vec<CapturedSpan> {
+        let spans = self.layer.spans.lock().unwrap();
+        spans.iter().filter(|s| s.name == name).cloned().collect()
+    }
+
+    /// Returns all events with the given target
+    pub(crate) fn events(&self, target: &str) -> Vec<CapturedEvent> {
+        let events = self.layer.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|e| e.target == target)
+            .cloned()
+            .collect()
+    }
+}
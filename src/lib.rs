@@ -8,6 +8,7 @@
 #![doc = include_str!("../docs/08_receiving_activities.md")]
 #![doc = include_str!("../docs/09_sending_activities.md")]
 #![doc = include_str!("../docs/10_fetching_objects_with_unknown_type.md")]
+#![doc = include_str!("../docs/11_tracing.md")]
 #![deny(missing_docs)]
 
 pub mod activity_queue;
@@ -15,15 +16,37 @@ pub mod activity_queue;
 pub mod actix_web;
 #[cfg(feature = "axum")]
 pub mod axum;
+pub mod clock;
 pub mod config;
 pub mod error;
+pub mod events;
 pub mod fetch;
 pub mod http_signatures;
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+pub(crate) mod inbox;
+#[cfg(test)]
+pub(crate) mod instrument;
+pub mod media_type;
+pub mod metrics;
 pub mod protocol;
 pub(crate) mod reqwest_shim;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 pub mod traits;
 
 pub use activitystreams_kinds as kinds;
 
-/// Mime type for Activitypub data, used for `Accept` and `Content-Type` HTTP headers
-pub static FEDERATION_CONTENT_TYPE: &str = "application/activity+json";
+// Allows referring to this crate as `::activitypub_federation` from inside, eg in derive macros
+extern crate self as activitypub_federation;
+
+/// Items used by code generated from derive macros. Not public API.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use async_trait::async_trait;
+    pub use serde::de::DeserializeOwned;
+    pub use url::Url;
+}
+
+/// Mime type for Activitypub data, same as [media_type::ACTIVITY_JSON]
+pub static FEDERATION_CONTENT_TYPE: &str = media_type::ACTIVITY_JSON;
@@ -0,0 +1,177 @@
+//! Media types of Activitypub data, for `Accept` and `Content-Type` HTTP headers
+//!
+//! The Activitypub spec defines two equivalent media types, [ACTIVITY_JSON] and [LD_JSON]. Some
+//! implementations only respond with Activitypub data if the `Accept` header of a fetch contains
+//! the `ld+json` type with profile, and others ignore responses with plain `application/json`.
+//! This library sends [accept_header] with every fetch, and [ACTIVITY_JSON] as `Content-Type` of
+//! activities and of responses from
+//! [FederationJson (actix-web)](crate::actix_web::json::FederationJson) /
+//! [FederationJson (axum)](crate::axum::json::FederationJson).
+//!
+//! Incoming activities are checked less strictly, because many implementations send them as
+//! `application/ld+json` without profile, or as plain `application/json`. Inbox handlers accept
+//! all of these types, and only reject other types such as `text/html`.
+//!
+//! ```
+//! # use activitypub_federation::media_type::is_activitypub_type;
+//! assert!(is_activitypub_type("application/activity+json; charset=utf-8"));
+//! assert!(is_activitypub_type(
+//!     r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#
+//! ));
+//! assert!(!is_activitypub_type("application/ld+json"));
+//! assert!(!is_activitypub_type("application/json"));
+//! ```
+
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+use crate::error::Error;
+use http::HeaderValue;
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+use http::{header::CONTENT_TYPE, HeaderName};
+
+/// Media type `application/activity+json`, which is sent as `Content-Type` by this library
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// Media type `application/ld+json` with the Activitystreams profile
+pub const LD_JSON: &str = r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#;
+
+/// Profile of [LD_JSON]
+const ACTIVITYSTREAMS_PROFILE: &str = "https://www.w3.org/ns/activitystreams";
+
+/// Media types which are accepted as `Content-Type` of incoming activities, in addition to the
+/// Activitypub media types
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+const LENIENT_JSON_TYPES: [&str; 2] = ["application/ld+json", "application/json"];
+
+/// `Accept` header for fetches of Activitypub objects, which lists both media types
+pub fn accept_header() -> HeaderValue {
+    HeaderValue::from_static(
+        r#"application/activity+json, application/ld+json; profile="https://www.w3.org/ns/activitystreams""#,
+    )
+}
+
+/// `Content-Type` header for activities and other Activitypub data
+pub fn content_type_header() -> HeaderValue {
+    HeaderValue::from_static(ACTIVITY_JSON)
+}
+
+/// Returns true if the value of a `Content-Type` header is one of the Activitypub media types.
+///
+/// The comparison ignores case and parameters such as `charset`, but the `ld+json` type is only
+/// accepted with the Activitystreams profile.
+pub fn is_activitypub_type(content_type: &str) -> bool {
+    let mut parts = content_type.split(';');
+    let essence = parts.next().unwrap_or_default().trim();
+    if essence.eq_ignore_ascii_case(ACTIVITY_JSON) {
+        return true;
+    }
+    if !essence.eq_ignore_ascii_case("application/ld+json") {
+        return false;
+    }
+    parts
+        .filter_map(|param| param.split_once('='))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("profile"))
+        // the profile may be a space separated list
+        .any(|(_, value)| {
+            value
+                .trim()
+                .trim_matches('"')
+                .split_whitespace()
+                .any(|profile| profile == ACTIVITYSTREAMS_PROFILE)
+        })
+}
+
+/// Returns true if the value of an `Accept` header contains one of the Activitypub media types.
+///
+/// Use this in handlers which serve both HTML and Activitypub data at the same path, as other
+/// servers often send several types or parameters instead of exactly [ACTIVITY_JSON].
+pub fn accepts_activitypub(accept: &str) -> bool {
+    accept.split(',').any(is_activitypub_type)
+}
+
+/// Rejects an incoming activity whose `Content-Type` isn't an Activitypub media type, or
+/// `application/ld+json` / `application/json` with any parameters. Requests without the header are
+/// accepted, as some implementations don't send it.
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+pub(crate) fn verify_content_type<'a, H>(headers: H) -> Result<(), Error>
+where
+    H: IntoIterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+{
+    let Some(value) = headers
+        .into_iter()
+        .find_map(|(name, value)| (name == CONTENT_TYPE).then_some(value))
+    else {
+        return Ok(());
+    };
+    let content_type = String::from_utf8_lossy(value.as_bytes());
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    let is_json = LENIENT_JSON_TYPES
+        .iter()
+        .any(|json| essence.eq_ignore_ascii_case(json));
+    if is_json || is_activitypub_type(&content_type) {
+        Ok(())
+    } else {
+        Err(Error::ActivityContentTypeInvalid(content_type.into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderMap;
+
+    #[test]
+    fn test_is_activitypub_type() {
+        assert!(is_activitypub_type(ACTIVITY_JSON));
+        assert!(is_activitypub_type(LD_JSON));
+        assert!(is_activitypub_type("Application/Activity+JSON"));
+        assert!(is_activitypub_type(
+            "application/ld+json;profile=https://www.w3.org/ns/activitystreams"
+        ));
+        assert!(is_activitypub_type(
+            r#"application/ld+json; charset=utf-8; profile="https://example.com/ns https://www.w3.org/ns/activitystreams""#
+        ));
+        assert!(!is_activitypub_type(
+            r#"application/ld+json; profile="https://example.com/ns""#
+        ));
+        assert!(!is_activitypub_type("text/html"));
+        assert!(!is_activitypub_type(""));
+    }
+
+    #[test]
+    fn test_accept_header() {
+        let accept = accept_header();
+        let types: Vec<_> = accept.to_str().unwrap().split(", ").collect();
+        assert_eq!(types, vec![ACTIVITY_JSON, LD_JSON]);
+    }
+
+    #[test]
+    fn test_accepts_activitypub() {
+        assert!(accepts_activitypub(accept_header().to_str().unwrap()));
+        assert!(accepts_activitypub(
+            "text/html, application/activity+json;q=0.9"
+        ));
+        assert!(!accepts_activitypub("text/html, application/json"));
+    }
+
+    #[test]
+    fn test_verify_content_type() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(verify_content_type(&headers), Ok(()));
+        headers.insert(CONTENT_TYPE, content_type_header());
+        assert_eq!(verify_content_type(&headers), Ok(()));
+        for lenient in [
+            "application/ld+json",
+            r#"application/ld+json; profile="https://example.com/ns""#,
+            "application/json",
+            "Application/JSON; charset=utf-8",
+        ] {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(lenient));
+            assert_eq!(verify_content_type(&headers), Ok(()), "{lenient}");
+        }
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert_eq!(
+            verify_content_type(&headers),
+            Err(Error::ActivityContentTypeInvalid("text/plain".to_string()))
+        );
+    }
+}
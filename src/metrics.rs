@@ -0,0 +1,204 @@
+//! Metrics about federation, which are recorded when the `metrics` feature is enabled
+//!
+//! Metrics are recorded with the [metrics](https://docs.rs/metrics) facade, so any compatible
+//! exporter such as `metrics-exporter-prometheus` can be used. Without the feature, no metrics
+//! are recorded and there is no overhead.
+//!
+//! | Name | Type | Labels | Description |
+//! |------|------|--------|-------------|
+//! | [ACTIVITIES_RECEIVED] | counter | `domain`, `result` | Incoming activities. `result` is `accepted`, or the step which rejected the activity: `content_type`, `digest`, `parse`, `url_verification`, `actor_fetch`, `signature`, `verify` or `receive` |
+//! | [DELIVERIES] | counter | `domain`, `result` | Delivery attempts. `result` is `success`, `permanent`, `transient` or `rate_limited`, see [ErrorClass](crate::error::ErrorClass) |
+//! | [DELIVERY_PANICS] | counter | `domain` | Delivery attempts which panicked, eg in a client middleware. They are also counted in [DELIVERIES] as `transient` |
+//! | [DELIVERY_DURATION] | histogram | `domain` | Duration of delivery attempts in seconds |
+//! | [FETCHES] | counter | `domain`, `result` | Fetches of remote objects over http. `result` is `success`, `deleted` or `error`. `404 Not Found` is only `deleted` when an object is refetched by `ObjectId::dereference` |
+//! | [FETCH_DURATION] | histogram | `domain` | Duration of fetches in seconds |
+//! | [QUEUE_PENDING] | gauge | | Activities which are waiting for delivery |
+//! | [QUEUE_RUNNING] | gauge | | Activities which are being delivered |
+//!
+//! The `domain` label is the domain of the remote server, ie the actor domain for received
+//! activities, and the domain of the inbox or object url otherwise. To limit the number of time
+//! series, only the first [MAX_DOMAIN_LABELS] distinct domains are used as label values, and
+//! all further domains are recorded as `other`. `unknown` is used if an incoming activity
+//! couldn't be parsed.
+
+use url::Url;
+
+/// Name of the counter for incoming activities
+pub const ACTIVITIES_RECEIVED: &str = "activitypub_federation_activities_received_total";
+/// Name of the counter for delivery attempts
+pub const DELIVERIES: &str = "activitypub_federation_deliveries_total";
+/// Name of the counter for delivery attempts which panicked
+pub const DELIVERY_PANICS: &str = "activitypub_federation_delivery_panics_total";
+/// Name of the histogram for the duration of delivery attempts
+pub const DELIVERY_DURATION: &str = "activitypub_federation_delivery_duration_seconds";
+/// Name of the counter for fetches of remote objects
+pub const FETCHES: &str = "activitypub_federation_fetches_total";
+/// Name of the histogram for the duration of fetches
+pub const FETCH_DURATION: &str = "activitypub_federation_fetch_duration_seconds";
+/// Name of the gauge for pending activities in the queue
+pub const QUEUE_PENDING: &str = "activitypub_federation_queue_pending";
+/// Name of the gauge for running deliveries in the queue
+pub const QUEUE_RUNNING: &str = "activitypub_federation_queue_running";
+
+/// Maximum number of distinct values of the `domain` label
+pub const MAX_DOMAIN_LABELS: usize = 100;
+
+/// Measures the duration of a request to a remote server. Does nothing without the `metrics`
+/// feature.
+pub(crate) struct Timer {
+    #[cfg(feature = "metrics")]
+    start: std::time::Instant,
+    #[cfg(feature = "metrics")]
+    domain: String,
+}
+
+impl Timer {
+    pub(crate) fn start(url: &Url) -> Self {
+        #[cfg(not(feature = "metrics"))]
+        let _ = url;
+        Timer {
+            #[cfg(feature = "metrics")]
+            start: std::time::Instant::now(),
+            #[cfg(feature = "metrics")]
+            domain: domain_label(Some(url)),
+        }
+    }
+}
+
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+pub(crate) fn activity_received(actor: Option<&Url>, result: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(ACTIVITIES_RECEIVED, "domain" => domain_label(actor), "result" => result)
+        .increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (actor, result);
+}
+
+pub(crate) fn delivery_finished(timer: Timer, result: &'static str) {
+    #[cfg(feature = "metrics")]
+    {
+        let duration = timer.start.elapsed();
+        ::metrics::counter!(DELIVERIES, "domain" => timer.domain.clone(), "result" => result)
+            .increment(1);
+        ::metrics::histogram!(DELIVERY_DURATION, "domain" => timer.domain).record(duration);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (timer, result);
+}
+
+pub(crate) fn delivery_panicked(inbox: &Url) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(DELIVERY_PANICS, "domain" => domain_label(Some(inbox))).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = inbox;
+}
+
+pub(crate) fn fetch_finished(timer: Timer, result: &'static str) {
+    #[cfg(feature = "metrics")]
+    {
+        let duration = timer.start.elapsed();
+        ::metrics::counter!(FETCHES, "domain" => timer.domain.clone(), "result" => result)
+            .increment(1);
+        ::metrics::histogram!(FETCH_DURATION, "domain" => timer.domain).record(duration);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (timer, result);
+}
+
+pub(crate) fn queue_stats(pending: usize, running: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        #[allow(clippy::cast_precision_loss)]
+        ::metrics::gauge!(QUEUE_PENDING).set(pending as f64);
+        #[allow(clippy::cast_precision_loss)]
+        ::metrics::gauge!(QUEUE_RUNNING).set(running as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (pending, running);
+}
+
+#[cfg(feature = "metrics")]
+fn domain_label(url: Option<&Url>) -> String {
+    static DOMAIN_LABELS: once_cell::sync::Lazy<DomainLabels> =
+        once_cell::sync::Lazy::new(Default::default);
+    DOMAIN_LABELS.label(url)
+}
+
+/// Domains which are used as label values
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct DomainLabels(std::sync::Mutex<std::collections::HashSet<String>>);
+
+#[cfg(feature = "metrics")]
+impl DomainLabels {
+    /// Returns the domain of the url as label value, limited to [MAX_DOMAIN_LABELS] distinct
+    /// values
+    fn label(&self, url: Option<&Url>) -> String {
+        let Some(domain) = url.and_then(Url::host_str) else {
+            return "unknown".to_string();
+        };
+        let mut domains = self.0.lock().expect("lock domain labels");
+        if domains.contains(domain) {
+            domain.to_string()
+        } else if domains.len() < MAX_DOMAIN_LABELS {
+            domains.insert(domain.to_string());
+            domain.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+pub(crate) mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+    use std::future::Future;
+
+    /// Runs the future with a recorder for the current thread, and returns the recorded metrics
+    pub(crate) fn record_metrics<F: Future>(future: F) -> Snapshotter {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        ::metrics::with_local_recorder(&recorder, || {
+            actix_rt::System::new().block_on(future);
+        });
+        snapshotter
+    }
+
+    /// Returns the value of the counter with the given name and labels, or zero if it is missing
+    pub(crate) fn counter(snapshotter: &Snapshotter, name: &str, labels: &[(&str, &str)]) -> u64 {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .find(|(key, _, _, _)| {
+                let key = key.key();
+                key.name() == name
+                    && labels.iter().all(|(label, value)| {
+                        key.labels()
+                            .any(|l| l.key() == *label && l.value() == *value)
+                    })
+            })
+            .map_or(0, |(_, _, _, value)| match value {
+                DebugValue::Counter(value) => value,
+                _ => panic!("{name} is not a counter"),
+            })
+    }
+
+    #[test]
+    fn test_domain_label() {
+        let labels = DomainLabels::default();
+        let url = Url::parse("https://example.com/u/alice").unwrap();
+        assert_eq!(labels.label(Some(&url)), "example.com");
+        assert_eq!(labels.label(None), "unknown");
+        for i in 1..MAX_DOMAIN_LABELS {
+            let url = Url::parse(&format!("https://{i}.example.net")).unwrap();
+            assert_ne!(labels.label(Some(&url)), "other");
+        }
+        let url = Url::parse("https://example.org").unwrap();
+        assert_eq!(labels.label(Some(&url)), "other");
+        // domains which were seen before keep their label
+        let url = Url::parse("https://example.com/u/bob").unwrap();
+        assert_eq!(labels.label(Some(&url)), "example.com");
+    }
+}
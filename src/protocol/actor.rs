@@ -0,0 +1,162 @@
+//! Type of actors, which distinguishes users from bots, groups and instance actors
+//!
+//! ```
+//! # use activitypub_federation::protocol::actor::ActorKind;
+//! #[derive(serde::Deserialize, serde::Serialize)]
+//! struct Actor {
+//!     #[serde(rename = "type")]
+//!     kind: ActorKind,
+//! }
+//!
+//! let bot: Actor = serde_json::from_str(r#"{"type": "Service"}"#)?;
+//! assert!(bot.kind.is_bot());
+//! let unknown: Actor = serde_json::from_str(r#"{"type": "Automaton"}"#)?;
+//! assert_eq!(unknown.kind, ActorKind::Other("Automaton".to_string()));
+//! assert_eq!(serde_json::to_string(&unknown)?, r#"{"type":"Automaton"}"#);
+//! # Ok::<(), serde_json::Error>(())
+//! ```
+
+use crate::protocol::helpers::deserialize_one_or_many;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+
+/// Value of the `type` field of an actor.
+///
+/// Unknown types are kept as [ActorKind::Other], so deserialization never fails because of the
+/// type. If the type is a list, the first known type is used.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ActorKind {
+    /// `Person`, a regular user
+    Person,
+    /// `Service`, which Mastodon uses for bot accounts
+    Service,
+    /// `Application`, which is commonly used for instance actors
+    Application,
+    /// `Group`, eg a Lemmy community
+    Group,
+    /// `Organization`
+    Organization,
+    /// Any other type
+    Other(String),
+}
+
+impl ActorKind {
+    /// Type as it is written in the `type` field, eg `Person`
+    pub fn as_str(&self) -> &str {
+        match self {
+            ActorKind::Person => "Person",
+            ActorKind::Service => "Service",
+            ActorKind::Application => "Application",
+            ActorKind::Group => "Group",
+            ActorKind::Organization => "Organization",
+            ActorKind::Other(kind) => kind,
+        }
+    }
+
+    /// Returns true for automated actors, ie `Service` and `Application`. Mastodon shows these
+    /// with a bot badge.
+    pub fn is_bot(&self) -> bool {
+        matches!(self, ActorKind::Service | ActorKind::Application)
+    }
+
+    /// Returns true for `Group`
+    pub fn is_group(&self) -> bool {
+        matches!(self, ActorKind::Group)
+    }
+}
+
+impl From<&str> for ActorKind {
+    fn from(kind: &str) -> Self {
+        match kind {
+            "Person" => ActorKind::Person,
+            "Service" => ActorKind::Service,
+            "Application" => ActorKind::Application,
+            "Group" => ActorKind::Group,
+            "Organization" => ActorKind::Organization,
+            other => ActorKind::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for ActorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for ActorKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ActorKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let kinds: Vec<String> = deserialize_one_or_many(deserializer)?;
+        let kinds: Vec<_> = kinds
+            .iter()
+            .map(|kind| ActorKind::from(kind.as_str()))
+            .collect();
+        let known = kinds
+            .iter()
+            .position(|kind| !matches!(kind, ActorKind::Other(_)))
+            .unwrap_or(0);
+        Ok(kinds
+            .into_iter()
+            .nth(known)
+            .unwrap_or_else(|| ActorKind::Other(String::new())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn kind(fixture: &str) -> ActorKind {
+        let actor: Value = serde_json::from_str(fixture).unwrap();
+        serde_json::from_value(actor["type"].clone()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_fixtures() {
+        let person = kind(include_str!("../../assets/mastodon/person.json"));
+        assert_eq!(person, ActorKind::Person);
+        assert!(!person.is_bot());
+        let service = kind(include_str!("../../assets/mastodon/service.json"));
+        assert_eq!(service, ActorKind::Service);
+        assert!(service.is_bot());
+        let group = kind(include_str!("../../assets/lemmy/group.json"));
+        assert_eq!(group, ActorKind::Group);
+        assert!(group.is_group());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for name in [
+            "Person",
+            "Service",
+            "Application",
+            "Group",
+            "Organization",
+            "Automaton",
+        ] {
+            let kind: ActorKind = serde_json::from_value(json!(name)).unwrap();
+            assert_eq!(kind.as_str(), name);
+            assert_eq!(serde_json::to_value(&kind).unwrap(), json!(name));
+        }
+        let future: ActorKind = serde_json::from_value(json!("Automaton")).unwrap();
+        assert_eq!(future, ActorKind::Other("Automaton".to_string()));
+        assert!(!future.is_bot() && !future.is_group());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let kind: ActorKind = serde_json::from_value(json!(["foaf:Agent", "Group"])).unwrap();
+        assert_eq!(kind, ActorKind::Group);
+        let kind: ActorKind = serde_json::from_value(json!(["foaf:Agent"])).unwrap();
+        assert_eq!(kind, ActorKind::Other("foaf:Agent".to_string()));
+        let kind: ActorKind = serde_json::from_value(json!([])).unwrap();
+        assert_eq!(kind, ActorKind::Other(String::new()));
+    }
+}
@@ -0,0 +1,327 @@
+//! Images and other media which are attached to actors and objects
+//!
+//! Actors have `icon` (avatar) and `image` (banner) fields, while objects can have `icon`,
+//! `image` and `attachment`. Each of these can contain a single value or an array, so they
+//! should be deserialized with [deserialize_one_or_many].
+//!
+//! ```
+//! # use activitypub_federation::protocol::{attachment::{Attachment, ImageObject}, helpers::deserialize_one_or_many};
+//! #[derive(serde::Deserialize)]
+//! struct Note {
+//!     #[serde(deserialize_with = "deserialize_one_or_many", default)]
+//!     icon: Vec<ImageObject>,
+//!     #[serde(deserialize_with = "deserialize_one_or_many", default)]
+//!     attachment: Vec<Attachment>,
+//! }
+//!
+//! let note: Note = serde_json::from_str(
+//! r#"{
+//!     "icon": {"type": "Image", "url": "https://example.com/icon.png"},
+//!     "attachment": {"type": "Document", "mediaType": "image/png", "url": "https://example.com/cat.png", "name": "A cat"}
+//! }"#)?;
+//! assert_eq!(note.icon[0].url().unwrap().as_str(), "https://example.com/icon.png");
+//! assert_eq!(note.attachment[0].alt(), Some("A cat"));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::protocol::{
+    helpers::{deserialize_one_or_many, serialize_one_or_many},
+    kind::{DocumentType, ImageType},
+    tag::Link,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+/// Value of a `url` field, which is either sent as plain url or as [Link] object.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum UrlOrLink {
+    /// Plain url
+    Url(Url),
+    /// Link object, which may contain the media type
+    Link(Link),
+}
+
+impl UrlOrLink {
+    /// Returns the url, or the `href` of the link
+    pub fn href(&self) -> &Url {
+        match self {
+            UrlOrLink::Url(url) => url,
+            UrlOrLink::Link(link) => &link.href,
+        }
+    }
+
+    fn media_type(&self) -> Option<&str> {
+        match self {
+            UrlOrLink::Url(_) => None,
+            UrlOrLink::Link(link) => link.media_type.as_deref(),
+        }
+    }
+}
+
+impl From<Url> for UrlOrLink {
+    fn from(url: Url) -> Self {
+        UrlOrLink::Url(url)
+    }
+}
+
+/// Image, eg user avatar or banner
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageObject {
+    /// Always `Image`
+    #[serde(rename = "type")]
+    pub kind: ImageType,
+    /// One or more urls of the image
+    #[serde(
+        deserialize_with = "deserialize_one_or_many",
+        serialize_with = "serialize_one_or_many"
+    )]
+    pub url: Vec<UrlOrLink>,
+    /// Media type of the image, eg `image/png`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Alt text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Width in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl ImageObject {
+    /// Create a new image with the given url
+    pub fn new(url: Url) -> Self {
+        ImageObject {
+            kind: Default::default(),
+            url: vec![url.into()],
+            media_type: None,
+            name: None,
+            width: None,
+            height: None,
+        }
+    }
+
+    /// Returns the best url to display the image, see [best_url]
+    pub fn url(&self) -> Option<&Url> {
+        best_url(&self.url)
+    }
+
+    /// Returns the alt text, if it is not empty
+    pub fn alt(&self) -> Option<&str> {
+        non_empty(&self.name)
+    }
+
+    /// Returns the largest of the given images, which is useful if eg multiple thumbnails are
+    /// provided. Images without size are only returned if no size is known for any of them.
+    pub fn largest(images: &[ImageObject]) -> Option<&ImageObject> {
+        let size =
+            |i: &&ImageObject| u64::from(i.width.unwrap_or(0)) * u64::from(i.height.unwrap_or(0));
+        images.iter().rev().max_by_key(size)
+    }
+}
+
+/// Generic document, which is used by many platforms for attached media of any type.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    /// Always `Document`
+    #[serde(rename = "type")]
+    pub kind: DocumentType,
+    /// One or more urls of the document
+    #[serde(
+        deserialize_with = "deserialize_one_or_many",
+        serialize_with = "serialize_one_or_many"
+    )]
+    pub url: Vec<UrlOrLink>,
+    /// Media type of the document, eg `image/png` or `video/mp4`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Alt text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Width in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// Any attachment of an object.
+///
+/// Attachments of unknown type, like `PropertyValue` for profile fields, are kept as
+/// [Attachment::Other] so that they can be passed on unchanged.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Attachment {
+    /// Image
+    Image(ImageObject),
+    /// Generic document
+    Document(Document),
+    /// Any other attachment
+    Other(Value),
+}
+
+impl Attachment {
+    /// Returns the best url to display the attachment, see [best_url]
+    pub fn url(&self) -> Option<&Url> {
+        match self {
+            Attachment::Image(image) => image.url(),
+            Attachment::Document(document) => best_url(&document.url),
+            Attachment::Other(_) => None,
+        }
+    }
+
+    /// Returns the alt text, if it is not empty
+    pub fn alt(&self) -> Option<&str> {
+        match self {
+            Attachment::Image(image) => image.alt(),
+            Attachment::Document(document) => non_empty(&document.name),
+            Attachment::Other(_) => None,
+        }
+    }
+}
+
+impl From<ImageObject> for Attachment {
+    fn from(image: ImageObject) -> Self {
+        Attachment::Image(image)
+    }
+}
+
+impl From<Document> for Attachment {
+    fn from(document: Document) -> Self {
+        Attachment::Document(document)
+    }
+}
+
+/// Returns the first link with an image media type, or the first url otherwise.
+pub fn best_url(urls: &[UrlOrLink]) -> Option<&Url> {
+    urls.iter()
+        .find(|u| matches!(u.media_type(), Some(m) if m.starts_with("image/")))
+        .or_else(|| urls.first())
+        .map(UrlOrLink::href)
+}
+
+fn non_empty(name: &Option<String>) -> Option<&str> {
+    name.as_deref().filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct Media {
+        #[serde(deserialize_with = "deserialize_one_or_many", default)]
+        icon: Vec<ImageObject>,
+        #[serde(deserialize_with = "deserialize_one_or_many", default)]
+        image: Vec<ImageObject>,
+        #[serde(deserialize_with = "deserialize_one_or_many", default)]
+        attachment: Vec<Attachment>,
+    }
+
+    fn parse(fixture: &str) -> Media {
+        serde_json::from_str(fixture).unwrap()
+    }
+
+    #[test]
+    fn test_parse_mastodon() {
+        let person = parse(include_str!("../../assets/mastodon/person.json"));
+        let icon = ImageObject::largest(&person.icon).unwrap();
+        assert_eq!(icon.media_type.as_deref(), Some("image/png"));
+        assert!(icon.url().unwrap().as_str().contains("/avatars/"));
+        assert!(person.image[0]
+            .url()
+            .unwrap()
+            .as_str()
+            .contains("/headers/"));
+
+        let attachment = json!({
+            "type": "Document",
+            "mediaType": "image/jpeg",
+            "url": "https://files.mastodon.social/media_attachments/files/109/729/530/original/4f1d.jpg",
+            "name": "Screenshot of the release notes",
+            "blurhash": "UFRfIfxu~qxu?bofRjWB%Mt7RjofWBj[ayWB",
+            "focalPoint": [0.0, 0.0],
+            "width": 1200,
+            "height": 800
+        });
+        let attachment: Attachment = serde_json::from_value(attachment).unwrap();
+        let Attachment::Document(document) = &attachment else {
+            panic!("expected document, got {:?}", attachment);
+        };
+        assert_eq!(document.width, Some(1200));
+        assert_eq!(attachment.alt(), Some("Screenshot of the release notes"));
+        assert!(attachment.url().unwrap().as_str().ends_with("4f1d.jpg"));
+    }
+
+    #[test]
+    fn test_parse_pleroma() {
+        let person = parse(include_str!("../../assets/pleroma/person.json"));
+        assert_eq!(
+            person.icon[0].url().unwrap().as_str(),
+            "https://pleroma.example/media/a0f3c1e2b4.jpg"
+        );
+        assert_eq!(person.icon[0].alt(), None);
+        // profile fields are kept unchanged
+        assert!(matches!(person.attachment[0], Attachment::Other(_)));
+        assert_eq!(person.attachment[0].url(), None);
+
+        let note = parse(include_str!("../../assets/pleroma/note.json"));
+        let attachment = &note.attachment[0];
+        assert!(matches!(attachment, Attachment::Document(_)));
+        assert_eq!(
+            attachment.url().unwrap().as_str(),
+            "https://pleroma.example/media/2b94c7a3f2.png"
+        );
+        assert_eq!(attachment.alt(), Some("A cat sleeping on a keyboard"));
+    }
+
+    #[test]
+    fn test_parse_peertube() {
+        let video = parse(include_str!("../../assets/peertube/video.json"));
+        assert_eq!(video.icon.len(), 2);
+        let preview = ImageObject::largest(&video.icon).unwrap();
+        assert_eq!(preview.width, Some(850));
+        assert!(preview.url().unwrap().as_str().contains("/previews/"));
+    }
+
+    #[test]
+    fn test_best_url() {
+        let image: ImageObject = serde_json::from_value(json!({
+            "type": "Image",
+            "url": [
+                { "type": "Link", "href": "https://example.com/page.html", "mediaType": "text/html" },
+                { "type": "Link", "href": "https://example.com/image.webp", "mediaType": "image/webp" }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            image.url().unwrap().as_str(),
+            "https://example.com/image.webp"
+        );
+        assert_eq!(ImageObject::largest(&[]), None);
+    }
+
+    #[test]
+    fn test_serialize_image() {
+        let mut image = ImageObject::new(Url::parse("https://example.com/icon.png").unwrap());
+        image.name = Some("Avatar".to_string());
+        let json = serde_json::to_value(&image).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "type": "Image",
+                "url": "https://example.com/icon.png",
+                "name": "Avatar"
+            })
+        );
+        assert_eq!(serde_json::from_value::<ImageObject>(json).unwrap(), image);
+    }
+}
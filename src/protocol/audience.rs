@@ -0,0 +1,218 @@
+//! Helpers for addressing of activities and objects, and for checking if they are public
+//!
+//! ```
+//! # use activitypub_federation::protocol::audience::Audience;
+//! # use url::Url;
+//! #[derive(serde::Deserialize)]
+//! struct Note {
+//!     #[serde(flatten)]
+//!     audience: Audience,
+//!     content: String,
+//! }
+//!
+//! let note: Note = serde_json::from_str(r#"{"to": "as:Public", "content": "Hello world"}"#)?;
+//! assert!(note.audience.is_public());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::protocol::helpers::deserialize_one_or_many;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use url::Url;
+
+/// Canonical form of the special collection which contains all users, used to address public
+/// activities and objects.
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Returns the url of the [PUBLIC] collection.
+///
+/// This is the same function as `activitystreams_kinds::public`, re-exported here so that it can
+/// be imported together with the other addressing helpers.
+pub use activitystreams_kinds::public;
+
+/// Returns true if `id` refers to the [PUBLIC] collection, either in canonical form or one of the
+/// compact forms `as:Public` and `Public` which are allowed by JSON-LD.
+///
+/// The fragment is compared case-insensitively, as some implementations send `#public`.
+pub fn is_public_id(id: &str) -> bool {
+    let Some((prefix, name)) = id.rsplit_once(['#', ':']) else {
+        return id.eq_ignore_ascii_case("Public");
+    };
+    if !name.eq_ignore_ascii_case("Public") {
+        return false;
+    }
+    prefix.eq_ignore_ascii_case("as")
+        || prefix.eq_ignore_ascii_case("https://www.w3.org/ns/activitystreams")
+        || prefix.eq_ignore_ascii_case("http://www.w3.org/ns/activitystreams")
+}
+
+/// Returns true if the [PUBLIC] collection is contained in `to` or `cc`.
+///
+/// Recipients which were deserialized with [deserialize_audience] are already normalized, but
+/// other forms are recognized as well.
+pub fn is_public(to: &[Url], cc: &[Url]) -> bool {
+    to.iter().chain(cc).any(|id| is_public_id(id.as_str()))
+}
+
+/// Deserialize a single recipient or list of recipients, replacing the compact forms of the
+/// [PUBLIC] collection with its canonical url.
+///
+/// This is necessary because `Public` can't be parsed as [Url].
+///
+/// ```
+/// # use activitypub_federation::protocol::audience::{deserialize_audience, public};
+/// # use url::Url;
+/// #[derive(serde::Deserialize)]
+/// struct Note {
+///     #[serde(deserialize_with = "deserialize_audience")]
+///     to: Vec<Url>,
+/// }
+///
+/// let note: Note = serde_json::from_str(r#"{"to": "Public"}"#)?;
+/// assert_eq!(note.to, vec![public()]);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn deserialize_audience<'de, D>(deserializer: D) -> Result<Vec<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let ids: Vec<String> = deserialize_one_or_many(deserializer)?;
+    ids.into_iter()
+        .map(|id| {
+            if is_public_id(&id) {
+                Ok(public())
+            } else {
+                Url::parse(&id).map_err(D::Error::custom)
+            }
+        })
+        .collect()
+}
+
+/// Recipients of an activity or object.
+///
+/// Use it with `#[serde(flatten)]` in protocol structs. All fields are optional when
+/// deserializing and accept a single value or a list. `bto` and `bcc` are never serialized, as
+/// the spec requires removing them before delivery.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Audience {
+    /// Primary recipients
+    #[serde(
+        default,
+        deserialize_with = "deserialize_audience",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub to: Vec<Url>,
+    /// Secondary recipients
+    #[serde(
+        default,
+        deserialize_with = "deserialize_audience",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub cc: Vec<Url>,
+    /// Hidden primary recipients
+    #[serde(default, deserialize_with = "deserialize_audience", skip_serializing)]
+    pub bto: Vec<Url>,
+    /// Hidden secondary recipients
+    #[serde(default, deserialize_with = "deserialize_audience", skip_serializing)]
+    pub bcc: Vec<Url>,
+}
+
+impl Audience {
+    /// Returns true if [PUBLIC] is contained in `to` or `cc`
+    pub fn is_public(&self) -> bool {
+        is_public(&self.to, &self.cc)
+    }
+
+    /// Returns true if this is not public, but addressed to the followers collection with the
+    /// given url.
+    pub fn followers_only(&self, followers: &Url) -> bool {
+        !self.is_public() && self.recipients().any(|r| r == followers)
+    }
+
+    /// Returns true if `id` is one of the recipients, including hidden ones
+    pub fn contains(&self, id: &Url) -> bool {
+        self.recipients().any(|r| r == id)
+    }
+
+    /// Iterates over all recipients, including hidden ones
+    pub fn recipients(&self) -> impl Iterator<Item = &Url> {
+        self.to
+            .iter()
+            .chain(&self.cc)
+            .chain(&self.bto)
+            .chain(&self.bcc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_public_aliases() {
+        for id in [
+            "https://www.w3.org/ns/activitystreams#Public",
+            "as:Public",
+            "Public",
+            "https://www.w3.org/ns/activitystreams#public",
+            "as:public",
+            "HTTPS://WWW.W3.ORG/ns/activitystreams#Public",
+            "http://www.w3.org/ns/activitystreams#Public",
+        ] {
+            assert!(is_public_id(id), "{id}");
+            let audience: Audience = serde_json::from_value(json!({ "to": id })).unwrap();
+            assert_eq!(audience.to, vec![public()], "{id}");
+        }
+
+        for id in [
+            "https://example.com/u/alice",
+            "https://example.com/ns/activitystreams#Public",
+            "as:Publicity",
+            "https://www.w3.org/ns/activitystreams",
+        ] {
+            assert!(!is_public_id(id), "{id}");
+        }
+    }
+
+    #[test]
+    fn test_is_public() {
+        let alice = Url::parse("https://example.com/u/alice").unwrap();
+        assert!(is_public(&[], &[alice.clone(), public()]));
+        assert!(is_public(&[Url::parse("as:Public").unwrap()], &[]));
+        assert!(!is_public(&[alice], &[]));
+    }
+
+    #[test]
+    fn test_audience() {
+        let followers = Url::parse("https://example.com/u/alice/followers").unwrap();
+        let audience: Audience = serde_json::from_value(json!({
+            "to": [followers],
+            "bcc": "https://example.com/u/bob",
+        }))
+        .unwrap();
+        assert!(!audience.is_public());
+        assert!(audience.followers_only(&followers));
+        assert!(audience.contains(&Url::parse("https://example.com/u/bob").unwrap()));
+        // hidden recipients are not serialized
+        assert_eq!(
+            serde_json::to_value(&audience).unwrap(),
+            json!({ "to": ["https://example.com/u/alice/followers"] })
+        );
+
+        let public: Audience = serde_json::from_value(json!({
+            "to": "as:Public",
+            "cc": [followers],
+        }))
+        .unwrap();
+        assert!(public.is_public());
+        assert!(!public.followers_only(&followers));
+        // always serialized in canonical form
+        assert_eq!(
+            serde_json::to_value(&public).unwrap(),
+            json!({
+                "to": [PUBLIC],
+                "cc": ["https://example.com/u/alice/followers"]
+            })
+        );
+    }
+}
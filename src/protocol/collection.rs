@@ -0,0 +1,624 @@
+//! Structs and helpers for serving and receiving paged collections, such as followers or outbox
+
+use crate::protocol::{
+    helpers::deserialize_skip_error,
+    kind::{CollectionPageType, CollectionType, OrderedCollectionPageType, OrderedCollectionType},
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use url::Url;
+
+/// Top-level document of a paged collection.
+///
+/// It only contains the number of items and a link to the first page. The items themselves are
+/// served separately in [OrderedCollectionPage].
+///
+/// When receiving, remote servers may also embed the first page or include the items directly,
+/// and `totalItems` may be missing, in which case `total_items` is `None`. Items which don't match
+/// `T` are skipped, use [CollectionItem] to accept both urls and embedded objects.
+///
+/// Unordered collections of type `Collection` with `items`, as served by Lemmy for followers, are
+/// also accepted and treated like an `OrderedCollection`. They are always serialized as
+/// `OrderedCollection`.
+///
+/// ```
+/// # use activitypub_federation::protocol::collection::{OrderedCollection, OrderedCollectionPage};
+/// # use url::Url;
+/// let id = Url::parse("https://example.com/u/alice/followers")?;
+/// let followers: Vec<Url> = vec![Url::parse("https://example.net/u/bob")?];
+/// let collection = OrderedCollection::<Url>::new(id.clone(), followers.len());
+/// assert_eq!(collection.first.unwrap().id().as_str(), "https://example.com/u/alice/followers?page=1");
+/// let page = OrderedCollectionPage::new(id, followers, 1, 20);
+/// assert_eq!(page.next, None);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Use [CollectionPageBuilder] if the items are read from storage one page at a time.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", bound(deserialize = "T: DeserializeOwned"))]
+pub struct OrderedCollection<T> {
+    /// Always `OrderedCollection`, also if a `Collection` was received
+    #[serde(
+        rename = "type",
+        deserialize_with = "deserialize_ordered_kind::<_, OrderedCollectionType, CollectionType>"
+    )]
+    pub kind: OrderedCollectionType,
+    /// Id of the collection
+    pub id: Url,
+    /// Number of items in the collection, `None` if the remote server doesn't publish it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<usize>,
+    /// The first page, `None` for empty collections
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<PageRef<T>>,
+    /// Link to the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<Url>,
+    /// Items which are included directly in the collection instead of a separate page. Received
+    /// from `items` for unordered collections.
+    #[serde(
+        alias = "items",
+        default = "Vec::new",
+        deserialize_with = "deserialize_skip_error",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub ordered_items: Vec<T>,
+}
+
+impl<T> OrderedCollection<T> {
+    /// Create a collection with `total_items` and a link to the first page, if there are any items.
+    pub fn new(id: Url, total_items: usize) -> Self {
+        CollectionPageBuilder::new(id, total_items, 1).collection()
+    }
+}
+
+/// Link to a page of an [OrderedCollection], or the embedded page itself.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged, bound(deserialize = "T: DeserializeOwned"))]
+pub enum PageRef<T> {
+    /// Url of the page, which needs to be fetched separately
+    Url(Url),
+    /// Embedded page, as sent by Mastodon for the first page of some collections
+    Page(Box<OrderedCollectionPage<T>>),
+}
+
+impl<T> PageRef<T> {
+    /// Id of the page
+    pub fn id(&self) -> &Url {
+        match self {
+            PageRef::Url(url) => url,
+            PageRef::Page(page) => &page.id,
+        }
+    }
+}
+
+/// Single page of an [OrderedCollection].
+///
+/// Like [OrderedCollection], a `CollectionPage` with `items` is also accepted.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase", bound(deserialize = "T: DeserializeOwned"))]
+pub struct OrderedCollectionPage<T> {
+    /// Always `OrderedCollectionPage`, also if a `CollectionPage` was received
+    #[serde(
+        rename = "type",
+        deserialize_with = "deserialize_ordered_kind::<_, OrderedCollectionPageType, CollectionPageType>"
+    )]
+    pub kind: OrderedCollectionPageType,
+    /// Id of this page
+    pub id: Url,
+    /// Id of the collection which this page belongs to
+    pub part_of: Url,
+    /// Link to the next page, `None` for the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<Url>,
+    /// Link to the previous page, `None` for the first page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<Url>,
+    /// Items on this page, received from `items` for unordered pages
+    #[serde(
+        alias = "items",
+        default = "Vec::new",
+        deserialize_with = "deserialize_skip_error"
+    )]
+    pub ordered_items: Vec<T>,
+}
+
+/// Accepts the unordered type `U` in place of the ordered type `K`
+fn deserialize_ordered_kind<'de, D, K, U>(deserializer: D) -> Result<K, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Default,
+    U: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Kind<K, U> {
+        Ordered(K),
+        Unordered(U),
+    }
+    Ok(match Kind::<K, U>::deserialize(deserializer)? {
+        Kind::Ordered(kind) => kind,
+        Kind::Unordered(_) => K::default(),
+    })
+}
+
+impl<T> OrderedCollectionPage<T> {
+    /// Create the page with number `page` out of all `items` in the collection. Page numbers start
+    /// at 1, each page contains `page_size` items. A `page_size` of 0 is treated as 1.
+    ///
+    /// Pages behind the last item are empty.
+    pub fn new(collection_id: Url, items: Vec<T>, page: usize, page_size: usize) -> Self {
+        let page_size = page_size.max(1);
+        let builder = CollectionPageBuilder::new(collection_id, items.len(), page_size);
+        let start = page.saturating_sub(1).saturating_mul(page_size);
+        let items = items.into_iter().skip(start).take(page_size).collect();
+        builder.page(page, items)
+    }
+}
+
+/// Builds a paged [OrderedCollection] from items which are read from storage one page at a time,
+/// for example an outbox.
+///
+/// Page urls are formed by adding the query parameter `page` to the collection id, eg
+/// `https://example.com/outbox?page=2`. Page numbers start at 1.
+///
+/// ```
+/// # use activitypub_federation::protocol::collection::CollectionPageBuilder;
+/// # use url::Url;
+/// let id = Url::parse("https://example.com/u/alice/outbox")?;
+/// // total number of activities in the outbox, and page size
+/// let builder = CollectionPageBuilder::new(id, 45, 20).page_param("p");
+/// let collection = builder.collection::<serde_json::Value>();
+/// assert_eq!(collection.first.unwrap().id().as_str(), "https://example.com/u/alice/outbox?p=1");
+///
+/// // second page, with activities read from the database
+/// let activities: Vec<serde_json::Value> = vec![];
+/// let page = builder.page(2, activities);
+/// assert_eq!(page.prev.unwrap().as_str(), "https://example.com/u/alice/outbox?p=1");
+/// assert_eq!(page.next.unwrap().as_str(), "https://example.com/u/alice/outbox?p=3");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct CollectionPageBuilder {
+    collection_id: Url,
+    total_items: usize,
+    page_size: usize,
+    page_param: String,
+}
+
+impl CollectionPageBuilder {
+    /// Create a new builder for the collection with `collection_id`, which has `total_items`
+    /// items in total and `page_size` items per page. A `page_size` of 0 is treated as 1, so that
+    /// the pages always end.
+    pub fn new(collection_id: Url, total_items: usize, page_size: usize) -> Self {
+        CollectionPageBuilder {
+            collection_id,
+            total_items,
+            page_size: page_size.max(1),
+            page_param: "page".to_string(),
+        }
+    }
+
+    /// Name of the query parameter for page numbers, defaults to `page`
+    pub fn page_param(mut self, page_param: impl Into<String>) -> Self {
+        self.page_param = page_param.into();
+        self
+    }
+
+    /// Returns the top-level collection document
+    pub fn collection<T>(&self) -> OrderedCollection<T> {
+        OrderedCollection {
+            kind: Default::default(),
+            id: self.collection_id.clone(),
+            total_items: Some(self.total_items),
+            first: (self.total_items > 0).then(|| PageRef::Url(self.page_url(1))),
+            last: None,
+            ordered_items: vec![],
+        }
+    }
+
+    /// Returns the page with number `page`, containing `items`. These should be at most
+    /// `page_size` items, already converted to their Activitypub representation.
+    pub fn page<T>(&self, page: usize, items: Vec<T>) -> OrderedCollectionPage<T> {
+        let page = page.max(1);
+        let next = (page.saturating_mul(self.page_size) < self.total_items)
+            .then(|| self.page_url(page + 1));
+        let prev = (page > 1).then(|| self.page_url(page - 1));
+        OrderedCollectionPage {
+            kind: Default::default(),
+            id: self.page_url(page),
+            part_of: self.collection_id.clone(),
+            next,
+            prev,
+            ordered_items: items,
+        }
+    }
+
+    /// Returns the url of the page with number `page`
+    pub fn page_url(&self, page: usize) -> Url {
+        let mut url = self.collection_id.clone();
+        url.query_pairs_mut()
+            .append_pair(&self.page_param, &page.to_string());
+        url
+    }
+}
+
+/// Item of a remote collection, which may be either a url or an embedded object.
+///
+/// ```
+/// # use activitypub_federation::protocol::collection::{CollectionItem, OrderedCollectionPage};
+/// let page: OrderedCollectionPage<CollectionItem> = serde_json::from_str(
+/// r#"{
+///     "type": "OrderedCollectionPage",
+///     "id": "https://example.com/outbox?page=1",
+///     "partOf": "https://example.com/outbox",
+///     "orderedItems": [
+///         "https://example.com/activities/1",
+///         {"type": "Create", "id": "https://example.com/activities/2"}
+///     ]
+/// }"#)?;
+/// for item in &page.ordered_items {
+///     assert!(item.id().unwrap().as_str().starts_with("https://example.com/activities/"));
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum CollectionItem {
+    /// Id of the item, which needs to be fetched separately
+    Url(Url),
+    /// Embedded item
+    Object(Value),
+}
+
+impl CollectionItem {
+    /// Id of the item, if it is a url or an embedded object with valid `id`
+    pub fn id(&self) -> Option<Url> {
+        match self {
+            CollectionItem::Url(url) => Some(url.clone()),
+            CollectionItem::Object(value) => value
+                .get("id")
+                .and_then(Value::as_str)
+                .and_then(|id| Url::parse(id).ok()),
+        }
+    }
+
+    /// Parses the embedded object, returns `None` for urls
+    pub fn object<T: DeserializeOwned>(&self) -> Option<Result<T, serde_json::Error>> {
+        match self {
+            CollectionItem::Url(_) => None,
+            CollectionItem::Object(value) => Some(T::deserialize(value)),
+        }
+    }
+}
+
+impl From<Value> for CollectionItem {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::String(url) => match Url::parse(&url) {
+                Ok(url) => CollectionItem::Url(url),
+                Err(_) => CollectionItem::Object(Value::String(url)),
+            },
+            value => CollectionItem::Object(value),
+        }
+    }
+}
+
+/// Whether a followers collection enumerates the individual followers, see
+/// [followers_collection].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FollowersMode {
+    /// Link to the first page of followers, with `page_size` followers per page
+    Full {
+        /// Number of followers on each page
+        page_size: usize,
+    },
+    /// Only publish the number of followers, but not who they are. This is used by many servers
+    /// for privacy reasons.
+    CountOnly,
+}
+
+/// Builds the top-level followers collection of an actor.
+///
+/// In [FollowersMode::Full] the pages need to be served as well, using [CollectionPageBuilder]
+/// with the same `page_size`. In [FollowersMode::CountOnly] there are no pages.
+///
+/// ```
+/// # use activitypub_federation::protocol::collection::{followers_collection, FollowersMode};
+/// # use url::Url;
+/// let id = Url::parse("https://example.com/u/alice/followers")?;
+/// let collection = followers_collection::<Url>(id, 42, FollowersMode::CountOnly);
+/// assert_eq!(collection.total_items, Some(42));
+/// assert_eq!(collection.first, None);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn followers_collection<T>(
+    followers_url: Url,
+    follower_count: usize,
+    mode: FollowersMode,
+) -> OrderedCollection<T> {
+    match mode {
+        FollowersMode::Full { page_size } => {
+            CollectionPageBuilder::new(followers_url, follower_count, page_size).collection()
+        }
+        FollowersMode::CountOnly => OrderedCollection {
+            kind: Default::default(),
+            id: followers_url,
+            total_items: Some(follower_count),
+            first: None,
+            last: None,
+            ordered_items: vec![],
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection_id() -> Url {
+        Url::parse("https://example.com/u/alice/followers").unwrap()
+    }
+
+    #[test]
+    fn test_empty_collection() {
+        let collection = OrderedCollection::<Url>::new(collection_id(), 0);
+        assert_eq!(collection.first, None);
+        assert_eq!(
+            serde_json::to_value(&collection).unwrap(),
+            serde_json::json!({
+                "type": "OrderedCollection",
+                "id": "https://example.com/u/alice/followers",
+                "totalItems": 0
+            })
+        );
+
+        let page = OrderedCollectionPage::<Url>::new(collection_id(), vec![], 1, 10);
+        assert!(page.ordered_items.is_empty());
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn test_multi_page_collection() {
+        let items: Vec<usize> = (0..25).collect();
+        let collection = OrderedCollection::<usize>::new(collection_id(), items.len());
+        assert_eq!(collection.total_items, Some(25));
+        assert_eq!(
+            collection.first.unwrap().id().as_str(),
+            "https://example.com/u/alice/followers?page=1"
+        );
+
+        let first = OrderedCollectionPage::new(collection_id(), items.clone(), 1, 10);
+        assert_eq!(first.ordered_items, (0..10).collect::<Vec<_>>());
+        assert_eq!(first.part_of, collection_id());
+        assert_eq!(
+            first.next.unwrap().as_str(),
+            "https://example.com/u/alice/followers?page=2"
+        );
+
+        let last = OrderedCollectionPage::new(collection_id(), items.clone(), 3, 10);
+        assert_eq!(
+            last.id.as_str(),
+            "https://example.com/u/alice/followers?page=3"
+        );
+        assert_eq!(last.ordered_items, (20..25).collect::<Vec<_>>());
+        assert_eq!(last.next, None);
+
+        let behind_last = OrderedCollectionPage::new(collection_id(), items, 4, 10);
+        assert!(behind_last.ordered_items.is_empty());
+        assert_eq!(behind_last.next, None);
+    }
+
+    #[test]
+    fn test_page_builder_boundary() {
+        // exactly one full page
+        let builder = CollectionPageBuilder::new(collection_id(), 10, 10);
+        let page = builder.page(1, (0..10).collect::<Vec<_>>());
+        assert_eq!(page.next, None);
+        assert_eq!(page.prev, None);
+
+        // one item more than a full page
+        let builder = CollectionPageBuilder::new(collection_id(), 11, 10);
+        let first = builder.page(1, (0..10).collect::<Vec<_>>());
+        assert_eq!(first.next, Some(builder.page_url(2)));
+        let second = builder.page(2, vec![10]);
+        assert_eq!(second.next, None);
+        assert_eq!(second.prev, Some(builder.page_url(1)));
+    }
+
+    #[test]
+    fn test_page_param() {
+        let builder = CollectionPageBuilder::new(collection_id(), 30, 10).page_param("p");
+        let page = builder.page::<Url>(2, vec![]);
+        assert_eq!(
+            page.id.as_str(),
+            "https://example.com/u/alice/followers?p=2"
+        );
+        assert_eq!(
+            page.next.unwrap().as_str(),
+            "https://example.com/u/alice/followers?p=3"
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let builder = CollectionPageBuilder::new(collection_id(), 3, 2);
+        let collection = builder.collection::<serde_json::Value>();
+        let json = serde_json::to_value(&collection).unwrap();
+        let parsed: OrderedCollection<serde_json::Value> =
+            serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(parsed).unwrap(), json);
+
+        let items =
+            vec![serde_json::json!({"type": "Create", "id": "https://example.com/activities/3"})];
+        let page = builder.page(2, items);
+        let json = serde_json::to_value(&page).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "OrderedCollectionPage",
+                "id": "https://example.com/u/alice/followers?page=2",
+                "partOf": "https://example.com/u/alice/followers",
+                "prev": "https://example.com/u/alice/followers?page=1",
+                "orderedItems": [{"type": "Create", "id": "https://example.com/activities/3"}]
+            })
+        );
+        let parsed: OrderedCollectionPage<serde_json::Value> =
+            serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_followers_collection() {
+        let full =
+            followers_collection::<Url>(collection_id(), 3, FollowersMode::Full { page_size: 2 });
+        assert_eq!(full.total_items, Some(3));
+        assert_eq!(
+            full.first.unwrap().id().as_str(),
+            "https://example.com/u/alice/followers?page=1"
+        );
+
+        let count_only = followers_collection::<Url>(collection_id(), 3, FollowersMode::CountOnly);
+        assert_eq!(
+            serde_json::to_value(&count_only).unwrap(),
+            serde_json::json!({
+                "type": "OrderedCollection",
+                "id": "https://example.com/u/alice/followers",
+                "totalItems": 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_mastodon_count_only_followers() {
+        let json = include_str!("../../assets/mastodon/followers_count_only.json");
+        let collection: OrderedCollection<Url> = serde_json::from_str(json).unwrap();
+        assert_eq!(collection.total_items, Some(344281));
+        assert_eq!(collection.first, None);
+        assert!(collection.ordered_items.is_empty());
+    }
+
+    #[test]
+    fn test_parse_mastodon_embedded_first_page() {
+        let json = include_str!("../../assets/mastodon/outbox.json");
+        let collection: OrderedCollection<CollectionItem> = serde_json::from_str(json).unwrap();
+        assert_eq!(collection.total_items, Some(3));
+        let Some(PageRef::Page(first)) = &collection.first else {
+            panic!("expected embedded page, got {:?}", collection.first);
+        };
+        assert_eq!(
+            collection.first.as_ref().unwrap().id().as_str(),
+            "https://mastodon.social/users/LemmyDev/outbox?page=true"
+        );
+        assert!(first.next.is_some());
+        let ids: Vec<_> = first
+            .ordered_items
+            .iter()
+            .map(|i| i.id().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                "https://mastodon.social/users/LemmyDev/statuses/109770616953364331/activity",
+                "https://mastodon.social/users/LemmyDev/statuses/109750400282296426/activity"
+            ]
+        );
+        assert!(matches!(first.ordered_items[1], CollectionItem::Object(_)));
+
+        let json = include_str!("../../assets/mastodon/outbox_page2.json");
+        let page: OrderedCollectionPage<CollectionItem> = serde_json::from_str(json).unwrap();
+        assert_eq!(page.next, None);
+        assert_eq!(page.ordered_items.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_lemmy_outbox() {
+        let json = include_str!("../../assets/lemmy/outbox.json");
+        let collection: OrderedCollection<CollectionItem> = serde_json::from_str(json).unwrap();
+        assert_eq!(collection.total_items, Some(2));
+        assert_eq!(collection.first, None);
+        let kinds: Vec<_> = collection
+            .ordered_items
+            .iter()
+            .map(|item| item.object::<Value>().unwrap().unwrap()["type"].clone())
+            .collect();
+        assert_eq!(kinds, vec!["Announce", "Announce"]);
+    }
+
+    #[test]
+    fn test_parse_unordered_collection() {
+        let json = include_str!("../../assets/lemmy/followers.json");
+        let collection: OrderedCollection<Url> = serde_json::from_str(json).unwrap();
+        assert_eq!(collection.total_items, Some(52));
+        assert!(collection.ordered_items.is_empty());
+        let json = serde_json::to_value(&collection).unwrap();
+        assert_eq!(json["type"], "OrderedCollection");
+
+        let page: OrderedCollectionPage<Url> = serde_json::from_value(serde_json::json!({
+            "type": "CollectionPage",
+            "id": "https://example.com/followers?page=1",
+            "partOf": "https://example.com/followers",
+            "items": ["https://example.com/u/alice"]
+        }))
+        .unwrap();
+        assert_eq!(page.ordered_items.len(), 1);
+
+        let invalid = serde_json::from_value::<OrderedCollection<Url>>(serde_json::json!({
+            "type": "Person",
+            "id": "https://example.com/followers",
+        }));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_parse_tolerant() {
+        let page: OrderedCollectionPage<Url> = serde_json::from_value(serde_json::json!({
+            "type": "OrderedCollectionPage",
+            "id": "https://example.com/outbox?page=1",
+            "partOf": "https://example.com/outbox",
+            "orderedItems": ["https://example.com/1", {"invalid": true}, "https://example.com/2"]
+        }))
+        .unwrap();
+        assert_eq!(page.ordered_items.len(), 2);
+
+        // totalItems and orderedItems missing, single item instead of array
+        let collection: OrderedCollection<Url> = serde_json::from_value(serde_json::json!({
+            "type": "OrderedCollection",
+            "id": "https://example.com/outbox",
+            "orderedItems": "https://example.com/1",
+            "last": "https://example.com/outbox?page=3"
+        }))
+        .unwrap();
+        assert_eq!(collection.total_items, None);
+        let json = serde_json::to_value(&collection).unwrap();
+        assert!(json.get("totalItems").is_none(), "{json}");
+        assert_eq!(collection.ordered_items.len(), 1);
+        assert_eq!(
+            collection.last.unwrap().as_str(),
+            "https://example.com/outbox?page=3"
+        );
+    }
+
+    #[test]
+    fn test_builder_zero_page_size() {
+        let builder = CollectionPageBuilder::new(collection_id(), 2, 0);
+        assert!(builder.collection::<usize>().first.is_some());
+        let first = builder.page(1, vec![0]);
+        assert_eq!(
+            first.next.unwrap().as_str(),
+            "https://example.com/u/alice/followers?page=2"
+        );
+        assert_eq!(builder.page(2, vec![1]).next, None);
+    }
+
+    #[test]
+    fn test_page_zero_page_size() {
+        let items = vec![1, 2];
+        let first = OrderedCollectionPage::new(collection_id(), items.clone(), 1, 0);
+        assert_eq!(first.ordered_items, vec![1]);
+        assert!(first.next.is_some());
+        let second = OrderedCollectionPage::new(collection_id(), items, 2, 0);
+        assert_eq!(second.ordered_items, vec![2]);
+        assert_eq!(second.next, None);
+    }
+}
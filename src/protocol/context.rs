@@ -15,17 +15,106 @@
 //! };
 //! let note_with_context = WithContext::new_default(note);
 //! let serialized = serde_json::to_string(&note_with_context)?;
-//! assert_eq!(serialized, r#"{"@context":["https://www.w3.org/ns/activitystreams"],"content":"Hello world"}"#);
+//! assert_eq!(serialized, r#"{"@context":["https://www.w3.org/ns/activitystreams","https://w3id.org/security/v1"],"content":"Hello world"}"#);
+//! Ok::<(), serde_json::error::Error>(())
+//! ```
+//!
+//! If extensions such as `sensitive` or `manuallyApprovesFollowers` are used, their term
+//! definitions need to be added with [ContextBuilder]:
+//!
+//! ```
+//! # use activitypub_federation::protocol::context::{ContextBuilder, WithContext};
+//! # use serde_json::json;
+//! let context = ContextBuilder::new()
+//!     .term("sensitive", "as:sensitive")
+//!     .term("toot", "http://joinmastodon.org/ns#")
+//!     .build();
+//! let note_with_context = WithContext::new(json!({"sensitive": true}), context);
+//! let serialized = serde_json::to_string(&note_with_context)?;
+//! assert_eq!(serialized, r#"{"@context":["https://www.w3.org/ns/activitystreams","https://w3id.org/security/v1",{"sensitive":"as:sensitive","toot":"http://joinmastodon.org/ns#"}],"sensitive":true}"#);
 //! Ok::<(), serde_json::error::Error>(())
 //! ```
 
 use crate::{config::Data, protocol::helpers::deserialize_one_or_many, traits::ActivityHandler};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use url::Url;
 
-/// Default context used in Activitypub
-const DEFAULT_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+/// Context of the ActivityStreams vocabulary, which is used by all Activitypub data
+pub const ACTIVITYSTREAMS_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+
+/// Context which defines `publicKey` and other terms used for HTTP signatures
+pub const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+
+/// Returns the default context, consisting of [ACTIVITYSTREAMS_CONTEXT] and [SECURITY_CONTEXT].
+///
+/// Use [ContextBuilder] to add definitions for extension terms.
+pub fn context() -> Vec<Value> {
+    ContextBuilder::new().build()
+}
+
+/// Builds a `@context` value which contains the default context and additional contexts or term
+/// definitions for extensions.
+///
+/// The output follows the format used by Mastodon: first the context urls, then a single object
+/// containing all term definitions. Contexts or terms which were already added are ignored.
+#[derive(Clone, Debug)]
+pub struct ContextBuilder {
+    contexts: Vec<String>,
+    terms: Map<String, Value>,
+}
+
+impl ContextBuilder {
+    /// Create a new builder which contains the [default context](context).
+    pub fn new() -> Self {
+        ContextBuilder {
+            contexts: vec![
+                ACTIVITYSTREAMS_CONTEXT.to_string(),
+                SECURITY_CONTEXT.to_string(),
+            ],
+            terms: Map::new(),
+        }
+    }
+
+    /// Add another context by its url
+    pub fn context(mut self, url: impl Into<String>) -> Self {
+        let url = url.into();
+        if !self.contexts.contains(&url) {
+            self.contexts.push(url);
+        }
+        self
+    }
+
+    /// Add the definition of a single term, eg `.term("sensitive", "as:sensitive")`
+    pub fn term(mut self, name: impl Into<String>, definition: impl Into<Value>) -> Self {
+        self.terms
+            .entry(name.into())
+            .or_insert_with(|| definition.into());
+        self
+    }
+
+    /// Add the definitions of all terms in `terms`
+    pub fn terms(self, terms: Map<String, Value>) -> Self {
+        terms.into_iter().fold(self, |builder, (name, definition)| {
+            builder.term(name, definition)
+        })
+    }
+
+    /// Returns the finished context, for use with [WithContext::new]
+    pub fn build(self) -> Vec<Value> {
+        let mut context: Vec<Value> = self.contexts.into_iter().map(Value::String).collect();
+        if !self.terms.is_empty() {
+            context.push(Value::Object(self.terms));
+        }
+        context
+    }
+}
+
+impl Default for ContextBuilder {
+    fn default() -> Self {
+        ContextBuilder::new()
+    }
+}
 
 /// Wrapper for federated structs which handles `@context` field.
 #[derive(Serialize, Deserialize, Debug)]
@@ -38,13 +127,13 @@ pub struct WithContext<T> {
 }
 
 impl<T> WithContext<T> {
-    /// Create a new wrapper with the default Activitypub context.
+    /// Create a new wrapper with the [default context](context).
     pub fn new_default(inner: T) -> WithContext<T> {
-        let context = vec![Value::String(DEFAULT_CONTEXT.to_string())];
-        WithContext::new(inner, context)
+        WithContext::new(inner, context())
     }
 
-    /// Create new wrapper with custom context. Use this in case you are implementing extensions.
+    /// Create new wrapper with custom context. Use this in case you are implementing extensions,
+    /// the context can be created with [ContextBuilder].
     pub fn new(inner: T, context: Vec<Value>) -> WithContext<T> {
         WithContext { context, inner }
     }
@@ -91,3 +180,78 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_default_context() {
+        assert_eq!(
+            Value::Array(context()),
+            json!([ACTIVITYSTREAMS_CONTEXT, SECURITY_CONTEXT])
+        );
+    }
+
+    #[test]
+    fn test_builder_ignores_duplicates() {
+        let context = ContextBuilder::new()
+            .context(SECURITY_CONTEXT)
+            .term("sensitive", "as:sensitive")
+            .term("sensitive", "toot:sensitive")
+            .build();
+        assert_eq!(
+            Value::Array(context),
+            json!([
+                ACTIVITYSTREAMS_CONTEXT,
+                SECURITY_CONTEXT,
+                {"sensitive": "as:sensitive"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_builder_matches_mastodon_person() {
+        let person: Value =
+            serde_json::from_str(include_str!("../../assets/mastodon/person.json")).unwrap();
+        let id = |id: &str| json!({"@id": id, "@type": "@id"});
+        let context = ContextBuilder::new()
+            .term("manuallyApprovesFollowers", "as:manuallyApprovesFollowers")
+            .term("toot", "http://joinmastodon.org/ns#")
+            .term("featured", id("toot:featured"))
+            .term("featuredTags", id("toot:featuredTags"))
+            .term("alsoKnownAs", id("as:alsoKnownAs"))
+            .term("movedTo", id("as:movedTo"))
+            .term("schema", "http://schema.org#")
+            .term("PropertyValue", "schema:PropertyValue")
+            .term("value", "schema:value")
+            .term("discoverable", "toot:discoverable")
+            .term("suspended", "toot:suspended")
+            .term(
+                "focalPoint",
+                json!({"@container": "@list", "@id": "toot:focalPoint"}),
+            )
+            .build();
+        assert_eq!(Value::Array(context.clone()), person["@context"]);
+        // same ordering as Mastodon
+        assert_eq!(
+            serde_json::to_string(&context).unwrap(),
+            serde_json::to_string(&person["@context"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_with_context_round_trip_mastodon_note() {
+        let json: Value =
+            serde_json::from_str(include_str!("../../assets/mastodon/note.json")).unwrap();
+        let note: WithContext<Map<String, Value>> = serde_json::from_value(json.clone()).unwrap();
+        let terms = note.context[1].as_object().unwrap().clone();
+        assert!(terms.contains_key("sensitive"));
+        assert_eq!(serde_json::to_value(note).unwrap(), json);
+
+        // terms can be copied into a new context
+        let context = ContextBuilder::new().terms(terms).build();
+        assert_eq!(context[2], json["@context"][1]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Struct which is used to federate the endpoints of an actor, like its shared inbox
+//!
+//! The shared inbox needs to be returned from [Actor::shared_inbox] so that activities are
+//! delivered there, and also included in the actor json so that other servers use it. To keep
+//! both consistent, store the shared inbox in a single place and generate the json field with
+//! [Endpoints::from_actor]:
+//!
+//! ```
+//! # use activitypub_federation::protocol::endpoints::Endpoints;
+//! # use activitypub_federation::{test_utils::DbUser, traits::Actor};
+//! # use url::Url;
+//! #[derive(serde::Serialize)]
+//! #[serde(rename_all = "camelCase")]
+//! struct Person {
+//!     id: Url,
+//!     inbox: Url,
+//!     #[serde(skip_serializing_if = "Option::is_none")]
+//!     endpoints: Option<Endpoints>,
+//! }
+//!
+//! fn into_json(user: &DbUser) -> Person {
+//!     Person {
+//!         id: user.id(),
+//!         inbox: user.inbox(),
+//!         endpoints: Endpoints::from_actor(user),
+//!     }
+//! }
+//! ```
+
+use crate::{config::FederationConfig, traits::Actor};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Endpoints of an actor, which are federated in the `endpoints` field.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoints {
+    /// Inbox which is shared by all actors of the instance. Remote servers send a single
+    /// activity there instead of delivering it to each local recipient.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_inbox: Option<Url>,
+}
+
+impl Endpoints {
+    /// Generates endpoints from [Actor::shared_inbox], or `None` if the actor has no shared inbox.
+    pub fn from_actor<A: Actor>(actor: &A) -> Option<Self> {
+        actor.shared_inbox().map(|shared_inbox| Endpoints {
+            shared_inbox: Some(shared_inbox),
+        })
+    }
+}
+
+/// Builds the shared inbox url for local actors, based on the configured domain.
+///
+/// It uses `http` in [debug mode](crate::config::FederationConfigBuilder::debug) and `https`
+/// otherwise.
+///
+/// ```
+/// # use activitypub_federation::config::FederationConfig;
+/// # use activitypub_federation::protocol::endpoints::ActorEndpoints;
+/// # let _ = actix_rt::System::new();
+/// let config = FederationConfig::builder()
+///     .domain("example.com")
+///     .app_data(())
+///     .build()?;
+/// let endpoints = ActorEndpoints::new(&config).shared_inbox_path("/shared_inbox");
+/// assert_eq!(endpoints.shared_inbox()?.as_str(), "https://example.com/shared_inbox");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ActorEndpoints {
+    base: String,
+    shared_inbox_path: String,
+}
+
+impl ActorEndpoints {
+    /// Create a new instance with default shared inbox path `/inbox`
+    pub fn new<T: Clone>(config: &FederationConfig<T>) -> Self {
+        let scheme = if config.debug { "http" } else { "https" };
+        ActorEndpoints {
+            base: format!("{}://{}", scheme, config.domain()),
+            shared_inbox_path: "/inbox".to_string(),
+        }
+    }
+
+    /// Path of the shared inbox, relative to the domain
+    pub fn shared_inbox_path(mut self, path: &str) -> Self {
+        self.shared_inbox_path = format!("/{}", path.trim_start_matches('/'));
+        self
+    }
+
+    /// Url of the shared inbox, use this to implement [Actor::shared_inbox]
+    pub fn shared_inbox(&self) -> Result<Url, url::ParseError> {
+        Url::parse(&format!("{}{}", self.base, self.shared_inbox_path))
+    }
+
+    /// Generates the endpoints struct for use in the actor json
+    pub fn build(&self) -> Result<Endpoints, url::ParseError> {
+        Ok(Endpoints {
+            shared_inbox: Some(self.shared_inbox()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::DbConnection;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_parse_mastodon_endpoints() {
+        let person: Value =
+            serde_json::from_str(include_str!("../../assets/mastodon/person.json")).unwrap();
+        let endpoints: Endpoints = serde_json::from_value(person["endpoints"].clone()).unwrap();
+        assert_eq!(
+            endpoints.shared_inbox.as_ref().map(Url::as_str),
+            Some("https://mastodon.social/inbox")
+        );
+        assert_eq!(
+            serde_json::to_value(&endpoints).unwrap(),
+            person["endpoints"]
+        );
+
+        assert_eq!(
+            serde_json::to_value(Endpoints::default()).unwrap(),
+            json!({})
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_actor_endpoints() {
+        let config = FederationConfig::builder()
+            .domain("localhost:8001")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let endpoints = ActorEndpoints::new(&config);
+        assert_eq!(
+            endpoints.shared_inbox().unwrap().as_str(),
+            "http://localhost:8001/inbox"
+        );
+        assert_eq!(
+            serde_json::to_value(endpoints.build().unwrap()).unwrap(),
+            json!({ "sharedInbox": "http://localhost:8001/inbox" })
+        );
+    }
+}
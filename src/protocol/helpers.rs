@@ -1,11 +1,20 @@
-//! Serde deserialization functions which help to receive differently shaped data
+//! Serde functions which help to receive and send differently shaped data
 
-use serde::{Deserialize, Deserializer};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use serde_json::Value;
+use tracing::debug;
 
 /// Deserialize JSON single value or array into Vec.
 ///
 /// Useful if your application can handle multiple values for a field, but another federated
-/// platform only sends a single one.
+/// platform only sends a single one. `null` is deserialized as empty Vec. Use it together with
+/// `#[serde(default)]` if the field may be missing.
 ///
 /// ```
 /// # use activitypub_federation::protocol::helpers::deserialize_one_or_many;
@@ -31,24 +40,28 @@ where
     T: Deserialize<'de>,
     D: Deserializer<'de>,
 {
+    // `Many` needs to be tried first, otherwise an array is deserialized as single value if `T`
+    // also accepts arrays (eg `serde_json::Value`)
     #[derive(Deserialize)]
     #[serde(untagged)]
     enum OneOrMany<T> {
-        One(T),
         Many(Vec<T>),
+        One(T),
     }
 
-    let result: OneOrMany<T> = Deserialize::deserialize(deserializer)?;
+    let result: Option<OneOrMany<T>> = Deserialize::deserialize(deserializer)?;
     Ok(match result {
-        OneOrMany::Many(list) => list,
-        OneOrMany::One(value) => vec![value],
+        Some(OneOrMany::Many(list)) => list,
+        Some(OneOrMany::One(value)) => vec![value],
+        None => vec![],
     })
 }
 
-/// Deserialize JSON single value or single element array into single value.
+/// Deserialize JSON single value or array into single value.
 ///
 /// Useful if your application can only handle a single value for a field, but another federated
-/// platform sends single value wrapped in array. Fails if array contains multiple items.
+/// platform sends single value wrapped in array. If the array contains multiple items, the first
+/// one is used. Fails on empty array, and on `null` unless `T` is an `Option`.
 ///
 /// ```
 /// # use activitypub_federation::protocol::helpers::deserialize_one;
@@ -70,21 +83,67 @@ where
     #[serde(untagged)]
     enum MaybeArray<T> {
         Simple(T),
-        Array([T; 1]),
+        Array(Vec<T>),
     }
 
     let result: MaybeArray<T> = Deserialize::deserialize(deserializer)?;
-    Ok(match result {
-        MaybeArray::Simple(value) => value,
-        MaybeArray::Array([value]) => value,
-    })
+    match result {
+        MaybeArray::Simple(value) => Ok(value),
+        MaybeArray::Array(list) => list
+            .into_iter()
+            .next()
+            .ok_or_else(|| D::Error::custom("expected at least one value, found empty array")),
+    }
 }
 
-/// Attempts to deserialize item, in case of error falls back to the type's default value.
+/// Serialize Vec with a single element as bare value, and other Vecs as JSON array.
+///
+/// By default Vec is always serialized as array. Some federated platforms only handle a single
+/// value for fields like `to` or `attributedTo`, so this can be enabled for compatibility with
+/// them. The output can be read back with [deserialize_one_or_many].
+///
+/// ```
+/// # use activitypub_federation::protocol::helpers::{deserialize_one_or_many, serialize_one_or_many};
+/// # use url::Url;
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Note {
+///     #[serde(
+///         deserialize_with = "deserialize_one_or_many",
+///         serialize_with = "serialize_one_or_many"
+///     )]
+///     to: Vec<Url>
+/// }
+///
+/// let note = Note { to: vec![Url::parse("https://example.com/u/alice")?] };
+/// assert_eq!(
+///     serde_json::to_string(&note)?,
+///     r#"{"to":"https://example.com/u/alice"}"#
+/// );
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn serialize_one_or_many<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    match values {
+        [value] => value.serialize(serializer),
+        values => values.serialize(serializer),
+    }
+}
+
+/// Deserialize a list or optional value, dropping any elements which fail to parse.
+///
+/// Useful for fields like `tag` or `attachment`, where a single malformed entry sent by another
+/// platform would otherwise cause the whole object to be rejected. Works on `Vec<T>` and
+/// `Option<T>` (see [SkipError]), use [deserialize_default_on_error] for other types. Vecs accept a single value or array like
+/// [deserialize_one_or_many], and `null` gives an empty Vec or `None`. Dropped values are logged
+/// at debug level. Should always be used together with `#[serde(default)]`, so that a missing
+/// value doesn't cause an error.
 ///
-/// Useful for optional fields which are sent with a different type from another platform,
-/// eg object instead of array. Should always be used together with `#[serde(default)]`, so
-/// that a mssing value doesn't cause an error.
+/// Only use this for supplementary data which can safely be ignored. Never use it for ids,
+/// actors or other fields which are needed to verify or handle an object, as these must result
+/// in an error when invalid.
 ///
 /// ```
 /// # use activitypub_federation::protocol::helpers::deserialize_skip_error;
@@ -93,7 +152,9 @@ where
 /// struct Note {
 ///     content: String,
 ///     #[serde(deserialize_with = "deserialize_skip_error", default)]
-///     source: Option<String>
+///     source: Option<String>,
+///     #[serde(deserialize_with = "deserialize_skip_error", default)]
+///     attachment: Vec<Url>,
 /// }
 ///
 /// let note = serde_json::from_str::<Note>(
@@ -102,16 +163,259 @@ where
 ///     "source": {
 ///         "content": "How are you?",
 ///         "mediaType": "text/markdown"
-///     }
-/// }"#);
-/// assert_eq!(note.unwrap().source, None);
+///     },
+///     "attachment": ["https://example.com/image.png", 1]
+/// }"#)?;
+/// assert_eq!(note.source, None);
+/// assert_eq!(note.attachment.len(), 1);
 /// # Ok::<(), anyhow::Error>(())
 pub fn deserialize_skip_error<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: SkipError,
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(T::from_value_skip_error(value))
+}
+
+/// Attempts to deserialize item, in case of error falls back to the type's default value.
+///
+/// Unlike [deserialize_skip_error] this works for any type which implements [Default], but a Vec
+/// with a single invalid element results in an empty Vec. Should always be used together with
+/// `#[serde(default)]`, so that a missing value doesn't cause an error.
+///
+/// ```
+/// # use activitypub_federation::protocol::helpers::deserialize_default_on_error;
+/// #[derive(serde::Deserialize)]
+/// struct Note {
+///     content: String,
+///     #[serde(deserialize_with = "deserialize_default_on_error", default)]
+///     summary: String,
+/// }
+///
+/// let note = serde_json::from_str::<Note>(r#"{"content": "How are you?", "summary": 1}"#)?;
+/// assert_eq!(note.summary, "");
+/// # Ok::<(), anyhow::Error>(())
+pub fn deserialize_default_on_error<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
     T: Deserialize<'de> + Default,
     D: Deserializer<'de>,
 {
-    let value = serde_json::Value::deserialize(deserializer)?;
-    let inner = T::deserialize(value).unwrap_or_default();
-    Ok(inner)
+    let value = Value::deserialize(deserializer)?;
+    Ok(T::deserialize(value).unwrap_or_default())
+}
+
+/// Types which can be deserialized with [deserialize_skip_error]
+pub trait SkipError: Sized {
+    /// Parse the value, dropping invalid elements
+    fn from_value_skip_error(value: Value) -> Self;
+}
+
+impl<T: DeserializeOwned> SkipError for Vec<T> {
+    fn from_value_skip_error(value: Value) -> Self {
+        let values = match value {
+            Value::Array(values) => values,
+            Value::Null => vec![],
+            value => vec![value],
+        };
+        values.into_iter().filter_map(parse_or_log).collect()
+    }
+}
+
+impl<T: DeserializeOwned> SkipError for Option<T> {
+    fn from_value_skip_error(value: Value) -> Self {
+        match value {
+            Value::Null => None,
+            value => parse_or_log(value),
+        }
+    }
+}
+
+fn parse_or_log<T: DeserializeOwned>(value: Value) -> Option<T> {
+    match serde_json::from_value(value.clone()) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            debug!("Skipping invalid value {value}: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use url::Url;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Many {
+        #[serde(
+            default,
+            deserialize_with = "deserialize_one_or_many",
+            serialize_with = "serialize_one_or_many"
+        )]
+        to: Vec<Url>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct One {
+        #[serde(deserialize_with = "deserialize_one")]
+        to: Url,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct OptionalOne {
+        #[serde(default, deserialize_with = "deserialize_one")]
+        to: Option<Url>,
+    }
+
+    fn alice() -> Url {
+        Url::parse("https://example.com/u/alice").unwrap()
+    }
+
+    fn bob() -> Url {
+        Url::parse("https://example.com/u/bob").unwrap()
+    }
+
+    #[test]
+    fn test_deserialize_one_or_many() {
+        let parse = |value| serde_json::from_value::<Many>(value).map(|m| m.to);
+        assert_eq!(parse(json!({ "to": alice() })).unwrap(), vec![alice()]);
+        assert_eq!(
+            parse(json!({ "to": [alice(), bob()] })).unwrap(),
+            vec![alice(), bob()]
+        );
+        assert_eq!(parse(json!({ "to": [] })).unwrap(), vec![]);
+        assert_eq!(parse(json!({ "to": null })).unwrap(), vec![]);
+        assert_eq!(parse(json!({})).unwrap(), vec![]);
+        assert!(parse(json!({ "to": "invalid" })).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_one() {
+        let parse = |value| serde_json::from_value::<One>(value).map(|o| o.to);
+        assert_eq!(parse(json!({ "to": alice() })).unwrap(), alice());
+        assert_eq!(parse(json!({ "to": [alice(), bob()] })).unwrap(), alice());
+        assert!(parse(json!({ "to": [] })).is_err());
+        assert!(parse(json!({ "to": null })).is_err());
+        assert!(parse(json!({})).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_one_optional() {
+        let parse = |value| serde_json::from_value::<OptionalOne>(value).map(|o| o.to);
+        assert_eq!(parse(json!({ "to": alice() })).unwrap(), Some(alice()));
+        assert_eq!(
+            parse(json!({ "to": [bob(), alice()] })).unwrap(),
+            Some(bob())
+        );
+        assert!(parse(json!({ "to": [] })).is_err());
+        assert_eq!(parse(json!({ "to": null })).unwrap(), None);
+        assert_eq!(parse(json!({})).unwrap(), None);
+    }
+
+    #[test]
+    fn test_serialize_one_or_many() {
+        let serialize = |to| serde_json::to_value(Many { to }).unwrap();
+        assert_eq!(serialize(vec![alice()]), json!({ "to": alice() }));
+        assert_eq!(
+            serialize(vec![alice(), bob()]),
+            json!({ "to": [alice(), bob()] })
+        );
+        assert_eq!(serialize(vec![]), json!({ "to": [] }));
+
+        // output can be read back
+        for to in [vec![], vec![alice()], vec![alice(), bob()]] {
+            let parsed: Many = serde_json::from_value(serialize(to.clone())).unwrap();
+            assert_eq!(parsed.to, to);
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Tag {
+        #[serde(rename = "type")]
+        kind: String,
+        href: Url,
+        name: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Tagged {
+        #[serde(default, deserialize_with = "deserialize_skip_error")]
+        tag: Vec<Tag>,
+        #[serde(default, deserialize_with = "deserialize_skip_error")]
+        icon: Option<Url>,
+    }
+
+    #[test]
+    fn test_deserialize_skip_error() {
+        let tagged: Tagged = serde_json::from_value(json!({
+            "tag": [
+                { "type": "Mention", "href": alice(), "name": "@alice" },
+                { "type": "Hashtag", "href": "not a url", "name": "#garbage" },
+                "garbage",
+                { "type": "Mention", "href": bob(), "name": "@bob" },
+            ],
+            "icon": { "url": "https://example.com/icon.png" }
+        }))
+        .unwrap();
+        let names: Vec<_> = tagged.tag.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["@alice", "@bob"]);
+        assert_eq!(tagged.icon, None);
+
+        // single value like deserialize_one_or_many
+        let tagged: Tagged = serde_json::from_value(json!({
+            "tag": { "type": "Mention", "href": alice(), "name": "@alice" },
+            "icon": alice()
+        }))
+        .unwrap();
+        assert_eq!(tagged.tag.len(), 1);
+        assert_eq!(tagged.icon, Some(alice()));
+
+        for value in [
+            json!({}),
+            json!({ "tag": null, "icon": null }),
+            json!({ "tag": [] }),
+        ] {
+            let tagged: Tagged = serde_json::from_value(value).unwrap();
+            assert_eq!(tagged.tag, vec![]);
+            assert_eq!(tagged.icon, None);
+        }
+    }
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    struct Source {
+        content: String,
+        media_type: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct WithSource {
+        #[serde(default, deserialize_with = "deserialize_default_on_error")]
+        source: Source,
+        #[serde(default, deserialize_with = "deserialize_default_on_error")]
+        tag: Vec<Url>,
+    }
+
+    #[test]
+    fn test_deserialize_default_on_error() {
+        let parsed: WithSource = serde_json::from_value(json!({
+            "source": "garbage",
+            "tag": [alice(), "garbage"]
+        }))
+        .unwrap();
+        assert_eq!(parsed.source, Source::default());
+        assert_eq!(parsed.tag, vec![]);
+
+        let parsed: WithSource = serde_json::from_value(json!({
+            "source": { "content": "hi", "media_type": "text/markdown" },
+            "tag": [alice()]
+        }))
+        .unwrap();
+        assert_eq!(parsed.source.content, "hi");
+        assert_eq!(parsed.tag, vec![alice()]);
+
+        let parsed: WithSource = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(parsed.source, Source::default());
+    }
 }
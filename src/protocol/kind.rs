@@ -0,0 +1,235 @@
+//! Marker types for the `type` field of activities and objects
+//!
+//! Each type is a unit struct which serializes to a single fixed string, and whose deserialization
+//! fails for any other value. This makes it possible to distinguish activities by their type in
+//! an untagged enum, and typos in the type name are caught by the compiler.
+//!
+//! ```
+//! # use activitypub_federation::protocol::kind::FollowType;
+//! #[derive(serde::Deserialize, serde::Serialize)]
+//! struct Follow {
+//!     #[serde(rename = "type")]
+//!     kind: FollowType,
+//! }
+//!
+//! let follow: Follow = serde_json::from_str(r#"{"type": "Follow"}"#)?;
+//! assert_eq!(follow.kind, FollowType);
+//! assert_eq!(serde_json::to_string(&follow)?, r#"{"type":"Follow"}"#);
+//! assert!(serde_json::from_str::<Follow>(r#"{"type": "Like"}"#).is_err());
+//! # Ok::<(), serde_json::Error>(())
+//! ```
+//!
+//! Types which are not defined here, eg for extensions of other platforms, can be declared with
+//! [kind!](crate::kind):
+//!
+//! ```
+//! activitypub_federation::kind!(EmojiReactType, EmojiReact);
+//!
+//! assert_eq!(serde_json::to_string(&EmojiReactType)?, r#""EmojiReact""#);
+//! assert_eq!(EmojiReactType::NAME, "EmojiReact");
+//! # Ok::<(), serde_json::Error>(())
+//! ```
+
+use serde::{
+    de::{Error, Unexpected, Visitor},
+    Deserializer,
+};
+use std::fmt::Formatter;
+
+#[doc(hidden)]
+pub use serde as __serde;
+
+/// Declares a unit struct `$name` which is (de)serialized as the string `$value`, see
+/// [protocol::kind](crate::protocol::kind).
+///
+/// ```
+/// activitypub_federation::kind!(FollowType, Follow);
+/// ```
+#[macro_export]
+macro_rules! kind {
+    ($(#[$meta:meta])* $name:ident, $value:ident) => {
+        #[doc = concat!("Type `", stringify!($value), "`")]
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name;
+
+        impl $name {
+            /// Value of the `type` field
+            pub const NAME: &'static str = stringify!($value);
+        }
+
+        impl ::std::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(Self::NAME)
+            }
+        }
+
+        impl $crate::protocol::kind::__serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+            where
+                S: $crate::protocol::kind::__serde::Serializer,
+            {
+                serializer.serialize_str(Self::NAME)
+            }
+        }
+
+        impl<'de> $crate::protocol::kind::__serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+            where
+                D: $crate::protocol::kind::__serde::Deserializer<'de>,
+            {
+                $crate::protocol::kind::deserialize_kind(deserializer, Self::NAME).map(|()| $name)
+            }
+        }
+    };
+}
+
+/// Used by [kind!](crate::kind), succeeds if the value is the string `name`
+#[doc(hidden)]
+pub fn deserialize_kind<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    name: &'static str,
+) -> Result<(), D::Error> {
+    deserializer.deserialize_str(KindVisitor(name))
+}
+
+struct KindVisitor(&'static str);
+
+impl<'de> Visitor<'de> for KindVisitor {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the string {:?}", self.0)
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<(), E> {
+        if value == self.0 {
+            Ok(())
+        } else {
+            Err(E::invalid_value(Unexpected::Str(value), &self))
+        }
+    }
+}
+
+// Activity types
+kind!(AcceptType, Accept);
+kind!(AddType, Add);
+kind!(AnnounceType, Announce);
+kind!(BlockType, Block);
+kind!(CreateType, Create);
+kind!(DeleteType, Delete);
+kind!(DislikeType, Dislike);
+kind!(FlagType, Flag);
+kind!(FollowType, Follow);
+kind!(LikeType, Like);
+kind!(MoveType, Move);
+kind!(RejectType, Reject);
+kind!(RemoveType, Remove);
+kind!(UndoType, Undo);
+kind!(UpdateType, Update);
+
+// Actor types
+kind!(ApplicationType, Application);
+kind!(GroupType, Group);
+kind!(OrganizationType, Organization);
+kind!(PersonType, Person);
+kind!(ServiceType, Service);
+
+// Object types
+kind!(ArticleType, Article);
+kind!(DocumentType, Document);
+kind!(EventType, Event);
+kind!(ImageType, Image);
+kind!(NoteType, Note);
+kind!(PageType, Page);
+kind!(QuestionType, Question);
+kind!(TombstoneType, Tombstone);
+kind!(VideoType, Video);
+
+// Collection types
+kind!(CollectionType, Collection);
+kind!(CollectionPageType, CollectionPage);
+kind!(OrderedCollectionType, OrderedCollection);
+kind!(OrderedCollectionPageType, OrderedCollectionPage);
+
+// Link types
+kind!(LinkType, Link);
+kind!(MentionType, Mention);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Follow {
+        #[serde(rename = "type")]
+        kind: FollowType,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Like {
+        #[serde(rename = "type")]
+        kind: LikeType,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    enum Activity {
+        Follow(Follow),
+        Like(Like),
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(serde_json::to_value(FollowType).unwrap(), json!("Follow"));
+        let kind: FollowType = serde_json::from_value(json!("Follow")).unwrap();
+        assert_eq!(kind, FollowType);
+        let follow: Follow = serde_json::from_str(r#"{"type":"Follow"}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&follow).unwrap(),
+            r#"{"type":"Follow"}"#
+        );
+        assert_eq!(
+            OrderedCollectionPageType.to_string(),
+            "OrderedCollectionPage"
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        for value in [
+            json!("Like"),
+            json!("follow"),
+            json!(""),
+            json!(["Follow"]),
+            json!(1),
+        ] {
+            assert!(
+                serde_json::from_value::<FollowType>(value.clone()).is_err(),
+                "{value}"
+            );
+        }
+        let err = serde_json::from_str::<Follow>(r#"{"type":"Like"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(r#"expected the string "Follow""#), "{err}");
+        assert!(serde_json::from_str::<Follow>("{}").is_err());
+    }
+
+    #[test]
+    fn test_untagged() {
+        let like: Value = json!({"type": "Like"});
+        assert!(matches!(
+            serde_json::from_value(like).unwrap(),
+            Activity::Like(_)
+        ));
+        let follow: Value = json!({"type": "Follow"});
+        assert!(matches!(
+            serde_json::from_value(follow).unwrap(),
+            Activity::Follow(_)
+        ));
+        assert!(serde_json::from_value::<Activity>(json!({"type": "Undo"})).is_err());
+    }
+}
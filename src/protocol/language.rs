@@ -0,0 +1,228 @@
+//! Natural language values with multiple translations, like `contentMap` and `nameMap`
+//!
+//! Objects contain the default text in fields like `content`, and can have the same text in
+//! one or more languages in the corresponding map field, eg `contentMap`. On outgoing objects
+//! both should contain the same text, which is easiest with [LanguageMap::localize]:
+//!
+//! ```
+//! # use activitypub_federation::protocol::language::LanguageMap;
+//! #[derive(serde::Serialize)]
+//! #[serde(rename_all = "camelCase")]
+//! struct Note {
+//!     content: String,
+//!     content_map: LanguageMap,
+//! }
+//!
+//! let (content, content_map) = LanguageMap::localize("<p>Hallo</p>".to_string(), "de");
+//! let note = Note { content, content_map };
+//! assert_eq!(
+//!     serde_json::to_string(&note)?,
+//!     r#"{"content":"<p>Hallo</p>","contentMap":{"de":"<p>Hallo</p>"}}"#
+//! );
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use std::fmt::Formatter;
+
+/// Language tag which is used if the language of a plain string is unknown
+pub const UNDETERMINED_LANGUAGE: &str = "und";
+
+/// Map of [BCP 47](https://www.rfc-editor.org/info/bcp47) language tags to text in the
+/// respective language.
+///
+/// Entries are kept in the order in which they were received or inserted. When deserializing a
+/// plain string is also accepted, its language is [UNDETERMINED_LANGUAGE].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LanguageMap(Vec<(String, String)>);
+
+impl LanguageMap {
+    /// Create a new map with a single entry
+    pub fn single(language: &str, text: String) -> Self {
+        LanguageMap(vec![(language.to_string(), text)])
+    }
+
+    /// Returns the `text` for use in a field like `content`, together with a map containing it
+    /// for use in the corresponding `contentMap`.
+    pub fn localize(text: String, language: &str) -> (String, Self) {
+        let map = LanguageMap::single(language, text.clone());
+        (text, map)
+    }
+
+    /// Sets the text for `language`, replacing any existing entry for the same language
+    pub fn insert(&mut self, language: &str, text: String) {
+        match self
+            .0
+            .iter_mut()
+            .find(|(l, _)| l.eq_ignore_ascii_case(language))
+        {
+            Some(entry) => entry.1 = text,
+            None => self.0.push((language.to_string(), text)),
+        }
+    }
+
+    /// Returns text in exactly the given language, compared case-insensitively
+    pub fn get(&self, language: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(l, _)| l.eq_ignore_ascii_case(language))
+            .map(|(_, t)| t.as_str())
+    }
+
+    /// Returns text in the preferred language, eg from the `Accept-Language` header.
+    ///
+    /// Languages are tried in the given order, first with an exact match and then by primary
+    /// language subtag, so that `en-US` matches `en` and the other way around. If no language
+    /// matches, the first entry is returned.
+    pub fn get_preferred(&self, languages: &[&str]) -> Option<&str> {
+        let primary = |l: &str| l.split('-').next().unwrap_or(l).to_ascii_lowercase();
+        languages
+            .iter()
+            .find_map(|language| {
+                self.get(language).or_else(|| {
+                    self.0
+                        .iter()
+                        .find(|(l, _)| primary(l) == primary(language))
+                        .map(|(_, t)| t.as_str())
+                })
+            })
+            .or_else(|| self.0.first().map(|(_, t)| t.as_str()))
+    }
+
+    /// Iterates over all languages and their text
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(l, t)| (l.as_str(), t.as_str()))
+    }
+
+    /// Number of languages
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there is no entry
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Serialize for LanguageMap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (language, text) in &self.0 {
+            map.serialize_entry(language, text)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for LanguageMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct LanguageMapVisitor;
+
+        impl<'de> Visitor<'de> for LanguageMapVisitor {
+            type Value = LanguageMap;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("map of language tags to strings, or a string")
+            }
+
+            fn visit_str<E>(self, text: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(LanguageMap::single(UNDETERMINED_LANGUAGE, text.to_string()))
+            }
+
+            fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut map = LanguageMap::default();
+                while let Some((language, text)) = access.next_entry::<String, String>()? {
+                    map.insert(&language, text);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_any(LanguageMapVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_parse_mastodon_content_map() {
+        let note: Value =
+            serde_json::from_str(include_str!("../../assets/mastodon/note.json")).unwrap();
+        let map: LanguageMap = serde_json::from_value(note["contentMap"].clone()).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("en"), note["content"].as_str());
+        assert_eq!(map.get_preferred(&["de"]), note["content"].as_str());
+        assert_eq!(serde_json::to_value(&map).unwrap(), note["contentMap"]);
+    }
+
+    #[test]
+    fn test_multiple_languages() {
+        let json = json!({
+            "de-AT": "<p>Servus</p>",
+            "en": "<p>Hello</p>",
+            "pt-BR": "<p>Olá</p>"
+        });
+        let map: LanguageMap = serde_json::from_value(json.clone()).unwrap();
+        let languages: Vec<_> = map.iter().map(|(l, _)| l).collect();
+        assert_eq!(languages, vec!["de-AT", "en", "pt-BR"]);
+
+        assert_eq!(map.get("en"), Some("<p>Hello</p>"));
+        assert_eq!(map.get("EN"), Some("<p>Hello</p>"));
+        assert_eq!(map.get("de"), None);
+        assert_eq!(map.get_preferred(&["de"]), Some("<p>Servus</p>"));
+        assert_eq!(map.get_preferred(&["en-GB", "de"]), Some("<p>Hello</p>"));
+        assert_eq!(map.get_preferred(&["fr", "pt"]), Some("<p>Olá</p>"));
+        assert_eq!(map.get_preferred(&["fr"]), Some("<p>Servus</p>"));
+        assert_eq!(map.get_preferred(&[]), Some("<p>Servus</p>"));
+        assert_eq!(LanguageMap::default().get_preferred(&["en"]), None);
+
+        // order is preserved
+        assert_eq!(
+            serde_json::to_string(&map).unwrap(),
+            serde_json::to_string(&json).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_plain_string() {
+        let map: LanguageMap = serde_json::from_value(json!("Hello")).unwrap();
+        assert_eq!(map.get(UNDETERMINED_LANGUAGE), Some("Hello"));
+        assert_eq!(map.get_preferred(&["en"]), Some("Hello"));
+        assert!(serde_json::from_value::<LanguageMap>(json!(["Hello"])).is_err());
+    }
+
+    #[test]
+    fn test_localize() {
+        let (content, mut map) = LanguageMap::localize("Hello".to_string(), "en");
+        assert_eq!(map.get("en"), Some(content.as_str()));
+        map.insert("EN", "Hi".to_string());
+        map.insert("fr", "Salut".to_string());
+        assert_eq!(
+            serde_json::to_value(&map).unwrap(),
+            json!({ "en": "Hi", "fr": "Salut" })
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Account migration with `Move` activities, `movedTo` and `alsoKnownAs`
+//!
+//! To move an account, the user first adds the old account id to `alsoKnownAs` of the new
+//! account. Then the old account sets `movedTo` to the new account id, and sends a [Move]
+//! activity to its followers. Receivers should only act on the move (eg by following the new
+//! account) after checking both links with [verify_account_move], as otherwise anyone could
+//! claim to be the new home of an account.
+//!
+//! ```
+//! # use activitypub_federation::protocol::migration::ActorMigration;
+//! # use url::Url;
+//! #[derive(serde::Deserialize)]
+//! struct Person {
+//!     id: Url,
+//!     #[serde(flatten)]
+//!     migration: ActorMigration,
+//! }
+//!
+//! let person: Person = serde_json::from_str(
+//! r#"{
+//!     "id": "https://example.com/u/alice",
+//!     "movedTo": "https://example.net/u/alice",
+//!     "alsoKnownAs": "https://example.org/u/alice"
+//! }"#)?;
+//! assert_eq!(person.migration.moved_to.unwrap().as_str(), "https://example.net/u/alice");
+//! assert_eq!(person.migration.also_known_as.len(), 1);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::object_id::ObjectId,
+    protocol::{
+        helpers::{deserialize_one_or_many, serialize_one_or_many},
+        kind::MoveType,
+        verification::verify_urls_match,
+    },
+    traits::{Actor, Object},
+};
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Migration fields of an actor, which should be flattened into the actor json.
+///
+/// Use [ActorMigration::from_actor] to generate them from [Actor::moved_to] and
+/// [Actor::also_known_as].
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActorMigration {
+    /// New account of the actor, if it was moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<Url>,
+    /// Other accounts which belong to the same person
+    #[serde(
+        default,
+        deserialize_with = "deserialize_one_or_many",
+        serialize_with = "serialize_one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub also_known_as: Vec<Url>,
+}
+
+impl ActorMigration {
+    /// Generates the migration fields from [Actor::moved_to] and [Actor::also_known_as]
+    pub fn from_actor<A: Actor>(actor: &A) -> Self {
+        ActorMigration {
+            moved_to: actor.moved_to(),
+            also_known_as: actor.also_known_as(),
+        }
+    }
+}
+
+/// Activity which announces that the account `object` moved to `target`.
+///
+/// It is sent by the old account, so `actor` and `object` are identical.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", bound(serialize = "", deserialize = ""))]
+pub struct Move<A>
+where
+    A: Object + Send + 'static,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+{
+    /// Always `Move`
+    #[serde(rename = "type")]
+    pub kind: MoveType,
+    /// Id of the activity
+    pub id: Url,
+    /// Old account which sends the activity
+    pub actor: ObjectId<A>,
+    /// Old account which is moved
+    pub object: ObjectId<A>,
+    /// New account
+    pub target: ObjectId<A>,
+}
+
+impl<A> Move<A>
+where
+    A: Actor,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+    <A as Object>::Error: From<Error>,
+{
+    /// Create a new activity which moves `old` to `new`
+    pub fn new(old: ObjectId<A>, new: ObjectId<A>, id: Url) -> Self {
+        Move {
+            kind: Default::default(),
+            id,
+            actor: old.clone(),
+            object: old,
+            target: new,
+        }
+    }
+
+    /// Checks that the actor moves its own account, and that the move is confirmed by both
+    /// accounts with [verify_account_move]. Returns the old and new account.
+    pub async fn verify(&self, data: &Data<A::DataType>) -> Result<(A, A), A::Error> {
+        verify_urls_match(self.actor.inner(), self.object.inner())?;
+        verify_account_move(&self.object, &self.target, data).await
+    }
+}
+
+/// Reason why an account move is invalid
+#[derive(thiserror::Error, Clone, Debug, Display, PartialEq, Eq)]
+pub enum AccountMoveError {
+    /// Old and new account are identical: {0}
+    SameAccount(Url),
+    /// New account {new} doesn't list old account {old} in alsoKnownAs
+    MissingAlsoKnownAs {
+        /// Old account
+        old: Url,
+        /// New account
+        new: Url,
+    },
+    /// Old account {old} doesn't point to new account {new} with movedTo
+    MissingMovedTo {
+        /// Old account
+        old: Url,
+        /// New account
+        new: Url,
+    },
+}
+
+/// Verifies that the account `old` was moved to `new`, and returns both accounts.
+///
+/// Both accounts are always fetched over http (see [ObjectId::dereference_forced]), so that the
+/// check uses their current state. The move is valid if `alsoKnownAs` of the new account
+/// contains the old account, and `movedTo` of the old account is the new account. Otherwise
+/// [Error::AccountMoveInvalid] is returned, describing which link is missing.
+pub async fn verify_account_move<A>(
+    old: &ObjectId<A>,
+    new: &ObjectId<A>,
+    data: &Data<A::DataType>,
+) -> Result<(A, A), A::Error>
+where
+    A: Actor,
+    for<'de2> <A as Object>::Kind: Deserialize<'de2>,
+    <A as Object>::Error: From<Error>,
+{
+    let (old_id, new_id) = (old.inner(), new.inner());
+    if old_id == new_id {
+        return Err(invalid(AccountMoveError::SameAccount(old_id.clone())));
+    }
+    let new_actor = new.dereference_forced(data).await?;
+    if !new_actor.also_known_as().contains(old_id) {
+        return Err(invalid(AccountMoveError::MissingAlsoKnownAs {
+            old: old_id.clone(),
+            new: new_id.clone(),
+        }));
+    }
+    let old_actor = old.dereference_forced(data).await?;
+    if old_actor.moved_to().as_ref() != Some(new_id) {
+        return Err(invalid(AccountMoveError::MissingMovedTo {
+            old: old_id.clone(),
+            new: new_id.clone(),
+        }));
+    }
+    Ok((old_actor, new_actor))
+}
+
+fn invalid<E: From<Error>>(error: AccountMoveError) -> E {
+    Error::AccountMoveInvalid(Box::new(error)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::FederationConfig, fetch::test_server::serve, test_utils::DbConnection};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Person {
+        id: Url,
+        #[serde(flatten)]
+        migration: ActorMigration,
+    }
+
+    /// Remote actor which is never stored, so that it is always fetched from the test server
+    #[derive(Debug)]
+    struct DbPerson(Url, ActorMigration);
+
+    #[async_trait::async_trait]
+    impl Object for DbPerson {
+        type DataType = DbConnection;
+        type Kind = Person;
+        type Error = Error;
+
+        async fn read_from_id(_: Url, _: &Data<Self::DataType>) -> Result<Option<Self>, Error> {
+            Ok(None)
+        }
+
+        async fn into_json(&self, _: &Data<Self::DataType>) -> Result<Self::Kind, Error> {
+            Err(Error::NotFound)
+        }
+
+        async fn from_json(json: Self::Kind, _: &Data<Self::DataType>) -> Result<Self, Error> {
+            Ok(DbPerson(json.id, json.migration))
+        }
+    }
+
+    impl Actor for DbPerson {
+        fn id(&self) -> Url {
+            self.0.clone()
+        }
+
+        fn public_key_pem(&self) -> &str {
+            ""
+        }
+
+        fn private_key_pem(&self) -> Option<String> {
+            None
+        }
+
+        fn inbox(&self) -> Url {
+            self.0.clone()
+        }
+
+        fn moved_to(&self) -> Option<Url> {
+            self.1.moved_to.clone()
+        }
+
+        fn also_known_as(&self) -> Vec<Url> {
+            self.1.also_known_as.clone()
+        }
+    }
+
+    /// Serves the old and new account with the given migration fields, and verifies the move
+    async fn verify_move(mut old: Value, mut new: Value) -> Result<(DbPerson, DbPerson), Error> {
+        old["id"] = json!("{base}/u/old");
+        new["id"] = json!("{base}/u/new");
+        let port = serve(HashMap::from([
+            ("/u/old".to_string(), ("200 OK", old.to_string())),
+            ("/u/new".to_string(), ("200 OK", new.to_string())),
+        ]));
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .debug(true)
+            .build()
+            .unwrap();
+        let data = config.to_request_data();
+        let base = format!("http://localhost:{port}");
+        let old = ObjectId::<DbPerson>::parse(format!("{base}/u/old").as_str()).unwrap();
+        let new = ObjectId::<DbPerson>::parse(format!("{base}/u/new").as_str()).unwrap();
+        verify_account_move(&old, &new, &data).await
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_account_move() {
+        let (old, new) = verify_move(
+            json!({ "movedTo": "{base}/u/new" }),
+            json!({ "alsoKnownAs": ["{base}/u/old"] }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(old.moved_to(), Some(new.id()));
+        assert_eq!(new.also_known_as(), vec![old.id()]);
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_account_move_missing_also_known_as() {
+        let res = verify_move(
+            json!({ "movedTo": "{base}/u/new" }),
+            json!({ "alsoKnownAs": ["{base}/u/other"] }),
+        )
+        .await;
+        let Err(Error::AccountMoveInvalid(error)) = res else {
+            panic!("expected invalid move, got {:?}", res);
+        };
+        assert!(matches!(
+            *error,
+            AccountMoveError::MissingAlsoKnownAs { .. }
+        ));
+        assert!(error.to_string().contains("alsoKnownAs"));
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_account_move_missing_moved_to() {
+        for old in [json!({}), json!({ "movedTo": "{base}/u/other" })] {
+            let res = verify_move(old, json!({ "alsoKnownAs": "{base}/u/old" })).await;
+            let Err(Error::AccountMoveInvalid(error)) = res else {
+                panic!("expected invalid move, got {:?}", res);
+            };
+            assert!(matches!(*error, AccountMoveError::MissingMovedTo { .. }));
+            assert!(error.to_string().contains("movedTo"));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_account_move_same_account() {
+        let config = FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap();
+        let id = ObjectId::<DbPerson>::parse("https://example.net/u/alice").unwrap();
+        let res = verify_account_move(&id, &id, &config.to_request_data()).await;
+        let Err(Error::AccountMoveInvalid(error)) = res else {
+            panic!("expected invalid move, got {:?}", res);
+        };
+        assert!(matches!(*error, AccountMoveError::SameAccount(_)));
+    }
+
+    #[test]
+    fn test_serialize_migration() {
+        let migration = ActorMigration {
+            moved_to: None,
+            also_known_as: vec![Url::parse("https://example.org/u/alice").unwrap()],
+        };
+        assert_eq!(
+            serde_json::to_value(&migration).unwrap(),
+            json!({ "alsoKnownAs": "https://example.org/u/alice" })
+        );
+        assert_eq!(
+            serde_json::to_value(ActorMigration::default()).unwrap(),
+            json!({})
+        );
+    }
+}
@@ -1,7 +1,20 @@
 //! Data structures which help to define federated messages
 
+pub mod actor;
+pub mod attachment;
+pub mod audience;
+pub mod collection;
 pub mod context;
+pub mod endpoints;
 pub mod helpers;
+pub mod kind;
+pub mod language;
+pub mod migration;
 pub mod public_key;
+pub mod relay;
+pub mod source;
+pub mod tag;
+pub mod tombstone;
+pub mod unknown_activity;
 pub mod values;
 pub mod verification;
@@ -1,5 +1,8 @@
 //! Struct which is used to federate actor key for HTTP signatures
 
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+use crate::protocol::verification::verify_domains_match;
+use crate::traits::Actor;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -20,8 +23,9 @@ pub struct PublicKey {
 impl PublicKey {
     /// Create a new [PublicKey] struct for the `owner` with `public_key_pem`.
     ///
-    /// It uses an standard key id of `{actor_id}#main-key`
-    pub(crate) fn new(owner: Url, public_key_pem: String) -> Self {
+    /// It uses an standard key id of `{actor_id}#main-key`, which is also used as `keyId` when
+    /// signing outgoing requests.
+    pub fn new(owner: Url, public_key_pem: String) -> Self {
         let id = main_key_id(&owner);
         PublicKey {
             id,
@@ -29,8 +33,74 @@ impl PublicKey {
             public_key_pem,
         }
     }
+
+    /// Create a new [PublicKey] struct from the id and public key of `actor`.
+    pub fn from_actor<A: Actor>(actor: &A) -> Self {
+        PublicKey::new(actor.id(), actor.public_key_pem().to_string())
+    }
 }
 
+/// Key id which is used for actor keys created by this library
 pub(crate) fn main_key_id(owner: &Url) -> String {
     format!("{}#main-key", &owner)
 }
+
+/// Returns true if `key_id` may belong to the actor with id `owner`.
+///
+/// Other platforms use different formats for key ids, eg `{actor_id}/main-key`, so only the
+/// domain is compared unless it is the standard key id.
+#[cfg(any(feature = "actix-web", feature = "axum", feature = "test-utils"))]
+pub(crate) fn key_id_matches_owner(key_id: &str, owner: &Url) -> bool {
+    if key_id == main_key_id(owner) {
+        return true;
+    }
+    match Url::parse(key_id) {
+        Ok(key_id) => verify_domains_match(&key_id, owner).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_id_matches_owner() {
+        let owner = Url::parse("https://example.com/u/alice").unwrap();
+        assert!(key_id_matches_owner(
+            "https://example.com/u/alice#main-key",
+            &owner
+        ));
+        assert!(key_id_matches_owner(
+            "https://example.com/u/alice/main-key",
+            &owner
+        ));
+        assert!(!key_id_matches_owner(
+            "https://evil.com/u/alice#main-key",
+            &owner
+        ));
+        assert!(!key_id_matches_owner("main-key", &owner));
+    }
+
+    #[test]
+    fn test_key_id_matches_owner_idn() {
+        let owner = Url::parse("https://xn--kbenhavn-54a.example/u/soren").unwrap();
+        assert!(key_id_matches_owner(
+            "https://københavn.example/u/soren#main-key",
+            &owner
+        ));
+        assert!(key_id_matches_owner(
+            "https://KØBENHAVN.example/u/soren#main-key",
+            &owner
+        ));
+        let owner = Url::parse("https://københavn.example/u/soren").unwrap();
+        assert!(key_id_matches_owner(
+            "https://xn--kbenhavn-54a.example/u/soren#main-key",
+            &owner
+        ));
+        assert!(!key_id_matches_owner(
+            "https://kobenhavn.example/u/soren#main-key",
+            &owner
+        ));
+    }
+}
@@ -0,0 +1,419 @@
+//! Subscriptions to relays, which forward public activities between instances
+//!
+//! To subscribe, the instance actor sends a [RelayFollow] of the relay actor, addressed to the
+//! [public](crate::protocol::audience::PUBLIC) collection, to the relay inbox. The relay answers
+//! with a [RelayAccept], which should be passed to [RelayAccept::receive] from the handler for
+//! `Accept` activities. Every change of the subscription is reported to
+//! [FederationEvents::relay_state_changed](crate::events::FederationEvents::relay_state_changed),
+//! so that the application can store it.
+//!
+//! Once the subscription is accepted, add the relay inbox to
+//! [FederationConfigBuilder::relays](crate::config::FederationConfigBuilder::relays). Activities
+//! which are sent with [send_public_activity](crate::activity_queue::send_public_activity) are
+//! then also delivered to the relay, which forwards them to the other subscribers.
+//!
+//! ```
+//! # use activitypub_federation::protocol::relay::RelayFollow;
+//! # use url::Url;
+//! let instance_actor = Url::parse("https://example.com/actor")?;
+//! let relay = Url::parse("https://relay.example/actor")?;
+//! let id = Url::parse("https://example.com/activities/follow/1")?;
+//! let follow = RelayFollow::new(instance_actor, relay, id);
+//!
+//! let json = serde_json::to_value(&follow)?;
+//! assert_eq!(json["object"], "https://relay.example/actor");
+//! assert_eq!(json["to"][0], "https://www.w3.org/ns/activitystreams#Public");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{
+    activity_queue::queue_activity,
+    config::Data,
+    error::Error,
+    events::notify,
+    protocol::{
+        audience::{public, Audience},
+        context::WithContext,
+        kind::{AcceptType, FollowType, UndoType},
+    },
+    traits::Actor,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use url::Url;
+
+/// State of the subscription to a relay, which is passed to
+/// [FederationEvents::relay_state_changed](crate::events::FederationEvents::relay_state_changed)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelayState {
+    /// The follow was sent, but not yet accepted by the relay
+    Pending,
+    /// The relay accepted the follow, so it forwards public activities
+    Accepted,
+    /// The follow was undone
+    Unsubscribed,
+}
+
+/// Activity which subscribes the instance actor to a relay
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayFollow {
+    /// Always `Follow`
+    #[serde(rename = "type")]
+    pub kind: FollowType,
+    /// Id of the activity
+    pub id: Url,
+    /// Instance actor which subscribes to the relay
+    pub actor: Url,
+    /// Relay actor, eg `https://relay.example/actor`
+    pub object: Url,
+    /// Recipients, which are only the public collection
+    #[serde(flatten)]
+    pub audience: Audience,
+}
+
+impl RelayFollow {
+    /// Create a new follow of the `relay` actor
+    pub fn new(instance_actor: Url, relay: Url, id: Url) -> Self {
+        RelayFollow {
+            kind: Default::default(),
+            id,
+            actor: instance_actor,
+            object: relay,
+            audience: Audience {
+                to: vec![public()],
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Sends the follow to the inbox of the relay, and reports [RelayState::Pending].
+    ///
+    /// `instance_actor` is used to sign the request, so it must be the actor of the follow.
+    pub async fn send<A: Actor, T: Clone>(
+        &self,
+        instance_actor: &A,
+        relay_inbox: Url,
+        data: &Data<T>,
+    ) -> Result<(), Error> {
+        send_to_relay(
+            self,
+            &self.id,
+            &self.actor,
+            instance_actor,
+            relay_inbox,
+            data,
+        )
+        .await?;
+        relay_state_changed(&self.object, RelayState::Pending, data).await;
+        Ok(())
+    }
+}
+
+/// Activity which ends the subscription to a relay
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayUndo {
+    /// Always `Undo`
+    #[serde(rename = "type")]
+    pub kind: UndoType,
+    /// Id of the activity
+    pub id: Url,
+    /// Instance actor which unsubscribes from the relay
+    pub actor: Url,
+    /// Follow which is undone
+    pub object: RelayFollow,
+    /// Recipients, which are the same as those of the follow
+    #[serde(flatten)]
+    pub audience: Audience,
+}
+
+impl RelayUndo {
+    /// Create a new activity which undoes `follow`
+    pub fn new(follow: RelayFollow, id: Url) -> Self {
+        RelayUndo {
+            kind: Default::default(),
+            id,
+            actor: follow.actor.clone(),
+            audience: follow.audience.clone(),
+            object: follow,
+        }
+    }
+
+    /// Sends the undo to the inbox of the relay, and reports [RelayState::Unsubscribed].
+    ///
+    /// `instance_actor` is used to sign the request, so it must be the actor of the follow.
+    pub async fn send<A: Actor, T: Clone>(
+        &self,
+        instance_actor: &A,
+        relay_inbox: Url,
+        data: &Data<T>,
+    ) -> Result<(), Error> {
+        send_to_relay(
+            self,
+            &self.id,
+            &self.actor,
+            instance_actor,
+            relay_inbox,
+            data,
+        )
+        .await?;
+        relay_state_changed(&self.object.object, RelayState::Unsubscribed, data).await;
+        Ok(())
+    }
+}
+
+/// Activity with which a relay accepts a [RelayFollow]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayAccept {
+    /// Always `Accept`
+    #[serde(rename = "type")]
+    pub kind: AcceptType,
+    /// Id of the activity
+    pub id: Url,
+    /// Relay actor
+    pub actor: Url,
+    /// Id of the follow which is accepted. Relays send either the id or the whole follow.
+    #[serde(deserialize_with = "deserialize_object_id")]
+    pub object: Url,
+}
+
+impl RelayAccept {
+    /// Reports [RelayState::Accepted] for the relay actor.
+    ///
+    /// Call this after the activity was received with `receive_activity`, which verifies that it
+    /// was sent by the relay actor. Returns [Error::UrlVerificationError] if the accepted follow
+    /// is not from the local instance.
+    pub async fn receive<T: Clone>(&self, data: &Data<T>) -> Result<(), Error> {
+        if !data.config.is_local_url(&self.object) {
+            return Err(Error::UrlVerificationError(format!(
+                "Accepted follow {} is not from local instance",
+                self.object
+            )));
+        }
+        relay_state_changed(&self.actor, RelayState::Accepted, data).await;
+        Ok(())
+    }
+}
+
+/// Reads the id of an object which is either embedded or given as url
+fn deserialize_object_id<'de, D>(deserializer: D) -> Result<Url, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IdOrObject {
+        Id(Url),
+        Object { id: Url },
+    }
+
+    Ok(match IdOrObject::deserialize(deserializer)? {
+        IdOrObject::Id(id) | IdOrObject::Object { id } => id,
+    })
+}
+
+async fn send_to_relay<Activity: Serialize, A: Actor, T: Clone>(
+    activity: &Activity,
+    id: &Url,
+    actor_id: &Url,
+    instance_actor: &A,
+    relay_inbox: Url,
+    data: &Data<T>,
+) -> Result<(), Error> {
+    let activity = serde_json::to_string_pretty(&WithContext::new_default(activity))?;
+    queue_activity(
+        actor_id,
+        id,
+        activity,
+        instance_actor,
+        vec![relay_inbox],
+        data,
+    )
+    .await
+}
+
+async fn relay_state_changed<T: Clone>(relay: &Url, state: RelayState, data: &Data<T>) {
+    notify(
+        "relay_state_changed",
+        data.config.events.relay_state_changed(relay, state),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        activity_queue::send_public_activity,
+        config::FederationConfig,
+        events::tests::{Event, RecordingEvents},
+        test_utils::{
+            fixtures::{create_note, Create},
+            harness::{Instance, Network},
+            DbConnection,
+            DbUser,
+            DB_USER,
+        },
+    };
+    use serde_json::json;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_relay_accept_object() {
+        let follow = RelayFollow::new(
+            url("https://alpha.example/actor"),
+            url("https://relay.example/actor"),
+            url("https://alpha.example/activities/follow/1"),
+        );
+        for object in [json!(follow.id), serde_json::to_value(&follow).unwrap()] {
+            let accept: RelayAccept = serde_json::from_value(json!({
+                "type": "Accept",
+                "id": "https://relay.example/activities/accept/1",
+                "actor": "https://relay.example/actor",
+                "object": object,
+            }))
+            .unwrap();
+            assert_eq!(accept.object, follow.id);
+        }
+    }
+
+    struct RelayNetwork {
+        alpha: Instance<DbConnection>,
+        relay: Instance<DbConnection>,
+        beta: Instance<DbConnection>,
+        events: RecordingEvents,
+        instance_actor: DbUser,
+    }
+
+    /// Network with the instance alpha which uses a relay, the relay and a follower on beta
+    fn relay_network() -> RelayNetwork {
+        let network = Network::default();
+        let events = RecordingEvents::default();
+        let mut config = FederationConfig::builder();
+        config
+            .domain("alpha.example")
+            .app_data(DbConnection::default())
+            .relays(vec![url("https://relay.example/inbox")])
+            .events(Box::new(events.clone()));
+        let alpha = network.instance::<Create, DbUser, _>(&mut config).unwrap();
+        let mut config = FederationConfig::builder();
+        config.app_data(DbConnection::default());
+        config.domain("relay.example");
+        let relay = network.instance::<Create, DbUser, _>(&mut config).unwrap();
+        config.domain("beta.example");
+        let beta = network.instance::<Create, DbUser, _>(&mut config).unwrap();
+
+        let mut instance_actor = DB_USER.clone();
+        instance_actor.federation_id = url("https://alpha.example/actor");
+        instance_actor.inbox = url("https://alpha.example/inbox");
+        RelayNetwork {
+            alpha,
+            relay,
+            beta,
+            events,
+            instance_actor,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_relay_subscription() {
+        let RelayNetwork {
+            alpha,
+            relay,
+            events,
+            instance_actor,
+            ..
+        } = relay_network();
+        let relay_actor = url("https://relay.example/actor");
+        let relay_inbox = url("https://relay.example/inbox");
+        let data = alpha.data();
+
+        let follow = RelayFollow::new(
+            instance_actor.federation_id.clone(),
+            relay_actor.clone(),
+            url("https://alpha.example/activities/follow/1"),
+        );
+        follow
+            .send(&instance_actor, relay_inbox.clone(), &data)
+            .await
+            .unwrap();
+        let received = relay.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].activity["type"], "Follow");
+        assert_eq!(received[0].activity["object"], relay_actor.as_str());
+        assert!(received[0].activity["@context"].is_array());
+
+        let accept: RelayAccept = serde_json::from_value(json!({
+            "type": "Accept",
+            "id": "https://relay.example/activities/accept/1",
+            "actor": relay_actor,
+            "object": follow,
+        }))
+        .unwrap();
+        accept.receive(&data).await.unwrap();
+        let mut forged = accept.clone();
+        forged.object = url("https://relay.example/activities/follow/1");
+        assert!(forged.receive(&data).await.is_err());
+
+        RelayUndo::new(follow, url("https://alpha.example/activities/undo/1"))
+            .send(&instance_actor, relay_inbox, &data)
+            .await
+            .unwrap();
+        let received = relay.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].activity["type"], "Undo");
+        assert_eq!(received[1].activity["object"]["type"], "Follow");
+
+        let relay_events: Vec<_> = events
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::Relay(..)))
+            .collect();
+        assert_eq!(
+            relay_events,
+            vec![
+                Event::Relay(relay_actor.clone(), RelayState::Pending),
+                Event::Relay(relay_actor.clone(), RelayState::Accepted),
+                Event::Relay(relay_actor, RelayState::Unsubscribed),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_public_activity_fan_out() {
+        let RelayNetwork {
+            alpha,
+            relay,
+            beta,
+            instance_actor,
+            ..
+        } = relay_network();
+        let bob_inbox = url("https://beta.example/u/bob/inbox");
+        let relay_inbox = url("https://relay.example/inbox");
+
+        let create = create_note(&instance_actor.federation_id, &[public()], "Hello world");
+        send_public_activity(
+            create.clone(),
+            &instance_actor,
+            vec![bob_inbox],
+            &alpha.data(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(beta.received().len(), 1);
+        let received = relay.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].activity["id"], create.id.as_str());
+
+        // a relay inbox which is also the shared inbox of a follower only receives it once
+        let create = create_note(&instance_actor.federation_id, &[public()], "Hello again");
+        send_public_activity(create, &instance_actor, vec![relay_inbox], &alpha.data())
+            .await
+            .unwrap();
+        assert_eq!(relay.received().len(), 2);
+    }
+}
@@ -0,0 +1,179 @@
+//! Struct which is used to federate the original markup of an object
+//!
+//! ```
+//! # use activitypub_federation::protocol::source::{Source, SourceMediaType};
+//! #[derive(serde::Deserialize)]
+//! struct Note {
+//!     content: String,
+//!     #[serde(default)]
+//!     source: Option<Source>,
+//! }
+//!
+//! let note: Note = serde_json::from_str(
+//! r#"{
+//!     "content": "<p><strong>Hello</strong></p>",
+//!     "source": {
+//!         "content": "**Hello**",
+//!         "mediaType": "text/markdown"
+//!     }
+//! }"#)?;
+//! let source = note.source.unwrap();
+//! assert_eq!(source.content, "**Hello**");
+//! assert_eq!(source.media_type, SourceMediaType::Markdown);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Media type of the [Source] content.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum SourceMediaType {
+    /// `text/markdown`
+    Markdown,
+    /// `text/html`
+    Html,
+    /// Any other media type, eg `text/x.misskeymarkdown`
+    Other(String),
+}
+
+impl From<String> for SourceMediaType {
+    fn from(media_type: String) -> Self {
+        match media_type.as_str() {
+            "text/markdown" => SourceMediaType::Markdown,
+            "text/html" => SourceMediaType::Html,
+            _ => SourceMediaType::Other(media_type),
+        }
+    }
+}
+
+impl From<SourceMediaType> for String {
+    fn from(media_type: SourceMediaType) -> Self {
+        match media_type {
+            SourceMediaType::Markdown => "text/markdown".to_string(),
+            SourceMediaType::Html => "text/html".to_string(),
+            SourceMediaType::Other(media_type) => media_type,
+        }
+    }
+}
+
+/// Original markup of an object, which is federated in the `source` field.
+///
+/// The `content` field of the object contains the rendered HTML, while this allows the receiver
+/// to edit the object without losing formatting. Some servers send a bare string instead of an
+/// object, this is parsed as markdown.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+    /// Content in original markup
+    pub content: String,
+    /// Markup language of the content
+    pub media_type: SourceMediaType,
+}
+
+impl Source {
+    /// Create a new source with markdown content
+    pub fn new_markdown(content: String) -> Self {
+        Source {
+            content,
+            media_type: SourceMediaType::Markdown,
+        }
+    }
+
+    /// Renders `markdown` with the given function, and returns the resulting HTML for use in
+    /// the `content` field together with the source.
+    ///
+    /// ```
+    /// # use activitypub_federation::protocol::source::Source;
+    /// let (content, source) = Source::render_markdown("*hi*", |m| format!("<p>{m}</p>"));
+    /// assert_eq!(content, "<p>*hi*</p>");
+    /// assert_eq!(source.content, "*hi*");
+    /// ```
+    pub fn render_markdown<F>(markdown: &str, render: F) -> (String, Self)
+    where
+        F: FnOnce(&str) -> String,
+    {
+        (render(markdown), Source::new_markdown(markdown.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Source {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Full {
+            content: String,
+            media_type: SourceMediaType,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum MaybeBare {
+            Full(Full),
+            Bare(String),
+        }
+
+        Ok(match MaybeBare::deserialize(deserializer)? {
+            MaybeBare::Full(Full {
+                content,
+                media_type,
+            }) => Source {
+                content,
+                media_type,
+            },
+            MaybeBare::Bare(content) => Source::new_markdown(content),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Note {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<Source>,
+    }
+
+    #[test]
+    fn test_parse_lemmy_source() {
+        for fixture in [
+            include_str!("../../assets/lemmy/page.json"),
+            include_str!("../../assets/lemmy/comment.json"),
+        ] {
+            let json: Value = serde_json::from_str(fixture).unwrap();
+            let source: Source = serde_json::from_value(json["source"].clone()).unwrap();
+            assert_eq!(source.media_type, SourceMediaType::Markdown);
+            assert!(json["content"].as_str().unwrap().starts_with("<p>"));
+            assert!(!source.content.starts_with("<p>"));
+            assert_eq!(serde_json::to_value(&source).unwrap(), json["source"]);
+        }
+    }
+
+    #[test]
+    fn test_source_variants() {
+        let parse = |value| serde_json::from_value::<Note>(value).unwrap().source;
+        assert_eq!(parse(json!({ "content": "" })), None);
+        assert_eq!(parse(json!({ "content": "", "source": null })), None);
+        assert_eq!(
+            parse(json!({ "content": "", "source": "**hi**" })),
+            Some(Source::new_markdown("**hi**".to_string()))
+        );
+        let misskey = json!({
+            "content": "$[x2 hi]",
+            "mediaType": "text/x.misskeymarkdown"
+        });
+        let source = parse(json!({ "content": "", "source": misskey })).unwrap();
+        assert_eq!(
+            source.media_type,
+            SourceMediaType::Other("text/x.misskeymarkdown".to_string())
+        );
+        assert_eq!(serde_json::to_value(&source).unwrap(), misskey);
+    }
+}
@@ -0,0 +1,256 @@
+//! Tags which are attached to objects, like mentions and hashtags
+//!
+//! ```
+//! # use activitypub_federation::protocol::{helpers::deserialize_skip_error, tag::Tag};
+//! #[derive(serde::Deserialize)]
+//! struct Note {
+//!     #[serde(deserialize_with = "deserialize_skip_error", default)]
+//!     tag: Vec<Tag>,
+//! }
+//!
+//! let note: Note = serde_json::from_str(
+//! r#"{"tag": [
+//!     {"type": "Mention", "href": "https://lemmy.ml/u/nutomic", "name": "@nutomic@lemmy.ml"},
+//!     {"type": "Emoji", "name": ":blobcat:"}
+//! ]}"#)?;
+//! assert!(matches!(note.tag[0], Tag::Mention(_)));
+//! assert!(matches!(note.tag[1], Tag::Other(_)));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{
+    fetch::object_id::ObjectId,
+    protocol::{
+        helpers::deserialize_one_or_many,
+        kind::{LinkType, MentionType},
+    },
+    traits::Object,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+/// Mention of an actor.
+///
+/// Mastodon only sends a notification to the mentioned user if the object contains this tag.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Mention {
+    /// Always `Mention`
+    #[serde(rename = "type")]
+    pub kind: MentionType,
+    /// Id of the mentioned actor
+    pub href: Url,
+    /// Webfinger handle of the mentioned actor, eg `@alice@example.com`
+    pub name: String,
+}
+
+impl Mention {
+    /// Create a new mention of `actor`, with its webfinger `handle` like `alice@example.com`.
+    ///
+    /// A leading `@` is added to the handle if it is missing.
+    pub fn new<Kind>(actor: &ObjectId<Kind>, handle: &str) -> Self
+    where
+        Kind: Object + Send + 'static,
+        for<'de2> <Kind as Object>::Kind: Deserialize<'de2>,
+    {
+        Mention {
+            kind: Default::default(),
+            href: actor.inner().clone(),
+            name: format!("@{}", handle.trim_start_matches('@')),
+        }
+    }
+}
+
+// Hashtags are a Mastodon extension, not part of the core vocabulary
+crate::kind!(HashtagType, Hashtag);
+
+/// Hashtag which is used in an object
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Hashtag {
+    /// Always `Hashtag`
+    #[serde(rename = "type")]
+    pub kind: HashtagType,
+    /// Url which lists objects with this hashtag
+    pub href: Url,
+    /// Name of the hashtag including leading `#`, eg `#lemmy`
+    pub name: String,
+}
+
+impl Hashtag {
+    /// Create a new hashtag with the given `name`.
+    ///
+    /// A leading `#` is added to the name if it is missing.
+    pub fn new(href: Url, name: &str) -> Self {
+        Hashtag {
+            kind: Default::default(),
+            href,
+            name: format!("#{}", name.trim_start_matches('#')),
+        }
+    }
+}
+
+/// Generic link, only `href` is required
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Link {
+    /// Always `Link`
+    #[serde(rename = "type")]
+    pub kind: LinkType,
+    /// Target of the link
+    pub href: Url,
+    /// Human readable name of the link
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Media type of the link target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Relation of the link target to the object, eg `alternate`
+    #[serde(
+        default,
+        deserialize_with = "deserialize_one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub rel: Vec<String>,
+}
+
+impl Link {
+    /// Create a new link to `href`
+    pub fn new(href: Url) -> Self {
+        Link {
+            kind: Default::default(),
+            href,
+            name: None,
+            media_type: None,
+            rel: vec![],
+        }
+    }
+}
+
+/// Any tag of an object.
+///
+/// Tags with unknown type, or which are missing required fields, are kept as [Tag::Other] so
+/// that they can be passed on unchanged.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Tag {
+    /// Mention of an actor
+    Mention(Mention),
+    /// Hashtag
+    Hashtag(Hashtag),
+    /// Generic link
+    Link(Link),
+    /// Any other tag, eg custom emoji
+    Other(Value),
+}
+
+impl From<Mention> for Tag {
+    fn from(mention: Mention) -> Self {
+        Tag::Mention(mention)
+    }
+}
+
+impl From<Hashtag> for Tag {
+    fn from(hashtag: Hashtag) -> Self {
+        Tag::Hashtag(hashtag)
+    }
+}
+
+impl From<Link> for Tag {
+    fn from(link: Link) -> Self {
+        Tag::Link(link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::DbUser;
+    use serde_json::json;
+
+    fn tags(fixture: &str) -> Value {
+        let mut object: Value = serde_json::from_str(fixture).unwrap();
+        object["tag"].take()
+    }
+
+    #[test]
+    fn test_parse_mastodon_tags() {
+        let json = tags(include_str!("../../assets/mastodon/note.json"));
+        let parsed: Vec<Tag> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                Mention {
+                    kind: MentionType,
+                    href: Url::parse("https://lemmy.ml/u/nutomic").unwrap(),
+                    name: "@nutomic@lemmy.ml".to_string(),
+                }
+                .into(),
+                Hashtag::new(
+                    Url::parse("https://mastodon.social/tags/lemmy").unwrap(),
+                    "lemmy"
+                )
+                .into(),
+            ]
+        );
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_parse_lemmy_tags() {
+        let json = tags(include_str!("../../assets/lemmy/comment.json"));
+        let parsed: Vec<Tag> = serde_json::from_value(json.clone()).unwrap();
+        let Tag::Mention(mention) = &parsed[0] else {
+            panic!("expected mention, got {:?}", parsed[0]);
+        };
+        assert_eq!(mention.name, "@LemmyDev@mastodon.social");
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_other_tags_round_trip() {
+        let json = json!([
+            {
+                "id": "https://mastodon.social/emojis/1234",
+                "type": "Emoji",
+                "name": ":blobcat:",
+                "icon": {
+                    "type": "Image",
+                    "mediaType": "image/png",
+                    "url": "https://files.mastodon.social/custom_emojis/images/blobcat.png"
+                }
+            },
+            { "type": "Hashtag", "name": "#nohref" },
+            {
+                "type": "Link",
+                "href": "https://example.com/post.html",
+                "mediaType": "text/html",
+                "rel": "alternate"
+            }
+        ]);
+        let parsed: Vec<Tag> = serde_json::from_value(json.clone()).unwrap();
+        assert!(matches!(parsed[0], Tag::Other(_)));
+        assert!(matches!(parsed[1], Tag::Other(_)));
+        let Tag::Link(link) = &parsed[2] else {
+            panic!("expected link, got {:?}", parsed[2]);
+        };
+        assert_eq!(link.rel, vec!["alternate"]);
+
+        let mut expected = json;
+        expected[2]["rel"] = json!(["alternate"]);
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_mention_new() {
+        let actor = ObjectId::<DbUser>::parse("https://example.com/u/alice").unwrap();
+        let expected = json!({
+            "type": "Mention",
+            "href": "https://example.com/u/alice",
+            "name": "@alice@example.com"
+        });
+        for handle in ["alice@example.com", "@alice@example.com"] {
+            let mention = Mention::new(&actor, handle);
+            assert_eq!(serde_json::to_value(mention).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Struct which is returned in place of objects that were deleted
+
+use crate::protocol::kind::TombstoneType;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Placeholder for an object which was deleted.
+///
+/// Some servers return this with HTTP status `410 Gone` when a deleted object is fetched, it is
+/// also used as `object` of `Delete` activities. To serve it for deleted local objects, use
+/// `tombstone_response` from the `actix_web::json` or `axum::json` module.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tombstone {
+    /// Always `Tombstone`
+    #[serde(rename = "type")]
+    pub kind: TombstoneType,
+    /// Id of the deleted object
+    pub id: Url,
+    /// Type of the deleted object, eg `Note`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub former_type: Option<String>,
+    /// Time when the object was deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<DateTime<FixedOffset>>,
+}
+
+impl Tombstone {
+    /// Create a new tombstone for the object with the given `id`
+    pub fn new(id: Url) -> Self {
+        Tombstone {
+            kind: Default::default(),
+            id,
+            former_type: None,
+            deleted: None,
+        }
+    }
+
+    /// Sets the type of the deleted object, eg `Note`
+    pub fn former_type(mut self, former_type: &str) -> Self {
+        self.former_type = Some(former_type.to_string());
+        self
+    }
+
+    /// Sets the time when the object was deleted
+    pub fn deleted(mut self, deleted: DateTime<Utc>) -> Self {
+        self.deleted = Some(deleted.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_parse_mastodon_tombstone() {
+        let delete: Value =
+            serde_json::from_str(include_str!("../../assets/mastodon/delete_note.json")).unwrap();
+        let tombstone: Tombstone = serde_json::from_value(delete["object"].clone()).unwrap();
+        assert_eq!(tombstone.id.as_str(), delete["object"]["id"]);
+        assert_eq!(tombstone.former_type, None);
+        assert_eq!(tombstone.deleted, None);
+        assert_eq!(
+            serde_json::to_value(&tombstone).unwrap(),
+            json!({ "type": "Tombstone", "id": delete["object"]["id"] })
+        );
+    }
+
+    #[test]
+    fn test_serialize_tombstone() {
+        let deleted = Utc.with_ymd_and_hms(2023, 1, 23, 14, 5, 42).unwrap();
+        let tombstone = Tombstone::new(Url::parse("https://example.com/post/1").unwrap())
+            .former_type("Note")
+            .deleted(deleted);
+        let json = json!({
+            "type": "Tombstone",
+            "id": "https://example.com/post/1",
+            "formerType": "Note",
+            "deleted": "2023-01-23T14:05:42Z"
+        });
+        assert_eq!(serde_json::to_value(&tombstone).unwrap(), json);
+        let parsed: Tombstone = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.deleted.unwrap(), deleted);
+    }
+}
@@ -0,0 +1,371 @@
+//! Activity of a type which is not handled by the application
+//!
+//! Use it as the last variant of an activity enum with `#[activity_handler(other)]`, so that
+//! activities of unknown types are accepted and ignored instead of being rejected. Otherwise the
+//! sending server considers the delivery failed and keeps retrying it.
+//!
+//! The variant has to wrap `UnknownActivity<Self>`. Activities whose `type` is handled by one of
+//! the other variants are not parsed as [UnknownActivity], so that eg a `Follow` with a missing
+//! field is still rejected as invalid instead of being ignored (see [KnownActivityTypes]).
+//!
+//! ```
+//! # use activitypub_federation::protocol::unknown_activity::UnknownActivity;
+//! # use activitypub_federation::{test_utils::Follow, traits::ActivityHandler};
+//! #[derive(serde::Deserialize, ActivityHandler)]
+//! #[serde(untagged)]
+//! enum PersonInbox {
+//!     Follow(Follow),
+//!     #[activity_handler(other)]
+//!     Other(UnknownActivity<Self>),
+//! }
+//!
+//! let activity: PersonInbox = serde_json::from_str(
+//! r#"{
+//!     "id": "https://example.com/activities/1",
+//!     "type": "EmojiReact",
+//!     "actor": "https://example.com/u/alice",
+//!     "object": "https://example.com/post/1",
+//!     "content": "🐈"
+//! }"#)?;
+//! let PersonInbox::Other(unknown) = activity else { panic!() };
+//! assert_eq!(unknown.kind(), Some("EmojiReact"));
+//!
+//! // known type, but `object` is missing
+//! let invalid = serde_json::from_str::<PersonInbox>(
+//! r#"{
+//!     "id": "https://example.com/activities/2",
+//!     "type": "Follow",
+//!     "actor": "https://example.com/u/alice"
+//! }"#);
+//! assert!(invalid.is_err());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::protocol::context::ACTIVITYSTREAMS_CONTEXT;
+use serde::{
+    de::{
+        value::StrDeserializer,
+        DeserializeOwned,
+        DeserializeSeed,
+        Error as _,
+        IntoDeserializer,
+        MapAccess,
+        Visitor,
+    },
+    forward_to_deserialize_any,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use serde_json::Value;
+use std::{
+    fmt::{Debug, Display, Formatter},
+    marker::PhantomData,
+};
+use url::Url;
+
+/// Activity with any type, from which only `id` and `actor` are parsed.
+///
+/// `actor` may also be an embedded object with `id`. The complete json is available with
+/// [UnknownActivity::value].
+///
+/// Activities for which [KnownActivityTypes::is_known_type] of `K` returns true fail to
+/// deserialize. `K` is the activity enum in which this is used as
+/// `#[activity_handler(other)]` variant, the default `()` accepts all types.
+pub struct UnknownActivity<K = ()> {
+    id: Url,
+    actor: Url,
+    value: Value,
+    known: PhantomData<fn() -> K>,
+}
+
+impl<K> UnknownActivity<K> {
+    /// Id of the activity
+    pub fn id(&self) -> &Url {
+        &self.id
+    }
+
+    /// Actor who sent the activity
+    pub fn actor(&self) -> &Url {
+        &self.actor
+    }
+
+    /// Value of the `type` field, if it is a string
+    pub fn kind(&self) -> Option<&str> {
+        self.value.get("type").and_then(Value::as_str)
+    }
+
+    /// The complete activity json
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Returns the complete activity json
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+}
+
+impl<K> Clone for UnknownActivity<K> {
+    fn clone(&self) -> Self {
+        UnknownActivity {
+            id: self.id.clone(),
+            actor: self.actor.clone(),
+            value: self.value.clone(),
+            known: PhantomData,
+        }
+    }
+}
+
+impl<K> Debug for UnknownActivity<K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnknownActivity")
+            .field("id", &self.id)
+            .field("actor", &self.actor)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<K> PartialEq for UnknownActivity<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<'de, K: KnownActivityTypes> Deserialize<'de> for UnknownActivity<K> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        if let Some(kind) = value.get("type").and_then(Value::as_str) {
+            if K::is_known_type(kind) {
+                return Err(D::Error::custom(format!(
+                    "invalid activity of known type {kind}"
+                )));
+            }
+        }
+        let url = |field: &str, value: Option<&Value>| {
+            let value = match value {
+                Some(Value::Object(object)) => object.get("id"),
+                value => value,
+            };
+            let url = value
+                .and_then(Value::as_str)
+                .ok_or_else(|| D::Error::custom(format!("activity has no {field}")))?;
+            Url::parse(url).map_err(D::Error::custom)
+        };
+        Ok(UnknownActivity {
+            id: url("id", value.get("id"))?,
+            actor: url("actor", value.get("actor"))?,
+            value,
+            known: PhantomData,
+        })
+    }
+}
+
+impl<K> Serialize for UnknownActivity<K> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.value.serialize(serializer)
+    }
+}
+
+/// Activity types which are handled by the other variants of an activity enum, and must not be
+/// parsed as [UnknownActivity].
+///
+/// Implemented by `#[derive(ActivityHandler)]` for enums with an `#[activity_handler(other)]`
+/// variant. A type counts as known if the `type` field of one of the other variants accepts it,
+/// see [accepts_type]. Variants which are enums themselves are not checked, so activities of
+/// nested enums which fail to parse are still treated as unknown. The same applies to variants
+/// whose `type` field is a plain `String`, as it doesn't restrict the type at all.
+pub trait KnownActivityTypes {
+    /// Returns true if activities with the given `type` are handled elsewhere
+    fn is_known_type(kind: &str) -> bool;
+}
+
+impl KnownActivityTypes for () {
+    fn is_known_type(_: &str) -> bool {
+        false
+    }
+}
+
+/// Returns true if the `type` field of `T` accepts the value `kind`.
+///
+/// This deserializes `T` from an object which only contains the `type` and a default `@context`,
+/// so it works for the marker types in [protocol::kind](crate::protocol::kind) as well as enums
+/// like those in `activitystreams_kinds`, also when wrapped in
+/// [WithContext](crate::protocol::context::WithContext) or `Box`. Other required fields of `T`
+/// are ignored.
+///
+/// The type is only considered accepted if the `type` field actually checks its value, that is
+/// if it rejects an empty type. Fields of type `String` accept everything, so this returns false
+/// for them. The same happens if `T` fails on some other missing field before the `type` is
+/// checked.
+///
+/// ```
+/// # use activitypub_federation::protocol::{context::WithContext, unknown_activity::accepts_type};
+/// # use activitypub_federation::test_utils::Follow;
+/// assert!(accepts_type::<Follow>("Follow"));
+/// assert!(!accepts_type::<Follow>("Like"));
+/// assert!(accepts_type::<WithContext<Follow>>("Follow"));
+/// assert!(!accepts_type::<WithContext<Follow>>("Like"));
+/// ```
+pub fn accepts_type<T: DeserializeOwned>(kind: &str) -> bool {
+    probe_type::<T>(kind) && !probe_type::<T>("")
+}
+
+/// Returns true if deserializing `T` with the given `type` fails at most because of missing or
+/// unexpected fields
+fn probe_type<T: DeserializeOwned>(kind: &str) -> bool {
+    let entries = [("type", kind), ("@context", ACTIVITYSTREAMS_CONTEXT)];
+    match T::deserialize(TypeProbe(&entries)) {
+        Ok(_) | Err(ProbeError::Incomplete) => true,
+        Err(ProbeError::Other) => false,
+    }
+}
+
+/// Deserializer for an object with the given string fields
+struct TypeProbe<'a>(&'a [(&'static str, &'a str)]);
+
+impl<'de, 'a> Deserializer<'de> for TypeProbe<'a> {
+    type Error = ProbeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_map(TypeProbeMap(self.0.iter()))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Returns the fields in order, `type` first so that it is checked before anything else
+struct TypeProbeMap<'a>(std::slice::Iter<'a, (&'static str, &'a str)>);
+
+impl<'de, 'a> MapAccess<'de> for TypeProbeMap<'a> {
+    type Error = ProbeError;
+
+    fn next_key_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, ProbeError> {
+        let Some((key, _)) = self.0.clone().next() else {
+            return Ok(None);
+        };
+        let key: StrDeserializer<'_, ProbeError> = key.into_deserializer();
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, ProbeError> {
+        let value = self.0.next().map(|(_, value)| *value).unwrap_or_default();
+        seed.deserialize(ProbeValue(value))
+    }
+}
+
+/// String value which can also be deserialized as `Some`, like a json string
+struct ProbeValue<'a>(&'a str);
+
+impl<'de, 'a> Deserializer<'de> for ProbeValue<'a> {
+    type Error = ProbeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_some(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Distinguishes missing and unexpected fields, which show that the `type` was accepted, from
+/// other errors
+#[derive(Debug)]
+enum ProbeError {
+    Incomplete,
+    Other,
+}
+
+impl serde::de::Error for ProbeError {
+    fn custom<T: Display>(_: T) -> Self {
+        ProbeError::Other
+    }
+
+    fn missing_field(_: &'static str) -> Self {
+        ProbeError::Incomplete
+    }
+
+    fn unknown_field(_: &str, _: &'static [&'static str]) -> Self {
+        ProbeError::Incomplete
+    }
+}
+
+impl Display for ProbeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_unknown_activity() {
+        let json = json!({
+            "id": "https://example.com/activities/1",
+            "type": "Listen",
+            "actor": { "type": "Person", "id": "https://example.com/u/alice" },
+            "object": "https://example.com/track/1"
+        });
+        let activity: UnknownActivity = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(activity.id().as_str(), "https://example.com/activities/1");
+        assert_eq!(activity.actor().as_str(), "https://example.com/u/alice");
+        assert_eq!(activity.kind(), Some("Listen"));
+        assert_eq!(serde_json::to_value(&activity).unwrap(), json);
+
+        for invalid in [
+            json!({ "actor": "https://example.com/u/alice" }),
+            json!({ "id": "https://example.com/activities/1" }),
+            json!({ "id": "invalid", "actor": "https://example.com/u/alice" }),
+            json!("https://example.com/activities/1"),
+        ] {
+            assert!(serde_json::from_value::<UnknownActivity>(invalid).is_err());
+        }
+    }
+    #[test]
+    fn test_accepts_type() {
+        use crate::{protocol::context::WithContext, test_utils::Follow};
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct AnyType {
+            #[serde(rename = "type")]
+            kind: String,
+            id: Url,
+        }
+
+        assert!(accepts_type::<WithContext<Follow>>("Follow"));
+        assert!(accepts_type::<Box<WithContext<Follow>>>("Follow"));
+        assert!(!accepts_type::<WithContext<Follow>>("Like"));
+        assert!(!accepts_type::<AnyType>("Like"));
+    }
+}
@@ -28,7 +28,8 @@
 //! ```
 //!
 //! The enums in [activitystreams_kinds] work in the same way, and can be used to
-//! distinguish different activity types.
+//! distinguish different activity types. [protocol::kind](crate::protocol::kind) has unit structs
+//! for the same purpose.
 
 use serde::{Deserialize, Serialize};
 
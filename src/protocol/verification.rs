@@ -1,9 +1,13 @@
 //! Verify that received data is valid
+//!
+//! Urls are normalized by [Url::parse], so that the comparisons here are not affected by
+//! different hostname casing, internationalized domain names which are sent in unicode or
+//! punycode form, and default ports which are given explicitly.
 
 use crate::error::Error;
-use url::Url;
+use url::{Host, Url};
 
-/// Check that both urls have the same domain. If not, return UrlVerificationError.
+/// Check that both urls have the same domain and port. If not, return UrlVerificationError.
 ///
 /// ```
 /// # use url::Url;
@@ -14,10 +18,12 @@ use url::Url;
 /// # Ok::<(), url::ParseError>(())
 /// ```
 pub fn verify_domains_match(a: &Url, b: &Url) -> Result<(), Error> {
-    if a.domain() != b.domain() {
-        return Err(Error::UrlVerificationError("Domains do not match"));
+    match (host_with_port(a), host_with_port(b)) {
+        (Some(host_a), Some(host_b)) if host_a == host_b => Ok(()),
+        _ => Err(Error::UrlVerificationError(format!(
+            "Domains do not match: {a} and {b}"
+        ))),
     }
-    Ok(())
 }
 
 /// Check that both urls are identical. If not, return UrlVerificationError.
@@ -32,7 +38,148 @@ pub fn verify_domains_match(a: &Url, b: &Url) -> Result<(), Error> {
 /// ```
 pub fn verify_urls_match(a: &Url, b: &Url) -> Result<(), Error> {
     if a != b {
-        return Err(Error::UrlVerificationError("Urls do not match"));
+        return Err(Error::UrlVerificationError(format!(
+            "Urls do not match: {a} and {b}"
+        )));
     }
     Ok(())
 }
+
+/// Returns the host of the url in normalized form, followed by the port if it is not the
+/// default, eg `localhost:8001`. All comparisons of hosts use this form, see [normalize_domain].
+pub(crate) fn host_with_port(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    })
+}
+
+/// Converts a domain with optional port, eg from the config or a webfinger identifier, to the
+/// form which is returned by [host_with_port] for urls on that domain. Internationalized domain
+/// names are converted to lowercase punycode, so `BLOG.københavn.example` becomes
+/// `blog.xn--kbenhavn-54a.example`. Returns `None` if the domain or port is invalid.
+pub(crate) fn normalize_domain(domain: &str) -> Option<String> {
+    let (host, port) = match domain.rsplit_once(':') {
+        // the colon may also be part of an IPv6 address, eg `[::1]`
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port.parse::<u16>().ok()?)),
+        _ => (domain, None),
+    };
+    let host = Host::parse(host).ok()?;
+    Some(match port {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(a: &str, b: &str) -> (Url, Url) {
+        (Url::parse(a).unwrap(), Url::parse(b).unwrap())
+    }
+
+    #[test]
+    fn test_verify_domains_match() {
+        for (a, b, matches) in [
+            ("https://example.com/a", "https://example.com/b", true),
+            ("https://EXAMPLE.com/a", "https://example.COM/b", true),
+            ("https://example.com:443/a", "https://example.com/b", true),
+            ("http://example.com:80/a", "https://example.com/b", true),
+            ("https://example.com/a/", "https://example.com", true),
+            ("https://münchen.de/a", "https://xn--mnchen-3ya.de/b", true),
+            ("https://MÜNCHEN.de/a", "https://münchen.de/b", true),
+            ("http://localhost:8001/a", "http://localhost:8001/b", true),
+            ("http://localhost:8001/a", "http://localhost:8002/a", false),
+            ("https://example.com:8443/a", "https://example.com/a", false),
+            ("https://example.com/a", "https://sub.example.com/a", false),
+            (
+                "https://example.com/a",
+                "https://example.com.evil.net/a",
+                false,
+            ),
+            ("https://münchen.de/a", "https://munchen.de/a", false),
+            ("http://127.0.0.1/a", "http://127.0.0.1/b", true),
+            ("http://127.0.0.1/a", "http://127.0.0.2/a", false),
+        ] {
+            let (a, b) = urls(a, b);
+            assert_eq!(verify_domains_match(&a, &b).is_ok(), matches, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn test_verify_urls_match() {
+        for (a, b, matches) in [
+            ("https://example.com/a", "https://example.com/a", true),
+            ("https://EXAMPLE.com/a", "https://example.com/a", true),
+            ("https://example.com:443/a", "https://example.com/a", true),
+            ("https://example.com", "https://example.com/", true),
+            (
+                "https://münchen.de/u/b",
+                "https://xn--mnchen-3ya.de/u/b",
+                true,
+            ),
+            ("https://example.com/a/", "https://example.com/a", false),
+            ("https://example.com/A", "https://example.com/a", false),
+            ("http://example.com/a", "https://example.com/a", false),
+            ("https://example.com:8443/a", "https://example.com/a", false),
+            ("https://example.com/a?b", "https://example.com/a", false),
+        ] {
+            let (a, b) = urls(a, b);
+            assert_eq!(verify_urls_match(&a, &b).is_ok(), matches, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn test_error_message() {
+        let (a, b) = urls("https://example.com/a", "https://sample.net/b");
+        let err = verify_domains_match(&a, &b).unwrap_err().to_string();
+        assert!(err.contains("https://example.com/a"), "{err}");
+        assert!(err.contains("https://sample.net/b"), "{err}");
+        let err = verify_urls_match(&a, &b).unwrap_err().to_string();
+        assert!(err.contains("https://example.com/a"), "{err}");
+        assert!(err.contains("https://sample.net/b"), "{err}");
+    }
+
+    #[test]
+    fn test_normalize_domain() {
+        for (domain, normalized) in [
+            ("example.com", Some("example.com")),
+            ("Example.COM", Some("example.com")),
+            (
+                "blog.københavn.example",
+                Some("blog.xn--kbenhavn-54a.example"),
+            ),
+            (
+                "BLOG.KØBENHAVN.example",
+                Some("blog.xn--kbenhavn-54a.example"),
+            ),
+            (
+                "blog.xn--kbenhavn-54a.example",
+                Some("blog.xn--kbenhavn-54a.example"),
+            ),
+            ("localhost:8001", Some("localhost:8001")),
+            ("münchen.de:8443", Some("xn--mnchen-3ya.de:8443")),
+            ("[::1]:8001", Some("[::1]:8001")),
+            ("[::1]", Some("[::1]")),
+            ("example.com:port", None),
+            ("exa mple.com", None),
+            ("", None),
+        ] {
+            assert_eq!(normalize_domain(domain).as_deref(), normalized, "{domain}");
+        }
+        let url = Url::parse("https://blog.københavn.example:8443/u/soren").unwrap();
+        assert_eq!(
+            host_with_port(&url),
+            normalize_domain("blog.københavn.example:8443")
+        );
+    }
+
+    #[test]
+    fn test_host_with_port() {
+        let (a, b) = urls("http://LocalHost:8001/a", "https://example.com:443/");
+        assert_eq!(host_with_port(&a).as_deref(), Some("localhost:8001"));
+        assert_eq!(host_with_port(&b).as_deref(), Some("example.com"));
+    }
+}
@@ -32,7 +32,7 @@ impl Future for BytesFuture {
             let this = self.as_mut().project();
             if let Some(chunk) = ready!(this.stream.poll_next(cx))
                 .transpose()
-                .map_err(Error::other)?
+                .map_err(Error::Reqwest)?
             {
                 this.aggregator.put(chunk);
                 if this.aggregator.len() > *this.limit {
@@ -66,24 +66,7 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let bytes = ready!(this.future.poll(cx))?;
-        Poll::Ready(serde_json::from_slice(&bytes).map_err(Error::other))
-    }
-}
-
-pin_project! {
-    pub struct TextFuture {
-        #[pin]
-        future: BytesFuture,
-    }
-}
-
-impl Future for TextFuture {
-    type Output = Result<String, Error>;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-        let bytes = ready!(this.future.poll(cx))?;
-        Poll::Ready(String::from_utf8(bytes.to_vec()).map_err(Error::other))
+        Poll::Ready(serde_json::from_slice(&bytes).map_err(Error::Json))
     }
 }
 
@@ -92,26 +75,22 @@ impl Future for TextFuture {
 /// Reqwest doesn't limit the response body size by default nor does it offer an option to configure one.
 /// Since we have to fetch data from untrusted sources, not restricting the maximum size is a DoS hazard for us.
 ///
-/// This shim reimplements the `bytes`, `json`, and `text` functions and restricts the bodies to 100KB.
+/// This shim reimplements the `bytes` and `json` functions and restricts the bodies to 100KB.
 ///
 /// TODO: Remove this shim as soon as reqwest gets support for size-limited bodies.
 pub trait ResponseExt {
     type BytesFuture;
     type JsonFuture<T>;
-    type TextFuture;
 
     /// Size limited version of `bytes` to work around a reqwest issue. Check [`ResponseExt`] docs for details.
     fn bytes_limited(self) -> Self::BytesFuture;
     /// Size limited version of `json` to work around a reqwest issue. Check [`ResponseExt`] docs for details.
     fn json_limited<T>(self) -> Self::JsonFuture<T>;
-    /// Size limited version of `text` to work around a reqwest issue. Check [`ResponseExt`] docs for details.
-    fn text_limited(self) -> Self::TextFuture;
 }
 
 impl ResponseExt for Response {
     type BytesFuture = BytesFuture;
     type JsonFuture<T> = JsonFuture<T>;
-    type TextFuture = TextFuture;
 
     fn bytes_limited(self) -> Self::BytesFuture {
         BytesFuture {
@@ -127,10 +106,4 @@ impl ResponseExt for Response {
             future: self.bytes_limited(),
         }
     }
-
-    fn text_limited(self) -> Self::TextFuture {
-        TextFuture {
-            future: self.bytes_limited(),
-        }
-    }
 }
@@ -0,0 +1,86 @@
+//! Axum router with an inbox, to test receiving activities through HTTP
+//!
+//! The router can be called without opening a socket with `tower::ServiceExt::oneshot`, together
+//! with a request from [SignedRequestBuilder](crate::test_utils::request::SignedRequestBuilder):
+//!
+//! ```
+//! # use activitypub_federation::config::FederationConfig;
+//! # use activitypub_federation::test_utils::{axum::inbox_router, fixtures, request::SignedRequestBuilder};
+//! # use activitypub_federation::test_utils::{DbConnection, DbUser, Follow, DB_USER, DB_USER_KEYPAIR};
+//! # use activitypub_federation::traits::Actor;
+//! # use axum::body::Body;
+//! # use http::StatusCode;
+//! # use tower::ServiceExt;
+//! # use url::Url;
+//! # actix_rt::System::new().block_on(async {
+//! let config = FederationConfig::builder()
+//!     .domain("example.com")
+//!     .app_data(DbConnection::default())
+//!     .debug(true)
+//!     .build()?;
+//! let router = inbox_router::<Follow, DbUser, _>("/inbox", config);
+//!
+//! // signed by the mock user, which is always found in the database
+//! let actor_id = DB_USER.id();
+//! let follow = fixtures::follow(&actor_id, &Url::parse("https://example.com/u/bob")?);
+//! let request = SignedRequestBuilder::new(&follow, &DB_USER_KEYPAIR, &actor_id)
+//!     .build()
+//!     .await?;
+//! let response = router.oneshot(request.into_http().map(Body::from)).await?;
+//! assert_eq!(response.status(), StatusCode::OK);
+//! # Ok::<(), anyhow::Error>(())
+//! # }).unwrap();
+//! ```
+
+use crate::{
+    axum::inbox::{receive_activity, ActivityData},
+    config::{Data, FederationConfig, FederationMiddleware},
+    error::Error,
+    traits::{ActivityHandler, Actor, Object},
+};
+use axum::{
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+
+/// Router which receives activities at `path` with [inbox_handler], and passes the config to it
+/// with [FederationMiddleware]
+pub fn inbox_router<Activity, ActorT, T>(path: &str, config: FederationConfig<T>) -> Router
+where
+    Activity: ActivityHandler<DataType = T> + DeserializeOwned + Send + Sync + 'static,
+    ActorT: Object<DataType = T> + Actor + Send + Sync + 'static,
+    for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2> + Send,
+    <Activity as ActivityHandler>::Error:
+        From<Error> + From<<ActorT as Object>::Error> + Display + Send,
+    <ActorT as Object>::Error: From<Error>,
+    T: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route(path, post(inbox_handler::<Activity, ActorT, T>))
+        .layer(FederationMiddleware::new(config))
+}
+
+/// Inbox handler which responds with `200 OK` if the activity was received, and with
+/// `400 Bad Request` and the error message otherwise
+pub async fn inbox_handler<Activity, ActorT, T>(
+    data: Data<T>,
+    activity_data: ActivityData,
+) -> Response
+where
+    Activity: ActivityHandler<DataType = T> + DeserializeOwned + Send + 'static,
+    ActorT: Object<DataType = T> + Actor + Send + 'static,
+    for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2>,
+    <Activity as ActivityHandler>::Error:
+        From<Error> + From<<ActorT as Object>::Error> + Display + Send,
+    <ActorT as Object>::Error: From<Error>,
+    T: Clone,
+{
+    match receive_activity::<Activity, ActorT, T>(activity_data, &data).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,63 @@
+//! Manual [Clock] for tests of time dependent behaviour
+//!
+//! ```
+//! # use activitypub_federation::clock::Clock;
+//! # use activitypub_federation::config::FederationConfig;
+//! # use activitypub_federation::test_utils::{clock::ManualClock, DbConnection};
+//! # use std::time::Duration;
+//! # let _ = actix_rt::System::new();
+//! let clock = ManualClock::default();
+//! let config = FederationConfig::builder()
+//!     .domain("example.com")
+//!     .app_data(DbConnection::default())
+//!     .clock(Box::new(clock.clone()))
+//!     .build()?;
+//!
+//! let start = clock.now();
+//! // eg to let incoming signatures expire, or stored objects become outdated
+//! clock.advance(Duration::from_secs(60));
+//! assert_eq!(clock.now(), start + Duration::from_secs(60));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::clock::Clock;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// Clock which stands still until it is advanced or set. Clones share the same time.
+///
+/// The default starts at the current system time, so that it can be used with signatures and
+/// timestamps which are generated from the system time.
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    /// Clock which starts at `now`
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock(Arc::new(Mutex::new(now)))
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("lock clock") += duration;
+    }
+
+    /// Sets the clock to the given time, which may also be in the past
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().expect("lock clock") = now;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(SystemTime::now())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().expect("lock clock")
+    }
+}
@@ -0,0 +1,317 @@
+//! Generators for activities of common interactions, with unique ids
+//!
+//! Each activity gets an id on the domain of its actor, which is unique within the test process.
+//! The activities are accepted by an inbox of [DbConnection] without any checks, so they can be
+//! signed with [SignedRequestBuilder](crate::test_utils::request::SignedRequestBuilder) or sent
+//! between instances of the [harness](crate::test_utils::harness). Use [json] for the serialized
+//! form with `@context`.
+//!
+//! ```
+//! # use activitypub_federation::test_utils::{fixtures, DB_USER};
+//! # use activitypub_federation::protocol::audience::public;
+//! # use activitypub_federation::traits::{ActivityHandler, Actor};
+//! # use url::Url;
+//! let bob = Url::parse("https://example.com/u/bob")?;
+//! let follow = fixtures::follow(&DB_USER.id(), &bob);
+//! let undo = fixtures::undo(follow.clone());
+//! assert_eq!(undo.object.id, follow.id);
+//! assert_ne!(undo.id, follow.id);
+//!
+//! let create = fixtures::create_note(&DB_USER.id(), &[public()], "Hello world");
+//! let json = fixtures::json(&create);
+//! assert_eq!(json["type"], "Create");
+//! assert_eq!(json["object"]["content"], "Hello world");
+//! assert_eq!(json["@context"][0], "https://www.w3.org/ns/activitystreams");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::object_id::ObjectId,
+    protocol::{
+        audience::Audience,
+        context::WithContext,
+        kind::{AcceptType, AnnounceType, CreateType, DeleteType, UndoType},
+    },
+    test_utils::{DbConnection, DbUser, Follow, Note},
+    traits::ActivityHandler,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use url::Url;
+
+/// Accepts an activity, usually a [Follow]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Accept<A = Follow> {
+    /// Always `Accept`
+    #[serde(rename = "type")]
+    pub kind: AcceptType,
+    /// Id of the activity
+    pub id: Url,
+    /// User who accepts
+    pub actor: ObjectId<DbUser>,
+    /// Activity which is accepted
+    pub object: A,
+}
+
+/// Reverts a previous activity of the same actor
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Undo<A> {
+    /// Always `Undo`
+    #[serde(rename = "type")]
+    pub kind: UndoType,
+    /// Id of the activity
+    pub id: Url,
+    /// User who reverts the activity
+    pub actor: ObjectId<DbUser>,
+    /// Activity which is reverted
+    pub object: A,
+}
+
+/// Publishes a new object, usually a [Note]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Create<O = Note> {
+    /// Always `Create`
+    #[serde(rename = "type")]
+    pub kind: CreateType,
+    /// Id of the activity
+    pub id: Url,
+    /// Author of the object
+    pub actor: ObjectId<DbUser>,
+    /// Object which is created
+    pub object: O,
+    /// Recipients, same as those of the object
+    #[serde(flatten)]
+    pub audience: Audience,
+}
+
+/// Deletes an object
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delete {
+    /// Always `Delete`
+    #[serde(rename = "type")]
+    pub kind: DeleteType,
+    /// Id of the activity
+    pub id: Url,
+    /// User who deletes
+    pub actor: ObjectId<DbUser>,
+    /// Id of the object which is deleted
+    pub object: Url,
+    /// Recipients
+    #[serde(flatten)]
+    pub audience: Audience,
+}
+
+/// Shares an object, also known as boost
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Announce {
+    /// Always `Announce`
+    #[serde(rename = "type")]
+    pub kind: AnnounceType,
+    /// Id of the activity
+    pub id: Url,
+    /// User who shares
+    pub actor: ObjectId<DbUser>,
+    /// Id of the object which is shared
+    pub object: Url,
+    /// Recipients
+    #[serde(flatten)]
+    pub audience: Audience,
+}
+
+/// Returns a new id on the domain of `actor`, eg `https://example.com/activities/follow/1`
+pub fn generate_id(actor: &Url, kind: &str) -> Url {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    let mut id = actor.clone();
+    let number = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    id.set_path(&format!("/{kind}/{number}"));
+    id.set_query(None);
+    id.set_fragment(None);
+    id
+}
+
+/// `actor` follows `object`
+pub fn follow(actor: &Url, object: &Url) -> Follow {
+    Follow {
+        actor: actor.clone().into(),
+        object: object.clone().into(),
+        kind: Default::default(),
+        id: generate_id(actor, "activities/follow"),
+    }
+}
+
+/// `actor` accepts `activity`, eg the follow of one of its followers
+pub fn accept<A: ActivityHandler>(actor: &Url, activity: A) -> Accept<A> {
+    Accept {
+        kind: Default::default(),
+        id: generate_id(actor, "activities/accept"),
+        actor: actor.clone().into(),
+        object: activity,
+    }
+}
+
+/// The actor of `activity` reverts it
+pub fn undo<A: ActivityHandler>(activity: A) -> Undo<A> {
+    Undo {
+        kind: Default::default(),
+        id: generate_id(activity.actor(), "activities/undo"),
+        actor: activity.actor().clone().into(),
+        object: activity,
+    }
+}
+
+/// `author` publishes a new note with the content, which is addressed to `to`
+pub fn create_note(author: &Url, to: &[Url], content: &str) -> Create<Note> {
+    let audience = Audience {
+        to: to.to_vec(),
+        ..Default::default()
+    };
+    let note = Note {
+        kind: Default::default(),
+        id: generate_id(author, "objects").into(),
+        attributed_to: author.clone().into(),
+        content: content.to_string(),
+        audience: audience.clone(),
+    };
+    Create {
+        kind: Default::default(),
+        id: generate_id(author, "activities/create"),
+        actor: author.clone().into(),
+        object: note,
+        audience,
+    }
+}
+
+/// `actor` deletes the object, and notifies `to` about it
+pub fn delete(actor: &Url, object: &Url, to: &[Url]) -> Delete {
+    Delete {
+        kind: Default::default(),
+        id: generate_id(actor, "activities/delete"),
+        actor: actor.clone().into(),
+        object: object.clone(),
+        audience: Audience {
+            to: to.to_vec(),
+            ..Default::default()
+        },
+    }
+}
+
+/// `actor` shares the object with `to`
+pub fn announce(actor: &Url, object: &Url, to: &[Url]) -> Announce {
+    Announce {
+        kind: Default::default(),
+        id: generate_id(actor, "activities/announce"),
+        actor: actor.clone().into(),
+        object: object.clone(),
+        audience: Audience {
+            to: to.to_vec(),
+            ..Default::default()
+        },
+    }
+}
+
+/// Serializes the activity with the default `@context`, as it is sent to other servers
+pub fn json<A: Serialize>(activity: &A) -> Value {
+    serde_json::to_value(WithContext::new_default(activity)).expect("serialize fixture")
+}
+
+/// Implements [ActivityHandler] for an activity which is accepted without any checks
+macro_rules! accept_without_checks {
+    ($activity:ty $(, $param:ident)?) => {
+        #[async_trait]
+        impl$(<$param: Send + Sync>)? ActivityHandler for $activity {
+            type DataType = DbConnection;
+            type Error = Error;
+
+            fn id(&self) -> &Url {
+                &self.id
+            }
+
+            fn actor(&self) -> &Url {
+                self.actor.inner()
+            }
+
+            async fn verify(&self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+                Ok(())
+            }
+
+            async fn receive(self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+                Ok(())
+            }
+        }
+    };
+}
+
+accept_without_checks!(Accept<A>, A);
+accept_without_checks!(Undo<A>, A);
+accept_without_checks!(Create<O>, O);
+accept_without_checks!(Delete);
+accept_without_checks!(Announce);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::FederationConfig,
+        protocol::audience::public,
+        test_utils::{harness::Network, DB_USER},
+        traits::Actor,
+    };
+
+    #[test]
+    fn test_unique_ids() {
+        let bob = Url::parse("https://example.com/u/bob").unwrap();
+        let first = follow(&DB_USER.id(), &bob);
+        let second = follow(&DB_USER.id(), &bob);
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.id.domain(), DB_USER.id().domain());
+
+        let undo = undo(first.clone());
+        assert_eq!(undo.actor.inner(), first.actor.inner());
+        assert_ne!(undo.id, first.id);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let bob = Url::parse("https://example.com/u/bob").unwrap();
+        let accept = accept(&bob, follow(&DB_USER.id(), &bob));
+        let json = json(&accept);
+        assert_eq!(json["type"], "Accept");
+        assert_eq!(json["object"]["type"], "Follow");
+
+        let parsed: WithContext<Accept> = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.inner().id, accept.id);
+        assert_eq!(parsed.inner().object.id, accept.object.id);
+    }
+
+    #[actix_rt::test]
+    async fn test_send_through_harness() {
+        let network = Network::default();
+        let mut config = FederationConfig::builder();
+        config
+            .domain("alpha.example")
+            .app_data(DbConnection::default());
+        let alpha = network.instance::<Create, DbUser, _>(&mut config).unwrap();
+        config.domain("beta.example");
+        let beta = network.instance::<Create, DbUser, _>(&mut config).unwrap();
+
+        let mut bob = DB_USER.clone();
+        bob.inbox = Url::parse("https://beta.example/u/bob/inbox").unwrap();
+        let create = create_note(&DB_USER.id(), &[public()], "Hello world");
+        alpha.send(create.clone(), &*DB_USER, &[bob]).await.unwrap();
+
+        let received = beta.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].result, Ok(()));
+        assert_eq!(received[0].activity["id"], create.id.as_str());
+        assert_eq!(received[0].activity["object"]["content"], "Hello world");
+    }
+}
@@ -0,0 +1,532 @@
+//! In-process network for testing federation between multiple instances
+//!
+//! Each instance gets a [FederationConfig] whose HTTP client doesn't open any connections.
+//! Instead requests are routed by domain to the other instances of the same [Network]:
+//!
+//! - `POST` requests are handled like an inbox, including verification of the digest and HTTP
+//!   signature, and then passed to the [trait@ActivityHandler] of the instance.
+//! - `GET` requests return the objects which were published with [Instance::expose], so that
+//!   actors and other objects can be fetched.
+//!
+//! The configs are built in debug mode, so activities are delivered before
+//! [Instance::send] returns, and can be checked directly afterwards with
+//! [Instance::received].
+//!
+//! ```
+//! # use activitypub_federation::config::FederationConfig;
+//! # use activitypub_federation::fetch::{fetch_object_http, object_id::ObjectId};
+//! # use activitypub_federation::test_utils::{harness::Network, DbConnection, DbUser, Follow, Person, DB_USER};
+//! # use activitypub_federation::traits::Object;
+//! # use url::Url;
+//! # actix_rt::System::new().block_on(async {
+//! let network = Network::default();
+//! let mut config = FederationConfig::builder();
+//! config.domain("alpha.example").app_data(DbConnection::default());
+//! let alpha = network.instance::<Follow, DbUser, _>(&mut config)?;
+//! config.domain("beta.example");
+//! let beta = network.instance::<Follow, DbUser, _>(&mut config)?;
+//!
+//! let id = Url::parse("https://alpha.example/u/alice")?;
+//! let mut alice = DB_USER.into_json(&alpha.data()).await?;
+//! alice.id = ObjectId::from(id.clone());
+//! alpha.expose(&id, &alice)?;
+//! let fetched: Person = fetch_object_http(&id, &beta.data()).await?;
+//! assert_eq!(fetched.id, alice.id);
+//! # Ok::<(), anyhow::Error>(())
+//! # }).unwrap();
+//! ```
+
+use crate::{
+    activity_queue::send_activity_to_actors,
+    config::{Data, FederationConfig, FederationConfigBuilder, FederationConfigBuilderError},
+    error::Error,
+    media_type::content_type_header,
+    protocol::verification::host_with_port,
+    traits::{ActivityHandler, Actor, Object},
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use http::{Method, StatusCode, Uri};
+use reqwest_middleware::{ClientBuilder, Middleware, Next};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    marker::PhantomData,
+    sync::{Arc, Mutex, RwLock},
+};
+use task_local_extensions::Extensions;
+use url::Url;
+
+/// Connects instances, which are registered with [Network::instance]
+#[derive(Clone, Default)]
+pub struct Network {
+    instances: Arc<RwLock<HashMap<String, Arc<dyn Handler>>>>,
+}
+
+impl Network {
+    /// Builds the config and registers the instance under its domain.
+    ///
+    /// The HTTP client and debug mode of the builder are overwritten. Incoming activities are
+    /// deserialized as `Activity`, and must be signed by an actor of type `ActorT`.
+    pub fn instance<Activity, ActorT, T>(
+        &self,
+        config: &mut FederationConfigBuilder<T>,
+    ) -> Result<Instance<T>, FederationConfigBuilderError>
+    where
+        Activity: ActivityHandler<DataType = T> + DeserializeOwned + Send + Sync + 'static,
+        ActorT: Object<DataType = T> + Actor + Send + Sync + 'static,
+        for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2> + Send,
+        <Activity as ActivityHandler>::Error:
+            From<Error> + From<<ActorT as Object>::Error> + Display + Send,
+        <ActorT as Object>::Error: From<Error>,
+        T: Clone + Send + Sync + 'static,
+    {
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(Transport(self.clone()))
+            .build();
+        let config = config.client(client).debug(true).build()?;
+        let instance = Instance {
+            config,
+            objects: Default::default(),
+            received: Default::default(),
+        };
+        let handler = InstanceHandler::<Activity, ActorT, T> {
+            instance: instance.clone(),
+            _types: PhantomData,
+        };
+        self.instances
+            .write()
+            .expect("lock instances")
+            .insert(instance.config.domain().to_string(), Arc::new(handler));
+        Ok(instance)
+    }
+}
+
+/// Instance which is part of a [Network]
+#[derive(Clone)]
+pub struct Instance<T: Clone> {
+    config: FederationConfig<T>,
+    objects: Arc<Mutex<HashMap<Url, Value>>>,
+    received: Arc<Mutex<Vec<ReceivedActivity>>>,
+}
+
+/// Activity which was posted to an [Instance]
+#[derive(Clone, Debug)]
+pub struct ReceivedActivity {
+    /// Json of the activity, or [Value::Null] if it isn't valid json
+    pub activity: Value,
+    /// Error message if the activity was rejected
+    pub result: Result<(), String>,
+}
+
+impl<T: Clone> Instance<T> {
+    /// Config of the instance
+    pub fn config(&self) -> &FederationConfig<T> {
+        &self.config
+    }
+
+    /// Creates new [Data] for the instance, eg to read or dereference objects
+    pub fn data(&self) -> Data<T> {
+        self.config.to_request_data()
+    }
+
+    /// Publishes an object, so that other instances can fetch it from its id
+    pub fn expose<Kind: Serialize>(&self, id: &Url, json: &Kind) -> Result<(), Error> {
+        let json = serde_json::to_value(json)?;
+        self.objects
+            .lock()
+            .expect("lock objects")
+            .insert(id.clone(), json);
+        Ok(())
+    }
+
+    /// Signs the activity and delivers it to the recipients, see [send_activity_to_actors]
+    pub async fn send<Activity, ActorType, RecipientType>(
+        &self,
+        activity: Activity,
+        actor: &ActorType,
+        recipients: &[RecipientType],
+    ) -> Result<(), <Activity as ActivityHandler>::Error>
+    where
+        Activity: ActivityHandler + Serialize,
+        <Activity as ActivityHandler>::Error: From<Error>,
+        ActorType: Actor,
+        RecipientType: Actor,
+    {
+        send_activity_to_actors(activity, actor, recipients, &self.data()).await
+    }
+
+    /// All activities which were posted to this instance, in order
+    pub fn received(&self) -> Vec<ReceivedActivity> {
+        self.received.lock().expect("lock received").clone()
+    }
+}
+
+/// Handles requests to an instance, with the types of the instance erased
+#[async_trait]
+trait Handler: Send + Sync {
+    async fn handle(&self, request: reqwest::Request) -> http::Response<Vec<u8>>;
+}
+
+struct InstanceHandler<Activity, ActorT, T: Clone> {
+    instance: Instance<T>,
+    _types: PhantomData<fn() -> (Activity, ActorT)>,
+}
+
+#[async_trait]
+impl<Activity, ActorT, T> Handler for InstanceHandler<Activity, ActorT, T>
+where
+    Activity: ActivityHandler<DataType = T> + DeserializeOwned + Send + Sync + 'static,
+    ActorT: Object<DataType = T> + Actor + Send + Sync + 'static,
+    for<'de2> <ActorT as Object>::Kind: serde::Deserialize<'de2> + Send,
+    <Activity as ActivityHandler>::Error:
+        From<Error> + From<<ActorT as Object>::Error> + Display + Send,
+    <ActorT as Object>::Error: From<Error>,
+    T: Clone + Send + Sync + 'static,
+{
+    async fn handle(&self, request: reqwest::Request) -> http::Response<Vec<u8>> {
+        if request.method() == Method::GET {
+            let objects = self.instance.objects.lock().expect("lock objects");
+            return match objects.get(request.url()) {
+                Some(json) => response(StatusCode::OK, json.to_string().into_bytes()),
+                None => response(StatusCode::NOT_FOUND, vec![]),
+            };
+        }
+
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .unwrap_or_default();
+        let Ok(uri) = request.url().as_str().parse::<Uri>() else {
+            return response(StatusCode::BAD_REQUEST, vec![]);
+        };
+        let data = self.instance.data();
+        let result = crate::inbox::receive_activity::<Activity, ActorT, T, _>(
+            request.headers(),
+            request.method(),
+            &uri,
+            body,
+            None,
+            &data,
+        )
+        .await
+        .map_err(|e| e.to_string());
+        let status = match result {
+            Ok(()) => StatusCode::OK,
+            Err(_) => StatusCode::BAD_REQUEST,
+        };
+        let activity = serde_json::from_slice(body).unwrap_or(Value::Null);
+        self.instance
+            .received
+            .lock()
+            .expect("lock received")
+            .push(ReceivedActivity { activity, result });
+        response(status, vec![])
+    }
+}
+
+fn response(status: StatusCode, body: Vec<u8>) -> http::Response<Vec<u8>> {
+    let mut response = http::Response::new(body);
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(http::header::CONTENT_TYPE, content_type_header());
+    response
+}
+
+/// Client middleware which passes requests to the instance with the same domain, instead of
+/// sending them over the network
+struct Transport(Network);
+
+#[async_trait]
+impl Middleware for Transport {
+    async fn handle(
+        &self,
+        request: reqwest::Request,
+        _: &mut Extensions,
+        _: Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        let url = request.url();
+        let domain = host_with_port(url).unwrap_or_default();
+        let handler = self
+            .0
+            .instances
+            .read()
+            .expect("lock instances")
+            .get(&domain)
+            .cloned()
+            .ok_or_else(|| anyhow!("No instance with domain {domain} in test network"))?;
+        Ok(handler.handle(request).await.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fetch::object_id::ObjectId,
+        http_signatures::generate_actor_keypair,
+        protocol::{
+            kind::{AcceptType, FollowType, PersonType},
+            public_key::PublicKey,
+        },
+    };
+    use serde::Deserialize;
+
+    /// Database of an instance, with local and remote users
+    #[derive(Clone, Default)]
+    struct Db {
+        users: Arc<Mutex<Vec<User>>>,
+        accepted: Arc<Mutex<Vec<Url>>>,
+    }
+
+    #[derive(Clone, Debug)]
+    struct User {
+        id: Url,
+        inbox: Url,
+        public_key: String,
+        private_key: Option<String>,
+        followers: Vec<Url>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Person {
+        #[serde(rename = "type")]
+        kind: PersonType,
+        id: ObjectId<User>,
+        inbox: Url,
+        public_key: PublicKey,
+    }
+
+    #[async_trait]
+    impl Object for User {
+        type DataType = Db;
+        type Kind = Person;
+        type Error = Error;
+
+        async fn read_from_id(id: Url, data: &Data<Db>) -> Result<Option<Self>, Error> {
+            let users = data.users.lock().unwrap();
+            Ok(users.iter().find(|u| u.id == id).cloned())
+        }
+
+        async fn into_json(&self, _: &Data<Db>) -> Result<Person, Error> {
+            Ok(Person {
+                kind: Default::default(),
+                id: self.id.clone().into(),
+                inbox: self.inbox.clone(),
+                public_key: self.public_key(),
+            })
+        }
+
+        async fn from_json(json: Person, data: &Data<Db>) -> Result<Self, Error> {
+            let user = User {
+                id: json.id.into_inner(),
+                inbox: json.inbox,
+                public_key: json.public_key.public_key_pem,
+                private_key: None,
+                followers: vec![],
+            };
+            let mut users = data.users.lock().unwrap();
+            users.retain(|u| u.id != user.id);
+            users.push(user.clone());
+            Ok(user)
+        }
+    }
+
+    impl Actor for User {
+        fn id(&self) -> Url {
+            self.id.clone()
+        }
+
+        fn public_key_pem(&self) -> &str {
+            &self.public_key
+        }
+
+        fn private_key_pem(&self) -> Option<String> {
+            self.private_key.clone()
+        }
+
+        fn inbox(&self) -> Url {
+            self.inbox.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Follow {
+        #[serde(rename = "type")]
+        kind: FollowType,
+        id: Url,
+        actor: ObjectId<User>,
+        object: ObjectId<User>,
+    }
+
+    #[async_trait]
+    impl ActivityHandler for Follow {
+        type DataType = Db;
+        type Error = Error;
+
+        fn id(&self) -> &Url {
+            &self.id
+        }
+
+        fn actor(&self) -> &Url {
+            self.actor.inner()
+        }
+
+        async fn verify(&self, _: &Data<Db>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn receive(self, data: &Data<Db>) -> Result<(), Error> {
+            let follower = self.actor.dereference(data).await?;
+            let followed = self.object.dereference_local(data).await?;
+            for user in data.users.lock().unwrap().iter_mut() {
+                if user.id == followed.id {
+                    user.followers.push(follower.id.clone());
+                }
+            }
+            let accept = Accept {
+                kind: Default::default(),
+                id: Url::parse(&format!("https://{}/activities/accept/1", data.domain()))?,
+                actor: followed.id.clone().into(),
+                object: self,
+            };
+            send_activity_to_actors(accept, &followed, &[follower], data).await
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Accept {
+        #[serde(rename = "type")]
+        kind: AcceptType,
+        id: Url,
+        actor: ObjectId<User>,
+        object: Follow,
+    }
+
+    #[async_trait]
+    impl ActivityHandler for Accept {
+        type DataType = Db;
+        type Error = Error;
+
+        fn id(&self) -> &Url {
+            &self.id
+        }
+
+        fn actor(&self) -> &Url {
+            self.actor.inner()
+        }
+
+        async fn verify(&self, _: &Data<Db>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn receive(self, data: &Data<Db>) -> Result<(), Error> {
+            data.accepted.lock().unwrap().push(self.object.id);
+            Ok(())
+        }
+    }
+
+    /// Creates a local user and exposes it, so that other instances can fetch it
+    async fn local_user(instance: &Instance<Db>, name: &str) -> User {
+        let keypair = generate_actor_keypair().unwrap();
+        let id = Url::parse(&format!("https://{}/u/{name}", instance.config().domain())).unwrap();
+        let user = User {
+            inbox: Url::parse(&format!("{id}/inbox")).unwrap(),
+            id: id.clone(),
+            public_key: keypair.public_key,
+            private_key: Some(keypair.private_key),
+            followers: vec![],
+        };
+        let data = instance.data();
+        data.users.lock().unwrap().push(user.clone());
+        instance
+            .expose(&id, &user.into_json(&data).await.unwrap())
+            .unwrap();
+        user
+    }
+
+    /// Returns two instances, where alpha receives `Accept` and beta receives `Follow`
+    fn network() -> (Instance<Db>, Instance<Db>) {
+        let network = Network::default();
+        let mut config = FederationConfig::builder();
+        config.domain("alpha.example").app_data(Db::default());
+        let alpha = network.instance::<Accept, User, _>(&mut config).unwrap();
+        config.domain("beta.example").app_data(Db::default());
+        let beta = network.instance::<Follow, User, _>(&mut config).unwrap();
+        (alpha, beta)
+    }
+
+    fn follow(alice: &User, bob: &User) -> Follow {
+        Follow {
+            kind: Default::default(),
+            id: Url::parse("https://alpha.example/activities/follow/1").unwrap(),
+            actor: alice.id.clone().into(),
+            object: bob.id.clone().into(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_follow_accept() {
+        let (alpha, beta) = network();
+        let alice = local_user(&alpha, "alice").await;
+        let bob = local_user(&beta, "bob").await;
+
+        // alpha fetches bob to find his inbox
+        let remote_bob = ObjectId::<User>::from(bob.id.clone())
+            .dereference(&alpha.data())
+            .await
+            .unwrap();
+        assert_eq!(remote_bob.inbox, bob.inbox);
+        let follow = follow(&alice, &bob);
+        alpha
+            .send(follow.clone(), &alice, &[remote_bob])
+            .await
+            .unwrap();
+
+        let received = beta.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].activity["type"], "Follow");
+        assert_eq!(received[0].result, Ok(()));
+        let bob = ObjectId::<User>::from(bob.id)
+            .dereference_local(&beta.data())
+            .await
+            .unwrap();
+        assert_eq!(bob.followers, vec![alice.id]);
+
+        let received = alpha.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].activity["type"], "Accept");
+        assert_eq!(received[0].result, Ok(()));
+        assert_eq!(*alpha.data().accepted.lock().unwrap(), vec![follow.id]);
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_signature_rejected() {
+        let (alpha, beta) = network();
+        let mut alice = local_user(&alpha, "alice").await;
+        let bob = local_user(&beta, "bob").await;
+
+        // sign with a key which doesn't match the public key of alice
+        alice.private_key = Some(generate_actor_keypair().unwrap().private_key);
+        alpha
+            .send(follow(&alice, &bob), &alice, std::slice::from_ref(&bob))
+            .await
+            .unwrap();
+
+        let received = beta.received();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].result.is_err());
+        let bob = ObjectId::<User>::from(bob.id)
+            .dereference_local(&beta.data())
+            .await
+            .unwrap();
+        assert!(bob.followers.is_empty());
+        assert!(alpha.received().is_empty());
+    }
+}
@@ -0,0 +1,421 @@
+//! Mock implementations of the federation traits, for use in tests
+//!
+//! This module is only available with the `test-utils` feature. It contains a minimal user
+//! ([DbUser]), post ([DbPost]) and activity ([Follow]), together with an in-memory
+//! [DbConnection] which serves as app data. They can be used to test code which is generic over
+//! the federation traits, or to call library functions such as
+//! [receive_activity](crate::actix_web::inbox::receive_activity) without setting up a database.
+//! Further activities with unique ids are generated by [fixtures].
+//!
+//! ```
+//! # use activitypub_federation::config::FederationConfig;
+//! # use activitypub_federation::fetch::object_id::ObjectId;
+//! # use activitypub_federation::test_utils::{DbConnection, DbUser, DB_USER};
+//! # use activitypub_federation::traits::Object;
+//! # actix_rt::System::new().block_on(async {
+//! let config = FederationConfig::builder()
+//!     .domain("example.com")
+//!     .app_data(DbConnection::default())
+//!     .build()?;
+//! let data = config.to_request_data();
+//!
+//! // objects which are received from remote servers are stored in the mock database
+//! let mut json = DB_USER.into_json(&data).await?;
+//! json.id = ObjectId::parse("https://lemmy.ml/u/nutomic")?;
+//! json.preferred_username = "nutomic".to_string();
+//! DbUser::from_json(json, &data).await?;
+//! let user = ObjectId::<DbUser>::parse("https://lemmy.ml/u/nutomic")?
+//!     .dereference_local(&data)
+//!     .await?;
+//! assert_eq!(user.name, "nutomic");
+//! # Ok::<(), anyhow::Error>(())
+//! # }).unwrap();
+//! ```
+//!
+//! Dont use these types outside of tests. In particular [DB_USER_KEYPAIR] is generated at runtime,
+//! and methods of [DbConnection] such as [DbConnection::upsert] only exist for documentation
+//! examples and don't store anything.
+
+#![allow(clippy::unwrap_used)]
+
+#[cfg(feature = "axum")]
+pub mod axum;
+pub mod clock;
+pub mod fixtures;
+pub mod harness;
+pub mod request;
+pub mod transport;
+
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::object_id::ObjectId,
+    http_signatures::{generate_actor_keypair, Keypair},
+    protocol::{
+        audience::Audience,
+        kind::{FollowType, NoteType, PersonType},
+        public_key::PublicKey,
+        verification::verify_domains_match,
+    },
+    traits::{ActivityHandler, Actor, Object},
+};
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use url::Url;
+
+/// In-memory database, which stores objects as json by their id
+#[derive(Clone, Default)]
+pub struct DbConnection {
+    objects: Arc<Mutex<HashMap<Url, Value>>>,
+}
+
+impl DbConnection {
+    /// Stores the json of an object, replacing any previous value with the same id
+    pub fn insert<T: Serialize>(&self, id: Url, object: &T) -> Result<(), Error> {
+        let json = serde_json::to_value(object)?;
+        self.objects.lock().unwrap().insert(id, json);
+        Ok(())
+    }
+
+    /// Reads the object with the given id, or returns `None` if it wasn't stored
+    pub fn read<T: DeserializeOwned>(&self, id: &Url) -> Result<Option<T>, Error> {
+        let objects = self.objects.lock().unwrap();
+        let json = objects.get(id).cloned();
+        Ok(json.map(serde_json::from_value).transpose()?)
+    }
+
+    /// Mock method for documentation examples, always returns `None`
+    pub async fn read_post_from_json_id<T>(&self, _: Url) -> Result<Option<T>, Error> {
+        Ok(None)
+    }
+
+    /// Reads the stored user with the given preferred username, or returns [Error::NotFound]
+    pub async fn read_local_user(&self, name: String) -> Result<DbUser, Error> {
+        let objects = self.objects.lock().unwrap();
+        objects
+            .values()
+            .filter_map(|json| serde_json::from_value::<Person>(json.clone()).ok())
+            .find(|person| person.preferred_username == name)
+            .map(DbUser::from)
+            .ok_or(Error::NotFound)
+    }
+
+    /// Mock method for documentation examples, does nothing
+    pub async fn upsert<T>(&self, _: &T) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Mock method for documentation examples, does nothing
+    pub async fn add_follower(&self, _: DbUser, _: DbUser) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Activitypub representation of [DbUser]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Person {
+    /// Always `Person`
+    #[serde(rename = "type")]
+    pub kind: PersonType,
+    /// Username of the user
+    pub preferred_username: String,
+    /// Id of the user
+    pub id: ObjectId<DbUser>,
+    /// Inbox of the user
+    pub inbox: Url,
+    /// Public key of the user
+    pub public_key: PublicKey,
+}
+
+/// User as it is stored in the database.
+///
+/// [Object::read_from_id] returns users which were stored in [DbConnection] with
+/// [Object::from_json]. For all other ids it returns [DB_USER], so that tests don't need to
+/// fetch actors over the network.
+#[derive(Debug, Clone)]
+pub struct DbUser {
+    /// Username of the user
+    pub name: String,
+    /// Id of the user
+    pub federation_id: Url,
+    /// Inbox of the user
+    pub inbox: Url,
+    /// Shared inbox of the user's instance
+    pub shared_inbox: Option<Url>,
+    /// Public key in PEM format
+    pub public_key: String,
+    /// Private key in PEM format, only known for local users
+    pub private_key: Option<String>,
+    /// Ids of users who follow this user
+    pub followers: Vec<Url>,
+    /// True if the user belongs to the local instance
+    pub local: bool,
+}
+
+/// Keypair of [DB_USER]
+pub static DB_USER_KEYPAIR: Lazy<Keypair> = Lazy::new(|| generate_actor_keypair().unwrap());
+
+/// User with id `https://localhost/123` and [DB_USER_KEYPAIR]
+pub static DB_USER: Lazy<DbUser> = Lazy::new(|| DbUser {
+    name: String::new(),
+    federation_id: "https://localhost/123".parse().unwrap(),
+    inbox: "https://localhost/123/inbox".parse().unwrap(),
+    shared_inbox: None,
+    public_key: DB_USER_KEYPAIR.public_key.clone(),
+    private_key: Some(DB_USER_KEYPAIR.private_key.clone()),
+    followers: vec![],
+    local: false,
+});
+
+impl From<Person> for DbUser {
+    fn from(json: Person) -> Self {
+        DbUser {
+            name: json.preferred_username,
+            federation_id: json.id.into(),
+            inbox: json.inbox,
+            shared_inbox: None,
+            public_key: json.public_key.public_key_pem,
+            private_key: None,
+            followers: vec![],
+            local: false,
+        }
+    }
+}
+
+#[async_trait]
+impl Object for DbUser {
+    type DataType = DbConnection;
+    type Kind = Person;
+    type Error = Error;
+
+    async fn read_from_id(
+        object_id: Url,
+        data: &Data<Self::DataType>,
+    ) -> Result<Option<Self>, Self::Error> {
+        let stored: Option<Person> = data.read(&object_id)?;
+        Ok(Some(stored.map_or_else(|| DB_USER.clone(), DbUser::from)))
+    }
+
+    async fn into_json(&self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+        Ok(Person {
+            preferred_username: self.name.clone(),
+            kind: Default::default(),
+            id: self.federation_id.clone().into(),
+            inbox: self.inbox.clone(),
+            public_key: self.public_key(),
+        })
+    }
+
+    async fn verify(
+        json: &Self::Kind,
+        expected_domain: &Url,
+        _data: &Data<Self::DataType>,
+    ) -> Result<(), Self::Error> {
+        verify_domains_match(json.id.inner(), expected_domain)?;
+        Ok(())
+    }
+
+    async fn from_json(json: Self::Kind, data: &Data<Self::DataType>) -> Result<Self, Self::Error> {
+        data.insert(json.id.inner().clone(), &json)?;
+        Ok(json.into())
+    }
+}
+
+impl Actor for DbUser {
+    fn id(&self) -> Url {
+        self.federation_id.clone()
+    }
+
+    fn public_key_pem(&self) -> &str {
+        &self.public_key
+    }
+
+    fn private_key_pem(&self) -> Option<String> {
+        self.private_key.clone()
+    }
+
+    fn inbox(&self) -> Url {
+        self.inbox.clone()
+    }
+
+    fn shared_inbox(&self) -> Option<Url> {
+        self.shared_inbox.clone()
+    }
+}
+
+/// Follow activity, which is accepted without any checks
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Follow {
+    /// User who follows
+    pub actor: ObjectId<DbUser>,
+    /// User who is followed
+    pub object: ObjectId<DbUser>,
+    /// Always `Follow`
+    #[serde(rename = "type")]
+    pub kind: FollowType,
+    /// Id of the activity
+    pub id: Url,
+}
+
+#[async_trait]
+impl ActivityHandler for Follow {
+    type DataType = DbConnection;
+    type Error = Error;
+
+    fn id(&self) -> &Url {
+        &self.id
+    }
+
+    fn actor(&self) -> &Url {
+        self.actor.inner()
+    }
+
+    async fn verify(&self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn receive(self, _data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Activitypub representation of [DbPost]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    /// Always `Note`
+    #[serde(rename = "type")]
+    pub kind: NoteType,
+    /// Id of the post
+    pub id: ObjectId<DbPost>,
+    /// Author of the post
+    pub attributed_to: ObjectId<DbUser>,
+    /// Recipients of the post
+    #[serde(flatten)]
+    pub audience: Audience,
+    /// Text of the post
+    pub content: String,
+}
+
+/// Post as it is stored in the database.
+///
+/// [Object::read_from_id] returns posts which were stored in [DbConnection] with
+/// [Object::from_json], and `None` for all other ids.
+#[derive(Debug, Clone)]
+pub struct DbPost {
+    /// Id of the post
+    pub ap_id: ObjectId<DbPost>,
+    /// Author of the post
+    pub creator: ObjectId<DbUser>,
+    /// Recipients of the post
+    pub audience: Audience,
+    /// Text of the post
+    pub text: String,
+}
+
+impl From<Note> for DbPost {
+    fn from(json: Note) -> Self {
+        DbPost {
+            ap_id: json.id,
+            creator: json.attributed_to,
+            audience: json.audience,
+            text: json.content,
+        }
+    }
+}
+
+#[async_trait]
+impl Object for DbPost {
+    type DataType = DbConnection;
+    type Kind = Note;
+    type Error = Error;
+
+    async fn read_from_id(
+        object_id: Url,
+        data: &Data<Self::DataType>,
+    ) -> Result<Option<Self>, Self::Error> {
+        let stored: Option<Note> = data.read(&object_id)?;
+        Ok(stored.map(DbPost::from))
+    }
+
+    async fn into_json(&self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+        Ok(Note {
+            kind: Default::default(),
+            id: self.ap_id.clone(),
+            attributed_to: self.creator.clone(),
+            audience: self.audience.clone(),
+            content: self.text.clone(),
+        })
+    }
+
+    async fn verify(
+        json: &Self::Kind,
+        expected_domain: &Url,
+        _data: &Data<Self::DataType>,
+    ) -> Result<(), Self::Error> {
+        verify_domains_match(json.id.inner(), expected_domain)?;
+        verify_domains_match(json.id.inner(), json.attributed_to.inner())?;
+        Ok(())
+    }
+
+    async fn from_json(json: Self::Kind, data: &Data<Self::DataType>) -> Result<Self, Self::Error> {
+        data.insert(json.id.inner().clone(), &json)?;
+        Ok(json.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::FederationConfig, protocol::audience::public};
+
+    fn data() -> Data<DbConnection> {
+        FederationConfig::builder()
+            .domain("example.com")
+            .app_data(DbConnection::default())
+            .build()
+            .unwrap()
+            .to_request_data()
+    }
+
+    #[actix_rt::test]
+    async fn test_read_local_user() {
+        let data = data();
+        let mut json = DB_USER.into_json(&data).await.unwrap();
+        json.id = ObjectId::parse("https://example.com/u/alice").unwrap();
+        json.preferred_username = "alice".to_string();
+        DbUser::from_json(json, &data).await.unwrap();
+
+        let user = data.read_local_user("alice".to_string()).await.unwrap();
+        assert_eq!(user.federation_id.as_str(), "https://example.com/u/alice");
+        let res = data.read_local_user("bob".to_string()).await;
+        assert_eq!(res.err(), Some(Error::NotFound));
+    }
+
+    #[actix_rt::test]
+    async fn test_post_round_trip() {
+        let data = data();
+        let author = Url::parse("https://example.com/u/alice").unwrap();
+        let note = fixtures::create_note(&author, &[public()], "hello").object;
+        let id = note.id.clone();
+        assert!(id.dereference_local(&data).await.is_err());
+
+        DbPost::verify(&note, &author, &data).await.unwrap();
+        DbPost::from_json(note, &data).await.unwrap();
+        let post = id.dereference_local(&data).await.unwrap();
+        assert_eq!(post.text, "hello");
+        assert_eq!(post.creator.inner(), &author);
+
+        let json = post.into_json(&data).await.unwrap();
+        assert_eq!(json.id, id);
+        assert_eq!(json.audience.to, vec![public()]);
+    }
+}
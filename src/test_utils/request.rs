@@ -0,0 +1,189 @@
+//! Signed inbox requests, as they are sent by remote servers
+//!
+//! [SignedRequestBuilder] signs an activity in the same way as
+//! [send_activity](crate::activity_queue::send_activity), so that the resulting request can be
+//! passed to your own inbox handler. Parts of the request can be corrupted to test that invalid
+//! requests are rejected.
+//!
+//! ```
+//! # use activitypub_federation::test_utils::{request::SignedRequestBuilder, DB_USER_KEYPAIR};
+//! # use serde_json::json;
+//! # use url::Url;
+//! # actix_rt::System::new().block_on(async {
+//! let actor_id = Url::parse("https://example.net/u/alice")?;
+//! let activity = json!({ "id": "https://example.net/activities/1", "actor": actor_id });
+//! let request = SignedRequestBuilder::new(&activity, &DB_USER_KEYPAIR, &actor_id)
+//!     .uri("/u/bob/inbox")
+//!     .build()
+//!     .await?;
+//!
+//! assert!(request.headers.contains_key("signature"));
+//! assert!(request.headers.contains_key("digest"));
+//! // with actix-web
+//! let actix_request = request.to_test_request().to_http_request();
+//! // with axum or other frameworks which use the http crate
+//! let http_request = request.into_http();
+//! assert_eq!(http_request.uri(), "/u/bob/inbox");
+//! # Ok::<(), anyhow::Error>(())
+//! # }).unwrap();
+//! ```
+
+use crate::{
+    activity_queue::generate_request_headers,
+    error::Error,
+    http_signatures::{sign_request, Keypair},
+};
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Method, Uri};
+use reqwest::Client;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Serialize;
+use std::time::SystemTime;
+use url::Url;
+
+/// Builder for a signed `POST` request which delivers an activity to an inbox
+#[derive(Debug)]
+pub struct SignedRequestBuilder {
+    body: Result<String, serde_json::Error>,
+    private_key: String,
+    actor_id: Url,
+    host: String,
+    uri: String,
+    date: Option<SystemTime>,
+    content_type: Option<&'static str>,
+    wrong_digest: bool,
+    removed_headers: Vec<&'static str>,
+}
+
+/// Inbox request which was created by [SignedRequestBuilder]
+#[derive(Clone, Debug)]
+pub struct SignedRequest {
+    /// Always `POST`
+    pub method: Method,
+    /// Path and query of the inbox
+    pub uri: Uri,
+    /// Headers of the request, including `Digest`, `Date`, `Host` and `Signature`
+    pub headers: HeaderMap,
+    /// Serialized activity
+    pub body: Vec<u8>,
+}
+
+impl SignedRequestBuilder {
+    /// Request which contains `activity`, signed with the private key of `keypair`. The key id
+    /// is the main key of `actor_id`.
+    pub fn new<Activity: Serialize>(
+        activity: &Activity,
+        keypair: &Keypair,
+        actor_id: &Url,
+    ) -> Self {
+        SignedRequestBuilder {
+            body: serde_json::to_string(activity),
+            private_key: keypair.private_key.clone(),
+            actor_id: actor_id.clone(),
+            host: "example.com".to_string(),
+            uri: "/inbox".to_string(),
+            date: None,
+            content_type: None,
+            wrong_digest: false,
+            removed_headers: vec![],
+        }
+    }
+
+    /// Path and query of the inbox, defaults to `/inbox`
+    pub fn uri(mut self, uri: &str) -> Self {
+        self.uri = uri.to_string();
+        self
+    }
+
+    /// Value of the `Host` header, defaults to `example.com`
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Value of the `Date` header, defaults to the current time. The date is signed, so a date
+    /// which is older than a few seconds makes the request expire.
+    pub fn date(mut self, date: SystemTime) -> Self {
+        self.date = Some(date);
+        self
+    }
+
+    /// Value of the `Content-Type` header, which is signed. Defaults to
+    /// [ACTIVITY_JSON](crate::media_type::ACTIVITY_JSON).
+    pub fn content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /// Replaces the `Digest` header after signing with the hash of a different body
+    pub fn wrong_digest(mut self) -> Self {
+        self.wrong_digest = true;
+        self
+    }
+
+    /// Removes the header after signing, eg `signature` or `digest`
+    pub fn without_header(mut self, name: &'static str) -> Self {
+        self.removed_headers.push(name);
+        self
+    }
+
+    /// Signs the request
+    pub async fn build(self) -> Result<SignedRequest, Error> {
+        let body = self.body?;
+        let inbox = Url::parse(&format!("https://{}{}", self.host, self.uri))?;
+        let date = self.date.unwrap_or_else(SystemTime::now);
+        let mut headers = generate_request_headers(&inbox, date);
+        if let Some(content_type) = self.content_type {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        let request_builder = ClientWithMiddleware::from(Client::default())
+            .post(inbox.as_str())
+            .headers(headers);
+        let request = sign_request(
+            request_builder,
+            self.actor_id,
+            body.clone(),
+            self.private_key,
+            false,
+        )
+        .await?;
+
+        let mut headers = request.headers().clone();
+        if self.wrong_digest {
+            // sha-256 of an empty body
+            let digest = "SHA-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+            headers.insert("digest", HeaderValue::from_static(digest));
+        }
+        for name in self.removed_headers {
+            headers.remove(name);
+        }
+        Ok(SignedRequest {
+            method: Method::POST,
+            uri: Uri::try_from(self.uri).map_err(|e| Error::Other(e.to_string()))?,
+            headers,
+            body: body.into_bytes(),
+        })
+    }
+}
+
+impl SignedRequest {
+    /// Converts the request into a request of the `http` crate, which is used by axum
+    pub fn into_http(self) -> http::Request<Vec<u8>> {
+        let mut request = http::Request::new(self.body);
+        *request.method_mut() = self.method;
+        *request.uri_mut() = self.uri;
+        *request.headers_mut() = self.headers;
+        request
+    }
+
+    /// Converts the request into an actix-web test request
+    #[cfg(feature = "actix-web")]
+    pub fn to_test_request(&self) -> actix_web::test::TestRequest {
+        let mut request = actix_web::test::TestRequest::post()
+            .uri(&self.uri.to_string())
+            .set_payload(self.body.clone());
+        for header in &self.headers {
+            request = request.append_header(header);
+        }
+        request
+    }
+}
@@ -0,0 +1,215 @@
+//! Mock [HttpTransport] which answers fetches with canned responses
+//!
+//! ```
+//! # use activitypub_federation::config::FederationConfig;
+//! # use activitypub_federation::fetch::fetch_object_http;
+//! # use activitypub_federation::test_utils::{transport::{MockResponse, MockTransport}, DbConnection};
+//! # use serde_json::{json, Value};
+//! # use url::Url;
+//! # actix_rt::System::new().block_on(async {
+//! let transport = MockTransport::default();
+//! let url = Url::parse("https://example.net/objects/1")?;
+//! transport.respond(&url, MockResponse::json(&json!({ "id": url }))?);
+//! let config = FederationConfig::builder()
+//!     .domain("example.com")
+//!     .app_data(DbConnection::default())
+//!     .transport(Box::new(transport.clone()))
+//!     .build()?;
+//!
+//! let object: Value = fetch_object_http(&url, &config.to_request_data()).await?;
+//! assert_eq!(object["id"], url.as_str());
+//! assert_eq!(transport.requests()[0].url, url);
+//! # Ok::<(), anyhow::Error>(())
+//! # }).unwrap();
+//! ```
+
+use crate::{fetch::transport::HttpTransport, media_type::content_type_header};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Method, StatusCode};
+use reqwest::{Request, Response};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use url::Url;
+
+/// Transport which returns the registered response for each url, and `404 Not Found` for all
+/// other urls. All requests are recorded.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    responses: Arc<Mutex<HashMap<Url, MockResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+/// Response of a [MockTransport], or an error if `status` is `None`
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    status: Option<StatusCode>,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    delay: Duration,
+}
+
+/// Request which was sent to a [MockTransport]
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    /// HTTP method
+    pub method: Method,
+    /// Requested url
+    pub url: Url,
+    /// Headers of the request, including signatures
+    pub headers: HeaderMap,
+}
+
+impl MockTransport {
+    /// Answers requests for the url with the response, replacing any previous response
+    pub fn respond(&self, url: &Url, response: MockResponse) {
+        let mut responses = self.responses.lock().expect("lock responses");
+        responses.insert(url.clone(), response);
+    }
+
+    /// All requests which were sent so far, in order
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().expect("lock requests").clone()
+    }
+}
+
+impl MockResponse {
+    /// Response with the status and an empty body
+    pub fn status(status: StatusCode) -> Self {
+        MockResponse {
+            status: Some(status),
+            headers: HeaderMap::new(),
+            body: vec![],
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// `200 OK` response with the serialized json
+    pub fn json<Kind: Serialize>(json: &Kind) -> Result<Self, serde_json::Error> {
+        let mut response = Self::status(StatusCode::OK).body(serde_json::to_vec(json)?);
+        response.headers.insert(CONTENT_TYPE, content_type_header());
+        Ok(response)
+    }
+
+    /// Simulates a failed connection, so that no response is received
+    pub fn error() -> Self {
+        MockResponse {
+            status: None,
+            ..Self::status(StatusCode::OK)
+        }
+    }
+
+    /// Sets the response body
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Adds a response header
+    pub fn header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.insert(name, HeaderValue::from_static(value));
+        self
+    }
+
+    /// Waits before responding. If the delay is longer than the timeout of the request, the
+    /// request fails after the timeout.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+#[async_trait]
+impl HttpTransport for MockTransport {
+    async fn execute(&self, request: Request) -> Result<Response, reqwest_middleware::Error> {
+        self.requests
+            .lock()
+            .expect("lock requests")
+            .push(RecordedRequest {
+                method: request.method().clone(),
+                url: request.url().clone(),
+                headers: request.headers().clone(),
+            });
+        let response = self
+            .responses
+            .lock()
+            .expect("lock responses")
+            .get(request.url())
+            .cloned()
+            .unwrap_or_else(|| MockResponse::status(StatusCode::NOT_FOUND));
+
+        if let Some(timeout) = request.timeout().filter(|t| **t < response.delay) {
+            tokio::time::sleep(*timeout).await;
+            return Err(anyhow!("Request to {} timed out", request.url()).into());
+        }
+        tokio::time::sleep(response.delay).await;
+        let Some(status) = response.status else {
+            return Err(anyhow!("Failed to connect to {}", request.url()).into());
+        };
+        let mut http_response = http::Response::new(response.body);
+        *http_response.status_mut() = status;
+        *http_response.headers_mut() = response.headers;
+        Ok(http_response.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &Url, timeout: Duration) -> Request {
+        let mut request = Request::new(Method::GET, url.clone());
+        *request.timeout_mut() = Some(timeout);
+        request
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_transport() {
+        let transport = MockTransport::default();
+        let url = Url::parse("https://example.net/objects/1").unwrap();
+        let unknown = Url::parse("https://example.net/objects/2").unwrap();
+        let response = MockResponse::status(StatusCode::TOO_MANY_REQUESTS)
+            .header("retry-after", "30")
+            .body("slow down");
+        transport.respond(&url, response);
+
+        let res = transport.execute(request(&url, Duration::MAX)).await;
+        let res = res.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "30");
+        assert_eq!(res.text().await.unwrap(), "slow down");
+        let res = transport.execute(request(&unknown, Duration::MAX)).await;
+        assert_eq!(res.unwrap().status(), StatusCode::NOT_FOUND);
+
+        transport.respond(&url, MockResponse::error());
+        let res = transport.execute(request(&url, Duration::MAX)).await;
+        assert!(res.is_err());
+
+        let requests = transport.requests();
+        let urls: Vec<_> = requests.iter().map(|r| r.url.clone()).collect();
+        assert_eq!(urls, vec![url.clone(), unknown, url]);
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_transport_delay() {
+        let transport = MockTransport::default();
+        let url = Url::parse("https://example.net/objects/1").unwrap();
+        let delay = Duration::from_millis(200);
+        let response = MockResponse::status(StatusCode::OK).delay(delay);
+        transport.respond(&url, response);
+
+        let start = std::time::Instant::now();
+        let res = transport.execute(request(&url, Duration::MAX)).await;
+        assert!(res.is_ok());
+        assert!(start.elapsed() >= delay);
+
+        let start = std::time::Instant::now();
+        let res = transport.execute(request(&url, delay / 4)).await;
+        assert!(res.is_err());
+        assert!(start.elapsed() < delay);
+    }
+}
@@ -1,16 +1,28 @@
 //! Traits which need to be implemented for federated data types
 
-use crate::{config::Data, protocol::public_key::PublicKey};
+use crate::{
+    config::Data,
+    error::Error,
+    fetch::DeletionInfo,
+    protocol::{collection::OrderedCollectionPage, public_key::PublicKey},
+};
 use async_trait::async_trait;
 use chrono::NaiveDateTime;
 use serde::Deserialize;
-use std::{fmt::Debug, ops::Deref};
+use std::ops::Deref;
 use url::Url;
 
+/// Derive macro which implements [ActivityHandler](trait@ActivityHandler) for an enum of activities.
+///
+/// Requires the `derive` feature. See the trait documentation for details.
+#[cfg(feature = "derive")]
+pub use activitypub_federation_derive::ActivityHandler;
+
 /// Helper for converting between database structs and federated protocol structs.
 ///
 /// ```
-/// # use activitystreams_kinds::{object::NoteType, public};
+/// # use activitystreams_kinds::object::NoteType;
+/// # use activitypub_federation::protocol::audience::public;
 /// # use chrono::{Local, NaiveDateTime};
 /// # use serde::{Deserialize, Serialize};
 /// # use url::Url;
@@ -19,7 +31,7 @@ use url::Url;
 /// # use activitypub_federation::fetch::object_id::ObjectId;
 /// # use activitypub_federation::protocol::verification::verify_domains_match;
 /// # use activitypub_federation::traits::{Actor, Object};
-/// # use activitypub_federation::traits::tests::{DbConnection, DbUser};
+/// # use activitypub_federation::test_utils::{DbConnection, DbUser};
 /// #
 /// /// How the post is read/written in the local database
 /// pub struct DbPost {
@@ -54,15 +66,15 @@ use url::Url;
 ///         Ok(post)
 ///     }
 ///
-/// async fn into_json(self, data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
+/// async fn into_json(&self, data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
 ///         // Called when a local object gets sent out over Activitypub. Simply convert it to the
 ///         // protocol struct
 ///         Ok(Note {
 ///             kind: Default::default(),
-///             id: self.ap_id.clone().into(),
-///             attributed_to: self.creator,
+///             id: self.ap_id.clone(),
+///             attributed_to: self.creator.clone(),
 ///             to: vec![public()],
-///             content: self.text,
+///             content: self.text.clone(),
 ///         })
 ///     }
 ///
@@ -111,6 +123,11 @@ pub trait Object: Sized {
     ///
     /// The object is refetched if `last_refreshed_at` value is more than 24 hours ago. In debug
     /// mode this is reduced to 20 seconds.
+    ///
+    /// The default implementation returns `None`, which means that
+    /// [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) never refetches
+    /// the object once it is stored in the local database. This is appropriate for local or
+    /// ephemeral types where refresh tracking is meaningless.
     fn last_refreshed_at(&self) -> Option<NaiveDateTime> {
         None
     }
@@ -130,24 +147,55 @@ pub trait Object: Sized {
         Ok(())
     }
 
+    /// Mark remote object as deleted in local database, with details about the deletion.
+    ///
+    /// Called when [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference)
+    /// refetches an object from the local database and the remote server responds with
+    /// `410 Gone` or `404 Not Found`. `info` contains the status code, the returned `Tombstone`
+    /// if any and the time of the fetch.
+    ///
+    /// The default implementation calls [Object::delete]. Note that a plain `404 Not Found` may
+    /// also be caused by a temporary misconfiguration of the remote server, override this method
+    /// to handle that case differently.
+    async fn delete_with_info(
+        self,
+        _info: DeletionInfo,
+        data: &Data<Self::DataType>,
+    ) -> Result<(), Self::Error> {
+        self.delete(data).await
+    }
+
     /// Convert database type to Activitypub type.
     ///
     /// Called when a local object gets fetched by another instance over HTTP, or when an object
-    /// gets sent in an activity.
-    async fn into_json(self, data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error>;
+    /// gets sent in an activity. The object is only borrowed, so that it can still be used
+    /// afterwards without cloning the whole database struct.
+    // Name kept from when this took `self` by value, to avoid breaking more code than necessary
+    #[allow(clippy::wrong_self_convention)]
+    async fn into_json(&self, data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error>;
 
     /// Verifies that the received object is valid.
     ///
-    /// You should check here that the domain of id matches `expected_domain`. Additionally you
-    /// should perform any application specific checks.
+    /// You should check here that the domain of id matches `expected_domain`, which is the id
+    /// which was used to fetch the object or which the object claims to have. Additionally you
+    /// should perform any application specific checks, such as required fields or content length.
     ///
-    /// It is necessary to use a separate method for this, because it might be used for activities
-    /// like `Delete/Note`, which shouldn't perform any database write for the inner `Note`.
+    /// This is always called before [Object::from_json] by
+    /// [ObjectId::dereference](crate::fetch::object_id::ObjectId::dereference) and
+    /// [ObjectId::dereference_from_json](crate::fetch::object_id::ObjectId::dereference_from_json),
+    /// so rejected data never reaches the database. It is necessary to use a separate method for
+    /// this, because it might be used for activities like `Delete/Note`, which shouldn't perform
+    /// any database write for the inner `Note`.
+    ///
+    /// The default implementation accepts everything. Objects which are received from remote
+    /// instances should override it and at least verify the domain.
     async fn verify(
-        json: &Self::Kind,
-        expected_domain: &Url,
-        data: &Data<Self::DataType>,
-    ) -> Result<(), Self::Error>;
+        _json: &Self::Kind,
+        _expected_domain: &Url,
+        _data: &Data<Self::DataType>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
 
     /// Convert object from ActivityPub type to database type.
     ///
@@ -165,7 +213,7 @@ pub trait Object: Sized {
 /// # use activitypub_federation::fetch::object_id::ObjectId;
 /// # use activitypub_federation::config::Data;
 /// # use activitypub_federation::traits::ActivityHandler;
-/// # use activitypub_federation::traits::tests::{DbConnection, DbUser};
+/// # use activitypub_federation::test_utils::{DbConnection, DbUser};
 /// #[derive(serde::Deserialize)]
 /// struct Follow {
 ///     actor: ObjectId<DbUser>,
@@ -200,8 +248,14 @@ pub trait Object: Sized {
 ///     }
 /// }
 /// ```
+///
+/// An enum of multiple activity types can implement this trait with
+/// [`#[derive(ActivityHandler)]`](derive@ActivityHandler), which delegates all methods to the
+/// wrapped variant. This is useful for inboxes which accept different activities, see
+/// [receive_activity](crate::axum::inbox::receive_activity) for an example.
+/// Activities of unknown types can be accepted with a catch-all variant, see
+/// [UnknownActivity](crate::protocol::unknown_activity::UnknownActivity).
 #[async_trait]
-#[enum_delegate::register]
 pub trait ActivityHandler {
     /// App data type passed to handlers. Must be identical to
     /// [crate::config::FederationConfigBuilder::app_data] type.
@@ -250,14 +304,33 @@ pub trait Actor: Object + Send + 'static {
 
     /// Generates a public key struct for use in the actor json representation
     fn public_key(&self) -> PublicKey {
-        PublicKey::new(self.id(), self.public_key_pem().to_string())
+        PublicKey::from_actor(self)
     }
 
-    /// The actor's shared inbox, if any
+    /// The actor's shared inbox, if any.
+    ///
+    /// It also needs to be federated in the actor json, use
+    /// [Endpoints::from_actor](crate::protocol::endpoints::Endpoints::from_actor) for this.
     fn shared_inbox(&self) -> Option<Url> {
         None
     }
 
+    /// Id of the new account, if this actor moved to another account.
+    ///
+    /// It is federated in the `movedTo` field, see
+    /// [ActorMigration](crate::protocol::migration::ActorMigration).
+    fn moved_to(&self) -> Option<Url> {
+        None
+    }
+
+    /// Other accounts which belong to the same person, federated in the `alsoKnownAs` field.
+    ///
+    /// A new account lists the old one here before it can be moved to, see
+    /// [verify_account_move](crate::protocol::migration::verify_account_move).
+    fn also_known_as(&self) -> Vec<Url> {
+        vec![]
+    }
+
     /// Returns shared inbox if it exists, normal inbox otherwise.
     fn shared_inbox_or_inbox(&self) -> Url {
         self.shared_inbox().unwrap_or_else(|| self.inbox())
@@ -290,7 +363,37 @@ where
     }
 }
 
-/// Trait for federating collections
+/// Allow passing activities by reference. Receiving clones the activity, as it needs to be owned.
+#[async_trait]
+impl<T> ActivityHandler for &T
+where
+    T: ActivityHandler + Clone + Send + Sync,
+{
+    type DataType = T::DataType;
+    type Error = T::Error;
+
+    fn id(&self) -> &Url {
+        (**self).id()
+    }
+
+    fn actor(&self) -> &Url {
+        (**self).actor()
+    }
+
+    async fn verify(&self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        (**self).verify(data).await
+    }
+
+    async fn receive(self, data: &Data<Self::DataType>) -> Result<(), Self::Error> {
+        T::clone(self).receive(data).await
+    }
+}
+
+/// Trait for federating collections, such as followers or outbox of an actor.
+///
+/// Use [OrderedCollection](crate::protocol::collection::OrderedCollection) and
+/// [OrderedCollectionPage](crate::protocol::collection::OrderedCollectionPage) as `Kind` to
+/// serve a paged collection.
 #[async_trait]
 pub trait Collection: Sized {
     /// Actor or object that this collection belongs to
@@ -300,15 +403,41 @@ pub trait Collection: Sized {
     type DataType: Clone + Send + Sync;
     /// The type of protocol struct which gets sent over network to federate this database struct.
     type Kind: for<'de2> Deserialize<'de2>;
+    /// The type of a single item in the collection, eg `Url` for followers or an activity for
+    /// outbox. Pages of the collection are returned as
+    /// [OrderedCollectionPage] of this type by
+    /// [Collection::read_local_page].
+    type Item;
     /// Error type returned by handler methods
     type Error;
 
     /// Reads local collection from database and returns it as Activitypub JSON.
+    ///
+    /// For paged collections this returns only the top-level document with number of items and
+    /// link to the first page.
     async fn read_local(
         owner: &Self::Owner,
         data: &Data<Self::DataType>,
     ) -> Result<Self::Kind, Self::Error>;
 
+    /// Reads a single page of a paged local collection from database, with page numbers starting
+    /// at 1. Use [CollectionPageBuilder](crate::protocol::collection::CollectionPageBuilder) to
+    /// build pages which match the links in [Collection::read_local].
+    ///
+    /// The default implementation returns [Error::NotFound], for
+    /// collections which are not paged.
+    async fn read_local_page(
+        _owner: &Self::Owner,
+        _page: usize,
+        _data: &Data<Self::DataType>,
+    ) -> Result<OrderedCollectionPage<Self::Item>, Self::Error>
+    where
+        Self::Owner: Sync,
+        Self::Error: From<Error>,
+    {
+        Err(Error::NotFound.into())
+    }
+
     /// Verifies that the received object is valid.
     ///
     /// You should check here that the domain of id matches `expected_domain`. Additionally you
@@ -330,207 +459,3 @@ pub trait Collection: Sized {
         data: &Data<Self::DataType>,
     ) -> Result<Self, Self::Error>;
 }
-
-/// Some impls of these traits for use in tests. Dont use this from external crates.
-///
-/// TODO: Should be using `cfg[doctest]` but blocked by <https://github.com/rust-lang/rust/issues/67295>
-#[doc(hidden)]
-#[allow(clippy::unwrap_used)]
-pub mod tests {
-    use super::*;
-    use crate::{
-        fetch::object_id::ObjectId,
-        http_signatures::{generate_actor_keypair, Keypair},
-        protocol::{public_key::PublicKey, verification::verify_domains_match},
-    };
-    use activitystreams_kinds::{activity::FollowType, actor::PersonType};
-    use anyhow::Error;
-    use once_cell::sync::Lazy;
-    use serde::{Deserialize, Serialize};
-
-    #[derive(Clone)]
-    pub struct DbConnection;
-
-    impl DbConnection {
-        pub async fn read_post_from_json_id<T>(&self, _: Url) -> Result<Option<T>, Error> {
-            Ok(None)
-        }
-        pub async fn read_local_user(&self, _: String) -> Result<DbUser, Error> {
-            todo!()
-        }
-        pub async fn upsert<T>(&self, _: &T) -> Result<(), Error> {
-            Ok(())
-        }
-        pub async fn add_follower(&self, _: DbUser, _: DbUser) -> Result<(), Error> {
-            Ok(())
-        }
-    }
-
-    #[derive(Clone, Debug, Deserialize, Serialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Person {
-        #[serde(rename = "type")]
-        pub kind: PersonType,
-        pub preferred_username: String,
-        pub id: ObjectId<DbUser>,
-        pub inbox: Url,
-        pub public_key: PublicKey,
-    }
-    #[derive(Debug, Clone)]
-    pub struct DbUser {
-        pub name: String,
-        pub federation_id: Url,
-        pub inbox: Url,
-        pub public_key: String,
-        #[allow(dead_code)]
-        private_key: Option<String>,
-        pub followers: Vec<Url>,
-        pub local: bool,
-    }
-
-    pub static DB_USER_KEYPAIR: Lazy<Keypair> = Lazy::new(|| generate_actor_keypair().unwrap());
-
-    pub static DB_USER: Lazy<DbUser> = Lazy::new(|| DbUser {
-        name: String::new(),
-        federation_id: "https://localhost/123".parse().unwrap(),
-        inbox: "https://localhost/123/inbox".parse().unwrap(),
-        public_key: DB_USER_KEYPAIR.public_key.clone(),
-        private_key: Some(DB_USER_KEYPAIR.private_key.clone()),
-        followers: vec![],
-        local: false,
-    });
-
-    #[async_trait]
-    impl Object for DbUser {
-        type DataType = DbConnection;
-        type Kind = Person;
-        type Error = Error;
-
-        async fn read_from_id(
-            _object_id: Url,
-            _data: &Data<Self::DataType>,
-        ) -> Result<Option<Self>, Self::Error> {
-            Ok(Some(DB_USER.clone()))
-        }
-
-        async fn into_json(self, _data: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
-            Ok(Person {
-                preferred_username: self.name.clone(),
-                kind: Default::default(),
-                id: self.federation_id.clone().into(),
-                inbox: self.inbox.clone(),
-                public_key: self.public_key(),
-            })
-        }
-
-        async fn verify(
-            json: &Self::Kind,
-            expected_domain: &Url,
-            _data: &Data<Self::DataType>,
-        ) -> Result<(), Self::Error> {
-            verify_domains_match(json.id.inner(), expected_domain)?;
-            Ok(())
-        }
-
-        async fn from_json(
-            json: Self::Kind,
-            _data: &Data<Self::DataType>,
-        ) -> Result<Self, Self::Error> {
-            Ok(DbUser {
-                name: json.preferred_username,
-                federation_id: json.id.into(),
-                inbox: json.inbox,
-                public_key: json.public_key.public_key_pem,
-                private_key: None,
-                followers: vec![],
-                local: false,
-            })
-        }
-    }
-
-    impl Actor for DbUser {
-        fn id(&self) -> Url {
-            self.federation_id.clone()
-        }
-
-        fn public_key_pem(&self) -> &str {
-            &self.public_key
-        }
-
-        fn private_key_pem(&self) -> Option<String> {
-            self.private_key.clone()
-        }
-
-        fn inbox(&self) -> Url {
-            self.inbox.clone()
-        }
-    }
-
-    #[derive(Deserialize, Serialize, Clone, Debug)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Follow {
-        pub actor: ObjectId<DbUser>,
-        pub object: ObjectId<DbUser>,
-        #[serde(rename = "type")]
-        pub kind: FollowType,
-        pub id: Url,
-    }
-
-    #[async_trait]
-    impl ActivityHandler for Follow {
-        type DataType = DbConnection;
-        type Error = Error;
-
-        fn id(&self) -> &Url {
-            &self.id
-        }
-
-        fn actor(&self) -> &Url {
-            self.actor.inner()
-        }
-
-        async fn verify(&self, _: &Data<Self::DataType>) -> Result<(), Self::Error> {
-            Ok(())
-        }
-
-        async fn receive(self, _data: &Data<Self::DataType>) -> Result<(), Self::Error> {
-            Ok(())
-        }
-    }
-
-    #[derive(Clone, Debug, Deserialize, Serialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Note {}
-    #[derive(Debug, Clone)]
-    pub struct DbPost {}
-
-    #[async_trait]
-    impl Object for DbPost {
-        type DataType = DbConnection;
-        type Kind = Note;
-        type Error = Error;
-
-        async fn read_from_id(
-            _: Url,
-            _: &Data<Self::DataType>,
-        ) -> Result<Option<Self>, Self::Error> {
-            todo!()
-        }
-
-        async fn into_json(self, _: &Data<Self::DataType>) -> Result<Self::Kind, Self::Error> {
-            todo!()
-        }
-
-        async fn verify(
-            _: &Self::Kind,
-            _: &Url,
-            _: &Data<Self::DataType>,
-        ) -> Result<(), Self::Error> {
-            todo!()
-        }
-
-        async fn from_json(_: Self::Kind, _: &Data<Self::DataType>) -> Result<Self, Self::Error> {
-            todo!()
-        }
-    }
-}